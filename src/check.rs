@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use x11rb::rust_connection::RustConnection;
+
+use crate::config::{self, Key};
+use crate::daily::{combined_modmask, Command, Layout, Modifier};
+use crate::keyboard::Keymap;
+use crate::{theme, utils, wallpaper};
+
+// `daily2 --check-config` evaluates the configuration compiled from config.rs and reads the
+// theme file as the WM does at startup, without managing windows, and prints the problems found.
+// Errors would break the WM (e.g. malformed commands panic) or make a setting silently ignored;
+// warnings are what the WM logs and works around. Keysyms are resolved only if an X server is
+// reachable, since the keyboard mapping is read from it.
+
+thread_local! {
+    /// the message and the location of the last panic caught while evaluating the config
+    static PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn error(&mut self, message: impl Display) {
+        println!("error: {message}");
+        self.errors += 1;
+    }
+
+    fn warning(&mut self, message: impl Display) {
+        println!("warning: {message}");
+        self.warnings += 1;
+    }
+}
+
+/// Checks the configuration and prints the problems. Returns whether no error is found.
+pub fn check_config() -> bool {
+    let mut report = Report::default();
+
+    // panics are reported instead of aborting the check
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("panicked");
+        let message = match info.location() {
+            Some(location) => format!("{}:{}: {message}", location.file(), location.line()),
+            None => message.to_owned(),
+        };
+        PANIC.with(|panic| *panic.borrow_mut() = Some(message));
+    }));
+    let keybindings = evaluate("keybindings", config::keybindings, &mut report);
+    let chords = evaluate("key_chords", config::key_chords, &mut report);
+    let keysym_bindings = evaluate("keysym_bindings", config::keysym_bindings, &mut report);
+    let mode_bindings = evaluate("mode_keybindings", config::mode_keybindings, &mut report);
+    evaluate("root_menu_actions", config::root_menu_actions, &mut report);
+    evaluate("swipe_gestures", config::swipe_gestures, &mut report);
+    std::panic::set_hook(default_hook);
+
+    // keybindings are bound as chords of a single key
+    let mut chords_all: Vec<(Vec<Key>, Command)> = keybindings
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(modifiers, keycode, cmd)| (vec![(modifiers, keycode)], cmd))
+        .collect();
+    chords_all.extend(chords.unwrap_or_default());
+    check_chords(&chords_all, &mut report);
+
+    if let Some(bindings) = mode_bindings {
+        let mut bound = HashMap::new();
+        for binding in bindings {
+            check_keycode(binding.keycode, &mut report);
+            let key = (
+                u16::from(combined_modmask(binding.modifiers)),
+                binding.keycode,
+            );
+            if let Some(old) = bound.insert((binding.mode, key), binding.command.clone()) {
+                report.warning(format!(
+                    "{:?} in the {} mode is bound twice: {old:?} is replaced with {:?}",
+                    (binding.modifiers, binding.keycode),
+                    binding.mode,
+                    binding.command,
+                ));
+            }
+        }
+    }
+
+    if let Some(bindings) = keysym_bindings {
+        let grabbed = chords_all
+            .iter()
+            .map(|(keys, _)| (u16::from(combined_modmask(keys[0].0)), keys[0].1))
+            .collect();
+        check_keysyms(&bindings, &grabbed, &mut report);
+    }
+
+    check_desktops(&mut report);
+
+    if config::DEFAULT_LAYOUT == Layout::External && config::LAYOUT_PROGRAM.is_none() {
+        report.warning("the external layout works as the horizontal one without LAYOUT_PROGRAM");
+    }
+    if config::DEFAULT_COLUMNS == 0 {
+        report.error("DEFAULT_COLUMNS must be at least 1");
+    }
+    if config::IDLE_ACTIONS.windows(2).any(|w| w[0].0 > w[1].0) {
+        report.error("IDLE_ACTIONS must be sorted by the idle time");
+    }
+
+    let paths = config::DESKTOP_WALLPAPERS.iter().map(|&(_, path)| path);
+    for path in config::WALLPAPER.into_iter().chain(paths) {
+        if let Err(err) = wallpaper::Image::load(path) {
+            report.error(err);
+        }
+    }
+
+    for problem in theme::check() {
+        report.error(problem);
+    }
+
+    println!("{} error(s), {} warning(s)", report.errors, report.warnings);
+    report.errors == 0
+}
+
+/// Calls the function of the config, reporting its panic.
+fn evaluate<T>(name: &str, f: fn() -> T, report: &mut Report) -> Option<T> {
+    match std::panic::catch_unwind(f) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            let message = PANIC.with(|panic| panic.borrow_mut().take());
+            report.error(format!("config::{name}: {}", message.unwrap_or_default()));
+            None
+        }
+    }
+}
+
+fn check_keycode(keycode: u8, report: &mut Report) {
+    // the core protocol never generates keycodes below 8
+    if keycode < 8 {
+        report.error(format!("keycode {keycode} doesn't exist"));
+    }
+}
+
+/// Reports keys bound twice, and chords conflicting with their prefixes as `bind_chord` does.
+fn check_chords(chords: &[(Vec<Key>, Command)], report: &mut Report) {
+    let sequence = |keys: &[Key]| -> Vec<(u16, u8)> {
+        keys.iter()
+            .map(|&(modifiers, keycode)| (u16::from(combined_modmask(modifiers)), keycode))
+            .collect()
+    };
+    let mut bound: HashMap<Vec<(u16, u8)>, &Command> = HashMap::new();
+    for (keys, cmd) in chords {
+        keys.iter()
+            .for_each(|&(_, keycode)| check_keycode(keycode, report));
+        let seq = sequence(keys);
+        if let Some(old) = bound.insert(seq.clone(), cmd) {
+            report.warning(format!(
+                "{keys:?} is bound twice: {old:?} is replaced with {cmd:?}"
+            ));
+        }
+        for (other, _) in chords {
+            let other_seq = sequence(other);
+            if other_seq.len() > seq.len() && other_seq.starts_with(&seq) {
+                report.warning(format!(
+                    "{keys:?} conflicts with the longer chord {other:?}"
+                ));
+            }
+        }
+    }
+}
+
+/// Resolves the keysyms with the keyboard mapping of the X server, if it's reachable.
+fn check_keysyms(
+    bindings: &[(&[Modifier], u32, Command)],
+    grabbed: &HashSet<(u16, u8)>,
+    report: &mut Report,
+) {
+    if bindings.is_empty() {
+        return;
+    }
+    let keymap = RustConnection::connect(None)
+        .ok()
+        .and_then(|(conn, screen)| utils::Context::with_connection(conn, screen).ok())
+        .and_then(|ctx| {
+            let mut keymap = Keymap::default();
+            keymap.load(&ctx).ok()?;
+            Some(keymap)
+        });
+    let Some(keymap) = keymap else {
+        println!("note: keysyms are not resolved without an X server");
+        return;
+    };
+    for &(modifiers, keysym, ref cmd) in bindings {
+        let keycodes = keymap.keycodes(keysym);
+        if keycodes.is_empty() {
+            report.warning(format!(
+                "no key generates keysym 0x{keysym:X} (bound to {cmd:?}) on this keyboard"
+            ));
+        }
+        let state = u16::from(combined_modmask(modifiers));
+        for keycode in keycodes {
+            if grabbed.contains(&(state, keycode)) {
+                report.warning(format!(
+                    "keysym 0x{keysym:X} is ignored since keycode {keycode} is already bound"
+                ));
+            }
+        }
+    }
+}
+
+/// Reports the desktops (0-origin) in the config which don't exist.
+fn check_desktops(report: &mut Report) {
+    let mut check = |name: &str, desktop: usize| {
+        if desktop >= config::NUM_DESKTOPS {
+            report.error(format!(
+                "{name}: desktop {desktop} doesn't exist (NUM_DESKTOPS is {})",
+                config::NUM_DESKTOPS
+            ));
+        }
+    };
+    for &(desktop, _) in config::DESKTOP_WALLPAPERS {
+        check("DESKTOP_WALLPAPERS", desktop);
+    }
+    for &(desktop, _) in config::DESKTOP_INSERT_POSITIONS {
+        check("DESKTOP_INSERT_POSITIONS", desktop);
+    }
+    for &(_, desktop) in config::MONITOR_DESKTOPS {
+        check("MONITOR_DESKTOPS", desktop);
+    }
+}
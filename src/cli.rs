@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::Parser;
+
+// The arguments are kept as they are on restart, since the process is executed again with them.
+
+/// a tiling window manager for X11
+#[derive(Debug, Parser)]
+#[command(name = "daily2", version)]
+pub struct Args {
+    /// read the configuration (the theme) from PATH instead of $XDG_CONFIG_HOME/daily2/theme
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// connect to the X server NAME instead of $DISPLAY
+    #[arg(long, value_name = "NAME")]
+    pub display: Option<String>,
+    /// filter the log messages like $RUST_LOG, e.g. debug or daily2=info
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+    /// replace the running window manager
+    #[arg(long)]
+    pub replace: bool,
+    /// check the configuration and exit
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the path given by `--config`.
+pub fn set_config_file(path: PathBuf) {
+    let _ = CONFIG_FILE.set(path);
+}
+
+/// the configuration file read at runtime: given by `--config`, or
+/// `$XDG_CONFIG_HOME/daily2/theme` (`~/.config/daily2/theme`)
+pub fn config_file() -> Option<PathBuf> {
+    if let Some(path) = CONFIG_FILE.get() {
+        return Some(path.clone());
+    }
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("daily2/theme"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    fn parse(args: &[&str]) -> Result<Args, ErrorKind> {
+        Args::try_parse_from(std::iter::once("daily2").chain(args.iter().copied()))
+            .map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn values_are_given_separately_or_after_equals() {
+        let args = parse(&[
+            "--display",
+            ":1",
+            "--config=/tmp/theme",
+            "--log-level=debug",
+        ])
+        .unwrap();
+        assert_eq!(args.display.as_deref(), Some(":1"));
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/theme")));
+        assert_eq!(args.log_level.as_deref(), Some("debug"));
+        assert!(!args.replace && !args.check_config);
+
+        let args = parse(&["--replace", "--check-config"]).unwrap();
+        assert!(args.replace && args.check_config);
+        assert_eq!(args.display, None);
+    }
+
+    #[test]
+    fn malformed_arguments_are_rejected() {
+        assert_eq!(parse(&["--display"]).unwrap_err(), ErrorKind::InvalidValue);
+        assert_eq!(parse(&["--bogus"]).unwrap_err(), ErrorKind::UnknownArgument);
+        assert_eq!(
+            parse(&["--replace=yes"]).unwrap_err(),
+            ErrorKind::TooManyValues
+        );
+        assert_eq!(
+            parse(&["--version"]).unwrap_err(),
+            ErrorKind::DisplayVersion
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::daily::{Command, Direction, Layout, WindowMatch};
+
+// Commands are written as a name followed by arguments separated by whitespace:
+//
+//   move-window 3
+//   set-layout tabbed
+//   resize-window -20 0
+//   spawn xterm -e htop
+//
+// `spawn` runs the rest of the line in shell, and `exec` runs it directly. The arguments of
+// `exec` may be quoted as in shell: 'single quotes' take the text as it is, and in "double
+// quotes" and outside quotes a backslash escapes the next character.
+// `focus --class <class>` and `focus --title <part of title>` focus a matching window.
+// Desktop numbers are 0-origin as in `Command`.
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("empty command")]
+    Empty,
+
+    #[error("unknown command: {0:?}")]
+    UnknownCommand(String),
+
+    #[error("invalid arguments for {command:?}: {args:?}")]
+    InvalidArguments { command: String, args: String },
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Command, ParseError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let (name, rest) = match s.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (s, ""),
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let invalid = || ParseError::InvalidArguments {
+            command: name.to_owned(),
+            args: rest.to_owned(),
+        };
+
+        let no_args = |cmd: Command| if args.is_empty() { Some(cmd) } else { None };
+        let number = || match args[..] {
+            [n] => n.parse::<usize>().ok(),
+            _ => None,
+        };
+        let pair = || match args[..] {
+            [a, b] => Some((a.parse::<i32>().ok()?, b.parse::<i32>().ok()?)),
+            _ => None,
+        };
+
+        let cmd = match name {
+            "exit" => no_args(Command::Exit),
+            "restart" => no_args(Command::Restart),
+            "reload-theme" => no_args(Command::ReloadTheme),
+            "spawn" => (!rest.is_empty()).then(|| Command::SpawnProcess(rest.to_owned())),
+            "exec" => split_words(rest)
+                .filter(|args| !args.is_empty())
+                .map(Command::Exec),
+            "focus-next-monitor" => no_args(Command::FocusNextMonitor),
+            "focus-next-window" => no_args(Command::FocusNextWindow),
+            "focus-prev-window" => no_args(Command::FocusPrevWindow),
+            "focus-next-same-class" => no_args(Command::FocusNextSameClass),
+            "kill-window-process" => match args[..] {
+                [] => Some(Command::KillWindowProcess(false)),
+                ["--force"] => Some(Command::KillWindowProcess(true)),
+                _ => None,
+            },
+            "focus" => match rest.split_once(char::is_whitespace) {
+                Some(("--class", class)) => Some(WindowMatch::Class(class.trim().to_owned())),
+                Some(("--title", title)) => Some(WindowMatch::Title(title.trim().to_owned())),
+                _ => None,
+            }
+            .map(Command::FocusMatching),
+            "switch-desktop" => number().map(Command::SwitchDesktop),
+            "switch-next-desktop" => no_args(Command::SwitchNextDesktop),
+            "switch-prev-desktop" => no_args(Command::SwitchPrevDesktop),
+            "move-window" => number().map(Command::MoveWindow),
+            "toggle-floating" => no_args(Command::ToggleFloating),
+            "set-layout" => match args[..] {
+                [layout] => Layout::from_name(layout).map(Command::SetLayout),
+                _ => None,
+            },
+            "next-layout" => no_args(Command::NextLayout),
+            "inc-columns" => no_args(Command::IncColumns),
+            "dec-columns" => no_args(Command::DecColumns),
+            "split-horizontal" => no_args(Command::SplitHorizontal),
+            "split-vertical" => no_args(Command::SplitVertical),
+            "preselect" => match args[..] {
+                [direction] => Direction::from_name(direction).map(Command::Preselect),
+                _ => None,
+            },
+            "balance-layout" => no_args(Command::BalanceLayout),
+            "rotate-layout" => no_args(Command::RotateLayout),
+            "mirror-layout" => no_args(Command::MirrorLayout),
+            "toggle-pip" => no_args(Command::TogglePip),
+            "toggle-dropdown" => no_args(Command::ToggleDropdown),
+            "toggle-border" => no_args(Command::ToggleBorder),
+            "toggle-zoom" => no_args(Command::ToggleZoom),
+            "hide-window" => no_args(Command::HideWindow),
+            "unhide-last" => no_args(Command::UnhideLast),
+            "unhide" => match args[..] {
+                [id] => parse_window_id(id).map(Command::Unhide),
+                _ => None,
+            },
+            "group-with-focused" => no_args(Command::GroupWithFocused),
+            "cycle-group" => no_args(Command::CycleGroup),
+            "ungroup" => no_args(Command::Ungroup),
+            "resize-window" => pair().map(|(dw, dh)| Command::ResizeWindow(dw, dh)),
+            "move-floating" => pair().map(|(dx, dy)| Command::MoveFloating(dx, dy)),
+            "snap-left" => no_args(Command::SnapLeft),
+            "snap-right" => no_args(Command::SnapRight),
+            "snap-top" => no_args(Command::SnapTop),
+            "snap-bottom" => no_args(Command::SnapBottom),
+            "snap-top-left" => no_args(Command::SnapTopLeft),
+            "snap-top-right" => no_args(Command::SnapTopRight),
+            "snap-bottom-left" => no_args(Command::SnapBottomLeft),
+            "snap-bottom-right" => no_args(Command::SnapBottomRight),
+            "snap-maximize" => no_args(Command::SnapMaximize),
+            "raise-window" => no_args(Command::RaiseWindow),
+            "lower-window" => no_args(Command::LowerWindow),
+            "enter-mode" => match args[..] {
+                [mode] => Some(Command::EnterMode(mode.to_owned())),
+                _ => None,
+            },
+            "exit-mode" => no_args(Command::ExitMode),
+            "goto-desktop-prompt" => no_args(Command::GotoDesktopPrompt),
+            "next-keyboard-layout" => no_args(Command::NextKeyboardLayout),
+            "toggle-idle-inhibit" => no_args(Command::ToggleIdleInhibit),
+            "toggle-pager" => no_args(Command::TogglePager),
+            "overview" => match args[..] {
+                [] => Some(Command::Overview(false)),
+                ["--all"] => Some(Command::Overview(true)),
+                _ => None,
+            },
+            _ => return Err(ParseError::UnknownCommand(name.to_owned())),
+        };
+        cmd.ok_or_else(invalid)
+    }
+}
+
+/// Writes the command in the syntax accepted by `from_str`.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Exit => write!(f, "exit"),
+            Command::Restart => write!(f, "restart"),
+            Command::ReloadTheme => write!(f, "reload-theme"),
+            Command::SpawnProcess(cmdline) => write!(f, "spawn {cmdline}"),
+            Command::Exec(args) => {
+                let args: Vec<_> = args.iter().map(|arg| quote(arg)).collect();
+                write!(f, "exec {}", args.join(" "))
+            }
+            Command::FocusNextMonitor => write!(f, "focus-next-monitor"),
+            Command::FocusNextWindow => write!(f, "focus-next-window"),
+            Command::FocusPrevWindow => write!(f, "focus-prev-window"),
+            Command::FocusNextSameClass => write!(f, "focus-next-same-class"),
+            Command::KillWindowProcess(false) => write!(f, "kill-window-process"),
+            Command::KillWindowProcess(true) => write!(f, "kill-window-process --force"),
+            Command::FocusMatching(WindowMatch::Class(class)) => write!(f, "focus --class {class}"),
+            Command::FocusMatching(WindowMatch::Title(title)) => write!(f, "focus --title {title}"),
+            Command::SwitchDesktop(desktop) => write!(f, "switch-desktop {desktop}"),
+            Command::SwitchNextDesktop => write!(f, "switch-next-desktop"),
+            Command::SwitchPrevDesktop => write!(f, "switch-prev-desktop"),
+            Command::MoveWindow(desktop) => write!(f, "move-window {desktop}"),
+            Command::ToggleFloating => write!(f, "toggle-floating"),
+            Command::SetLayout(layout) => write!(f, "set-layout {}", layout.name()),
+            Command::NextLayout => write!(f, "next-layout"),
+            Command::IncColumns => write!(f, "inc-columns"),
+            Command::DecColumns => write!(f, "dec-columns"),
+            Command::SplitHorizontal => write!(f, "split-horizontal"),
+            Command::SplitVertical => write!(f, "split-vertical"),
+            Command::Preselect(direction) => write!(f, "preselect {}", direction.name()),
+            Command::BalanceLayout => write!(f, "balance-layout"),
+            Command::RotateLayout => write!(f, "rotate-layout"),
+            Command::MirrorLayout => write!(f, "mirror-layout"),
+            Command::TogglePip => write!(f, "toggle-pip"),
+            Command::ToggleDropdown => write!(f, "toggle-dropdown"),
+            Command::ToggleBorder => write!(f, "toggle-border"),
+            Command::ToggleZoom => write!(f, "toggle-zoom"),
+            Command::HideWindow => write!(f, "hide-window"),
+            Command::UnhideLast => write!(f, "unhide-last"),
+            Command::Unhide(window) => write!(f, "unhide 0x{window:X}"),
+            Command::GroupWithFocused => write!(f, "group-with-focused"),
+            Command::CycleGroup => write!(f, "cycle-group"),
+            Command::Ungroup => write!(f, "ungroup"),
+            Command::ResizeWindow(dw, dh) => write!(f, "resize-window {dw} {dh}"),
+            Command::MoveFloating(dx, dy) => write!(f, "move-floating {dx} {dy}"),
+            Command::SnapLeft => write!(f, "snap-left"),
+            Command::SnapRight => write!(f, "snap-right"),
+            Command::SnapTop => write!(f, "snap-top"),
+            Command::SnapBottom => write!(f, "snap-bottom"),
+            Command::SnapTopLeft => write!(f, "snap-top-left"),
+            Command::SnapTopRight => write!(f, "snap-top-right"),
+            Command::SnapBottomLeft => write!(f, "snap-bottom-left"),
+            Command::SnapBottomRight => write!(f, "snap-bottom-right"),
+            Command::SnapMaximize => write!(f, "snap-maximize"),
+            Command::RaiseWindow => write!(f, "raise-window"),
+            Command::LowerWindow => write!(f, "lower-window"),
+            Command::EnterMode(mode) => write!(f, "enter-mode {mode}"),
+            Command::ExitMode => write!(f, "exit-mode"),
+            Command::GotoDesktopPrompt => write!(f, "goto-desktop-prompt"),
+            Command::NextKeyboardLayout => write!(f, "next-keyboard-layout"),
+            Command::ToggleIdleInhibit => write!(f, "toggle-idle-inhibit"),
+            Command::Overview(false) => write!(f, "overview"),
+            Command::Overview(true) => write!(f, "overview --all"),
+            Command::TogglePager => write!(f, "toggle-pager"),
+        }
+    }
+}
+
+impl Command {
+    /// the name of the command without the arguments, e.g. "move-window"
+    pub fn name(&self) -> String {
+        let s = self.to_string();
+        match s.split_once(' ') {
+            Some((name, _)) => name.to_owned(),
+            None => s,
+        }
+    }
+}
+
+/// a window ID in decimal or hexadecimal with "0x" (as xprop and xwininfo show)
+fn parse_window_id(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Splits the text into words separated by whitespace, removing the quotes as in shell.
+/// Returns None if a quote isn't closed.
+pub fn split_words(s: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = s.chars();
+    // None between words
+    let mut word: Option<String> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// Quotes the word for `split_words` if needed.
+fn quote(word: &str) -> Cow<'_, str> {
+    let special = |c: char| c.is_whitespace() || matches!(c, '\'' | '"' | '\\');
+    if !word.is_empty() && !word.contains(special) {
+        return Cow::Borrowed(word);
+    }
+    Cow::Owned(format!("'{}'", word.replace('\'', r#"'\''"#)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_split_with_quotes() {
+        let split = |s| split_words(s).unwrap();
+        assert_eq!(split("  a  b\tc "), ["a", "b", "c"]);
+        assert_eq!(
+            split(r#"sh -c 'echo "$HOME"; exit'"#),
+            ["sh", "-c", r#"echo "$HOME"; exit"#]
+        );
+        assert_eq!(split(r#""a \"b\" \\c" d\ e"#), [r#"a "b" \c"#, "d e"]);
+        assert_eq!(split(r#"'' x'y'"z""#), ["", "xyz"]);
+        assert_eq!(split(""), Vec::<String>::new());
+        assert_eq!(split_words("'open"), None);
+        assert_eq!(split_words(r#""open \""#), None);
+        assert_eq!(split_words("trailing\\"), None);
+    }
+
+    #[test]
+    fn commands_are_written_as_they_are_parsed() {
+        let commands = [
+            "exit",
+            "spawn xterm -e 'htop'",
+            "exec xterm",
+            "kill-window-process --force",
+            "focus --class Firefox",
+            "focus --title a title",
+            "switch-desktop 3",
+            "set-layout tabbed",
+            "preselect left",
+            "unhide 0x400001",
+            "resize-window -20 0",
+            "enter-mode resize",
+            "overview --all",
+        ];
+        for text in commands {
+            let cmd: Command = text.parse().unwrap();
+            assert_eq!(cmd.to_string(), text);
+        }
+
+        let args = ["sh", "-c", "echo \"it's\" \\ $HOME", ""];
+        let cmd = Command::Exec(args.map(str::to_owned).to_vec());
+        match cmd.to_string().parse::<Command>().unwrap() {
+            Command::Exec(parsed) => assert_eq!(parsed, args),
+            other => panic!("parsed as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        assert!(matches!("".parse::<Command>(), Err(ParseError::Empty)));
+        assert!(matches!(
+            "bogus 1".parse::<Command>(),
+            Err(ParseError::UnknownCommand(name)) if name == "bogus"
+        ));
+        for text in [
+            "exit now",
+            "switch-desktop x",
+            "exec",
+            "exec 'open",
+            "resize-window 1",
+        ] {
+            assert!(
+                matches!(
+                    text.parse::<Command>(),
+                    Err(ParseError::InvalidArguments { .. })
+                ),
+                "{text:?} is accepted"
+            );
+        }
+    }
+}
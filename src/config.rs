@@ -1,18 +1,272 @@
-use crate::daily::{Command, Modifier};
+use crate::daily::{
+    Command, Corner, Direction, DragAction, FocusOrder, FocusPolicy, InsertPosition, Layout,
+    Modifier, PreviewStyle,
+};
+#[allow(unused_imports)]
+use crate::output::{OutputConfig, Rotation};
+use crate::wallpaper::Scaling;
 
 pub const HOT_KEY: Modifier = Modifier::Super;
 
+// Commands can also be written in the syntax shared with the IPC, e.g. command("move-window 3").
+// A malformed command panics at startup (and is reported with its line by `--check-config`).
+#[track_caller]
+fn command(s: &str) -> Command {
+    match s.parse() {
+        Ok(cmd) => cmd,
+        Err(err) => panic!("malformed command in config: {s:?}: {err}"),
+    }
+}
+
+// mouse buttons (with modifiers) to drag windows: 1 = left, 2 = middle, 3 = right
+// Resizing a tile in the horizontal or manual layout changes its share of the split. Moving a
+// tile onto another one swaps them, and it's made floating only when dropped elsewhere.
+pub const DRAG_BUTTONS: &[(&[Modifier], u8, DragAction)] = &[
+    (&[HOT_KEY], 1, DragAction::Move),
+    (&[HOT_KEY], 3, DragAction::Resize),
+];
+
+// whether right-clicking the desktop background opens a menu listing the desktops and
+// these actions, which is a way out when keybindings don't work
+pub const ROOT_MENU: bool = true;
+pub fn root_menu_actions() -> Vec<(&'static str, Command)> {
+    vec![
+        ("terminal", command("exec /usr/bin/xterm")),
+        ("restart", Command::Restart),
+        ("exit", Command::Exit),
+    ]
+}
+
+// scrolling the wheel with these modifiers switches the focused monitor to the previous (up)
+// or the next (down) desktop: Some(&[]) scrolls on the desktop background only,
+// Some(&[HOT_KEY]) anywhere with the key held, and None disables it
+pub const ROOT_SCROLL_MODIFIERS: Option<&[Modifier]> = Some(&[]);
+
 pub const WINDOW_BORDER_WIDTH: u32 = 1;
 
+// hide the border of tiled windows when only one of them is visible on the monitor
+// (a single window, or the tabbed and stacking layouts)
+pub const SMART_BORDERS: bool = true;
+
 pub const SNAPPING_WIDTH: u32 = 64;
 
+// the gap between the windows arranged in a grid by the overview
+pub const OVERVIEW_GAP: u32 = 16;
+
+// show the windows of an MRU cycle (with FocusOrder::MostRecentlyUsed) in a switcher window,
+// with live thumbnails if the server has the Composite extension (for which all windows are
+// redirected)
+pub const SWITCHER: bool = true;
+pub const SWITCHER_THUMBNAILS: bool = true;
+// the width of each thumbnail (the height follows the monitor), and the gap around them
+pub const SWITCHER_THUMBNAIL_WIDTH: u32 = 240;
+pub const SWITCHER_PADDING: u32 = 8;
+// how often the thumbnails are redrawn while the switcher is shown
+pub const SWITCHER_REFRESH_MS: u64 = 100;
+
+// the size of each desktop in the pager relative to the monitor, and the gap between them
+// (windows dragged with the hot key onto a desktop in the open pager are moved there)
+pub const PAGER_SCALE: f64 = 0.1;
+pub const PAGER_GAP: u32 = 4;
+
+// the preview of where a dragged window snaps, and of where the next window opens in the manual
+// layout: PreviewStyle::Filled, or Outline drawing only the border
+pub const PREVIEW_STYLE: PreviewStyle = PreviewStyle::Filled;
+// the opacity of the fill (0-255, opaque on servers without 32-bit visuals)
+pub const PREVIEW_ALPHA: u8 = 0x80;
+pub const PREVIEW_BORDER_WIDTH: u32 = 1;
+
+// whether border widths and snapping widths are scaled by the DPI of each monitor
+pub const DPI_SCALING: bool = true;
+
+// the DPI at which the sizes above are used as they are
+pub const BASE_DPI: f64 = 96.0;
+
+// how far (in pixels) the pointer must go beyond the edge of a monitor
+// before a dragged window moves to the next monitor
+pub const EDGE_RESISTANCE: i32 = 32;
+
 // This program will be run in shell when a monitor is connected or disconnected
 // Expected usage is to specify a script that updates monitor layout using xrandr utility.
 pub const MONITOR_UPDATE_PROG: Option<&str> = Some(r#"echo 'monitor changed'"#);
 
+// Outputs listed here are configured without any external program whenever outputs change:
+// connected ones are enabled with their preferred modes and disconnected ones are disabled.
+// e.g.
+// pub const OUTPUT_LAYOUT: &[OutputConfig] = &[
+//     OutputConfig { name: "eDP-1", position: (0, 0), rotation: Rotation::Normal, primary: true },
+//     OutputConfig { name: "HDMI-1", position: (1920, 0), rotation: Rotation::Normal, primary: false },
+// ];
+pub const OUTPUT_LAYOUT: &[OutputConfig] = &[];
+
+// space (top, bottom, left, right) in pixels left empty at the edges of monitors,
+// e.g. for bars which don't reserve space with struts
+pub const PADDING: Padding = (0, 0, 0, 0);
+
+/// (top, bottom, left, right)
+pub type Padding = (u32, u32, u32, u32);
+
+// the padding of specific monitors given by their output names or EDIDs like MONITOR_DESKTOPS,
+// e.g. &[("eDP-1", (24, 0, 0, 0))]
+pub const MONITOR_PADDING: &[(&str, Padding)] = &[];
+
+// desktops (0-origin) shown on monitors connected to these outputs, e.g. &[("eDP-1", 0), ("DP-1", 5)]
+// A monitor can also be specified by its EDID as "DEL-40F1-4C385A30" (shown in the log).
+// Other monitors show the first desktop not displayed on any monitor.
+pub const MONITOR_DESKTOPS: &[(&str, usize)] = &[];
+
+// whether new windows are opened on the desktop where a window of the same class (WM_CLASS)
+// was last used
+pub const REMEMBER_DESKTOPS: bool = false;
+
+// whether new windows are focused when mapped: FocusPolicy::Visible focuses them only if their
+// desktop is visible, Always switches to their desktop if not, and Never leaves the focus
+pub const DEFAULT_FOCUS_POLICY: FocusPolicy = FocusPolicy::Visible;
+
+// the focus policies of windows of specific classes (WM_CLASS),
+// e.g. &[("Slack", FocusPolicy::Never)]
+pub const FOCUS_POLICIES: &[(&str, FocusPolicy)] = &[];
+
+// whether the window under the pointer is focused when the focused window disappears and no
+// other window on the desktop has been focused before, instead of the first in the tiling order
+pub const FOCUS_UNDER_POINTER_FALLBACK: bool = false;
+
+// whether the pointer is moved to the center of a newly mapped window when it is focused
+pub const WARP_POINTER_ON_MAP: bool = false;
+
+// windows of these classes (WM_CLASS) are hidden while a window spawned from them is open
+pub const SWALLOW_CLASSES: &[&str] = &["XTerm"];
+
+// size (width, height) and position of picture-in-picture windows
+pub const PIP_SIZE: (u32, u32) = (480, 270);
+pub const PIP_CORNER: Corner = Corner::BottomRight;
+pub const PIP_MARGIN: u32 = 16;
+
+// the dropdown window is spawned by this command on first use, and recognized by its class
+// (WM_CLASS) when it's mapped
+pub const DROPDOWN_COMMAND: &[&str] = &["/usr/bin/xterm", "-class", "daily2-dropdown"];
+pub const DROPDOWN_CLASS: &str = "daily2-dropdown";
+// the height of the dropdown window relative to the work area of the monitor
+pub const DROPDOWN_HEIGHT: f64 = 0.4;
+// the command is run again if no window appears within this time
+pub const DROPDOWN_SPAWN_TIMEOUT_MS: u128 = 5000;
+
+// notification windows (_NET_WM_WINDOW_TYPE_NOTIFICATION) are stacked from this corner
+// of the focused monitor, never tiled nor focused
+pub const NOTIFICATION_CORNER: Corner = Corner::TopRight;
+// the gap between notifications and from the edges of the monitor
+pub const NOTIFICATION_MARGIN: u32 = 16;
+
+// the wallpaper drawn on each monitor, a binary PPM (P6) image converted from another format
+// e.g. with `convert wallpaper.jpg wallpaper.ppm` ("~/" is the home directory)
+pub const WALLPAPER: Option<&str> = None;
+// the wallpapers of specific desktops (0-origin) instead of WALLPAPER, swapped when desktops are
+// switched, e.g. &[(1, "~/wallpapers/work.ppm")]
+pub const DESKTOP_WALLPAPERS: &[(usize, &str)] = &[];
+// how the wallpaper fits monitors: Scaling::Fill (cropped), Fit (with margins), Stretch or Center
+pub const WALLPAPER_SCALING: Scaling = Scaling::Fill;
+
+// the layout used for desktops at startup
+pub const DEFAULT_LAYOUT: Layout = Layout::Horizontal;
+
+// where new windows are placed in the tiling order: InsertPosition::Master, AfterFocused or End
+// (the manual layout always opens them next to the focused window)
+pub const INSERT_POSITION: InsertPosition = InsertPosition::End;
+
+// the insert positions of specific desktops (0-origin), e.g. &[(2, InsertPosition::Master)]
+pub const DESKTOP_INSERT_POSITIONS: &[(usize, InsertPosition)] = &[];
+
+// This program is run in shell to place the windows in the external layout: it reads the area
+// and the tiled windows from stdin, and prints their geometries (see src/layout_program.rs).
+// The external layout works as the horizontal one while this is None or the program fails.
+pub const LAYOUT_PROGRAM: Option<&str> = None;
+// how long (in milliseconds) the layout program may take before it is killed
+pub const LAYOUT_PROGRAM_TIMEOUT_MS: u64 = 200;
+
+// the number of columns in the columns layout at startup
+pub const DEFAULT_COLUMNS: usize = 3;
+
+// height of the tab bar shown in the tabbed layout (each title height in the stacking layout)
+pub const TAB_BAR_HEIGHT: u32 = 20;
+
+// The colors of everything the WM draws, given as "#RRGGBB" (or "#RGB") or by another name in
+// the list, such as the palette at the top. The file $XDG_CONFIG_HOME/daily2/theme (or
+// ~/.config/daily2/theme) overrides them with lines like "border-focused #00FF00", and is read
+// again by the reload-theme command (see src/theme.rs).
+pub const THEME: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("white", "#FFFFFF"),
+    ("gray", "#888888"),
+    ("dark-gray", "#222222"),
+    ("green", "#00FF00"),
+    ("red", "#FF0000"),
+    ("blue", "#285577"),
+    ("ochre", "#A37A29"),
+    ("amber", "#FAAB23"),
+    ("slate", "#5F676A"),
+    // borders of windows
+    ("border-focused", "green"),
+    ("border-unfocused", "black"),
+    ("border-urgent", "red"),
+    // the preview of snapping (see PREVIEW_STYLE)
+    ("preview", "ochre"),
+    ("preview-border", "amber"),
+    // the margins around the wallpaper, and the root window outside monitors
+    ("wallpaper", "black"),
+    // behind thumbnails in the switcher and desktops in the pager
+    ("background", "black"),
+    // tab bars, and the menu, the pager and the switcher drawn like them
+    ("tab-active", "blue"),
+    ("tab-active-text", "white"),
+    ("tab-inactive", "dark-gray"),
+    ("tab-inactive-text", "gray"),
+    // the button under the pointer on title bars
+    ("button-hover", "slate"),
+];
+
+// the border colors (focused, unfocused) of windows of specific classes (WM_CLASS), given as in
+// THEME, e.g. &[("RootTerm", "red", "#550000"), ("work-browser", "blue", "dark-gray")]
+pub const BORDER_COLORS: &[(&str, &str, &str)] = &[];
+
+// whether floating windows have a title bar (of TAB_BAR_HEIGHT) above them, with buttons to
+// close, maximize and tile them; dragging the rest of the bar moves the window
+pub const TITLE_BARS: bool = true;
+
+// the order in which FocusNextWindow/FocusPrevWindow cycle windows on a desktop
+pub const FOCUS_ORDER: FocusOrder = FocusOrder::Tiling;
+
+// This program will be run in shell with the desktop number as an argument
+// when a window demands attention, e.g. to notify a bar.
+pub const URGENT_HOOK_PROG: Option<&str> = None;
+
+// These programs will be run in shell when there has been no keyboard or pointer input
+// for the seconds, e.g. `(300, "xset dpms force off")` or `(600, "slock")`. They must be
+// in ascending order of the seconds, and any input restarts the countdown.
+pub const IDLE_ACTIONS: &[(u64, &str)] = &[];
+
+// whether a fullscreen window on any monitor (e.g. video playback) suspends IDLE_ACTIONS and
+// the screen saver including DPMS; `toggle-idle-inhibit` overrides it
+pub const FULLSCREEN_INHIBITS_IDLE: bool = true;
+
+// how often (in milliseconds) the idle time is checked for input after all IDLE_ACTIONS have run
+pub const IDLE_POLL_INTERVAL_MS: u64 = 1000;
+
+// whether to speak the i3 IPC protocol on another socket (given by $I3SOCK and the I3_SOCKET_PATH
+// property of the root window), so that i3bar and the i3 modules of bars work with daily2
+pub const I3_IPC: bool = true;
+
+// whether to register to the session manager given by $SESSION_MANAGER (XSMP), which asks to
+// save the state before logging out (into $XDG_STATE_HOME/daily2/session, restored in the next
+// session) and tells when to exit
+pub const SESSION_MANAGEMENT: bool = true;
+
+// how long (in milliseconds) `--replace` waits for the running WM to exit
+pub const REPLACE_TIMEOUT_MS: u64 = 5000;
+
 // maximum number of the virtual desktops
 pub const NUM_DESKTOPS: usize = 20;
 
+const KEYCODE_ESCAPE: u8 = 9;
 const KEYCODE_1: u8 = 10;
 const KEYCODE_2: u8 = 11;
 const KEYCODE_3: u8 = 12;
@@ -25,11 +279,35 @@ const KEYCODE_9: u8 = 18;
 const KEYCODE_0: u8 = 19;
 const KEYCODE_TAB: u8 = 23;
 const KEYCODE_Q: u8 = 24;
+const KEYCODE_W: u8 = 25;
+const KEYCODE_E: u8 = 26;
 const KEYCODE_R: u8 = 27;
 const KEYCODE_T: u8 = 28;
+const KEYCODE_U: u8 = 30;
+const KEYCODE_I: u8 = 31;
+const KEYCODE_O: u8 = 32;
 const KEYCODE_P: u8 = 33;
+const KEYCODE_RETURN: u8 = 36;
+const KEYCODE_A: u8 = 38;
 const KEYCODE_S: u8 = 39;
+const KEYCODE_G: u8 = 42;
+const KEYCODE_D: u8 = 40;
+const KEYCODE_H: u8 = 43;
 const KEYCODE_J: u8 = 44;
+const KEYCODE_K: u8 = 45;
+const KEYCODE_L: u8 = 46;
+const KEYCODE_GRAVE: u8 = 49;
+const KEYCODE_Z: u8 = 52;
+const KEYCODE_X: u8 = 53;
+const KEYCODE_V: u8 = 55;
+const KEYCODE_B: u8 = 56;
+const KEYCODE_N: u8 = 57;
+const KEYCODE_M: u8 = 58;
+const KEYCODE_SPACE: u8 = 65;
+const KEYCODE_UP: u8 = 111;
+const KEYCODE_LEFT: u8 = 113;
+const KEYCODE_RIGHT: u8 = 114;
+const KEYCODE_DOWN: u8 = 116;
 
 pub fn keybindings() -> Vec<(&'static [Modifier], u8, Command)> {
     #[rustfmt::skip]
@@ -45,15 +323,89 @@ pub fn keybindings() -> Vec<(&'static [Modifier], u8, Command)> {
 
         // keys to change the input focus to another window on the same screen
         (&[HOT_KEY], KEYCODE_TAB, Command::FocusNextWindow),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_TAB, Command::FocusPrevWindow),
+
+        // key to cycle the windows of the focused application on all desktops
+        (&[HOT_KEY, Modifier::Control], KEYCODE_TAB, Command::FocusNextSameClass),
 
         // keys to toggle floating mode of the focused window
         (&[HOT_KEY], KEYCODE_S, Command::ToggleFloating),
 
+        // keys to group windows into one tile and cycle the visible member
+        (&[HOT_KEY], KEYCODE_G, Command::GroupWithFocused),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_G, Command::Ungroup),
+        (&[HOT_KEY], KEYCODE_GRAVE, Command::CycleGroup),
+
+        // keys to toggle picture-in-picture mode of the focused window
+        (&[HOT_KEY], KEYCODE_O, Command::TogglePip),
+
+        // key to show or hide the dropdown terminal
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_T, Command::ToggleDropdown),
+
+        // keys to snap the focused window into a half of its monitor, or maximize it
+        (&[HOT_KEY], KEYCODE_LEFT, Command::SnapLeft),
+        (&[HOT_KEY], KEYCODE_RIGHT, Command::SnapRight),
+        (&[HOT_KEY], KEYCODE_UP, Command::SnapTop),
+        (&[HOT_KEY], KEYCODE_DOWN, Command::SnapBottom),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_UP, Command::SnapMaximize),
+
+        // key to zoom the focused tile temporarily
+        (&[HOT_KEY], KEYCODE_RETURN, Command::ToggleZoom),
+
+        // keys to hide the focused window and show the last hidden one
+        (&[HOT_KEY], KEYCODE_U, Command::HideWindow),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_U, Command::UnhideLast),
+
+        // key to hide or show the border of the focused window
+        (&[HOT_KEY], KEYCODE_B, Command::ToggleBorder),
+
+        // keys to change the layout of the focused desktop
+        (&[HOT_KEY], KEYCODE_E, Command::SetLayout(Layout::Horizontal)),
+        (&[HOT_KEY], KEYCODE_W, Command::SetLayout(Layout::Tabbed)),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_W, Command::SetLayout(Layout::Stacking)),
+        (&[HOT_KEY], KEYCODE_SPACE, Command::NextLayout),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_R, Command::RotateLayout),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_M, Command::MirrorLayout),
+
+        // keys to change the number of columns in the columns layout
+        (&[HOT_KEY], KEYCODE_I, Command::IncColumns),
+        (&[HOT_KEY], KEYCODE_D, Command::DecColumns),
+
+        // keys to choose where the next window opens in the manual layout
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_E, Command::SetLayout(Layout::Manual)),
+        (&[HOT_KEY], KEYCODE_H, Command::SplitHorizontal),
+        (&[HOT_KEY], KEYCODE_V, Command::SplitVertical),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_H, Command::Preselect(Direction::Left)),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_J, Command::Preselect(Direction::Down)),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_K, Command::Preselect(Direction::Up)),
+        (&[HOT_KEY, Modifier::Control], KEYCODE_L, Command::Preselect(Direction::Right)),
+
+        // key to make the tiles equal in size
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_B, Command::BalanceLayout),
+
+        // keys to pick a window from the overview of the focused desktop, or of all desktops
+        (&[HOT_KEY], KEYCODE_A, Command::Overview(false)),
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_A, Command::Overview(true)),
+
+        // key to type the number (from 1) of the desktop to switch to, confirmed by Return
+        (&[HOT_KEY], KEYCODE_N, Command::GotoDesktopPrompt),
+
+        // key to show or hide the pager, where desktops are switched by clicking them and
+        // windows are moved to other desktops by dragging them
+        (&[HOT_KEY, Modifier::Shift], KEYCODE_N, Command::TogglePager),
+
+        // key to switch to the next keyboard layout
+        (&[HOT_KEY], KEYCODE_M, Command::NextKeyboardLayout),
+
+        // keys to enter keybinding modes
+        (&[HOT_KEY], KEYCODE_R, Command::EnterMode("resize".into())),
+        (&[HOT_KEY], KEYCODE_X, Command::EnterMode("launch".into())),
+
         // dmenu_run
-        (&[HOT_KEY], KEYCODE_P, Command::SpawnProcess("/usr/bin/dmenu_run".into())),
+        (&[HOT_KEY], KEYCODE_P, Command::Exec(vec!["/usr/bin/dmenu_run".into()])),
 
         // terminal
-        (&[HOT_KEY], KEYCODE_T, Command::SpawnProcess("/usr/bin/xterm".into())),
+        (&[HOT_KEY], KEYCODE_T, Command::Exec(vec!["/usr/bin/xterm".into()])),
     ];
 
     let digit_keys = [
@@ -67,3 +419,144 @@ pub fn keybindings() -> Vec<(&'static [Modifier], u8, Command)> {
 
     list
 }
+
+const XF86XK_MON_BRIGHTNESS_UP: u32 = 0x1008FF02;
+const XF86XK_MON_BRIGHTNESS_DOWN: u32 = 0x1008FF03;
+const XF86XK_AUDIO_LOWER_VOLUME: u32 = 0x1008FF11;
+const XF86XK_AUDIO_MUTE: u32 = 0x1008FF12;
+const XF86XK_AUDIO_RAISE_VOLUME: u32 = 0x1008FF13;
+const XF86XK_AUDIO_PLAY: u32 = 0x1008FF14;
+const XF86XK_AUDIO_PREV: u32 = 0x1008FF16;
+const XF86XK_AUDIO_NEXT: u32 = 0x1008FF17;
+const XF86XK_AUDIO_MIC_MUTE: u32 = 0x1008FFB2;
+
+// keybindings by keysyms, for keys whose keycodes differ among keyboards (e.g. multimedia keys)
+pub fn keysym_bindings() -> Vec<(&'static [Modifier], u32, Command)> {
+    #[rustfmt::skip]
+    let list: Vec<(&[Modifier], _, _)> = vec![
+        // volume keys
+        (&[], XF86XK_AUDIO_RAISE_VOLUME, command("spawn pactl set-sink-volume @DEFAULT_SINK@ +5%")),
+        (&[], XF86XK_AUDIO_LOWER_VOLUME, command("spawn pactl set-sink-volume @DEFAULT_SINK@ -5%")),
+        (&[], XF86XK_AUDIO_MUTE, command("spawn pactl set-sink-mute @DEFAULT_SINK@ toggle")),
+        (&[], XF86XK_AUDIO_MIC_MUTE, command("spawn pactl set-source-mute @DEFAULT_SOURCE@ toggle")),
+
+        // media player keys
+        (&[], XF86XK_AUDIO_PLAY, command("exec playerctl play-pause")),
+        (&[], XF86XK_AUDIO_PREV, command("exec playerctl previous")),
+        (&[], XF86XK_AUDIO_NEXT, command("exec playerctl next")),
+
+        // brightness keys
+        (&[], XF86XK_MON_BRIGHTNESS_UP, command("exec brightnessctl set +10%")),
+        (&[], XF86XK_MON_BRIGHTNESS_DOWN, command("exec brightnessctl set 10%-")),
+    ];
+
+    list
+}
+
+// commands run by swiping a touchpad with the number of fingers in the direction
+// (needs XInput 2.4, i.e. Xorg 21.1 or later with libinput)
+pub fn swipe_gestures() -> Vec<(u32, Direction, Command)> {
+    vec![
+        (3, Direction::Left, Command::SwitchNextDesktop),
+        (3, Direction::Right, Command::SwitchPrevDesktop),
+        (4, Direction::Left, Command::FocusNextMonitor),
+        (4, Direction::Right, Command::FocusNextMonitor),
+    ]
+}
+
+// how far (in pixels of the pointer motion) the fingers must move for a swipe to run its command
+pub const SWIPE_THRESHOLD: f64 = 100.0;
+
+// how long to wait for the next key of a key chord (in milliseconds)
+pub const CHORD_TIMEOUT_MS: u64 = 2000;
+
+// pressing this key in the middle of a key chord aborts it
+pub const CHORD_ABORT_KEY: u8 = KEYCODE_ESCAPE;
+
+/// a key with modifiers
+pub type Key = (&'static [Modifier], u8);
+
+pub fn key_chords() -> Vec<(Vec<Key>, Command)> {
+    let mut list = Vec::new();
+
+    // Super+z followed by a digit switches to or moves the window to the desktops 11-20
+    let prefix: Key = (&[HOT_KEY], KEYCODE_Z);
+    let digit_keys = [
+        KEYCODE_1, KEYCODE_2, KEYCODE_3, KEYCODE_4, KEYCODE_5, KEYCODE_6, KEYCODE_7, KEYCODE_8,
+        KEYCODE_9, KEYCODE_0,
+    ];
+    for (i, kc) in digit_keys.into_iter().enumerate() {
+        list.push((vec![prefix, (&[], kc)], Command::SwitchDesktop(10 + i)));
+        list.push((
+            vec![prefix, (&[Modifier::Shift], kc)],
+            Command::MoveWindow(10 + i),
+        ));
+    }
+
+    list
+}
+
+// holding a key bound to a repeatable command (ResizeWindow, MoveFloating) executes it
+// at most once in this interval (in milliseconds), however fast the key repeats
+pub const KEY_REPEAT_INTERVAL_MS: u32 = 40;
+
+// pressing this key in any mode leaves the mode
+pub const MODE_EXIT_KEY: u8 = KEYCODE_ESCAPE;
+
+pub struct ModeBinding {
+    pub mode: &'static str,
+    /// whether the mode is left after a command is executed
+    pub oneshot: bool,
+    pub modifiers: &'static [Modifier],
+    pub keycode: u8,
+    pub command: Command,
+}
+
+pub fn mode_keybindings() -> Vec<ModeBinding> {
+    let mut list = Vec::new();
+
+    // resize mode: h/j/k/l resize the focused floating window (or tile), and move it with Shift;
+    // u/d raise and lower it
+    #[rustfmt::skip]
+    let resize: [(&[Modifier], u8, Command); 12] = [
+        (&[], KEYCODE_H, Command::ResizeWindow(-20, 0)),
+        (&[], KEYCODE_J, Command::ResizeWindow(0, 20)),
+        (&[], KEYCODE_K, Command::ResizeWindow(0, -20)),
+        (&[], KEYCODE_L, Command::ResizeWindow(20, 0)),
+        (&[Modifier::Shift], KEYCODE_H, Command::MoveFloating(-20, 0)),
+        (&[Modifier::Shift], KEYCODE_J, Command::MoveFloating(0, 20)),
+        (&[Modifier::Shift], KEYCODE_K, Command::MoveFloating(0, -20)),
+        (&[Modifier::Shift], KEYCODE_L, Command::MoveFloating(20, 0)),
+        (&[], KEYCODE_U, Command::RaiseWindow),
+        (&[], KEYCODE_D, Command::LowerWindow),
+        (&[], KEYCODE_RETURN, Command::ExitMode),
+        (&[], KEYCODE_ESCAPE, Command::ExitMode),
+    ];
+    for (modifiers, keycode, command) in resize {
+        list.push(ModeBinding {
+            mode: "resize",
+            oneshot: false,
+            modifiers,
+            keycode,
+            command,
+        });
+    }
+
+    // launch mode: a single key launches a program
+    #[rustfmt::skip]
+    let launch: [(u8, Command); 2] = [
+        (KEYCODE_T, Command::Exec(vec!["/usr/bin/xterm".into()])),
+        (KEYCODE_P, Command::Exec(vec!["/usr/bin/dmenu_run".into()])),
+    ];
+    for (keycode, command) in launch {
+        list.push(ModeBinding {
+            mode: "launch",
+            oneshot: true,
+            modifiers: &[],
+            keycode,
+            command,
+        });
+    }
+
+    list
+}
@@ -1,67 +1,632 @@
 use std::collections::{HashMap, VecDeque};
 
-use x11rb::connection::Connection as _;
-use x11rb::protocol::{randr, xproto, Event};
+use std::os::unix::io::AsRawFd as _;
+use std::panic::AssertUnwindSafe;
+
+use x11rb::connection::{Connection as _, RequestConnection as _};
+use x11rb::errors::ConnectionError;
+use x11rb::protocol::{randr, render, res, screensaver, shape, xinput, xproto, Event};
 
 use randr::ConnectionExt as _;
+use res::ConnectionExt as _;
+use screensaver::ConnectionExt as _;
+use shape::ConnectionExt as _;
+use xinput::ConnectionExt as _;
 use xproto::ConnectionExt as _;
 
+use crate::command;
 use crate::config;
 use crate::error::{Error, Result};
+use crate::i3ipc;
+use crate::ipc::{self, Json};
+use crate::keyboard;
+use crate::layout::{self, Arrangement, Orientation, Rect, SnapRegion, MIN_TILE_WEIGHT};
+use crate::layout_program;
+use crate::metrics::Metrics;
+use crate::output;
+use crate::placement;
+use crate::signal;
+use crate::state;
+use crate::theme::Theme;
+use crate::thumbnail;
 use crate::utils;
+use crate::wallpaper;
+use crate::xsmp;
 
 #[derive(Debug, Clone)]
 pub enum Command {
     Exit,
     Restart,
+    /// reads the theme file again, and redraws everything with the colors
+    ReloadTheme,
     SpawnProcess(String),
+    /// runs the program (the first element) with the arguments directly, without a shell
+    Exec(Vec<String>),
     FocusNextMonitor,
     FocusNextWindow,
+    FocusPrevWindow,
+    /// focuses the next window of the same class (WM_CLASS) as the focused one on any desktop
+    FocusNextSameClass,
+    /// focuses a window matching the condition, switching to its desktop (for run-or-raise)
+    FocusMatching(WindowMatch),
+    /// sends SIGTERM (or SIGKILL if true) to the process owning the focused window
+    KillWindowProcess(bool),
     SwitchDesktop(usize),
+    /// switches the focused monitor to the next desktop, wrapping around
+    SwitchNextDesktop,
+    /// switches the focused monitor to the previous desktop, wrapping around
+    SwitchPrevDesktop,
     MoveWindow(usize),
     ToggleFloating,
+    SetLayout(Layout),
+    NextLayout,
+    IncColumns,
+    DecColumns,
+    SplitHorizontal,
+    SplitVertical,
+    /// opens the next window on the side of the focused window in the manual layout
+    Preselect(Direction),
+    /// makes the tiles on the focused desktop equal in size
+    BalanceLayout,
+    /// swaps the axes of the tiled layout, e.g. the master moves from the left to the top
+    RotateLayout,
+    /// flips the tiled layout, e.g. the master moves from the left to the right
+    MirrorLayout,
+    TogglePip,
+    /// shows the dropdown window at the top of the focused monitor (spawning it on first use),
+    /// or hides it
+    ToggleDropdown,
+    /// hides or shows the border of the focused window
+    ToggleBorder,
+    /// makes the focused tiled window cover the whole area, or restores the layout
+    ToggleZoom,
+    /// hides the focused window into the hidden stack of its desktop
+    HideWindow,
+    /// shows the window hidden last on the focused desktop
+    UnhideLast,
+    /// shows the hidden window on the focused desktop
+    Unhide(xproto::Window),
+    GroupWithFocused,
+    CycleGroup,
+    Ungroup,
+    /// resizes the focused floating window, or changes the weight of the focused tile
+    ResizeWindow(i32, i32),
+    /// moves the focused floating window by the offset
+    MoveFloating(i32, i32),
+    /// floats the focused window and snaps it into a half, a quarter or the whole of its monitor,
+    /// as dragging it to the edges does
+    SnapLeft,
+    SnapRight,
+    SnapTop,
+    SnapBottom,
+    SnapTopLeft,
+    SnapTopRight,
+    SnapBottomLeft,
+    SnapBottomRight,
+    SnapMaximize,
+    /// stacks the focused floating window above the other floating windows
+    RaiseWindow,
+    /// stacks the focused floating window below the other floating windows
+    LowerWindow,
+    EnterMode(String),
+    ExitMode,
+    /// reads a desktop number typed with digit keys and switches to the desktop
+    GotoDesktopPrompt,
+    /// switches to the next keyboard layout (XKB group)
+    NextKeyboardLayout,
+    /// lets idle actions run even while a fullscreen window is visible, or stops it
+    ToggleIdleInhibit,
+    /// arranges the windows of the focused desktop (or of all desktops if true) in a grid to
+    /// pick one of them with the keyboard or the mouse, and restores the layout afterwards
+    Overview(bool),
+    /// shows or hides the pager of all desktops at the top of the focused monitor
+    TogglePager,
+}
+
+impl Command {
+    /// whether holding the key repeats the command
+    fn is_repeatable(&self) -> bool {
+        matches!(self, Command::ResizeWindow(..) | Command::MoveFloating(..))
+    }
+
+    fn snap_region(&self) -> Option<SnapRegion> {
+        match self {
+            Command::SnapLeft => Some(SnapRegion::Left),
+            Command::SnapRight => Some(SnapRegion::Right),
+            Command::SnapTop => Some(SnapRegion::Top),
+            Command::SnapBottom => Some(SnapRegion::Bottom),
+            Command::SnapTopLeft => Some(SnapRegion::TopLeft),
+            Command::SnapTopRight => Some(SnapRegion::TopRight),
+            Command::SnapBottomLeft => Some(SnapRegion::BottomLeft),
+            Command::SnapBottomRight => Some(SnapRegion::BottomRight),
+            Command::SnapMaximize => Some(SnapRegion::Full),
+            _ => None,
+        }
+    }
+}
+
+/// a condition matching windows, used to find a window to focus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowMatch {
+    /// the class of WM_CLASS, ignoring case
+    Class(String),
+    /// a part of the title, ignoring case
+    Title(String),
+}
+
+/// what dragging a window with a mouse button does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DragAction {
+    Move,
+    Resize,
+}
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// how the preview of snapping and of the next split is drawn
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewStyle {
+    /// a translucent fill with the border
+    Filled,
+    /// only the border, leaving the area inside visible
+    Outline,
+}
+
+/// whether a newly mapped window is focused
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusPolicy {
+    /// never focused when mapped
+    Never,
+    /// focused, switching to its desktop if the desktop is not visible
+    Always,
+    /// focused only if mapped on a visible desktop
+    Visible,
+}
+
+/// where a new window is inserted in the tiling order
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPosition {
+    /// before the other windows, becoming the master
+    Master,
+    /// right after the focused window
+    AfterFocused,
+    /// after the other windows
+    End,
+}
+
+/// the arrangement of tiled windows on a desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// windows are placed side by side
+    Horizontal,
+    /// windows share the whole area and a tab bar shows their titles
+    Tabbed,
+    /// windows share the whole area and a vertical list of title bars shows their titles
+    Stacking,
+    /// each window takes half of the remaining area, spiraling clockwise
+    Spiral,
+    /// windows fill the columns from top to bottom
+    Columns,
+    /// windows are placed in the container tree built with the split commands
+    Manual,
+    /// windows are placed by config::LAYOUT_PROGRAM
+    External,
+}
+
+impl Layout {
+    const ALL: [Layout; 7] = [
+        Layout::Horizontal,
+        Layout::Tabbed,
+        Layout::Stacking,
+        Layout::Spiral,
+        Layout::Columns,
+        Layout::Manual,
+        Layout::External,
+    ];
+
+    /// whether this layout shows the titles of windows on the tab bar
+    fn has_tab_bar(self) -> bool {
+        matches!(self, Layout::Tabbed | Layout::Stacking)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Horizontal => "horizontal",
+            Layout::Tabbed => "tabbed",
+            Layout::Stacking => "stacking",
+            Layout::Spiral => "spiral",
+            Layout::Columns => "columns",
+            Layout::Manual => "manual",
+            Layout::External => "external",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Self::ALL.into_iter().find(|l| l.name() == name)
+    }
+
+    /// The external layout is skipped unless the layout program is configured.
+    fn next(self) -> Layout {
+        let i = Self::ALL.iter().position(|l| *l == self).unwrap();
+        let next = Self::ALL[(i + 1) % Self::ALL.len()];
+        if next == Layout::External && config::LAYOUT_PROGRAM.is_none() {
+            next.next()
+        } else {
+            next
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// children are placed side by side
+    Horizontal,
+    /// children are placed one above the other
+    Vertical,
+}
+
+/// a side of a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Left,
+        Direction::Right,
+        Direction::Up,
+        Direction::Down,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Direction> {
+        Self::ALL.into_iter().find(|d| d.name() == name)
+    }
+
+    /// the direction after swapping the axes
+    fn transposed(self) -> Direction {
+        match self {
+            Direction::Left => Direction::Up,
+            Direction::Right => Direction::Down,
+            Direction::Up => Direction::Left,
+            Direction::Down => Direction::Right,
+        }
+    }
+
+    /// the direction after flipping the x-axis
+    fn mirrored(self) -> Direction {
+        match self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            _ => self,
+        }
+    }
+
+    /// the split placing windows on this side of each other
+    fn split(self) -> SplitDirection {
+        match self {
+            Direction::Left | Direction::Right => SplitDirection::Horizontal,
+            Direction::Up | Direction::Down => SplitDirection::Vertical,
+        }
+    }
+
+    /// Returns the half of the rectangle on this side.
+    fn half_of(self, rect: Rect) -> Rect {
+        match self {
+            Direction::Left => Rect {
+                w: rect.w / 2,
+                ..rect
+            },
+            Direction::Right => Rect {
+                x: rect.x + rect.w / 2,
+                w: rect.w - rect.w / 2,
+                ..rect
+            },
+            Direction::Up => Rect {
+                h: rect.h / 2,
+                ..rect
+            },
+            Direction::Down => Rect {
+                y: rect.y + rect.h / 2,
+                h: rect.h - rect.h / 2,
+                ..rect
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-struct Rect {
-    x: i32,
-    y: i32,
-    w: i32,
-    h: i32,
+/// a node of the container tree used in the manual layout
+#[derive(Debug, Clone, PartialEq)]
+pub enum Container {
+    Window(xproto::Window),
+    Split {
+        direction: SplitDirection,
+        children: Vec<Container>,
+    },
 }
 
-impl Rect {
-    fn top(&self) -> i32 {
-        self.y
+impl Container {
+    fn contains(&self, window: xproto::Window) -> bool {
+        match self {
+            Container::Window(id) => *id == window,
+            Container::Split { children, .. } => children.iter().any(|c| c.contains(window)),
+        }
+    }
+
+    /// Removes windows not satisfying the predicate.
+    /// Containers left with a single child are replaced with the child.
+    fn retain(self, pred: &impl Fn(xproto::Window) -> bool) -> Option<Container> {
+        match self {
+            Container::Window(id) => pred(id).then_some(self),
+            Container::Split {
+                direction,
+                children,
+            } => {
+                let mut children: Vec<Container> = children
+                    .into_iter()
+                    .filter_map(|c| c.retain(pred))
+                    .collect();
+                match children.len() {
+                    0 => None,
+                    1 => children.pop(),
+                    _ => Some(Container::Split {
+                        direction,
+                        children,
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Inserts `window` next to `anchor`.
+    /// If `split` is given, `anchor` is replaced with a new container holding both windows,
+    /// where `window` is placed on the side.
+    fn insert(
+        &mut self,
+        anchor: xproto::Window,
+        window: xproto::Window,
+        split: Option<Direction>,
+    ) -> bool {
+        match self {
+            Container::Window(id) => {
+                if *id != anchor {
+                    return false;
+                }
+                let side = split.unwrap_or(Direction::Right);
+                let mut children = vec![Container::Window(anchor), Container::Window(window)];
+                if matches!(side, Direction::Left | Direction::Up) {
+                    children.reverse();
+                }
+                *self = Container::Split {
+                    direction: side.split(),
+                    children,
+                };
+                true
+            }
+            Container::Split { children, .. } => {
+                if split.is_none() {
+                    let pos = children
+                        .iter()
+                        .position(|c| matches!(c, Container::Window(id) if *id == anchor));
+                    if let Some(i) = pos {
+                        children.insert(i + 1, Container::Window(window));
+                        return true;
+                    }
+                }
+                children.iter_mut().any(|c| c.insert(anchor, window, split))
+            }
+        }
+    }
+
+    /// Merges splits into their parents of the same direction,
+    /// so that all windows placed side by side get equal sizes.
+    fn balance(self) -> Container {
+        match self {
+            Container::Window(_) => self,
+            Container::Split {
+                direction,
+                children,
+            } => {
+                let mut merged = Vec::with_capacity(children.len());
+                for child in children {
+                    match child.balance() {
+                        Container::Split {
+                            direction: d,
+                            children: grandchildren,
+                        } if d == direction => merged.extend(grandchildren),
+                        child => merged.push(child),
+                    }
+                }
+                Container::Split {
+                    direction,
+                    children: merged,
+                }
+            }
+        }
     }
-    fn bottom(&self) -> i32 {
-        self.y + self.h
+
+    /// Replaces `old` with `new`.
+    fn replace(&mut self, old: xproto::Window, new: xproto::Window) -> bool {
+        match self {
+            Container::Window(id) if *id == old => {
+                *id = new;
+                true
+            }
+            Container::Window(_) => false,
+            Container::Split { children, .. } => children.iter_mut().any(|c| c.replace(old, new)),
+        }
     }
-    fn left(&self) -> i32 {
-        self.x
+
+    /// Appends `window` to the top-level container.
+    fn push(&mut self, window: xproto::Window) {
+        match self {
+            Container::Window(_) => {
+                let first = std::mem::replace(self, Container::Window(window));
+                *self = Container::Split {
+                    direction: SplitDirection::Horizontal,
+                    children: vec![first, Container::Window(window)],
+                };
+            }
+            Container::Split { children, .. } => {
+                children.push(Container::Window(window));
+            }
+        }
     }
-    fn right(&self) -> i32 {
-        self.x + self.w
+
+    /// the direction of the split directly holding `window`
+    fn parent_direction(&self, window: xproto::Window) -> Option<SplitDirection> {
+        match self {
+            Container::Window(_) => None,
+            Container::Split {
+                direction,
+                children,
+            } => {
+                if children
+                    .iter()
+                    .any(|c| matches!(c, Container::Window(id) if *id == window))
+                {
+                    Some(*direction)
+                } else {
+                    children.iter().find_map(|c| c.parent_direction(window))
+                }
+            }
+        }
     }
-    fn contains(&self, x: i32, y: i32) -> bool {
-        self.left() <= x && x < self.right() && self.top() <= y && y < self.bottom()
+
+    /// Windows share the area of their split by their weights, and splits have the weight 1.
+    fn geometries(
+        &self,
+        area: Rect,
+        weight: &impl Fn(xproto::Window) -> f64,
+        out: &mut Vec<(xproto::Window, Rect)>,
+    ) {
+        match self {
+            Container::Window(id) => out.push((*id, area)),
+            Container::Split {
+                direction,
+                children,
+            } => {
+                let weights: Vec<f64> = children
+                    .iter()
+                    .map(|c| match c {
+                        Container::Window(id) => weight(*id),
+                        Container::Split { .. } => 1.0,
+                    })
+                    .collect();
+                let regions = match direction {
+                    SplitDirection::Horizontal => layout::weighted_horizontal(area, &weights),
+                    SplitDirection::Vertical => layout::weighted_vertical(area, &weights),
+                };
+                for (child, region) in children.iter().zip(regions) {
+                    child.geometries(region, weight, out);
+                }
+            }
+        }
     }
 }
 
+/// a monitor reported by the X server
+#[derive(Debug, Clone)]
+struct MonitorInfo {
+    /// the name atom of the RandR monitor, or the CRTC on servers older than RandR 1.5
+    id: u32,
+    crtc: randr::Crtc,
+    output: Option<output::OutputDescription>,
+    geometry: Rect,
+    scale: f64,
+}
+
 #[derive(Debug, Clone)]
 struct Monitor {
+    /// identifies the monitor across configuration changes (see `MonitorInfo::id`)
+    id: u32,
+    /// the CRTC of the (first) output of this monitor
     crtc: randr::Crtc,
+    /// the EDID or the output name of the monitor (see `OutputDescription::identity`)
+    identity: Option<String>,
+    /// the name of the output (e.g. "HDMI-1")
+    output: Option<String>,
     /// a region occupied by this monitor (absolute coordinates)
     geometry: Rect,
+    /// the region not reserved by docks, where windows are tiled (relative to `geometry`)
+    workarea: Rect,
     /// ID of the desktop displayed on this monitor
     desktop: usize,
     /// a dummy window used to control input focus
     dummy_window: xproto::Window,
+    /// a window on which the tab bar is drawn in the tabbed and stacking layouts
+    tab_bar: xproto::Window,
+    /// the factor by which sizes in the config are scaled on this monitor
+    scale: f64,
+    /// the windows in the stacking order last applied by `update_layout`, bottom first
+    stacking: Vec<xproto::Window>,
+}
+
+impl Monitor {
+    fn scaled(&self, value: u32) -> i32 {
+        let scaled = (value as f64 * self.scale).round() as i32;
+        if value > 0 {
+            scaled.max(1)
+        } else {
+            0
+        }
+    }
+    fn border_width(&self) -> i32 {
+        self.scaled(config::WINDOW_BORDER_WIDTH)
+    }
+    fn snapping_width(&self) -> i32 {
+        self.scaled(config::SNAPPING_WIDTH)
+    }
+    fn preview_border_width(&self) -> i32 {
+        self.scaled(config::PREVIEW_BORDER_WIDTH)
+    }
+    fn overview_gap(&self) -> i32 {
+        self.scaled(config::OVERVIEW_GAP)
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Desktop {
     monitor: Option<usize>,
+    layout: Layout,
+    /// the number of columns in the columns layout
+    columns: usize,
+    /// the container tree used in the manual layout
+    tree: Option<Container>,
+    /// the side of the focused window to which it is split when the next window is opened
+    next_split: Option<Direction>,
+    /// whether the axes of the tiled layout are swapped (RotateLayout)
+    transposed: bool,
+    /// whether the tiled layout is flipped along its main axis (MirrorLayout)
+    mirrored: bool,
+    /// the tiled window temporarily covering the whole area (ToggleZoom)
+    zoomed: Option<xproto::Window>,
+    /// windows hidden by HideWindow, the most recently hidden one is at the end
+    hidden_stack: Vec<xproto::Window>,
+    /// windows focused on this desktop, the most recently focused one is at the end
+    focus_history: Vec<xproto::Window>,
 }
 
 #[derive(Debug, Clone)]
@@ -83,6 +648,217 @@ struct Window {
     // so when we (actively) unmap a window turn on this flag and test it on UnmapNotifyEvents.
     // Is there any better way to deal with this issue?
     ignore_unmap_notify: bool,
+
+    /// a terminal hidden while this window is open
+    swallowed: Option<xproto::Window>,
+
+    /// the parent window specified by WM_TRANSIENT_FOR
+    transient_for: Option<xproto::Window>,
+
+    /// whether this window stays on its monitor when the desktop is switched
+    sticky: bool,
+    /// whether this window is kept above other floating windows (_NET_WM_STATE_ABOVE)
+    above: bool,
+    /// whether this window demands attention (_NET_WM_STATE_DEMANDS_ATTENTION)
+    urgent: bool,
+    /// whether this window asks not to be shown on taskbars (_NET_WM_STATE_SKIP_TASKBAR)
+    skip_taskbar: bool,
+    /// whether this window asks not to be shown on pagers (_NET_WM_STATE_SKIP_PAGER)
+    skip_pager: bool,
+    /// whether this window is hidden by the WM, e.g. swallowed (_NET_WM_STATE_HIDDEN)
+    hidden: bool,
+    /// the state to be restored when the picture-in-picture mode is turned off
+    pip: Option<PipState>,
+    /// the class of WM_CLASS
+    class: Option<String>,
+    /// _NET_WM_NAME or WM_NAME, updated when it is changed
+    title: String,
+    /// the process owning this window (_NET_WM_PID), unless the client runs on another host
+    pid: Option<u32>,
+    /// the input field of WM_HINTS, whether the WM should set the input focus to this window
+    input: bool,
+    /// whether this window takes the focus by itself when WM_TAKE_FOCUS is sent (WM_PROTOCOLS)
+    take_focus: bool,
+    /// whether the client closes this window when WM_DELETE_WINDOW is sent (WM_PROTOCOLS)
+    delete_window: bool,
+    /// whether this window has a non-rectangular shape (Shape extension), drawn without the border
+    shaped: bool,
+    /// whether the border is hidden by ToggleBorder
+    borderless: bool,
+    /// the share of the tile among its siblings (1 by default), changed by ResizeWindow
+    weight: f64,
+    /// the geometry restored by the maximize button on the title bar
+    restore_geometry: Option<Rect>,
+}
+
+impl Window {
+    /// Scales the weight as the tile is resized by the delta along the axis, and returns
+    /// whether it's changed.
+    fn resize_tile(&mut self, axis: SplitDirection, dw: i32, dh: i32) -> bool {
+        let (delta, size) = match axis {
+            SplitDirection::Horizontal => (dw, self.geometry.w),
+            SplitDirection::Vertical => (dh, self.geometry.h),
+        };
+        if delta == 0 || size <= 0 {
+            return false;
+        }
+        let scale = (size + delta).max(1) as f64 / size as f64;
+        self.weight = (self.weight * scale).max(MIN_TILE_WEIGHT);
+        true
+    }
+
+    fn border_width(&self, monitor: &Monitor) -> i32 {
+        if self.shaped || self.borderless {
+            0
+        } else {
+            monitor.border_width()
+        }
+    }
+
+    /// Windows neither accepting the input focus nor taking it by themselves are never focused.
+    fn focusable(&self) -> bool {
+        self.input || self.take_focus
+    }
+}
+
+/// space reserved by a dock at the edges of the screen (_NET_WM_STRUT_PARTIAL)
+#[derive(Debug, Clone, Copy, Default)]
+struct Strut {
+    left: i32,
+    right: i32,
+    top: i32,
+    bottom: i32,
+    /// the ranges (start, end) along the left, right, top and bottom edges covered by the strut
+    ranges: [(i32, i32); 4],
+}
+
+impl Strut {
+    fn from_cardinals(values: &[u32]) -> Strut {
+        let range = |i: usize| match values.get(4 + i * 2..6 + i * 2) {
+            Some(&[start, end]) => (start as i32, end as i32),
+            // _NET_WM_STRUT covers the whole edges
+            _ => (0, i32::MAX),
+        };
+        Strut {
+            left: values[0] as i32,
+            right: values[1] as i32,
+            top: values[2] as i32,
+            bottom: values[3] as i32,
+            ranges: [range(0), range(1), range(2), range(3)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PipState {
+    floating: bool,
+    above: bool,
+    geometry: Rect,
+}
+
+/// the order in which `FocusNextWindow`/`FocusPrevWindow` cycle windows
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusOrder {
+    /// the same order as the tiling layout
+    Tiling,
+    /// most recently focused windows first (like Alt-Tab)
+    MostRecentlyUsed,
+}
+
+/// the popup menu opened by right-clicking the desktop background
+#[derive(Debug, Clone)]
+struct RootMenu {
+    /// labels and the commands run by clicking them
+    items: Vec<(String, Command)>,
+    /// the item under the pointer
+    selected: Option<usize>,
+    /// the width of the menu
+    width: i32,
+}
+
+impl RootMenu {
+    fn item_height() -> i32 {
+        config::TAB_BAR_HEIGHT as i32
+    }
+
+    /// Returns the item at the position relative to the menu.
+    fn item_at(&self, x: i32, y: i32) -> Option<usize> {
+        let n = self.items.len() as i32;
+        if (0..self.width).contains(&x) && (0..Self::item_height() * n).contains(&y) {
+            Some((y / Self::item_height()) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// the windows arranged in a grid by Command::Overview
+#[derive(Debug, Clone)]
+struct Overview {
+    /// the monitor on which the grid is shown
+    monitor: usize,
+    /// the windows in the grid, row by row
+    windows: Vec<xproto::Window>,
+    /// the index of the highlighted window
+    selected: usize,
+    /// windows of hidden desktops mapped only for the overview
+    borrowed: Vec<xproto::Window>,
+}
+
+/// the pager overlay opened by Command::TogglePager
+#[derive(Debug, Clone)]
+struct Pager {
+    /// the monitor at the top of which the pager is shown
+    monitor: usize,
+    /// the area of each desktop in the pager window
+    cells: Vec<Rect>,
+    /// what was drawn last
+    view: PagerView,
+    /// the desktop and the window (if any) under the pointer when the button was pressed
+    pressed: Option<(usize, Option<xproto::Window>)>,
+    /// the absolute position of the pager window
+    origin: (i32, i32),
+    /// the desktop under a window dragged onto the pager, which the window is moved to on drop
+    drop_target: Option<usize>,
+}
+
+impl Pager {
+    /// Returns the desktop at the position relative to the pager.
+    fn desktop_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.cells.iter().position(|cell| cell.contains(x, y))
+    }
+
+    /// Returns the topmost window of the desktop at the position relative to the pager.
+    fn window_at(&self, desktop: usize, x: i32, y: i32) -> Option<xproto::Window> {
+        let windows = self.view.windows.get(desktop)?;
+        windows
+            .iter()
+            .rev()
+            .find(|(_, mini)| mini.contains(x, y))
+            .map(|(id, _)| *id)
+    }
+}
+
+/// the miniatures drawn in the pager
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PagerView {
+    /// the windows of each desktop from the bottom, and their geometries in the pager
+    windows: Vec<Vec<(xproto::Window, Rect)>>,
+    /// the desktop of the focused monitor
+    focused_desktop: Option<usize>,
+    focus: xproto::Window,
+}
+
+/// an ongoing MRU window cycle, which is finished when the hot key is released
+#[derive(Debug, Clone)]
+struct MruCycle {
+    windows: Vec<xproto::Window>,
+    index: usize,
+    /// keycodes bound to the hot key modifier
+    release_keys: Vec<u8>,
+    /// the thumbnail area of each window in the switcher window (empty if it isn't shown)
+    cells: Vec<Rect>,
 }
 
 #[allow(unused)]
@@ -112,18 +888,229 @@ impl Modifier {
     }
 }
 
+/// a core font and a graphics context used to draw tab bars
+#[derive(Debug, Clone, Copy, Default)]
+struct TabFont {
+    gc: xproto::Gcontext,
+    ascent: i32,
+    descent: i32,
+    char_width: i32,
+}
+
+/// the buttons on the title bar of a floating window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TitleButton {
+    Close,
+    Maximize,
+    /// tiles the window
+    Float,
+}
+
+impl TitleButton {
+    /// the buttons from the right end of the bar
+    const ALL: [TitleButton; 3] = [
+        TitleButton::Close,
+        TitleButton::Maximize,
+        TitleButton::Float,
+    ];
+
+    /// Returns the button at `x` on a title bar of the width. Each button is a square.
+    fn at(bar_w: i32, x: i32) -> Option<TitleButton> {
+        let size = config::TAB_BAR_HEIGHT as i32;
+        if x < 0 || x >= bar_w {
+            return None;
+        }
+        Self::ALL.get(((bar_w - 1 - x) / size) as usize).copied()
+    }
+
+    /// Returns the area of the button on a title bar of the width.
+    fn rect(self, bar_w: i32) -> Rect {
+        let size = config::TAB_BAR_HEIGHT as i32;
+        let i = Self::ALL.iter().position(|b| *b == self).unwrap() as i32;
+        Rect {
+            x: bar_w - size * (i + 1),
+            y: 0,
+            w: size,
+            h: size,
+        }
+    }
+}
+
+/// a node of the keybinding trie
+#[derive(Debug)]
+enum KeyBinding {
+    Command(Command),
+    /// the first keys of chords, waiting for the next key
+    Prefix(HashMap<(u16, u8), KeyBinding>),
+}
+
+/// a partially typed key chord
+struct Chord {
+    keys: Vec<(u16, u8)>,
+}
+
+/// things to be done at a time, waking up the event loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Timer {
+    /// aborts the key chord not completed within CHORD_TIMEOUT_MS
+    ChordTimeout,
+    /// checks the idle time for IDLE_ACTIONS
+    IdleCheck,
+    /// redraws the thumbnails in the switcher
+    SwitcherRefresh,
+    /// writes the placements changed since the last write
+    SavePlacements,
+}
+
+/// a named set of keybindings which replaces the normal ones while the mode is active
+struct KeyMode {
+    keybind: HashMap<(u16, u8), Command>,
+    /// whether the mode is left after a command is executed
+    oneshot: bool,
+}
+
 pub struct Daily {
     ctx: utils::Context,
-    keybind: HashMap<(u16, u8), Command>,
+    keybind: HashMap<(u16, u8), KeyBinding>,
+    /// keybindings by keysyms rather than keycodes
+    keysym_bindings: Vec<(u16, xproto::Keysym, Command)>,
+    /// the keys grabbed for the keysym bindings with the current keyboard mapping
+    keysym_grabs: Vec<(u16, u8)>,
+    /// commands run by touchpad swipes, by the number of fingers and the direction
+    swipe_bindings: HashMap<(u32, Direction), Command>,
+    /// the number of fingers and the distance (x, y) traveled by the swipe in progress
+    swipe: Option<(u32, f64, f64)>,
+    chord: Option<Chord>,
+    /// the key held for a repeatable command and when the command was last executed
+    key_repeat: Option<((u16, u8), xproto::Timestamp)>,
+    /// keycodes of the modifier keys, which don't abort the key chord
+    modifier_keycodes: Vec<u8>,
+    keymap: keyboard::Keymap,
+    /// digits typed so far in the desktop prompt
+    desktop_prompt: Option<String>,
+    modes: HashMap<String, KeyMode>,
+    active_mode: Option<String>,
     windows: HashMap<xproto::Window, Window>,
+    /// managed windows in the order they were added, which determines the tiling order
+    window_order: Vec<xproto::Window>,
     monitors: Vec<Monitor>,
+    /// the desktops last displayed on disconnected monitors, by their identities
+    last_desktops: HashMap<String, usize>,
+    /// whether the server supports the monitors of RandR 1.5
+    randr_monitors: bool,
     desktops: Vec<Desktop>,
     focus: xproto::Window,
     dnd_position: Option<(i32, i32)>,
+    drag_action: Option<DragAction>,
+    /// the horizontal and vertical edges moved by the resize drag (-1: left/top, 1: right/bottom)
+    resize_edges: Option<(i32, i32)>,
+    /// the tile under the dragged tile, which are swapped on release
+    swap_target: Option<xproto::Window>,
     button_count: usize,
+    /// whether the pointer has moved during the drag since its position was last followed
+    drag_moved: bool,
     preview_window: xproto::Window,
     preview_geometry: Rect,
+    /// the opacity of the preview, or None if it's drawn opaque in the root visual
+    preview_alpha: Option<u32>,
+    /// the colors of everything the WM draws
+    theme: Theme,
     stacking_counter: u64,
+    mru_cycle: Option<MruCycle>,
+    overview: Option<Overview>,
+    /// the title bars of floating windows (config::TITLE_BARS) by the windows
+    title_bars: HashMap<xproto::Window, xproto::Window>,
+    /// the title bar and its button under the pointer
+    title_bar_hover: Option<(xproto::Window, TitleButton)>,
+    /// the button pressed on the title bar, which is clicked when released on it
+    title_bar_pressed: Option<(xproto::Window, TitleButton)>,
+    /// the window of config::DROPDOWN_CLASS toggled by Command::ToggleDropdown
+    dropdown: Option<xproto::Window>,
+    /// when config::DROPDOWN_COMMAND was run, until its window is mapped
+    dropdown_spawned: Option<std::time::Instant>,
+    /// the window of the root menu, created at startup and mapped while it is open
+    menu_window: xproto::Window,
+    /// draws thumbnails of windows in the switcher, if config::SWITCHER_THUMBNAILS is enabled
+    /// and the server has Composite
+    thumbnailer: Option<thumbnail::Thumbnailer>,
+    /// the window listing the windows of an MRU cycle, created at startup
+    switcher_window: xproto::Window,
+    /// the picture of the switcher window into which the thumbnails are drawn
+    switcher_picture: Option<render::Picture>,
+    /// the window of the pager, created at startup and mapped while it is open
+    pager_window: xproto::Window,
+    pager: Option<Pager>,
+    menu: Option<RootMenu>,
+    /// the actions listed in the root menu below the desktops
+    menu_actions: Vec<(&'static str, Command)>,
+    /// the images loaded from config::WALLPAPER and config::DESKTOP_WALLPAPERS by their paths
+    wallpapers: HashMap<&'static str, wallpaper::Image>,
+    /// the wallpapers drawn in the sizes of monitors, by their paths and the sizes
+    wallpaper_pixmaps: HashMap<(&'static str, u16, u16), xproto::Pixmap>,
+    /// the background of the root window drawn with the wallpapers
+    root_pixmap: Option<xproto::Pixmap>,
+    /// the monitors and the wallpapers which the root pixmap was drawn with
+    wallpaper_monitors: Vec<(Rect, Option<&'static str>)>,
+    tab_font: TabFont,
+    /// groups of windows sharing one tile, only one member of each group is visible at a time
+    window_groups: Vec<Vec<xproto::Window>>,
+    /// where windows were placed in the previous sessions
+    placements: placement::Placements,
+    /// unmanaged windows like bars, reserving space at the edges of the screen
+    docks: HashMap<xproto::Window, Strut>,
+    /// unmanaged notification windows, the oldest one is nearest to the corner
+    notifications: Vec<xproto::Window>,
+    ipc: Option<ipc::Server>,
+    i3: Option<i3ipc::Server>,
+    /// the connection to the session manager (XSMP)
+    session: Option<xsmp::Client>,
+    /// whether the server supports the Shape extension
+    shape: bool,
+    /// whether the server supports the client IDs of X-Resource 1.2
+    res: bool,
+    /// whether the server supports the MIT-SCREEN-SAVER extension, which tells the idle time
+    screensaver: bool,
+    /// whether the server supports XInput 2.1, whose raw motion events are followed by drags
+    xinput: bool,
+    /// whether the server supports the gesture events of XInput 2.4
+    gestures: bool,
+    /// the number of IDLE_ACTIONS already run since the last input
+    idle_actions_run: usize,
+    /// the idle time when the idle actions were last inhibited, from which the countdown restarts
+    idle_offset: u64,
+    /// whether the idle actions and the screen saver are suspended by a fullscreen window
+    idle_inhibited: bool,
+    /// whether the user let the idle actions run regardless of fullscreen windows
+    idle_inhibit_overridden: bool,
+    /// the geometries (absolute) and border widths last applied by `update_layout`,
+    /// so that unchanged windows aren't configured again
+    configured: HashMap<xproto::Window, (Rect, u32)>,
+    metrics: Metrics,
+    /// the pending timers and when they expire
+    timers: Vec<(std::time::Instant, Timer)>,
+    /// SIGCHLD, SIGTERM and SIGHUP, read in the event loop
+    signals: Option<signal::SignalFd>,
+    /// the host name compared with WM_CLIENT_MACHINE of clients
+    hostname: Option<String>,
+    /// whether the running WM is replaced at startup (--replace)
+    replace: bool,
+    /// WM_Sn of the screen
+    wm_selection: xproto::Atom,
+    /// what the i3 IPC clients have been told last
+    i3_snapshot: I3Snapshot,
+    /// the last geometries given by LAYOUT_PROGRAM
+    layout_program_cache: layout_program::Cache,
+}
+
+/// the state of which i3 IPC clients are notified by events when it changes
+#[derive(Debug, Clone, Default, PartialEq)]
+struct I3Snapshot {
+    workspaces: String,
+    focused_desktop: Option<usize>,
+    monitors: Vec<(Option<String>, Rect)>,
+    mode: Option<String>,
+    focus: xproto::Window,
+    focus_title: String,
 }
 
 impl Daily {
@@ -131,25 +1118,192 @@ impl Daily {
         Ok(Self {
             ctx: utils::Context::new()?,
             keybind: HashMap::new(),
+            keysym_bindings: Vec::new(),
+            swipe_bindings: HashMap::new(),
+            swipe: None,
+            keysym_grabs: Vec::new(),
+            chord: None,
+            key_repeat: None,
+            modifier_keycodes: Vec::new(),
+            keymap: keyboard::Keymap::default(),
+            desktop_prompt: None,
+            modes: HashMap::new(),
+            active_mode: None,
             windows: HashMap::new(),
+            window_order: Vec::new(),
             monitors: Vec::new(),
+            last_desktops: HashMap::new(),
+            randr_monitors: false,
             desktops: Vec::new(),
             focus: x11rb::NONE,
             dnd_position: None,
+            drag_action: None,
+            drag_moved: false,
+            resize_edges: None,
+            swap_target: None,
             button_count: 0,
             preview_window: x11rb::NONE,
+            preview_alpha: None,
+            theme: Theme::load(),
             preview_geometry: Rect::default(),
             stacking_counter: 0,
+            mru_cycle: None,
+            overview: None,
+            title_bars: HashMap::new(),
+            title_bar_hover: None,
+            title_bar_pressed: None,
+            dropdown: None,
+            dropdown_spawned: None,
+            menu_window: x11rb::NONE,
+            thumbnailer: None,
+            switcher_window: x11rb::NONE,
+            switcher_picture: None,
+            pager_window: x11rb::NONE,
+            pager: None,
+            menu: None,
+            menu_actions: config::root_menu_actions(),
+            wallpapers: HashMap::new(),
+            wallpaper_pixmaps: HashMap::new(),
+            root_pixmap: None,
+            wallpaper_monitors: Vec::new(),
+            tab_font: TabFont::default(),
+            window_groups: Vec::new(),
+            placements: placement::Placements::load(),
+            docks: HashMap::new(),
+            notifications: Vec::new(),
+            ipc: None,
+            i3: None,
+            session: None,
+            shape: false,
+            res: false,
+            screensaver: false,
+            xinput: false,
+            gestures: false,
+            idle_actions_run: 0,
+            idle_offset: 0,
+            idle_inhibited: false,
+            idle_inhibit_overridden: false,
+            configured: HashMap::new(),
+            metrics: Metrics::new(),
+            timers: Vec::new(),
+            signals: None,
+            hostname: utils::hostname(),
+            replace: false,
+            wm_selection: x11rb::NONE,
+            i3_snapshot: I3Snapshot::default(),
+            layout_program_cache: layout_program::Cache::default(),
         })
     }
 
+    /// Makes `start` replace the running WM instead of failing.
+    pub fn set_replace(&mut self, replace: bool) {
+        self.replace = replace;
+    }
+
     pub fn bind_key(&mut self, modifiers: &[Modifier], keycode: u8, cmd: Command) -> Result<()> {
-        let mut modmask = xproto::ModMask::default();
-        for m in modifiers {
-            modmask = modmask | m.modmask();
+        self.bind_chord(&[(modifiers, keycode)], cmd)
+    }
+
+    /// Binds a sequence of keys to the command. Only the first key is grabbed,
+    /// the following ones are read by grabbing the whole keyboard.
+    pub fn bind_chord(&mut self, keys: &[(&[Modifier], u8)], cmd: Command) -> Result<()> {
+        let (modifiers, keycode) = match keys.first() {
+            Some(&first) => first,
+            None => return Ok(()),
+        };
+        let modmask = combined_modmask(modifiers);
+
+        // the first key of chords sharing a prefix is grabbed only once
+        if !self.keybind.contains_key(&(modmask.into(), keycode)) {
+            self.grab_key(modmask, keycode)?;
         }
 
-        self.ctx
+        let mut node = &mut self.keybind;
+        for (i, &(modifiers, keycode)) in keys.iter().enumerate() {
+            let key = (combined_modmask(modifiers).into(), keycode);
+            if i + 1 == keys.len() {
+                match node.get(&key) {
+                    Some(KeyBinding::Prefix(_)) => log::warn!(
+                        "keybinding {keys:?} overrides the longer chords starting with it"
+                    ),
+                    Some(KeyBinding::Command(old)) => log::warn!(
+                        "keybinding {keys:?} is bound twice: {old:?} is replaced with {cmd:?}"
+                    ),
+                    None => {}
+                }
+                node.insert(key, KeyBinding::Command(cmd.clone()));
+                break;
+            }
+            let entry = node
+                .entry(key)
+                .or_insert_with(|| KeyBinding::Prefix(HashMap::new()));
+            if let KeyBinding::Command(_) = entry {
+                log::warn!("key chord {keys:?} overrides the keybinding of its prefix");
+                *entry = KeyBinding::Prefix(HashMap::new());
+            }
+            node = match entry {
+                KeyBinding::Prefix(next) => next,
+                KeyBinding::Command(_) => unreachable!(),
+            };
+        }
+
+        log::info!("new keybinding: keys={keys:?}, cmd={cmd:?}");
+        Ok(())
+    }
+
+    /// Binds the keys generating the keysym (e.g. XF86AudioRaiseVolume) to the command.
+    /// The keycodes are looked up when the keyboard mapping is loaded, and again whenever it changes.
+    pub fn bind_keysym(&mut self, modifiers: &[Modifier], keysym: xproto::Keysym, cmd: Command) {
+        let modmask = combined_modmask(modifiers);
+        self.keysym_bindings.push((modmask.into(), keysym, cmd));
+    }
+
+    /// Binds the touchpad swipe with the number of fingers in the direction to the command.
+    pub fn bind_swipe(&mut self, fingers: u32, direction: Direction, cmd: Command) {
+        if let Some(old) = self
+            .swipe_bindings
+            .insert((fingers, direction), cmd.clone())
+        {
+            log::warn!(
+                "{fingers}-finger swipe {direction:?} is bound twice: {old:?} is replaced with {cmd:?}"
+            );
+        }
+    }
+
+    /// Grabs the button on the root window with the modifiers, together with any combination of
+    /// CapsLock and NumLock, which are ignored by the mouse bindings.
+    fn grab_button(
+        &self,
+        event_mask: xproto::EventMask,
+        button: u8,
+        modmask: xproto::ModMask,
+    ) -> Result<std::result::Result<(), x11rb::errors::ReplyError>> {
+        let locks = [
+            xproto::ModMask::default(),
+            xproto::ModMask::LOCK,
+            xproto::ModMask::M2,
+            xproto::ModMask::LOCK | xproto::ModMask::M2,
+        ];
+        let mut cookies = Vec::new();
+        for lock in locks {
+            cookies.push(self.ctx.conn.grab_button(
+                false,
+                self.ctx.root,
+                event_mask,
+                xproto::GrabMode::ASYNC, // pointer
+                xproto::GrabMode::ASYNC, // keyboard
+                x11rb::NONE,
+                x11rb::NONE,
+                button.into(),
+                modmask | lock,
+            )?);
+        }
+        Ok(cookies.into_iter().try_for_each(|cookie| cookie.check()))
+    }
+
+    fn grab_key(&self, modmask: xproto::ModMask, keycode: u8) -> Result<()> {
+        let grabbed = self
+            .ctx
             .conn
             .grab_key(
                 false,
@@ -159,32 +1313,141 @@ impl Daily {
                 xproto::GrabMode::ASYNC, // pointer
                 xproto::GrabMode::ASYNC, // keyboard
             )?
-            .check()?;
+            .check();
+        match grabbed {
+            Err(x11rb::errors::ReplyError::X11Error(err))
+                if err.error_kind == x11rb::protocol::ErrorKind::Access =>
+            {
+                Err(Error::KeyAlreadyGrabbed)
+            }
+            grabbed => Ok(grabbed?),
+        }
+    }
 
-        self.keybind.insert((modmask.into(), keycode), cmd.clone());
+    /// Adds a binding to the mode, creating the mode if it doesn't exist.
+    /// Bindings in modes are not grabbed since the whole keyboard is grabbed while a mode is active.
+    pub fn bind_mode_key(
+        &mut self,
+        mode: &str,
+        oneshot: bool,
+        modifiers: &[Modifier],
+        keycode: u8,
+        cmd: Command,
+    ) {
+        let modmask = combined_modmask(modifiers);
+        let keymode = self
+            .modes
+            .entry(mode.to_owned())
+            .or_insert_with(|| KeyMode {
+                keybind: HashMap::new(),
+                oneshot,
+            });
+        let old = keymode
+            .keybind
+            .insert((modmask.into(), keycode), cmd.clone());
+        if let Some(old) = old {
+            log::warn!(
+                "keybinding {modifiers:?}+{keycode} in mode {mode:?} is bound twice: {old:?} is replaced with {cmd:?}"
+            );
+        }
 
         log::info!(
-            "new keybinding: state={modmask:?} ({modifiers:?}), detail={keycode}, cmd={cmd:?}"
+            "new keybinding in mode {mode:?}: state={modmask:?} ({modifiers:?}), detail={keycode}, cmd={cmd:?}"
         );
-        Ok(())
     }
 
     pub fn start(mut self) -> Result<()> {
         self.init()?;
 
-        let mut cmdq = VecDeque::new();
-        loop {
-            let event = self.ctx.conn.wait_for_event()?;
-            self.handle_event(event, &mut cmdq)?;
-            self.process_commands(&mut cmdq)?;
+        // a panic (e.g. during a drag) must not leave the input of the whole session frozen by
+        // the grabs of the WM
+        match std::panic::catch_unwind(AssertUnwindSafe(|| self.event_loop())) {
+            Ok(result) => result,
+            Err(payload) => {
+                self.release_grabs();
+                std::panic::resume_unwind(payload);
+            }
         }
     }
-}
 
-macro_rules! mapped_windows {
-    ($slf:expr, $desktop:expr) => {
-        $slf.windows
-            .values()
+    /// Releases the keyboard and pointer grabs including frozen ones. Errors are ignored since
+    /// this is done after a panic.
+    fn release_grabs(&self) {
+        let conn = &self.ctx.conn;
+        let _ = conn.allow_events(xproto::Allow::ASYNC_BOTH, x11rb::CURRENT_TIME);
+        let _ = conn.ungrab_pointer(x11rb::CURRENT_TIME);
+        let _ = conn.ungrab_keyboard(x11rb::CURRENT_TIME);
+        let _ = conn.flush();
+        log::info!("grabs released");
+    }
+
+    fn event_loop(&mut self) -> Result<()> {
+        let mut cmdq = VecDeque::new();
+        loop {
+            // events already read from the connection (e.g. along with replies) must be handled
+            // before waiting, since they don't make the connection readable
+            while let Some(event) = self.ctx.conn.poll_for_event()? {
+                self.handle_event(event, &mut cmdq)?;
+                self.process_commands(&mut cmdq)?;
+            }
+            self.follow_drag()?;
+            self.update_idle_inhibit()?;
+            self.update_wallpaper()?;
+            self.update_pager()?;
+            self.update_title_bars()?;
+            self.notify_i3_clients()?;
+            // the requests made by the handlers are buffered until here, except that waiting for
+            // a reply sends the preceding ones
+            self.ctx.conn.flush()?;
+
+            let mut fds = vec![self.ctx.conn.stream().as_raw_fd()];
+            let ipc_index = fds.len();
+            if let Some(server) = &self.ipc {
+                fds.extend(server.fds());
+            }
+            let ipc_end = fds.len();
+            let signal_index = self.signals.as_ref().map(|signals| {
+                fds.push(signals.fd());
+                fds.len() - 1
+            });
+            let session_index = self.session.as_ref().map(|session| {
+                fds.push(session.fd());
+                fds.len() - 1
+            });
+            let i3_index = fds.len();
+            if let Some(server) = &self.i3 {
+                fds.extend(server.fds());
+            }
+
+            let timeout = self.next_timer();
+            let readable = utils::wait_readable(&fds, timeout).map_err(ConnectionError::IoError)?;
+            if signal_index.is_some_and(|i| readable[i]) {
+                self.handle_signals(&mut cmdq);
+                self.process_commands(&mut cmdq)?;
+            }
+            self.run_timers(&mut cmdq)?;
+            self.process_commands(&mut cmdq)?;
+            if readable[ipc_index..ipc_end].contains(&true) {
+                self.handle_ipc_requests(&readable[ipc_index..ipc_end], &mut cmdq)?;
+                self.process_commands(&mut cmdq)?;
+            }
+            if session_index.is_some_and(|i| readable[i]) {
+                self.handle_session_requests(&mut cmdq)?;
+                self.process_commands(&mut cmdq)?;
+            }
+            if readable[i3_index..].contains(&true) {
+                self.handle_i3_messages(&readable[i3_index..], &mut cmdq)?;
+                self.process_commands(&mut cmdq)?;
+            }
+        }
+    }
+}
+
+macro_rules! mapped_windows {
+    ($slf:expr, $desktop:expr) => {
+        $slf.window_order
+            .iter()
+            .map(|id| &$slf.windows[id])
             .filter(|win| win.desktop == $desktop && win.mapped)
     };
 }
@@ -198,10 +1461,18 @@ macro_rules! mapped_windows_mut {
 
 impl Daily {
     fn init(&mut self) -> Result<()> {
+        match signal::SignalFd::new() {
+            Ok(signals) => self.signals = Some(signals),
+            Err(err) => log::error!("Failed to create a signalfd: {err}"),
+        }
+
+        self.acquire_wm_selection()?;
+
         // become the window manager of the root window
         {
-            let interest =
-                xproto::EventMask::SUBSTRUCTURE_NOTIFY | xproto::EventMask::SUBSTRUCTURE_REDIRECT;
+            let interest = xproto::EventMask::SUBSTRUCTURE_NOTIFY
+                | xproto::EventMask::SUBSTRUCTURE_REDIRECT
+                | xproto::EventMask::BUTTON_PRESS;
             let aux = xproto::ChangeWindowAttributesAux::new().event_mask(interest);
             self.ctx
                 .conn
@@ -215,8 +1486,18 @@ impl Daily {
                 self.ctx.atom._NET_WM_ACTION_FULLSCREEN,
                 self.ctx.atom._NET_WM_STATE,
                 self.ctx.atom._NET_WM_STATE_FULLSCREEN,
+                self.ctx.atom._NET_WM_STATE_ABOVE,
+                self.ctx.atom._NET_WM_STATE_DEMANDS_ATTENTION,
+                self.ctx.atom._NET_WM_STATE_SKIP_TASKBAR,
+                self.ctx.atom._NET_WM_STATE_SKIP_PAGER,
+                self.ctx.atom._NET_WM_STATE_HIDDEN,
                 self.ctx.atom._NET_WM_WINDOW_TYPE,
                 self.ctx.atom._NET_WM_WINDOW_TYPE_DIALOG,
+                self.ctx.atom._NET_WM_WINDOW_TYPE_DOCK,
+                self.ctx.atom._NET_WM_WINDOW_TYPE_NOTIFICATION,
+                self.ctx.atom._NET_WM_STRUT,
+                self.ctx.atom._NET_WM_STRUT_PARTIAL,
+                self.ctx.atom._NET_WORKAREA,
                 self.ctx.atom._NET_WM_MOVERESIZE,
                 self.ctx.atom._NET_MOVERESIZE_WINDOW,
             ];
@@ -262,44 +1543,50 @@ impl Daily {
 
         // create preview window
         {
-            let (mut visual, mut depth) = (x11rb::COPY_FROM_PARENT, x11rb::COPY_DEPTH_FROM_PARENT);
-
-            let setup = self.ctx.conn.setup();
-            for d in setup.roots[0]
+            // the fill is translucent with a 32-bit visual (under a compositor), and opaque in
+            // the root visual on servers without one
+            let screen = self.ctx.screen();
+            let argb_visual = screen
                 .allowed_depths
                 .iter()
                 .filter(|d| d.depth == 32)
-            {
-                if let Some(v) = d.visuals.iter().find(|v| {
-                    v.class == xproto::VisualClass::TRUE_COLOR && v.bits_per_rgb_value == 8
-                }) {
-                    visual = v.visual_id;
-                    depth = 32;
-                    break;
+                .flat_map(|d| d.visuals.iter())
+                .find(|v| v.class == xproto::VisualClass::TRUE_COLOR && v.bits_per_rgb_value == 8)
+                .map(|v| v.visual_id);
+            let default_colormap = screen.default_colormap;
+            let (visual, depth, colormap, alpha) = match argb_visual {
+                Some(visual) => {
+                    let colormap = self.ctx.conn.generate_id()?;
+                    self.ctx
+                        .conn
+                        .create_colormap(
+                            xproto::ColormapAlloc::NONE,
+                            colormap,
+                            self.ctx.root,
+                            visual,
+                        )?
+                        .check()?;
+                    (visual, 32, colormap, Some(config::PREVIEW_ALPHA as u32))
                 }
-            }
-
-            let colormap = self.ctx.conn.generate_id()?;
-            self.ctx
-                .conn
-                .create_colormap(xproto::ColormapAlloc::NONE, colormap, self.ctx.root, visual)?
-                .check()?;
+                None => {
+                    log::info!("No 32-bit visual is available, so the preview is opaque");
+                    (
+                        x11rb::COPY_FROM_PARENT,
+                        x11rb::COPY_DEPTH_FROM_PARENT,
+                        default_colormap,
+                        None,
+                    )
+                }
+            };
+            self.preview_alpha = alpha;
+            let (background, border) = self.preview_pixels();
 
             let window = self.ctx.conn.generate_id()?;
             let class = xproto::WindowClass::INPUT_OUTPUT;
-
-            // FIXME: config color
-            let alpha = 0x80;
-            let (red, green, blue) = (0xA3, 0x7A, 0x29);
-            let bg_color = (alpha << 24)
-                | (((red * alpha) >> 8) << 16)
-                | (((green * alpha) >> 8) << 8)
-                | ((blue * alpha) >> 8);
-
             let aux = xproto::CreateWindowAux::new()
                 .colormap(colormap)
-                .border_pixel(0xFFfaab23)
-                .background_pixel(bg_color);
+                .border_pixel(border)
+                .background_pixel(background);
             self.ctx.conn.create_window(
                 depth,
                 window,
@@ -308,19 +1595,203 @@ impl Daily {
                 -1, // y
                 1,  // w
                 1,  // h
-                config::WINDOW_BORDER_WIDTH as u16,
+                config::PREVIEW_BORDER_WIDTH as u16,
                 class,
                 visual,
                 &aux,
             )?;
-            self.ctx.conn.flush()?;
 
             self.preview_window = window;
         }
 
+        // the root menu is drawn like tab bars
+        {
+            let window = self.ctx.conn.generate_id()?;
+            let aux = xproto::CreateWindowAux::new()
+                .override_redirect(1)
+                .background_pixel(self.theme.tab_inactive.0)
+                .border_pixel(self.theme.tab_active.0)
+                .event_mask(xproto::EventMask::EXPOSURE);
+            self.ctx.conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                self.ctx.root,
+                0, // x
+                0, // y
+                1, // width
+                1, // height
+                1, // border-width
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &aux,
+            )?;
+            self.menu_window = window;
+        }
+
+        // the pager is drawn with the colors of tab bars
+        {
+            let window = self.ctx.conn.generate_id()?;
+            let event_mask = xproto::EventMask::EXPOSURE
+                | xproto::EventMask::BUTTON_PRESS
+                | xproto::EventMask::BUTTON_RELEASE;
+            let aux = xproto::CreateWindowAux::new()
+                .override_redirect(1)
+                .background_pixel(self.theme.background)
+                .border_pixel(self.theme.tab_active.0)
+                .event_mask(event_mask);
+            self.ctx.conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                self.ctx.root,
+                0, // x
+                0, // y
+                1, // width
+                1, // height
+                1, // border-width
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &aux,
+            )?;
+            self.pager_window = window;
+        }
+
+        // the switcher is drawn like tab bars, with the thumbnails if available
+        {
+            // windows are redirected only when the switcher can be shown
+            let mru = matches!(config::FOCUS_ORDER, FocusOrder::MostRecentlyUsed);
+            if mru && config::SWITCHER && config::SWITCHER_THUMBNAILS {
+                self.thumbnailer = thumbnail::Thumbnailer::new(&self.ctx)?;
+            }
+            let window = self.ctx.conn.generate_id()?;
+            let aux = xproto::CreateWindowAux::new()
+                .override_redirect(1)
+                .background_pixel(self.theme.tab_inactive.0)
+                .border_pixel(self.theme.tab_active.0)
+                .event_mask(xproto::EventMask::EXPOSURE);
+            self.ctx.conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                self.ctx.root,
+                0, // x
+                0, // y
+                1, // width
+                1, // height
+                1, // border-width
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &aux,
+            )?;
+            self.switcher_window = window;
+            if let Some(thumbnailer) = &self.thumbnailer {
+                let visual = self.ctx.screen().root_visual;
+                self.switcher_picture = thumbnailer.create_picture(&self.ctx, window, visual)?;
+            }
+        }
+
+        // load the font used for tab bars
+        {
+            let font = self.ctx.conn.generate_id()?;
+            self.ctx.conn.open_font(font, b"fixed")?.check()?;
+            let info = self.ctx.conn.query_font(font)?.reply()?;
+
+            let gc = self.ctx.conn.generate_id()?;
+            let aux = xproto::CreateGCAux::new().font(font);
+            self.ctx.conn.create_gc(gc, self.ctx.root, &aux)?;
+            self.ctx.conn.close_font(font)?;
+
+            self.tab_font = TabFont {
+                gc,
+                ascent: info.font_ascent as i32,
+                descent: info.font_descent as i32,
+                char_width: (info.max_bounds.character_width as i32).max(1),
+            };
+        }
+
+        self.ipc = ipc::Server::bind();
+        if config::SESSION_MANAGEMENT {
+            self.session = xsmp::Client::connect();
+        }
+
+        self.shape = self
+            .ctx
+            .conn
+            .extension_information(shape::X11_EXTENSION_NAME)?
+            .is_some();
+        if config::PREVIEW_STYLE == PreviewStyle::Outline && !self.shape {
+            log::warn!("The preview is filled, since the SHAPE extension is not available");
+        }
+        if self
+            .ctx
+            .conn
+            .extension_information(res::X11_EXTENSION_NAME)?
+            .is_some()
+        {
+            let version = self.ctx.conn.res_query_version(1, 2)?.reply()?;
+            self.res = (version.server_major, version.server_minor) >= (1, 2);
+        }
+        if !self.res {
+            log::info!("X-Resource 1.2 is not available; client PIDs are taken from _NET_WM_PID");
+        }
+        if self
+            .ctx
+            .conn
+            .extension_information(screensaver::X11_EXTENSION_NAME)?
+            .is_some()
+        {
+            // 1.1 is needed to suspend the screen saver
+            let version = self.ctx.conn.screensaver_query_version(1, 1)?.reply()?;
+            self.screensaver =
+                (version.server_major_version, version.server_minor_version) >= (1, 1);
+        }
+        if !self.screensaver && !config::IDLE_ACTIONS.is_empty() {
+            log::warn!("MIT-SCREEN-SAVER is not available; idle actions are never run");
+        }
+        if self
+            .ctx
+            .conn
+            .extension_information(xinput::X11_EXTENSION_NAME)?
+            .is_some()
+        {
+            // raw events are delivered to the root window during grabs since 2.1
+            let version = self.ctx.conn.xinput_xi_query_version(2, 4)?.reply()?;
+            let version = (version.major_version, version.minor_version);
+            self.xinput = version >= (2, 1);
+            self.gestures = version >= (2, 4);
+        }
+        if !self.xinput {
+            log::info!("XInput 2.1 is not available; drags follow core motion events");
+        }
+        if config::I3_IPC {
+            self.i3 = i3ipc::Server::bind();
+            if let Some(server) = &self.i3 {
+                utils::replace_property(
+                    &self.ctx,
+                    self.ctx.root,
+                    self.ctx.atom.I3_SOCKET_PATH,
+                    utils::Property::Utf8String(&server.path().to_string_lossy()),
+                )?;
+            }
+        }
+
+        self.keymap.init(&self.ctx)?;
+        self.load_keyboard_mapping()?;
+        self.update_keyboard_layout_property()?;
+
         // setup for desktops
         {
-            self.desktops = vec![Desktop { monitor: None }; config::NUM_DESKTOPS];
+            let desktop = Desktop {
+                monitor: None,
+                layout: config::DEFAULT_LAYOUT,
+                columns: config::DEFAULT_COLUMNS,
+                tree: None,
+                next_split: None,
+                transposed: false,
+                mirrored: false,
+                zoomed: None,
+                hidden_stack: Vec::new(),
+                focus_history: Vec::new(),
+            };
+            self.desktops = vec![desktop; config::NUM_DESKTOPS];
         }
 
         // setup for monitors
@@ -330,108 +1801,332 @@ impl Daily {
                 self.ctx.root,
                 randr::NotifyMask::CRTC_CHANGE | randr::NotifyMask::OUTPUT_CHANGE,
             )?;
+            self.apply_output_layout();
 
-            let crtcs = self
-                .ctx
-                .conn
-                .randr_get_screen_resources_current(self.ctx.root)?
-                .reply()?
-                .crtcs;
+            let version = self.ctx.conn.randr_query_version(1, 5)?.reply()?;
+            self.randr_monitors = (version.major_version, version.minor_version) >= (1, 5);
+            log::info!("RandR {}.{}", version.major_version, version.minor_version);
 
             self.monitors.clear();
-            for (i, crtc) in crtcs.into_iter().enumerate() {
-                let crtc_info = self
-                    .ctx
-                    .conn
-                    .randr_get_crtc_info(crtc, x11rb::CURRENT_TIME)?
-                    .reply()?;
-                log::debug!("Crtc {crtc}: {crtc_info:?}");
+            for (i, info) in self.query_monitors()?.into_iter().enumerate() {
+                let desktop = self.desktop_for_monitor(&info, Some(i));
+                self.add_monitor(info, desktop)?;
+            }
+        }
 
-                if crtc_info.mode == x11rb::NONE {
-                    // ignore disabled CRTCs
-                    continue;
+        let paths = config::DESKTOP_WALLPAPERS.iter().map(|&(_, path)| path);
+        for path in config::WALLPAPER.into_iter().chain(paths) {
+            if self.wallpapers.contains_key(path) {
+                continue;
+            }
+            match wallpaper::Image::load(path) {
+                Ok(image) => {
+                    self.wallpapers.insert(path, image);
                 }
+                Err(err) => log::error!("Failed to load the wallpaper: {err}"),
+            }
+        }
 
-                let geometry = Rect {
-                    x: crtc_info.x as i32,
-                    y: crtc_info.y as i32,
-                    w: crtc_info.width as i32,
-                    h: crtc_info.height as i32,
-                };
-                self.add_monitor(crtc, geometry, i)?;
+        // grab mouse buttons used to drag windows
+        // (the pointer isn't frozen, since the drag buttons are never passed to clients)
+        let mut event_mask = xproto::EventMask::BUTTON_PRESS | xproto::EventMask::BUTTON_RELEASE;
+        if !self.xinput {
+            event_mask = event_mask | xproto::EventMask::BUTTON_MOTION;
+        }
+        for &(modifiers, button, action) in config::DRAG_BUTTONS {
+            let grabbed = self.grab_button(event_mask, button, combined_modmask(modifiers))?;
+            if let Err(err) = grabbed {
+                log::error!(
+                    "Failed to grab a mouse button: modifiers:{modifiers:?}, button:{button}, action:{action:?}"
+                );
+                log::debug!("detail: {err:?}");
             }
         }
 
-        // grab mouse button(s)
-        {
-            let event_mask = xproto::EventMask::BUTTON_PRESS
-                | xproto::EventMask::BUTTON_RELEASE
-                | xproto::EventMask::BUTTON_MOTION;
-            self.ctx
-                .conn
-                .grab_button(
-                    false,
-                    self.ctx.root,
-                    event_mask,
-                    xproto::GrabMode::SYNC,  // pointer
-                    xproto::GrabMode::ASYNC, // keyboard
-                    x11rb::NONE,
-                    x11rb::NONE,
-                    xproto::ButtonIndex::ANY,
-                    xproto::ModMask::ANY,
-                )?
-                .check()?;
+        // grab the wheel to switch desktops anywhere with the modifiers
+        // (the background is scrolled without them, since ButtonPress is selected on the root)
+        if let Some(modifiers) = config::ROOT_SCROLL_MODIFIERS.filter(|mods| !mods.is_empty()) {
+            for button in [SCROLL_UP, SCROLL_DOWN] {
+                let modmask = combined_modmask(modifiers);
+                let grabbed = self.grab_button(xproto::EventMask::BUTTON_PRESS, button, modmask)?;
+                if let Err(err) = grabbed {
+                    log::error!(
+                        "Failed to grab the wheel: modifiers:{modifiers:?}, button:{button}"
+                    );
+                    log::debug!("detail: {err:?}");
+                }
+            }
         }
 
+        self.grab_swipes()?;
+
+        // restore the state saved by the previous process, if any
+        let restored_focus = self.restore_state()?;
+
+        // manage the windows mapped before the WM started (other than the restored ones)
+        self.adopt_windows()?;
+
         // focus the first monitor
         {
             let dummy = self.monitors[0].dummy_window;
-            self.change_focus(dummy)?;
+            self.change_focus(restored_focus.unwrap_or(dummy))?;
         }
 
-        self.ctx.conn.flush()?;
+        if self.screensaver && !config::IDLE_ACTIONS.is_empty() {
+            self.set_timer(Timer::IdleCheck, std::time::Duration::ZERO);
+        }
+        Ok(())
+    }
+
+    /// Acquires the WM_Sn selection of the window managers of the screen (ICCCM 4.3). If it's
+    /// owned by the running WM, it's taken only with `--replace`, and the WM is waited for until
+    /// its window owning the selection is destroyed.
+    fn acquire_wm_selection(&mut self) -> Result<()> {
+        let selection = utils::intern_atom(&self.ctx, &format!("WM_S{}", self.ctx.screen_num))?;
+        self.wm_selection = selection;
+        let old = self.ctx.conn.get_selection_owner(selection)?.reply()?.owner;
+        if old != x11rb::NONE {
+            if !self.replace {
+                log::info!("another WM owns the selection (--replace replaces it)");
+                return Ok(());
+            }
+            let aux = xproto::ChangeWindowAttributesAux::new()
+                .event_mask(xproto::EventMask::STRUCTURE_NOTIFY);
+            if self
+                .ctx
+                .conn
+                .change_window_attributes(old, &aux)?
+                .check()
+                .is_err()
+            {
+                log::info!("the owner of the selection is already gone");
+            }
+        }
+
+        let window = self.ctx.conn.generate_id()?;
+        self.ctx.conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            self.ctx.root,
+            -1, // x
+            -1, // y
+            1,  // width
+            1,  // height
+            0,  // border-width
+            xproto::WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &xproto::CreateWindowAux::new(),
+        )?;
+        self.ctx
+            .conn
+            .set_selection_owner(window, selection, x11rb::CURRENT_TIME)?;
+        let owner = self.ctx.conn.get_selection_owner(selection)?.reply()?.owner;
+        if owner != window {
+            log::error!("Failed to acquire the selection of the window managers");
+            return Ok(());
+        }
+
+        if old != x11rb::NONE {
+            log::info!("waiting for the running WM to exit");
+            let deadline = std::time::Instant::now()
+                + std::time::Duration::from_millis(config::REPLACE_TIMEOUT_MS);
+            'wait: loop {
+                while let Some(event) = self.ctx.conn.poll_for_event()? {
+                    if matches!(event, Event::DestroyNotify(notif) if notif.window == old) {
+                        break 'wait;
+                    }
+                }
+                let Some(timeout) = deadline.checked_duration_since(std::time::Instant::now())
+                else {
+                    log::error!("the running WM didn't exit");
+                    break;
+                };
+                let fd = self.ctx.conn.stream().as_raw_fd();
+                utils::wait_readable(&[fd], Some(timeout)).map_err(ConnectionError::IoError)?;
+            }
+        }
+
+        // announce the new owner to the clients
+        let data = [x11rb::CURRENT_TIME, selection, window, 0, 0];
+        let event = xproto::ClientMessageEvent::new(32, self.ctx.root, self.ctx.atom.MANAGER, data);
+        self.ctx.conn.send_event(
+            false,
+            self.ctx.root,
+            xproto::EventMask::STRUCTURE_NOTIFY,
+            event,
+        )?;
         Ok(())
     }
 
     fn handle_event(&mut self, event: Event, cmdq: &mut VecDeque<Command>) -> Result<()> {
         log::trace!("handle_event: {event:?}");
+        self.metrics.record_event(event_kind(&event));
         match event {
+            // replaced by another WM
+            Event::SelectionClear(clear) if clear.selection == self.wm_selection => {
+                log::info!("replaced by another window manager");
+                cmdq.push_back(Command::Exit);
+            }
+
             Event::KeyPress(key_press) => {
-                let keys: (u16, u8) = (key_press.state.into(), key_press.detail);
-                if let Some(cmd) = self.keybind.get(&keys).cloned() {
-                    cmdq.push_back(cmd);
+                // the state also has the keyboard group in its higher bits with XKB,
+                // which must not affect keybindings
+                let state = u16::from(key_press.state) & MODIFIER_BITS;
+                let keys: (u16, u8) = (state, key_press.detail);
+
+                // auto-repeated presses of a key held for a repeatable command are coalesced
+                // so that the command is executed at the configured rate
+                let coalesced = self.key_repeat.is_some_and(|(key, last)| {
+                    key == keys
+                        && key_press.time.wrapping_sub(last) < config::KEY_REPEAT_INTERVAL_MS
+                });
+                let queued = cmdq.len();
+                if coalesced {
+                    log::trace!("coalesced a repeated key: {keys:?}");
+                } else if self.overview.is_some() {
+                    self.handle_overview_key(key_press.detail)?;
+                } else if self.desktop_prompt.is_some() {
+                    self.handle_prompt_key(key_press.detail, cmdq)?;
+                } else if let Some(mode) = self.active_mode.as_ref().and_then(|m| self.modes.get(m))
+                {
+                    if let Some(cmd) = mode.keybind.get(&keys).cloned() {
+                        cmdq.push_back(cmd);
+                        if mode.oneshot {
+                            cmdq.push_back(Command::ExitMode);
+                        }
+                    } else if key_press.detail == config::MODE_EXIT_KEY {
+                        cmdq.push_back(Command::ExitMode);
+                    }
+                } else {
+                    self.handle_chord_key(keys, cmdq)?;
+                }
+                if !coalesced {
+                    let repeatable = cmdq.iter().skip(queued).any(Command::is_repeatable);
+                    self.key_repeat = repeatable.then_some((keys, key_press.time));
+                }
+            }
+
+            Event::MappingNotify(notify) => {
+                if notify.request != xproto::Mapping::POINTER {
+                    self.load_keyboard_mapping()?;
+                }
+            }
+
+            Event::XkbStateNotify(notify) => {
+                if self.keymap.set_group(u8::from(notify.group)) {
+                    self.update_keyboard_layout_property()?;
+                }
+            }
+
+            Event::XkbMapNotify(_) | Event::XkbNewKeyboardNotify(_) => {
+                self.load_keyboard_mapping()?;
+                self.update_keyboard_layout_property()?;
+            }
+
+            Event::KeyRelease(key_release) => {
+                // without detectable auto-repeat, every repeated press is preceded by a release
+                let held = self.key_repeat.map(|((_, keycode), _)| keycode);
+                if self.keymap.detectable_auto_repeat() && held == Some(key_release.detail) {
+                    self.key_repeat = None;
+                }
+
+                let finished = self
+                    .mru_cycle
+                    .as_ref()
+                    .is_some_and(|cycle| cycle.release_keys.contains(&key_release.detail));
+                if finished {
+                    self.finish_mru_cycle()?;
                 }
             }
 
             Event::ButtonPress(button_press) => {
+                // the pointer is grabbed by the open menu, so clicks outside it are reported too
+                if let Some(menu) = &self.menu {
+                    let (x, y) = (button_press.event_x as i32, button_press.event_y as i32);
+                    if menu.item_at(x, y).is_none() {
+                        self.close_root_menu()?;
+                    }
+                    return Ok(());
+                }
+
+                if button_press.event == self.pager_window {
+                    if let Some(pager) = &mut self.pager {
+                        let (x, y) = (button_press.event_x as i32, button_press.event_y as i32);
+                        pager.pressed = pager
+                            .desktop_at(x, y)
+                            .map(|desktop| (desktop, pager.window_at(desktop, x, y)));
+                    }
+                    return Ok(());
+                }
+
+                if let Some(window) = self.title_bar_owner(button_press.event) {
+                    return self.press_title_bar(window, &button_press);
+                }
+
                 let x = button_press.root_x as i32;
                 let y = button_press.root_y as i32;
 
-                let mut allow = xproto::Allow::REPLAY_POINTER;
+                // the click-to-focus buttons are grabbed synchronously to pass the click
+                if button_press.event != self.ctx.root {
+                    self.ctx
+                        .conn
+                        .allow_events(xproto::Allow::REPLAY_POINTER, x11rb::CURRENT_TIME)?;
+                }
 
-                let hotkey = u16::from(config::HOT_KEY.keybutmask());
-                if u16::from(button_press.state) & hotkey > 0 {
-                    self.dnd_position = Some((x, y));
-                    self.button_count += 1;
-                    allow = xproto::Allow::SYNC_POINTER;
-                }
-
-                self.ctx.conn.allow_events(allow, x11rb::CURRENT_TIME)?;
-                self.ctx.conn.flush()?;
-
-                let new_focus =
-                    if button_press.child == x11rb::NONE && button_press.event == self.ctx.root {
-                        let mon = self
-                            .monitors
-                            .iter()
-                            .position(|mon| mon.geometry.contains(x, y))
-                            .unwrap_or(0);
-                        Some(self.monitors[mon].dummy_window)
-                    } else if self.windows.contains_key(&button_press.child) {
-                        Some(button_press.child)
+                // a click in the overview picks the window under the pointer, or cancels it
+                if let Some(overview) = &self.overview {
+                    let target = if button_press.event == self.ctx.root {
+                        button_press.child
                     } else {
-                        None
+                        button_press.event
                     };
+                    let picked = overview.windows.iter().copied().find(|id| *id == target);
+                    self.close_overview(picked)?;
+                    return Ok(());
+                }
+
+                if self.dnd_position.is_none() && button_press.event == self.ctx.root {
+                    let state = button_press.state;
+                    if let Some(cmd) = root_scroll(state, button_press.detail, button_press.child) {
+                        cmdq.push_back(cmd);
+                        return Ok(());
+                    }
+                }
+
+                let action = drag_action(button_press.state, button_press.detail);
+                if self.dnd_position.is_some() || action.is_some() {
+                    if self.dnd_position.is_none() {
+                        self.select_raw_motion(true)?;
+                    }
+                    self.dnd_position = Some((x, y));
+                    self.drag_action = self.drag_action.or(action);
+                    self.button_count += 1;
+                }
+
+                // the drag buttons are grabbed on the root window and
+                // the click-to-focus buttons on each unfocused window
+                let clicked = if button_press.event == self.ctx.root {
+                    button_press.child
+                } else {
+                    button_press.event
+                };
+
+                let tab_bar = self.monitors.iter().position(|mon| mon.tab_bar == clicked);
+
+                let new_focus = if let Some(mon) = tab_bar {
+                    self.tab_at(mon, x, y)
+                } else if clicked == x11rb::NONE {
+                    let mon = self
+                        .monitors
+                        .iter()
+                        .position(|mon| mon.geometry.contains(x, y))
+                        .unwrap_or(0);
+                    Some(self.monitors[mon].dummy_window)
+                } else if self.windows.contains_key(&clicked) {
+                    Some(clicked)
+                } else {
+                    None
+                };
 
                 const MOUSE_L: u8 = 1;
                 const MOUSE_R: u8 = 3;
@@ -448,145 +2143,198 @@ impl Daily {
                                     .stack_mode(xproto::StackMode::BELOW)
                                     .sibling(self.preview_window);
                                 self.ctx.conn.configure_window(window.id, &aux)?;
-                                self.ctx.conn.flush()?;
                             }
                         }
-                    }
-                }
-            }
 
-            Event::MotionNotify(motion) => {
-                if let Some((prev_x, prev_y)) = self.dnd_position {
-                    let x = motion.root_x as i32;
-                    let y = motion.root_y as i32;
-                    self.dnd_position = Some((x, y));
+                        self.invalidate_stacking();
 
-                    let dx = x - prev_x;
-                    let dy = y - prev_y;
+                        // keep transient windows above their parent
+                        for transient in self.transients_of(focus).into_iter().skip(1) {
+                            if let Some(window) = self.windows.get_mut(&transient) {
+                                window.stacking_order = self.stacking_counter;
+                                self.stacking_counter += 1;
 
-                    if let Some(window) = self.windows.get_mut(&self.focus) {
-                        if !window.floating {
-                            window.floating = true;
-                            if let Some(monitor) = self.desktops[window.desktop].monitor {
-                                self.update_layout(monitor)?;
+                                let aux = xproto::ConfigureWindowAux::new()
+                                    .stack_mode(xproto::StackMode::BELOW)
+                                    .sibling(self.preview_window);
+                                self.ctx.conn.configure_window(transient, &aux)?;
                             }
                         }
                     }
+                }
 
-                    if let Some(window) = self.windows.get_mut(&self.focus) {
-                        let state = u16::from(motion.state);
-                        let button1 = u16::from(xproto::KeyButMask::BUTTON1);
-                        let button3 = u16::from(xproto::KeyButMask::BUTTON3);
+                let plain = button_modifiers(button_press.state) == 0;
+                if config::ROOT_MENU
+                    && button_press.detail == MOUSE_R
+                    && clicked == x11rb::NONE
+                    && tab_bar.is_none()
+                    && action.is_none()
+                    && plain
+                {
+                    self.open_root_menu(x, y)?;
+                }
+            }
 
-                        if state & button1 > 0 {
-                            window.geometry.x += dx;
-                            window.geometry.y += dy;
-                        } else if state & button3 > 0 {
-                            window.geometry.w += dx;
-                            window.geometry.h += dy;
-                        }
+            // only for the root menu and drags without XInput 2.1
+            Event::MotionNotify(motion) => {
+                if motion.event == self.menu_window {
+                    self.highlight_menu_item(motion.event_x as i32, motion.event_y as i32)?;
+                } else if self.title_bar_owner(motion.event).is_some() {
+                    // the bar being dragged still gets the motion outside it
+                    let (x, y) = (motion.event_x as i32, motion.event_y as i32);
+                    self.hover_title_bar(Some(motion.event), x, y)?;
+                    self.drag_to(motion.root_x as i32, motion.root_y as i32)?;
+                } else if let Some(overview) = &self.overview {
+                    let hovered = overview.windows.iter().position(|id| *id == motion.child);
+                    if let Some(i) = hovered {
+                        self.select_in_overview(i)?;
+                    }
+                } else {
+                    self.drag_to(motion.root_x as i32, motion.root_y as i32)?;
+                }
+            }
 
-                        let mon = self.desktops[window.desktop].monitor.unwrap();
-                        let mg = self.monitors[mon].geometry;
-                        let ax = mg.x + window.geometry.x;
-                        let ay = mg.y + window.geometry.y;
+            Event::LeaveNotify(leave) if self.title_bar_owner(leave.event).is_some() => {
+                self.hover_title_bar(None, 0, 0)?;
+            }
 
-                        if !mg.contains(x, y) {
-                            // went out of the monitor
+            Event::XinputRawMotion(_) => {
+                self.drag_moved = self.dnd_position.is_some();
+            }
 
-                            if let Some(new_monitor) =
-                                self.monitors.iter().find(|mon| mon.geometry.contains(x, y))
-                            {
-                                window.desktop = new_monitor.desktop;
-                                window.geometry.x = ax - new_monitor.geometry.x;
-                                window.geometry.y = ay - new_monitor.geometry.y;
-                            }
-                        }
+            Event::XinputGestureSwipeBegin(swipe) => {
+                self.swipe = Some((swipe.detail, 0.0, 0.0));
+            }
 
-                        let mon = self.desktops[window.desktop].monitor.unwrap();
-                        let mg = self.monitors[mon].geometry;
-                        let aux = xproto::ConfigureWindowAux::new()
-                            .x(mg.left() + window.geometry.x)
-                            .y(mg.top() + window.geometry.y)
-                            .width(window.geometry.w as u32)
-                            .height(window.geometry.h as u32)
-                            .stack_mode(xproto::StackMode::BELOW)
-                            .sibling(self.preview_window);
-                        self.ctx.conn.configure_window(window.id, &aux)?;
-                        self.ctx.conn.flush()?;
+            Event::XinputGestureSwipeUpdate(swipe) => {
+                if let Some((_, dx, dy)) = &mut self.swipe {
+                    *dx += swipe.delta_x as f64 / 65536.0;
+                    *dy += swipe.delta_y as f64 / 65536.0;
+                }
+            }
 
-                        let mut preview_visible = false;
-                        if let Some(monitor) =
-                            self.monitors.iter().find(|mon| mon.geometry.contains(x, y))
-                        {
-                            if let Some(geometry) = snap(monitor.geometry, x, y) {
-                                preview_visible = true;
-                                if geometry != self.preview_geometry {
-                                    self.preview_geometry = geometry;
-                                    let aux = xproto::ConfigureWindowAux::new()
-                                        .stack_mode(xproto::StackMode::TOP_IF)
-                                        .x(geometry.x)
-                                        .y(geometry.y)
-                                        .width(geometry.w as u32)
-                                        .height(geometry.h as u32);
-                                    self.ctx.conn.configure_window(self.preview_window, &aux)?;
-                                }
-                            }
-                        }
-                        if state & button1 > 0 && preview_visible {
-                            self.ctx.conn.map_window(self.preview_window)?;
-                            self.ctx.conn.flush()?;
-                        } else {
-                            self.preview_geometry = Rect::default();
-                            self.ctx.conn.unmap_window(self.preview_window)?;
-                            self.ctx.conn.flush()?;
-                        }
+            Event::XinputGestureSwipeEnd(swipe) => {
+                let cancelled = u32::from(swipe.flags)
+                    & u32::from(xinput::GestureSwipeEventFlags::GESTURE_SWIPE_CANCELLED)
+                    != 0;
+                if let Some((fingers, dx, dy)) = self.swipe.take().filter(|_| !cancelled) {
+                    let cmd = swipe_direction(dx, dy)
+                        .and_then(|direction| self.swipe_bindings.get(&(fingers, direction)));
+                    if let Some(cmd) = cmd {
+                        cmdq.push_back(cmd.clone());
                     }
                 }
             }
 
             Event::ButtonRelease(button_release) => {
-                self.button_count -= 1;
-
-                let x = button_release.root_x as i32;
-                let y = button_release.root_y as i32;
+                if let Some(menu) = &self.menu {
+                    let (x, y) = (button_release.event_x as i32, button_release.event_y as i32);
+                    if let Some(i) = menu.item_at(x, y) {
+                        let cmd = menu.items[i].1.clone();
+                        self.close_root_menu()?;
+                        cmdq.push_back(cmd);
+                    }
+                    return Ok(());
+                }
 
-                if button_release.detail == 1 {
-                    if let Some(window) = self.windows.get_mut(&self.focus) {
-                        if let Some(monitor) = self
-                            .monitors
-                            .iter()
-                            .position(|mon| mon.geometry.contains(x, y))
-                        {
-                            let mg = self.monitors[monitor].geometry;
-                            if let Some(mut geometry) = snap(mg, x, y) {
-                                geometry.x -= mg.x;
-                                geometry.y -= mg.y;
-                                if geometry != window.geometry {
-                                    window.geometry = geometry;
-                                    self.update_layout(monitor)?;
-                                }
-                            }
+                if let Some((bar, button)) = self.title_bar_pressed.take() {
+                    let x = button_release.event_x as i32;
+                    let window = self
+                        .title_bar_owner(bar)
+                        .filter(|_| button_release.event == bar);
+                    if let Some(window) = window {
+                        if TitleButton::at(self.title_bar_width(window), x) == Some(button) {
+                            self.click_title_button(window, button)?;
                         }
                     }
+                    return Ok(());
                 }
 
-                if self.button_count > 0 {
-                    self.ctx
-                        .conn
-                        .allow_events(xproto::Allow::SYNC_POINTER, x11rb::CURRENT_TIME)?;
-                    self.ctx.conn.flush()?;
+                // a window dropped on another desktop in the pager moves there, and otherwise
+                // the click focuses the window or switches to the desktop
+                if button_release.event == self.pager_window {
+                    let Some(pager) = &mut self.pager else {
+                        return Ok(());
+                    };
+                    let (x, y) = (button_release.event_x as i32, button_release.event_y as i32);
+                    let target = pager.desktop_at(x, y);
+                    match (pager.pressed.take(), target) {
+                        (Some((from, Some(window))), Some(to)) if from != to => {
+                            self.move_window(window, to)?;
+                        }
+                        (Some((_, Some(window))), Some(_)) => self.focus_on_any_desktop(window)?,
+                        (Some(_), Some(to)) => cmdq.push_back(Command::SwitchDesktop(to)),
+                        _ => {}
+                    }
+                    return Ok(());
+                }
+
+                self.button_count = self.button_count.saturating_sub(1);
+
+                let x = button_release.root_x as i32;
+                let y = button_release.root_y as i32;
+                // the window catches up with the pointer before it is dropped
+                if self.drag_moved {
+                    self.drag_moved = false;
+                    self.drag_to(x, y)?;
+                }
+
+                if self.button_count == 0 {
+                    let dropped = self.pager.as_mut().and_then(|p| p.drop_target.take());
+                    if let Some(desktop) = dropped {
+                        // neither snapped nor swapped
+                        self.drag_action = None;
+                        self.swap_target = None;
+                        self.move_window(self.focus, desktop)?;
+                        self.draw_pager()?;
+                    } else if let Some(target) = self.swap_target.take() {
+                        self.swap_tiles(self.focus, target)?;
+                    }
+                }
+
+                if self.button_count == 0 && self.drag_action == Some(DragAction::Move) {
+                    let focus = self.focus;
+                    let snapped = self
+                        .monitors
+                        .iter()
+                        .position(|mon| mon.geometry.contains(x, y))
+                        .and_then(|monitor| Some((monitor, snap(&self.monitors[monitor], x, y)?)));
+                    let window = self.windows.get_mut(&focus).filter(|w| !w.shaped);
+                    if let (Some(window), Some((monitor, mut geometry))) = (window, snapped) {
+                        let mg = self.monitors[monitor].geometry;
+                        geometry.x -= mg.x;
+                        geometry.y -= mg.y;
+                        // the geometry is relative to the monitor the window is dropped on,
+                        // so the window moves to its desktop
+                        let desktop = self.monitors[monitor].desktop;
+                        if window.desktop != desktop {
+                            window.geometry = geometry;
+                            self.move_window(focus, desktop)?;
+                        } else if geometry != window.geometry {
+                            window.geometry = geometry;
+                            self.update_layout(monitor)?;
+                        }
+                    }
                 }
 
                 if self.button_count == 0 {
+                    self.select_raw_motion(false)?;
+                    self.remember_placement(self.focus);
                     self.dnd_position = None;
+                    self.drag_action = None;
+                    self.resize_edges = None;
                     self.preview_geometry = Rect::default();
                     self.ctx.conn.unmap_window(self.preview_window)?;
-                    self.ctx.conn.flush()?;
                 }
             }
 
             Event::MapRequest(req) => {
+                // a client mapping its window hidden by HideWindow brings it back
+                if self.take_hidden(req.window) {
+                    let desktop = self.windows[&req.window].desktop;
+                    self.show_hidden_window(req.window, desktop)?;
+                }
+
                 if let Some(window) = self.windows.get_mut(&req.window) {
                     if let Some(monitor) = self.desktops[window.desktop].monitor {
                         window.mapped = true;
@@ -604,58 +2352,17 @@ impl Daily {
                         self.change_focus(window_id)?;
                     }
                 } else {
-                    let geo = self.ctx.conn.get_geometry(req.window)?.reply()?;
-                    let stacking_order = self.stacking_counter;
-                    self.stacking_counter += 1;
-
-                    let monitor = self.focused_monitor().unwrap_or(0);
-                    let mon_geo = self.monitors[monitor].geometry;
-                    let desktop = self.monitors[monitor].desktop;
-
-                    let mut window = Window {
-                        id: req.window,
-                        desktop,
-                        mapped: true,
-                        floating: false,
-                        fullscreen: false,
-                        geometry: Rect {
-                            x: (geo.x as i32) - mon_geo.x,
-                            y: (geo.y as i32) - mon_geo.y,
-                            w: geo.width as i32,
-                            h: geo.height as i32,
-                        },
-                        stacking_order,
-                        ignore_unmap_notify: false,
-                    };
-
-                    // place this window at the center of the monitor if it is a dialog
-                    if utils::get_net_wm_window_type(&self.ctx, window.id)?
-                        == Some(self.ctx.atom._NET_WM_WINDOW_TYPE_DIALOG)
-                    {
-                        window.floating = true;
-
-                        let (center_x, center_y) = (mon_geo.w / 2, mon_geo.h / 2);
-                        window.geometry.x = center_x - window.geometry.w / 2;
-                        window.geometry.y = center_y - window.geometry.h / 2;
-                    }
-
-                    // _NET_WM_ALLOWED_ACTIONS
-                    let actions = [self.ctx.atom._NET_WM_ACTION_FULLSCREEN];
-                    utils::replace_property(
-                        &self.ctx,
-                        window.id,
-                        self.ctx.atom._NET_WM_ALLOWED_ACTIONS,
-                        utils::Property::AtomList(&actions),
-                    )?;
+                    self.manage_window(req.window)?;
+                }
+            }
 
-                    let window_id = window.id;
-                    log::debug!("window 0x{:X} added on desktop {}", window_id, desktop);
-                    self.windows.insert(window_id, window);
-                    self.update_layout(monitor)?;
+            Event::UnmapNotify(notif) if self.docks.contains_key(&notif.window) => {
+                self.remove_dock(notif.window)?;
+            }
 
-                    self.ctx.conn.map_window(window_id)?;
-                    self.change_focus(window_id)?;
-                }
+            Event::UnmapNotify(notif) if self.notifications.contains(&notif.window) => {
+                self.notifications.retain(|id| *id != notif.window);
+                self.arrange_notifications()?;
             }
 
             Event::UnmapNotify(notif) => {
@@ -667,23 +2374,95 @@ impl Daily {
                             log::debug!("window 0x{:X} is unmapped", window.id);
                             window.mapped = false;
 
-                            if self.focus == window.id {
-                                let desktop = window.desktop;
+                            let (window_id, desktop) = (window.id, window.desktop);
+                            self.release_swallowed(window_id)?;
+                            self.leave_group(window_id)?;
+                            if self.focus == window_id {
                                 self.focus_any(desktop)?;
                             }
                             self.update_layout(monitor)?;
                         }
                     }
                 } else {
-                    log::warn!("UnmapNotify: unknown window 0x{:X}", notif.window);
+                    // e.g. menus and tooltips, which are override-redirect
+                    log::trace!("UnmapNotify: unmanaged window 0x{:X}", notif.window);
+                }
+            }
+
+            Event::Expose(expose) => {
+                if expose.count == 0 && expose.window == self.menu_window {
+                    self.draw_root_menu()?;
+                } else if expose.count == 0 && expose.window == self.pager_window {
+                    self.draw_pager()?;
+                } else if expose.count == 0 && expose.window == self.switcher_window {
+                    self.draw_switcher()?;
+                } else if let Some(window) = self.title_bar_owner(expose.window) {
+                    if expose.count == 0 {
+                        self.draw_title_bar(window)?;
+                    }
+                } else if expose.count == 0 {
+                    if let Some(monitor) = self
+                        .monitors
+                        .iter()
+                        .position(|mon| mon.tab_bar == expose.window)
+                    {
+                        self.draw_tab_bar(monitor)?;
+                    }
                 }
             }
 
             Event::DestroyNotify(notif) => {
+                if self.docks.contains_key(&notif.window) {
+                    self.remove_dock(notif.window)?;
+                }
+                if self.notifications.contains(&notif.window) {
+                    self.notifications.retain(|id| *id != notif.window);
+                    self.arrange_notifications()?;
+                }
                 self.remove_window(notif.window)?;
             }
 
+            Event::ShapeNotify(notify) if notify.shape_kind == shape::SK::BOUNDING => {
+                if let Some(window) = self.windows.get_mut(&notify.affected_window) {
+                    if window.shaped != notify.shaped {
+                        log::debug!("window 0x{:X} shaped: {}", window.id, notify.shaped);
+                        window.shaped = notify.shaped;
+                        if let Some(monitor) = self.desktops[window.desktop].monitor {
+                            self.update_layout(monitor)?;
+                        }
+                    }
+                }
+            }
+
+            Event::PropertyNotify(notify) if self.docks.contains_key(&notify.window) => {
+                if notify.atom == self.ctx.atom._NET_WM_STRUT_PARTIAL
+                    || notify.atom == self.ctx.atom._NET_WM_STRUT
+                {
+                    self.add_dock(notify.window)?;
+                }
+            }
+
+            Event::PropertyNotify(notify) if self.windows.contains_key(&notify.window) => {
+                if notify.atom == self.ctx.atom._NET_WM_NAME
+                    || notify.atom == u32::from(xproto::AtomEnum::WM_NAME)
+                {
+                    let title = utils::get_window_title(&self.ctx, notify.window)?;
+                    let window = self.windows.get_mut(&notify.window).unwrap();
+                    if window.title != title {
+                        window.title = title;
+                        // the title is shown on the tab bar
+                        if let Some(monitor) = self.desktops[window.desktop].monitor {
+                            if self.tab_bar_state(monitor).is_some() {
+                                self.draw_tab_bar(monitor)?;
+                            }
+                        }
+                        self.draw_title_bar(notify.window)?;
+                    }
+                }
+            }
+
             Event::Error(err) => {
+                self.metrics.record_x11_error();
                 log::error!("X11 error: {err:?}");
             }
 
@@ -692,6 +2471,7 @@ impl Daily {
                     let output_change = notify.u.as_oc();
                     log::debug!("RROutputChangeNotify: {output_change:?}");
 
+                    self.apply_output_layout();
                     if let Some(prog) = config::MONITOR_UPDATE_PROG {
                         cmdq.push_back(Command::SpawnProcess(prog.to_owned()));
                     }
@@ -699,38 +2479,23 @@ impl Daily {
                     let crtc_change = notify.u.as_cc();
                     log::debug!("RRCrtcChangeNotify: {crtc_change:?}");
 
-                    let crtc = crtc_change.crtc;
-                    if let Some(monitor) = self.monitors.iter().position(|mon| mon.crtc == crtc) {
-                        if crtc_change.mode == x11rb::NONE {
-                            // monitor was disabled
-                            self.remove_monitor(monitor)?;
-                        } else {
-                            // monitor info was changed
-                            let geometry = &mut self.monitors.get_mut(monitor).unwrap().geometry;
-                            geometry.x = crtc_change.x as i32;
-                            geometry.y = crtc_change.y as i32;
-                            geometry.w = crtc_change.width as i32;
-                            geometry.h = crtc_change.height as i32;
-                            self.update_layout(monitor)?;
-                        }
-                    } else {
-                        // monitor was enabled
-                        let desktop = self
-                            .desktops
-                            .iter()
-                            .position(|scr| scr.monitor.is_none())
-                            .expect("too many monitors");
-                        let geometry = Rect {
-                            x: crtc_change.x as i32,
-                            y: crtc_change.y as i32,
-                            w: crtc_change.width as i32,
-                            h: crtc_change.height as i32,
-                        };
-                        self.add_monitor(crtc, geometry, desktop)?;
-                    }
+                    self.refresh_monitors()?;
                 }
             }
 
+            // notifications may resize themselves, but their positions are decided by the WM
+            Event::ConfigureRequest(req) if self.notifications.contains(&req.window) => {
+                let aux = xproto::ConfigureWindowAux {
+                    x: None,
+                    y: None,
+                    sibling: None,
+                    stack_mode: None,
+                    ..xproto::ConfigureWindowAux::from_configure_request(&req)
+                };
+                self.ctx.conn.configure_window(req.window, &aux)?;
+                self.arrange_notifications()?;
+            }
+
             Event::ConfigureRequest(req) => {
                 if let Some(window) = self.windows.get_mut(&req.window) {
                     if window.floating {
@@ -750,14 +2515,16 @@ impl Daily {
                         window.geometry.w = req.width as i32;
                         window.geometry.h = req.height as i32;
 
+                        self.configured.remove(&window.id);
                         let aux = xproto::ConfigureWindowAux::from_configure_request(&req);
                         self.ctx.conn.configure_window(window.id, &aux)?;
-                        self.ctx.conn.flush()?;
+                        if aux.stack_mode.is_some() {
+                            self.invalidate_stacking();
+                        }
                     }
                 } else {
                     let aux = xproto::ConfigureWindowAux::from_configure_request(&req);
                     self.ctx.conn.configure_window(req.window, &aux)?;
-                    self.ctx.conn.flush()?;
                 }
             }
 
@@ -768,17 +2535,14 @@ impl Daily {
                     msg
                 );
 
-                // FIXME: tidy up this part
                 if msg.type_ == self.ctx.atom._NET_WM_STATE {
-                    let action = msg.data.as_data32()[0];
-                    let first = msg.data.as_data32()[1];
-                    let second = msg.data.as_data32()[2];
+                    let [action, first, second, ..] = msg.data.as_data32();
 
-                    if action == 0 {
+                    if action == NET_WM_STATE_REMOVE {
                         log::debug!("actioin: _NET_WM_STATE_REMOVE");
-                    } else if action == 1 {
+                    } else if action == NET_WM_STATE_ADD {
                         log::debug!("actioin: _NET_WM_STATE_ADD");
-                    } else if action == 2 {
+                    } else if action == NET_WM_STATE_TOGGLE {
                         log::debug!("actioin: _NET_WM_STATE_TOGGLE");
                     }
 
@@ -787,41 +2551,37 @@ impl Daily {
                         log::debug!("second: {}", utils::get_atom_name(&self.ctx, second)?);
                     }
 
-                    if first == self.ctx.atom._NET_WM_STATE_FULLSCREEN
-                        || second == self.ctx.atom._NET_WM_STATE_FULLSCREEN
-                    {
-                        if action == 0 {
-                            // REMOVE
-                            if let Some(window) = self.windows.get_mut(&msg.window) {
-                                window.fullscreen = false;
-                                if let Some(monitor) = self.desktops[window.desktop].monitor {
-                                    self.update_layout(monitor)?;
+                    if let Some(window) = self.windows.get_mut(&msg.window) {
+                        for property in [first, second] {
+                            if property == self.ctx.atom._NET_WM_STATE_FULLSCREEN {
+                                window.fullscreen = apply_state_action(action, window.fullscreen);
+                            } else if property == self.ctx.atom._NET_WM_STATE_ABOVE {
+                                window.above = apply_state_action(action, window.above);
+                            } else if property == self.ctx.atom._NET_WM_STATE_SKIP_TASKBAR {
+                                window.skip_taskbar =
+                                    apply_state_action(action, window.skip_taskbar);
+                            } else if property == self.ctx.atom._NET_WM_STATE_SKIP_PAGER {
+                                window.skip_pager = apply_state_action(action, window.skip_pager);
+                            } else if property == self.ctx.atom._NET_WM_STATE_DEMANDS_ATTENTION {
+                                let urgent = apply_state_action(action, window.urgent);
+                                if urgent && !window.urgent && window.id != self.focus {
+                                    window.urgent = true;
+                                    log::debug!("window 0x{:X} demands attention", window.id);
+                                    if let Some(prog) = config::URGENT_HOOK_PROG {
+                                        let cmdline = format!("{prog} {}", window.desktop);
+                                        cmdq.push_back(Command::SpawnProcess(cmdline));
+                                    }
+                                } else if !urgent {
+                                    window.urgent = false;
                                 }
-
-                                let state = [];
-                                utils::replace_property(
-                                    &self.ctx,
-                                    msg.window,
-                                    self.ctx.atom._NET_WM_STATE,
-                                    utils::Property::AtomList(&state),
-                                )?;
                             }
-                        } else if action == 1 {
-                            // SET/ADD
-                            if let Some(window) = self.windows.get_mut(&msg.window) {
-                                window.fullscreen = true;
-                                if let Some(monitor) = self.desktops[window.desktop].monitor {
-                                    self.update_layout(monitor)?;
-                                }
+                        }
 
-                                let state = [self.ctx.atom._NET_WM_STATE_FULLSCREEN];
-                                utils::replace_property(
-                                    &self.ctx,
-                                    msg.window,
-                                    self.ctx.atom._NET_WM_STATE,
-                                    utils::Property::AtomList(&state),
-                                )?;
-                            }
+                        let monitor = self.desktops[window.desktop].monitor;
+                        self.update_net_wm_state(msg.window)?;
+                        self.update_border(msg.window)?;
+                        if let Some(monitor) = monitor {
+                            self.update_layout(monitor)?;
                         }
                     }
                 }
@@ -836,315 +2596,5069 @@ impl Daily {
 
     fn process_commands(&mut self, cmdq: &mut VecDeque<Command>) -> Result<()> {
         for cmd in cmdq.drain(..) {
-            log::debug!("cmd={cmd:?}");
-            match cmd {
-                Command::Exit => {
-                    return Err(Error::Interrupted { restart: false });
-                }
-                Command::Restart => {
-                    return Err(Error::Interrupted { restart: true });
-                }
-
-                Command::SpawnProcess(cmdline) => {
-                    use std::process::{Command, Stdio};
-                    let shell_cmdline = format!("{cmdline} &");
-                    let mut child = Command::new("/bin/sh")
-                        .arg("-c")
-                        .arg(shell_cmdline)
-                        .stdin(Stdio::null())
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .spawn()
-                        .unwrap();
-                    child.wait().unwrap();
-                }
-
-                Command::FocusNextMonitor => {
-                    let next = self
-                        .focused_monitor()
-                        .map(|i| (i + 1) % self.monitors.len())
-                        .unwrap_or(0);
+            let name = cmd.name();
+            let started = std::time::Instant::now();
+            let result = self.run_command(cmd);
+            self.metrics.record_command(name, started.elapsed());
+            result?;
+        }
+        Ok(())
+    }
 
-                    let desktop = self.monitors[next].desktop;
-                    self.focus_any(desktop)?;
-                }
+    fn run_command(&mut self, cmd: Command) -> Result<()> {
+        log::debug!("cmd={cmd:?}");
+        match cmd {
+            Command::Exit => {
+                self.flush_placements();
+                self.restore_clients()?;
+                return Err(Error::Interrupted { restart: false });
+            }
+            Command::Restart => {
+                self.flush_placements();
+                self.hand_over_state()?;
+                return Err(Error::Interrupted { restart: true });
+            }
 
-                Command::FocusNextWindow => {
-                    if let Some(window) = self.windows.get(&self.focus) {
-                        let desktop = window.desktop;
-                        let monitor = self.desktops[desktop].monitor.unwrap();
+            Command::ReloadTheme => {
+                self.theme = Theme::load();
+                self.apply_theme()?;
+            }
 
-                        let windows: Vec<xproto::Window> =
-                            mapped_windows!(self, desktop).map(|win| win.id).collect();
+            Command::SpawnProcess(cmdline) => {
+                use std::process::{Command, Stdio};
+                let shell_cmdline = format!("{cmdline} &");
+                let mut command = Command::new("/bin/sh");
+                command
+                    .arg("-c")
+                    .arg(shell_cmdline)
+                    .envs(self.spawn_env())
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                let mut child = signal::unblock_in_child(&mut command).spawn().unwrap();
+                child.wait().unwrap();
+            }
 
-                        if windows.len() > 1 {
-                            let next_window = windows
-                                .iter()
-                                .copied()
-                                .chain(windows.iter().copied())
-                                .skip_while(|id| *id != window.id)
-                                .nth(1)
-                                .unwrap_or_else(|| self.monitors[monitor].dummy_window);
-                            self.change_focus(next_window)?;
-                        }
-                    }
+            Command::Exec(argv) => {
+                use std::process::{Command, Stdio};
+                let (program, args) = match argv.split_first() {
+                    Some(split) => split,
+                    None => return Ok(()),
+                };
+                let mut command = Command::new(program);
+                command
+                    .args(args)
+                    .envs(self.spawn_env())
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                // the child is reaped on SIGCHLD when it exits
+                if let Err(err) = signal::unblock_in_child(&mut command).spawn() {
+                    log::error!("Failed to execute {argv:?}: {err}");
                 }
+            }
 
-                Command::SwitchDesktop(new_desktop) => {
-                    if let Some(monitor_a) = self.desktops[new_desktop].monitor {
-                        let desktop_a = new_desktop;
-                        let monitor_b = self.focused_monitor().unwrap_or(0);
-                        let desktop_b = self.monitors[monitor_b].desktop;
-                        self.monitors[monitor_a].desktop = desktop_b;
-                        self.monitors[monitor_b].desktop = desktop_a;
-                        self.desktops[desktop_a].monitor = Some(monitor_b);
-                        self.desktops[desktop_b].monitor = Some(monitor_a);
-                        self.update_layout(monitor_a)?;
-                        self.update_layout(monitor_b)?;
-                        self.focus_any(new_desktop)?;
-                    } else {
-                        let monitor = self.focused_monitor().unwrap_or(0);
-                        let old_desktop = self.monitors[monitor].desktop;
-                        self.hide_desktop(old_desktop)?;
-                        self.show_desktop(new_desktop, monitor)?;
-                        self.focus_any(new_desktop)?;
-                    }
-                }
+            Command::FocusNextMonitor => {
+                let next = self
+                    .focused_monitor()
+                    .map(|i| (i + 1) % self.monitors.len())
+                    .unwrap_or(0);
 
-                Command::MoveWindow(new_desktop) => {
-                    if let Some(window) = self.windows.get_mut(&self.focus) {
-                        let old_desktop = window.desktop;
-                        let old_monitor = self.desktops[old_desktop].monitor.unwrap();
-                        let new_monitor = self.desktops[new_desktop].monitor;
+                let desktop = self.monitors[next].desktop;
+                self.focus_any(desktop)?;
+            }
 
-                        window.desktop = new_desktop;
-                        if new_monitor.is_none() {
-                            window.ignore_unmap_notify = true;
-                            self.ctx.conn.unmap_window(window.id)?;
-                            self.ctx.conn.flush()?;
+            Command::FocusNextWindow => {
+                self.cycle_window_focus(false)?;
+            }
 
-                            if self.focus == window.id {
-                                self.focus_any(old_desktop)?;
-                            }
-                        }
+            Command::FocusPrevWindow => {
+                self.cycle_window_focus(true)?;
+            }
 
-                        self.update_layout(old_monitor)?;
-                        if let Some(mon) = new_monitor {
-                            self.update_layout(mon)?;
-                        }
-                    }
-                }
+            Command::FocusNextSameClass => {
+                self.cycle_same_class()?;
+            }
 
-                Command::ToggleFloating => {
-                    if let Some(window) = self.windows.get_mut(&self.focus) {
-                        window.floating ^= true;
-                        if let Some(monitor) = self.desktops[window.desktop].monitor {
-                            self.update_layout(monitor)?;
+            Command::KillWindowProcess(force) => {
+                match self.windows.get(&self.focus).and_then(|win| win.pid) {
+                    Some(pid) if pid > 1 => {
+                        let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+                        log::info!("send signal {signal} to process {pid}");
+                        if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+                            let err = std::io::Error::last_os_error();
+                            log::error!("Failed to send a signal to process {pid}: {err}");
                         }
                     }
+                    _ => log::warn!("the process of the focused window is unknown"),
                 }
             }
-        }
-        Ok(())
-    }
-
-    fn focused_monitor(&mut self) -> Option<usize> {
-        if let Some(window) = self.windows.get(&self.focus) {
-            self.desktops[window.desktop].monitor
-        } else {
-            self.monitors
-                .iter()
-                .position(|mon| mon.dummy_window == self.focus)
-        }
-    }
 
-    fn add_monitor(&mut self, crtc: randr::Crtc, geometry: Rect, desktop: usize) -> Result<usize> {
-        let i = self.monitors.len();
-        let dummy_window = self.ctx.conn.generate_id()?;
-        log::debug!("dummy window for monitor {i}: {dummy_window}");
+            Command::FocusMatching(condition) => match self.find_window(&condition) {
+                Some(window) => self.focus_on_any_desktop(window)?,
+                None => log::info!("no window matches {condition:?}"),
+            },
+
+            Command::SwitchNextDesktop | Command::SwitchPrevDesktop => {
+                let n = self.desktops.len();
+                let monitor = self.focused_monitor().unwrap_or(0);
+                let current = self.monitors[monitor].desktop;
+                let step = if matches!(cmd, Command::SwitchNextDesktop) {
+                    1
+                } else {
+                    n - 1
+                };
+                self.run_command(Command::SwitchDesktop((current + step) % n))?;
+            }
 
-        let depth = x11rb::COPY_DEPTH_FROM_PARENT;
-        let class = xproto::WindowClass::INPUT_ONLY;
-        let visual = x11rb::COPY_FROM_PARENT;
-        let aux = xproto::CreateWindowAux::new();
-        self.ctx.conn.create_window(
-            depth,
-            dummy_window,
-            self.ctx.root,
-            geometry.x as i16, // x
-            geometry.y as i16, // y
-            1,                 // width
-            1,                 // height
-            0,                 // border-width
-            class,
-            visual,
-            &aux,
-        )?;
-        self.ctx.conn.map_window(dummy_window)?;
+            Command::SwitchDesktop(new_desktop) => {
+                if let Some(monitor_a) = self.desktops[new_desktop].monitor {
+                    let desktop_a = new_desktop;
+                    let monitor_b = self.focused_monitor().unwrap_or(0);
+                    let desktop_b = self.monitors[monitor_b].desktop;
+                    self.monitors[monitor_a].desktop = desktop_b;
+                    self.monitors[monitor_b].desktop = desktop_a;
+                    self.desktops[desktop_a].monitor = Some(monitor_b);
+                    self.desktops[desktop_b].monitor = Some(monitor_a);
+                    self.carry_sticky_windows(&[(desktop_a, desktop_b), (desktop_b, desktop_a)]);
+                    self.update_layout(monitor_a)?;
+                    self.update_layout(monitor_b)?;
+                    self.focus_any(new_desktop)?;
+                } else {
+                    let monitor = self.focused_monitor().unwrap_or(0);
+                    let old_desktop = self.monitors[monitor].desktop;
+                    self.carry_sticky_windows(&[(old_desktop, new_desktop)]);
+                    self.hide_desktop(old_desktop)?;
+                    self.show_desktop(new_desktop, monitor)?;
+                    self.focus_any(new_desktop)?;
+                }
+            }
 
-        self.monitors.push(Monitor {
-            crtc,
-            desktop,
-            dummy_window,
-            geometry,
-        });
-        self.show_desktop(desktop, i)?;
-        Ok(i)
-    }
+            Command::MoveWindow(new_desktop) => {
+                self.move_window(self.focus, new_desktop)?;
+            }
 
-    fn remove_monitor(&mut self, monitor: usize) -> Result<()> {
-        let desktop = self.monitors[monitor].desktop;
-        self.hide_desktop(desktop)?;
+            Command::SetLayout(layout) => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].layout = layout;
+                    self.update_layout(monitor)?;
+                }
+            }
 
-        let dummy_window = self.monitors[monitor].dummy_window;
-        self.ctx.conn.destroy_window(dummy_window)?;
-        self.ctx.conn.flush()?;
+            Command::NextLayout => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].layout = self.desktops[desktop].layout.next();
+                    self.update_layout(monitor)?;
+                }
+            }
 
-        self.monitors.swap_remove(monitor);
-        if monitor < self.monitors.len() {
-            let desktop = self.monitors[monitor].desktop;
-            self.desktops[desktop].monitor = Some(monitor);
-        }
-        Ok(())
-    }
+            Command::IncColumns => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].columns += 1;
+                    self.update_layout(monitor)?;
+                }
+            }
 
-    fn focus_any(&mut self, desktop: usize) -> Result<()> {
-        let fallback = self.desktops[desktop]
-            .monitor
-            .map(|mon| self.monitors[mon].dummy_window)
-            .unwrap_or(x11rb::NONE);
-        let any_window_on_desktop: xproto::Window = mapped_windows!(self, desktop)
-            .map(|win| win.id)
-            .next()
-            .unwrap_or(fallback);
-        self.change_focus(any_window_on_desktop)?;
-        Ok(())
-    }
+            Command::DecColumns => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    let columns = &mut self.desktops[desktop].columns;
+                    *columns = columns.saturating_sub(1).max(1);
+                    self.update_layout(monitor)?;
+                }
+            }
 
-    fn change_focus(&mut self, focus: xproto::Window) -> Result<()> {
-        let old_focus = self.focus;
-        let new_focus = focus;
+            Command::SplitHorizontal => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].next_split = Some(Direction::Right);
+                    self.update_preselection_hint()?;
+                }
+            }
 
-        if old_focus == new_focus {
-            return Ok(());
-        }
-        self.focus = new_focus;
+            Command::SplitVertical => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].next_split = Some(Direction::Down);
+                    self.update_preselection_hint()?;
+                }
+            }
 
-        log::debug!("focus on window 0x{:X} ({})", new_focus, new_focus);
+            Command::Preselect(direction) => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    // preselecting the same side again cancels it
+                    let next_split = &mut self.desktops[desktop].next_split;
+                    if *next_split == Some(direction) {
+                        *next_split = None;
+                    } else {
+                        *next_split = Some(direction);
+                    }
+                    self.update_preselection_hint()?;
+                }
+            }
 
-        // TODO: config
-        if self.windows.contains_key(&old_focus) {
-            let aux = xproto::ChangeWindowAttributesAux::new().border_pixel(0x000000);
-            self.ctx.conn.change_window_attributes(old_focus, &aux)?;
-        }
-        if self.windows.contains_key(&new_focus) {
-            let aux = xproto::ChangeWindowAttributesAux::new().border_pixel(0x00FF00);
-            self.ctx.conn.change_window_attributes(new_focus, &aux)?;
-        }
+            Command::BalanceLayout => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    let tree = &mut self.desktops[desktop].tree;
+                    *tree = tree.take().map(Container::balance);
+                    for window in self.windows.values_mut() {
+                        if window.desktop == desktop {
+                            window.weight = 1.0;
+                        }
+                    }
+                    self.update_layout(monitor)?;
+                }
+            }
 
-        self.ctx
-            .conn
-            .set_input_focus(
-                xproto::InputFocus::NONE, // revert-to
-                new_focus,
-                x11rb::CURRENT_TIME,
-            )?
-            .check()?;
-        Ok(())
-    }
+            Command::RotateLayout => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].transposed ^= true;
+                    self.update_layout(monitor)?;
+                }
+            }
 
-    fn remove_window(&mut self, window: xproto::Window) -> Result<()> {
-        if let Some(window) = self.windows.remove(&window) {
-            let desktop = window.desktop;
-            log::debug!("window 0x{:X} removed from desktop {}", window.id, desktop);
-            if let Some(monitor) = self.desktops[desktop].monitor {
-                self.update_layout(monitor)?;
-                if self.focus == window.id {
-                    self.change_focus(x11rb::NONE)?;
+            Command::MirrorLayout => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    self.desktops[desktop].mirrored ^= true;
+                    self.update_layout(monitor)?;
                 }
             }
-        }
-        Ok(())
-    }
 
-    fn hide_desktop(&mut self, desktop: usize) -> Result<()> {
-        if self.desktops[desktop].monitor.is_none() {
-            return Ok(());
+            Command::TogglePip => {
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        if let Some(saved) = window.pip.take() {
+                            window.floating = saved.floating;
+                            window.above = saved.above;
+                            window.geometry = saved.geometry;
+                            window.sticky = false;
+                        } else {
+                            window.pip = Some(PipState {
+                                floating: window.floating,
+                                above: window.above,
+                                geometry: window.geometry,
+                            });
+                            window.floating = true;
+                            window.above = true;
+                            window.sticky = true;
+                            window.geometry = pip_geometry(&self.monitors[monitor]);
+                        }
+                        let window_id = window.id;
+                        self.update_net_wm_state(window_id)?;
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::ToggleDropdown => {
+                self.toggle_dropdown()?;
+            }
+
+            Command::ToggleZoom => {
+                if let Some(window) = self.windows.get(&self.focus) {
+                    if let (Some(monitor), false) = (
+                        self.desktops[window.desktop].monitor,
+                        window.floating || window.fullscreen,
+                    ) {
+                        let zoomed = &mut self.desktops[window.desktop].zoomed;
+                        if *zoomed == Some(window.id) {
+                            *zoomed = None;
+                        } else {
+                            *zoomed = Some(window.id);
+                        }
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::HideWindow => {
+                let focus = self.focus;
+                let grouped = self.window_groups.iter().any(|g| g.contains(&focus));
+                if let Some(window) = self.windows.get(&focus) {
+                    if grouped || window.swallowed.is_some() {
+                        log::warn!(
+                            "window 0x{focus:X} can't be hidden while grouped or swallowing"
+                        );
+                        return Ok(());
+                    }
+                    let desktop = window.desktop;
+                    self.hide_window(focus)?;
+                    self.desktops[desktop].hidden_stack.push(focus);
+                    if let Some(monitor) = self.desktops[desktop].monitor {
+                        self.update_layout(monitor)?;
+                        self.focus_any(desktop)?;
+                    }
+                }
+            }
+
+            Command::UnhideLast => {
+                if let Some(monitor) = self.focused_monitor() {
+                    let desktop = self.monitors[monitor].desktop;
+                    if let Some(&window) = self.desktops[desktop].hidden_stack.last() {
+                        self.unhide(window, monitor)?;
+                    }
+                }
+            }
+
+            Command::Unhide(window) => {
+                if let Some(monitor) = self.focused_monitor() {
+                    if self
+                        .desktops
+                        .iter()
+                        .any(|d| d.hidden_stack.contains(&window))
+                    {
+                        self.unhide(window, monitor)?;
+                    } else {
+                        log::warn!("window 0x{window:X} is not hidden");
+                    }
+                }
+            }
+
+            Command::ToggleBorder => {
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    window.borderless = !window.borderless;
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::GroupWithFocused => {
+                if let Some(window) = self.windows.get(&self.focus) {
+                    let (focus, desktop) = (window.id, window.desktop);
+
+                    // the previously focused tiled window joins the group of the focused one
+                    let previous = self.desktops[desktop]
+                        .focus_history
+                        .iter()
+                        .rev()
+                        .copied()
+                        .find(|id| {
+                            *id != focus
+                                && self.windows.get(id).is_some_and(|win| {
+                                    win.mapped && win.desktop == desktop && !win.floating
+                                })
+                        });
+                    if let Some(previous) = previous {
+                        self.join_group(focus, previous)?;
+                        if let Some(monitor) = self.desktops[desktop].monitor {
+                            self.update_layout(monitor)?;
+                        }
+                    }
+                }
+            }
+
+            Command::CycleGroup => {
+                let focus = self.focus;
+                if let Some(group) = self.window_groups.iter().find(|g| g.contains(&focus)) {
+                    let i = group.iter().position(|id| *id == focus).unwrap();
+                    let next = group[(i + 1) % group.len()];
+                    let desktop = self.windows[&focus].desktop;
+                    self.replace_window(focus, next)?;
+                    if let Some(monitor) = self.desktops[desktop].monitor {
+                        self.update_layout(monitor)?;
+                        self.change_focus(next)?;
+                    }
+                }
+            }
+
+            Command::Ungroup => {
+                let focus = self.focus;
+                if let Some(i) = self.window_groups.iter().position(|g| g.contains(&focus)) {
+                    self.window_groups[i].retain(|id| *id != focus);
+
+                    // another member appears in a new tile
+                    let next = self.window_groups[i][0];
+                    if self.window_groups[i].len() < 2 {
+                        self.window_groups.swap_remove(i);
+                    }
+                    let desktop = self.windows[&focus].desktop;
+                    self.show_hidden_window(next, desktop)?;
+                    if let Some(monitor) = self.desktops[desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::ResizeWindow(dw, dh) => {
+                let axis = self.tile_axis(self.focus);
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    if window.floating && !window.fullscreen {
+                        window.geometry.w = (window.geometry.w + dw).max(1);
+                        window.geometry.h = (window.geometry.h + dh).max(1);
+                    } else if let Some(axis) = axis {
+                        if !window.resize_tile(axis, dw, dh) {
+                            return Ok(());
+                        }
+                    }
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::MoveFloating(dx, dy) => {
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    if window.floating && !window.fullscreen {
+                        window.geometry.x += dx;
+                        window.geometry.y += dy;
+                        if let Some(monitor) = self.desktops[window.desktop].monitor {
+                            self.update_layout(monitor)?;
+                        }
+                    }
+                }
+            }
+
+            Command::SnapLeft
+            | Command::SnapRight
+            | Command::SnapTop
+            | Command::SnapBottom
+            | Command::SnapTopLeft
+            | Command::SnapTopRight
+            | Command::SnapBottomLeft
+            | Command::SnapBottomRight
+            | Command::SnapMaximize => {
+                let region = cmd.snap_region().unwrap();
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    if let (Some(monitor), false) =
+                        (self.desktops[window.desktop].monitor, window.fullscreen)
+                    {
+                        let mon = &self.monitors[monitor];
+                        let mut geometry = snap_geometry(mon, region);
+                        geometry.x -= mon.geometry.x;
+                        geometry.y -= mon.geometry.y;
+                        window.floating = true;
+                        window.geometry = geometry;
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::RaiseWindow => {
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    if window.floating {
+                        window.stacking_order = self.stacking_counter;
+                        self.stacking_counter += 1;
+                        if let Some(monitor) = self.desktops[window.desktop].monitor {
+                            self.update_layout(monitor)?;
+                        }
+                    }
+                }
+            }
+
+            Command::LowerWindow => {
+                if self
+                    .windows
+                    .get(&self.focus)
+                    .is_some_and(|win| win.floating)
+                {
+                    // shift the others up to make room for the lowest order
+                    for window in self.windows.values_mut() {
+                        window.stacking_order += 1;
+                    }
+                    self.stacking_counter += 1;
+
+                    let window = self.windows.get_mut(&self.focus).unwrap();
+                    window.stacking_order = 0;
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            Command::EnterMode(mode) => {
+                if !self.modes.contains_key(&mode) {
+                    log::warn!("unknown mode: {mode:?}");
+                } else if self.active_mode.as_ref() != Some(&mode) {
+                    if self.active_mode.is_none() {
+                        self.grab_keyboard()?;
+                    }
+
+                    // make the current mode visible to bars
+                    utils::replace_property(
+                        &self.ctx,
+                        self.ctx.root,
+                        self.ctx.atom._DAILY2_MODE,
+                        utils::Property::Utf8String(&mode),
+                    )?;
+                    log::info!("enter mode {mode:?}");
+                    self.active_mode = Some(mode);
+                }
+            }
+
+            Command::NextKeyboardLayout => {
+                self.keymap.lock_next_group(&self.ctx)?;
+            }
+
+            Command::ToggleIdleInhibit => {
+                self.idle_inhibit_overridden = !self.idle_inhibit_overridden;
+                log::info!("idle inhibit overridden: {}", self.idle_inhibit_overridden);
+            }
+
+            Command::Overview(all_desktops) => {
+                if self.overview.is_some() {
+                    self.close_overview(None)?;
+                } else {
+                    self.open_overview(all_desktops)?;
+                }
+            }
+
+            Command::TogglePager => {
+                if self.pager.is_some() {
+                    self.pager = None;
+                    self.ctx.conn.unmap_window(self.pager_window)?;
+                } else {
+                    self.open_pager()?;
+                }
+            }
+
+            Command::GotoDesktopPrompt => {
+                if self.desktop_prompt.is_none() {
+                    self.grab_keyboard()?;
+                    self.desktop_prompt = Some(String::new());
+                    utils::replace_property(
+                        &self.ctx,
+                        self.ctx.root,
+                        self.ctx.atom._DAILY2_PROMPT,
+                        utils::Property::Utf8String("desktop: "),
+                    )?;
+                }
+            }
+
+            Command::ExitMode => {
+                if let Some(mode) = self.active_mode.take() {
+                    self.ctx
+                        .conn
+                        .delete_property(self.ctx.root, self.ctx.atom._DAILY2_MODE)?;
+                    self.release_keyboard()?;
+                    log::info!("exit mode {mode:?}");
+                }
+            }
+
+            Command::ToggleFloating => {
+                self.remember_placement(self.focus);
+                if let Some(window) = self.windows.get_mut(&self.focus) {
+                    window.floating ^= true;
+                    let saved = window
+                        .class
+                        .as_ref()
+                        .and_then(|class| self.placements.floating.get(class));
+                    if let (true, Some(&[x, y, w, h])) = (window.floating, saved) {
+                        window.geometry = Rect { x, y, w, h };
+                    }
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Environment variables telling spawned processes the current state of the WM.
+    /// An empty `DAILY_FOCUSED_WINDOW` means no window is focused.
+    fn spawn_env(&self) -> Vec<(&'static str, String)> {
+        let monitor = self.focused_monitor().unwrap_or(0);
+        let desktop = self.monitors.get(monitor).map_or(0, |mon| mon.desktop);
+        let (focused, title) = match self.windows.get(&self.focus) {
+            Some(win) => (format!("0x{:X}", win.id), win.title.clone()),
+            None => (String::new(), String::new()),
+        };
+        vec![
+            ("DAILY_DESKTOP", desktop.to_string()),
+            ("DAILY_MONITOR", monitor.to_string()),
+            ("DAILY_FOCUSED_WINDOW", focused),
+            ("DAILY_FOCUSED_TITLE", title),
+        ]
+    }
+
+    /// Replies to the requests sent to the IPC socket.
+    /// Commands are queued and the reply only tells whether they are valid.
+    fn handle_ipc_requests(
+        &mut self,
+        readable: &[bool],
+        cmdq: &mut VecDeque<Command>,
+    ) -> Result<()> {
+        let requests = match &mut self.ipc {
+            Some(server) => server.receive(readable),
+            None => return Ok(()),
+        };
+        for request in requests {
+            log::debug!("IPC request: {:?}", request.line);
+            let reply = match request.line.as_str() {
+                "get_windows" => self.windows_json(),
+                "get_desktops" => self.desktops_json(),
+                "get_monitors" => self.monitors_json(),
+                "get_focus" => self.focus_json(),
+                "get_metrics" => self.metrics.to_json(),
+                line => match line.parse::<Command>() {
+                    Ok(cmd) => {
+                        cmdq.push_back(cmd);
+                        Json::Object(vec![("success", true.into())])
+                    }
+                    Err(err) => Json::Object(vec![
+                        ("success", false.into()),
+                        ("error", err.to_string().into()),
+                    ]),
+                },
+            };
+            request.reply(&reply);
+        }
+        Ok(())
+    }
+
+    /// managed windows in the tiling order
+    fn windows_json(&self) -> Json {
+        let mut windows = Vec::new();
+        for win in self.window_order.iter().map(|id| &self.windows[id]) {
+            windows.push(Json::Object(vec![
+                ("id", win.id.into()),
+                ("class", win.class.clone().into()),
+                ("title", win.title.clone().into()),
+                ("pid", win.pid.into()),
+                ("desktop", win.desktop.into()),
+                ("monitor", self.desktops[win.desktop].monitor.into()),
+                ("focused", (win.id == self.focus).into()),
+                ("mapped", win.mapped.into()),
+                ("floating", win.floating.into()),
+                ("fullscreen", win.fullscreen.into()),
+                ("sticky", win.sticky.into()),
+                ("above", win.above.into()),
+                ("urgent", win.urgent.into()),
+                ("hidden", win.hidden.into()),
+                // relative to the monitor
+                ("geometry", rect_json(win.geometry)),
+            ]));
+        }
+        Json::Array(windows)
+    }
+
+    fn desktops_json(&self) -> Json {
+        let desktops = self
+            .desktops
+            .iter()
+            .enumerate()
+            .map(|(i, desktop)| {
+                let windows: Vec<xproto::Window> = self
+                    .window_order
+                    .iter()
+                    .filter(|id| self.windows[*id].desktop == i)
+                    .copied()
+                    .collect();
+                Json::Object(vec![
+                    ("index", i.into()),
+                    ("monitor", desktop.monitor.into()),
+                    ("layout", desktop.layout.name().into()),
+                    ("columns", desktop.columns.into()),
+                    ("transposed", desktop.transposed.into()),
+                    ("mirrored", desktop.mirrored.into()),
+                    (
+                        "focused_window",
+                        desktop.focus_history.last().copied().into(),
+                    ),
+                    ("windows", windows.into()),
+                    ("hidden_windows", desktop.hidden_stack.clone().into()),
+                ])
+            })
+            .collect();
+        Json::Array(desktops)
+    }
+
+    fn monitors_json(&self) -> Json {
+        let focused = self.focused_monitor();
+        let monitors = self
+            .monitors
+            .iter()
+            .enumerate()
+            .map(|(i, mon)| {
+                Json::Object(vec![
+                    ("index", i.into()),
+                    ("crtc", mon.crtc.into()),
+                    ("identity", mon.identity.clone().into()),
+                    ("desktop", mon.desktop.into()),
+                    ("focused", (focused == Some(i)).into()),
+                    ("scale", mon.scale.into()),
+                    ("geometry", rect_json(mon.geometry)),
+                ])
+            })
+            .collect();
+        Json::Array(monitors)
+    }
+
+    fn focus_json(&self) -> Json {
+        let monitor = self.focused_monitor();
+        let window = self.windows.contains_key(&self.focus).then_some(self.focus);
+        Json::Object(vec![
+            ("window", window.into()),
+            ("monitor", monitor.into()),
+            (
+                "desktop",
+                monitor.map(|mon| self.monitors[mon].desktop).into(),
+            ),
+        ])
+    }
+
+    /// Replies to the messages from i3 IPC clients.
+    fn handle_i3_messages(
+        &mut self,
+        readable: &[bool],
+        cmdq: &mut VecDeque<Command>,
+    ) -> Result<()> {
+        let messages = match &mut self.i3 {
+            Some(server) => server.receive(readable),
+            None => return Ok(()),
+        };
+        for message in messages {
+            log::debug!("i3 IPC message {}: {:?}", message.kind, message.payload);
+            let success = |success: bool| Json::Object(vec![("success", success.into())]);
+            let reply = match message.kind {
+                i3ipc::RUN_COMMAND => {
+                    let results = split_i3_commands(&message.payload)
+                        .into_iter()
+                        .map(|(criteria, cmd)| match parse_i3_command(&criteria, &cmd) {
+                            Ok(cmd) => {
+                                cmdq.push_back(cmd);
+                                success(true)
+                            }
+                            Err(err) => {
+                                Json::Object(vec![("success", false.into()), ("error", err.into())])
+                            }
+                        })
+                        .collect();
+                    Json::Array(results)
+                }
+                i3ipc::GET_WORKSPACES => self.i3_workspaces(),
+                i3ipc::SUBSCRIBE => match i3ipc::parse_string_array(&message.payload) {
+                    Some(events) => {
+                        // the changes before the first subscription are not told
+                        if !self
+                            .i3
+                            .as_ref()
+                            .is_some_and(|server| server.has_subscribers())
+                        {
+                            self.i3_snapshot = self.current_i3_snapshot();
+                        }
+                        if let Some(server) = &mut self.i3 {
+                            server.subscribe(message.client, events);
+                        }
+                        success(true)
+                    }
+                    None => success(false),
+                },
+                i3ipc::GET_OUTPUTS => self.i3_outputs()?,
+                i3ipc::GET_TREE => self.i3_tree()?,
+                i3ipc::GET_MARKS => Json::Array(Vec::new()),
+                i3ipc::GET_BAR_CONFIG if message.payload.is_empty() => Json::Array(Vec::new()),
+                i3ipc::GET_BAR_CONFIG => Json::Object(vec![
+                    ("id", message.payload.clone().into()),
+                    ("mode", "dock".into()),
+                    ("position", "top".into()),
+                    ("workspace_buttons", true.into()),
+                    ("binding_mode_indicator", true.into()),
+                    ("verbose", false.into()),
+                    ("colors", Json::Object(Vec::new())),
+                ]),
+                i3ipc::GET_VERSION => Json::Object(vec![
+                    ("major", 4.into()),
+                    ("minor", 22.into()),
+                    ("patch", 0.into()),
+                    ("human_readable", "4.22 (daily2)".into()),
+                    ("loaded_config_file_name", "".into()),
+                ]),
+                i3ipc::GET_BINDING_MODES => {
+                    let mut modes = vec!["default".to_owned()];
+                    modes.extend(self.modes.keys().cloned());
+                    modes.into()
+                }
+                i3ipc::GET_CONFIG => Json::Object(vec![("config", "".into())]),
+                i3ipc::SEND_TICK => success(true),
+                _ => Json::Object(vec![
+                    ("success", false.into()),
+                    ("error", "unsupported message type".into()),
+                ]),
+            };
+            if let Some(server) = &mut self.i3 {
+                server.reply(message.client, message.kind, &reply);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends events to the subscribing i3 IPC clients about what changed since the last call.
+    fn notify_i3_clients(&mut self) -> Result<()> {
+        // the snapshot is taken again when a client subscribes
+        if !self
+            .i3
+            .as_ref()
+            .is_some_and(|server| server.has_subscribers())
+        {
+            return Ok(());
+        }
+        let snapshot = self.current_i3_snapshot();
+        if snapshot == self.i3_snapshot {
+            return Ok(());
+        }
+        let old = std::mem::replace(&mut self.i3_snapshot, snapshot.clone());
+
+        let mut events = Vec::new();
+        if snapshot.monitors != old.monitors {
+            let event = Json::Object(vec![("change", "unspecified".into())]);
+            events.push((i3ipc::EVENT_OUTPUT, event));
+        }
+        if snapshot.focused_desktop != old.focused_desktop {
+            let event = Json::Object(vec![
+                ("change", "focus".into()),
+                (
+                    "current",
+                    snapshot
+                        .focused_desktop
+                        .map(|d| self.i3_workspace(d))
+                        .into(),
+                ),
+                (
+                    "old",
+                    old.focused_desktop.map(|d| self.i3_workspace(d)).into(),
+                ),
+            ]);
+            events.push((i3ipc::EVENT_WORKSPACE, event));
+        } else if snapshot.workspaces != old.workspaces {
+            // clients fetch the workspaces again on any workspace event
+            let event = Json::Object(vec![("change", "reload".into())]);
+            events.push((i3ipc::EVENT_WORKSPACE, event));
+        }
+        if snapshot.mode != old.mode {
+            let event = Json::Object(vec![
+                (
+                    "change",
+                    snapshot.mode.as_deref().unwrap_or("default").into(),
+                ),
+                ("pango_markup", false.into()),
+            ]);
+            events.push((i3ipc::EVENT_MODE, event));
+        }
+        if snapshot.focus != old.focus && self.windows.contains_key(&snapshot.focus) {
+            let event = Json::Object(vec![
+                ("change", "focus".into()),
+                ("container", self.i3_window(&self.windows[&snapshot.focus])?),
+            ]);
+            events.push((i3ipc::EVENT_WINDOW, event));
+        } else if snapshot.focus_title != old.focus_title && self.windows.contains_key(&self.focus)
+        {
+            let event = Json::Object(vec![
+                ("change", "title".into()),
+                ("container", self.i3_window(&self.windows[&snapshot.focus])?),
+            ]);
+            events.push((i3ipc::EVENT_WINDOW, event));
+        }
+
+        if let Some(server) = &mut self.i3 {
+            for (event, payload) in events {
+                server.broadcast(event, &payload);
+            }
+        }
+        Ok(())
+    }
+
+    /// the state which the i3 IPC clients are notified of
+    fn current_i3_snapshot(&self) -> I3Snapshot {
+        let focused_desktop = self.focused_monitor().map(|mon| self.monitors[mon].desktop);
+        I3Snapshot {
+            workspaces: self.i3_workspaces().to_string(),
+            focused_desktop,
+            monitors: self
+                .monitors
+                .iter()
+                .map(|mon| (mon.output.clone(), mon.geometry))
+                .collect(),
+            mode: self.active_mode.clone(),
+            focus: self.focus,
+            focus_title: self
+                .windows
+                .get(&self.focus)
+                .map_or(String::new(), |win| win.title.clone()),
+        }
+    }
+
+    /// Desktops are shown as workspaces named by their 1-origin numbers.
+    /// Like i3, only the visible or non-empty ones exist.
+    fn i3_workspaces(&self) -> Json {
+        let workspaces = (0..self.desktops.len())
+            .filter(|&d| {
+                self.desktops[d].monitor.is_some() || self.windows.values().any(|w| w.desktop == d)
+            })
+            .map(|d| self.i3_workspace(d))
+            .collect();
+        Json::Array(workspaces)
+    }
+
+    fn i3_workspace(&self, desktop: usize) -> Json {
+        let focused_monitor = self.focused_monitor();
+        let monitor = self.desktops[desktop].monitor;
+        // hidden desktops belong to the focused monitor
+        let output = monitor
+            .or(focused_monitor)
+            .and_then(|mon| self.monitors[mon].output.clone());
+        let rect = monitor.map_or(Rect::default(), |mon| self.monitors[mon].geometry);
+        Json::Object(vec![
+            ("id", (desktop + 1).into()),
+            ("num", (desktop + 1).into()),
+            ("name", (desktop + 1).to_string().into()),
+            ("visible", monitor.is_some().into()),
+            (
+                "focused",
+                (monitor.is_some() && monitor == focused_monitor).into(),
+            ),
+            (
+                "urgent",
+                self.windows
+                    .values()
+                    .any(|w| w.desktop == desktop && w.urgent)
+                    .into(),
+            ),
+            ("rect", rect_json(rect)),
+            ("output", output.into()),
+        ])
+    }
+
+    fn i3_outputs(&self) -> Result<Json> {
+        let primary = self
+            .ctx
+            .conn
+            .randr_get_output_primary(self.ctx.root)?
+            .reply()?;
+        let primary = if primary.output != x11rb::NONE {
+            Some(output::describe_output(&self.ctx, primary.output)?.name)
+        } else {
+            None
+        };
+        let outputs = self
+            .monitors
+            .iter()
+            .map(|mon| {
+                Json::Object(vec![
+                    ("name", mon.output.clone().into()),
+                    ("active", true.into()),
+                    (
+                        "primary",
+                        (mon.output.is_some() && mon.output == primary).into(),
+                    ),
+                    ("current_workspace", (mon.desktop + 1).to_string().into()),
+                    ("rect", rect_json(mon.geometry)),
+                ])
+            })
+            .collect();
+        Ok(Json::Array(outputs))
+    }
+
+    /// the tree of outputs, workspaces and windows
+    fn i3_tree(&self) -> Result<Json> {
+        let mut outputs = Vec::new();
+        for (i, mon) in self.monitors.iter().enumerate() {
+            let mut workspaces = Vec::new();
+            for d in (0..self.desktops.len()).filter(|&d| {
+                self.desktops[d].monitor == Some(i)
+                    || (Some(i) == self.focused_monitor()
+                        && self.desktops[d].monitor.is_none()
+                        && self.windows.values().any(|w| w.desktop == d))
+            }) {
+                let (mut tiled, mut floating) = (Vec::new(), Vec::new());
+                for win in self.window_order.iter().map(|id| &self.windows[id]) {
+                    if win.desktop != d {
+                        continue;
+                    }
+                    let node = self.i3_window(win)?;
+                    if win.floating {
+                        floating.push(node);
+                    } else {
+                        tiled.push(node);
+                    }
+                }
+                let mut workspace = match self.i3_workspace(d) {
+                    Json::Object(members) => members,
+                    _ => unreachable!(),
+                };
+                workspace.extend([
+                    ("type", "workspace".into()),
+                    ("layout", self.desktops[d].layout.name().into()),
+                    ("nodes", Json::Array(tiled)),
+                    ("floating_nodes", Json::Array(floating)),
+                ]);
+                workspaces.push(Json::Object(workspace));
+            }
+            outputs.push(Json::Object(vec![
+                ("id", (0x1000 + i).into()),
+                ("type", "output".into()),
+                ("name", mon.output.clone().into()),
+                ("rect", rect_json(mon.geometry)),
+                ("nodes", Json::Array(workspaces)),
+            ]));
+        }
+        Ok(Json::Object(vec![
+            ("id", 0x100.into()),
+            ("type", "root".into()),
+            ("name", "root".into()),
+            ("nodes", Json::Array(outputs)),
+        ]))
+    }
+
+    fn i3_window(&self, win: &Window) -> Result<Json> {
+        let title = win.title.clone();
+        let rect = match self.desktops[win.desktop].monitor {
+            Some(mon) => {
+                let origin = self.monitors[mon].geometry;
+                Rect {
+                    x: origin.x + win.geometry.x,
+                    y: origin.y + win.geometry.y,
+                    ..win.geometry
+                }
+            }
+            None => win.geometry,
+        };
+        Ok(Json::Object(vec![
+            ("id", win.id.into()),
+            (
+                "type",
+                if win.floating { "floating_con" } else { "con" }.into(),
+            ),
+            ("window", win.id.into()),
+            ("name", title.clone().into()),
+            ("focused", (win.id == self.focus).into()),
+            ("urgent", win.urgent.into()),
+            ("fullscreen_mode", (win.fullscreen as u32).into()),
+            ("rect", rect_json(rect)),
+            (
+                "window_properties",
+                Json::Object(vec![
+                    ("class", win.class.clone().into()),
+                    ("title", title.into()),
+                ]),
+            ),
+        ]))
+    }
+
+    /// Leaves the windows as they would be shown without a WM before exiting: all of them
+    /// mapped at their geometries on the monitors without borders, the floating ones above the
+    /// tiled ones and the focused one on the top, and the focus following the pointer.
+    fn restore_clients(&mut self) -> Result<()> {
+        if self.overview.is_some() {
+            self.close_overview(None)?;
+        }
+        let fallback = self
+            .monitors
+            .first()
+            .map_or(Rect::default(), |mon| mon.geometry);
+        let mut windows: Vec<&Window> = self
+            .windows
+            .values()
+            .filter(|win| win.mapped || win.hidden)
+            .collect();
+        windows.sort_by_key(|win| {
+            (
+                win.id == self.focus,
+                win.floating || win.fullscreen,
+                win.stacking_order,
+            )
+        });
+        for win in windows {
+            // windows of hidden desktops are placed on the first monitor
+            let shown_on = self.desktops[win.desktop].monitor.filter(|_| win.mapped);
+            let mon_geo = shown_on
+                .or(self.desktops[win.desktop].monitor)
+                .map_or(fallback, |mon| self.monitors[mon].geometry);
+            let geo = if win.fullscreen {
+                mon_geo
+            } else {
+                Rect {
+                    x: mon_geo.x + win.geometry.x,
+                    y: mon_geo.y + win.geometry.y,
+                    ..win.geometry
+                }
+            };
+            let aux = xproto::ConfigureWindowAux::new()
+                .x(geo.x)
+                .y(geo.y)
+                .width(geo.w.max(1) as u32)
+                .height(geo.h.max(1) as u32)
+                .border_width(0)
+                .stack_mode(xproto::StackMode::ABOVE);
+            self.ctx.conn.configure_window(win.id, &aux)?;
+            if shown_on.is_none() {
+                self.ctx.conn.map_window(win.id)?;
+            }
+        }
+        self.ctx.conn.set_input_focus(
+            xproto::InputFocus::POINTER_ROOT,
+            xproto::InputFocus::POINTER_ROOT,
+            x11rb::CURRENT_TIME,
+        )?;
+        self.ctx.conn.flush()?;
+        log::info!("windows restored");
+        Ok(())
+    }
+
+    fn current_state(&self) -> state::State {
+        let monitors = self
+            .monitors
+            .iter()
+            .map(|mon| state::MonitorState {
+                crtc: mon.crtc,
+                identity: mon.identity.clone(),
+                desktop: mon.desktop,
+            })
+            .collect();
+        let desktops = self
+            .desktops
+            .iter()
+            .enumerate()
+            .map(|(index, desktop)| state::DesktopState {
+                index,
+                layout: desktop.layout,
+                columns: desktop.columns,
+                tree: desktop.tree.clone(),
+                transposed: desktop.transposed,
+                mirrored: desktop.mirrored,
+                hidden: desktop.hidden_stack.clone(),
+            })
+            .collect();
+        let windows = self
+            .window_order
+            .iter()
+            .map(|id| &self.windows[id])
+            .filter(|win| win.mapped || win.hidden)
+            .map(|win| state::WindowState {
+                id: win.id,
+                desktop: win.desktop,
+                floating: win.floating,
+                fullscreen: win.fullscreen,
+                geometry: [
+                    win.geometry.x,
+                    win.geometry.y,
+                    win.geometry.w,
+                    win.geometry.h,
+                ],
+                weight: win.weight,
+                hidden: win.hidden,
+            })
+            .collect();
+        state::State {
+            monitors,
+            desktops,
+            windows,
+            focus: self.windows.contains_key(&self.focus).then_some(self.focus),
+        }
+    }
+
+    /// Passes the state to the process executed on restart through an inherited descriptor,
+    /// falling back to the root window property.
+    fn hand_over_state(&self) -> Result<()> {
+        match state::hand_over(&self.current_state().serialize()) {
+            Ok(()) => {
+                log::info!("state handed over");
+                Ok(())
+            }
+            Err(err) => {
+                log::error!("failed to hand over the state: {err}");
+                self.save_state()
+            }
+        }
+    }
+
+    /// Saves the layouts and window assignments to a root window property
+    /// so that the next process can restore them on restart.
+    fn save_state(&self) -> Result<()> {
+        utils::replace_property(
+            &self.ctx,
+            self.ctx.root,
+            self.ctx.atom._DAILY2_STATE,
+            utils::Property::Utf8String(&self.current_state().serialize()),
+        )?;
+        // the next process reads it after this one exits without going back to the event loop
+        self.ctx.conn.flush()?;
+        log::info!("state saved");
+        Ok(())
+    }
+
+    /// Saves the state for the next session, as asked by the session manager.
+    fn save_session(&self) -> bool {
+        match state::save_session(&self.current_state().serialize()) {
+            Ok(()) => {
+                log::info!("session state saved");
+                true
+            }
+            Err(err) => {
+                log::error!("failed to save the session state: {err}");
+                false
+            }
+        }
+    }
+
+    /// Restores the state handed over by `hand_over_state` or saved by `save_state` (or by
+    /// `save_session` in the last session), and returns the window focused before restart.
+    fn restore_state(&mut self) -> Result<Option<xproto::Window>> {
+        let saved = self
+            .ctx
+            .conn
+            .get_property(
+                true, // delete
+                self.ctx.root,
+                self.ctx.atom._DAILY2_STATE,
+                self.ctx.atom.UTF8_STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        // the property is deleted anyway, not to be restored by a later process
+        let text = match state::take_over() {
+            Some(text) => Some(text),
+            None if saved.value.is_empty() => None,
+            None => Some(String::from_utf8_lossy(&saved.value).into_owned()),
+        };
+        let state = match text {
+            Some(text) => state::State::deserialize(&text),
+            // started by the session manager, which may have saved the state of the last session
+            None => match self.session.as_ref().and_then(|_| state::load_session()) {
+                Some(state) => state,
+                None => return Ok(None),
+            },
+        };
+        log::info!("restore state: {state:?}");
+
+        for saved in state.desktops {
+            if let Some(desktop) = self.desktops.get_mut(saved.index) {
+                desktop.layout = saved.layout;
+                desktop.columns = saved.columns;
+                desktop.tree = saved.tree;
+                desktop.transposed = saved.transposed;
+                desktop.mirrored = saved.mirrored;
+                desktop.hidden_stack = saved.hidden;
+            }
+        }
+
+        for saved in state.monitors {
+            // the identity is preferred since CRTCs may be renumbered
+            let found = match &saved.identity {
+                Some(identity) => self
+                    .monitors
+                    .iter()
+                    .position(|mon| mon.identity.as_ref() == Some(identity)),
+                None => self.monitors.iter().position(|mon| mon.crtc == saved.crtc),
+            };
+            let monitor = match found {
+                Some(monitor) if saved.desktop < self.desktops.len() => monitor,
+                _ => continue,
+            };
+
+            // swap the desktops if the desktop is already displayed on another monitor
+            let old_desktop = self.monitors[monitor].desktop;
+            let other = self.desktops[saved.desktop].monitor;
+            if let Some(other) = other {
+                self.monitors[other].desktop = old_desktop;
+            }
+            self.desktops[old_desktop].monitor = other;
+            self.monitors[monitor].desktop = saved.desktop;
+            self.desktops[saved.desktop].monitor = Some(monitor);
+        }
+
+        for saved in state.windows {
+            if saved.desktop >= self.desktops.len() || self.windows.contains_key(&saved.id) {
+                continue;
+            }
+
+            // the window may have been destroyed during restart
+            let ctx = self.ctx.clone();
+            let attrs = ctx.conn.get_window_attributes(saved.id)?;
+            let props = utils::ClientPropertiesCookie::new(&ctx, saved.id, self.res, self.shape)?;
+            let attrs = match attrs.reply() {
+                Ok(attrs) => attrs,
+                Err(_) => continue,
+            };
+            let props = props.reply()?;
+
+            // windows hidden by groups or swallowing are shown, since those aren't carried over
+            let hidden = saved.hidden
+                && self
+                    .desktops
+                    .iter()
+                    .any(|desktop| desktop.hidden_stack.contains(&saved.id));
+            let [x, y, w, h] = saved.geometry;
+            let mut window = Window {
+                id: saved.id,
+                desktop: saved.desktop,
+                mapped: !hidden,
+                floating: saved.floating,
+                fullscreen: saved.fullscreen,
+                geometry: Rect { x, y, w, h },
+                stacking_order: self.stacking_counter,
+                ignore_unmap_notify: false,
+                swallowed: None,
+                sticky: false,
+                pip: None,
+                above: false,
+                urgent: false,
+                skip_taskbar: false,
+                skip_pager: false,
+                hidden,
+                transient_for: None,
+                pid: self.local_pid(saved.id, &props),
+                input: props.input,
+                take_focus: props.protocols.contains(&self.ctx.atom.WM_TAKE_FOCUS),
+                delete_window: props.protocols.contains(&self.ctx.atom.WM_DELETE_WINDOW),
+                shaped: props.shaped,
+                class: props.class,
+                title: props.title,
+                borderless: false,
+                weight: saved.weight,
+                restore_geometry: None,
+            };
+            self.stacking_counter += 1;
+
+            let visible = self.desktops[saved.desktop].monitor.is_some() && !hidden;
+            if visible {
+                self.ctx.conn.map_window(window.id)?;
+            } else if attrs.map_state != xproto::MapState::UNMAPPED {
+                window.ignore_unmap_notify = true;
+                self.ctx.conn.unmap_window(window.id)?;
+            }
+
+            log::debug!(
+                "window 0x{:X} restored on desktop {}",
+                window.id,
+                window.desktop
+            );
+            self.select_property_change(window.id)?;
+            self.grab_focus_click(window.id)?;
+            self.window_order.push(window.id);
+            self.windows.insert(window.id, window);
+        }
+
+        // the windows destroyed during restart are left out of the hidden stacks
+        let windows = &self.windows;
+        for desktop in &mut self.desktops {
+            desktop
+                .hidden_stack
+                .retain(|id| windows.get(id).is_some_and(|win| win.hidden));
+        }
+
+        for monitor in 0..self.monitors.len() {
+            self.update_layout(monitor)?;
+        }
+
+        let focus = state.focus.filter(|id| {
+            self.windows
+                .get(id)
+                .is_some_and(|win| win.mapped && self.desktops[win.desktop].monitor.is_some())
+        });
+        Ok(focus)
+    }
+
+    /// Returns the window followed by its transient windows (recursively).
+    /// Moves the window to the desktop, together with its transient windows.
+    fn move_window(&mut self, window: xproto::Window, new_desktop: usize) -> Result<()> {
+        let Some(win) = self.windows.get(&window) else {
+            return Ok(());
+        };
+        let old_desktop = win.desktop;
+        let old_monitor = self.desktops[old_desktop].monitor;
+        let new_monitor = self.desktops[new_desktop].monitor;
+
+        // transient windows move together with their parent
+        for id in self.transients_of(window) {
+            let win = self.windows.get_mut(&id).unwrap();
+            win.desktop = new_desktop;
+            if win.mapped && new_monitor.is_none() && old_monitor.is_some() {
+                win.ignore_unmap_notify = true;
+                self.ctx.conn.unmap_window(id)?;
+            } else if win.mapped && new_monitor.is_some() && old_monitor.is_none() {
+                // e.g. dragged from a hidden desktop in the pager
+                self.ctx.conn.map_window(id)?;
+            }
+        }
+
+        if new_monitor.is_none() && window == self.focus {
+            self.focus_any(old_desktop)?;
+        }
+
+        if let Some(mon) = old_monitor {
+            self.update_layout(mon)?;
+        }
+        if let Some(mon) = new_monitor.filter(|mon| Some(*mon) != old_monitor) {
+            self.update_layout(mon)?;
+        }
+        self.remember_placement(window);
+        Ok(())
+    }
+
+    fn transients_of(&self, window: xproto::Window) -> Vec<xproto::Window> {
+        let mut group = vec![window];
+        let mut i = 0;
+        while i < group.len() {
+            let parent = group[i];
+            for id in &self.window_order {
+                let win = &self.windows[id];
+                if win.transient_for == Some(parent) && !group.contains(&win.id) {
+                    group.push(win.id);
+                }
+            }
+            i += 1;
+        }
+        group
+    }
+
+    /// Moves sticky windows on the desktop `from` to the desktop `to` for each pair,
+    /// so that they stay on the same monitor.
+    fn carry_sticky_windows(&mut self, moves: &[(usize, usize)]) {
+        for win in self.windows.values_mut().filter(|win| win.sticky) {
+            if let Some(&(_, to)) = moves.iter().find(|(from, _)| *from == win.desktop) {
+                win.desktop = to;
+            }
+        }
+    }
+
+    /// Updates _NET_WM_STATE of the window to reflect its current state.
+    fn update_net_wm_state(&self, window: xproto::Window) -> Result<()> {
+        let win = match self.windows.get(&window) {
+            Some(win) => win,
+            None => return Ok(()),
+        };
+
+        let mut state = Vec::new();
+        if win.fullscreen {
+            state.push(self.ctx.atom._NET_WM_STATE_FULLSCREEN);
+        }
+        if win.above {
+            state.push(self.ctx.atom._NET_WM_STATE_ABOVE);
+        }
+        if win.urgent {
+            state.push(self.ctx.atom._NET_WM_STATE_DEMANDS_ATTENTION);
+        }
+        if win.skip_taskbar {
+            state.push(self.ctx.atom._NET_WM_STATE_SKIP_TASKBAR);
+        }
+        if win.skip_pager {
+            state.push(self.ctx.atom._NET_WM_STATE_SKIP_PAGER);
+        }
+        if win.hidden {
+            state.push(self.ctx.atom._NET_WM_STATE_HIDDEN);
+        }
+        utils::replace_property(
+            &self.ctx,
+            window,
+            self.ctx.atom._NET_WM_STATE,
+            utils::Property::AtomList(&state),
+        )
+    }
+
+    fn focused_monitor(&self) -> Option<usize> {
+        if let Some(window) = self.windows.get(&self.focus) {
+            self.desktops[window.desktop].monitor
+        } else {
+            self.monitors
+                .iter()
+                .position(|mon| mon.dummy_window == self.focus)
+        }
+    }
+
+    /// Configures the outputs with `config::OUTPUT_LAYOUT`.
+    /// Failures are only logged since the monitors keep working with the old configuration.
+    fn apply_output_layout(&self) {
+        if config::OUTPUT_LAYOUT.is_empty() {
+            return;
+        }
+        if let Err(err) = output::apply_layout(&self.ctx, config::OUTPUT_LAYOUT) {
+            log::error!("Failed to configure outputs: {err:?}");
+        }
+    }
+
+    /// Chooses a hidden desktop to show on the newly enabled monitor.
+    /// The desktop configured for its output in `config::MONITOR_DESKTOPS` is preferred,
+    /// then the desktop last shown on the same monitor, then `fallback`, then the first hidden one.
+    fn desktop_for_monitor(&self, info: &MonitorInfo, fallback: Option<usize>) -> usize {
+        let hidden = |desktop: &usize| {
+            self.desktops
+                .get(*desktop)
+                .is_some_and(|d| d.monitor.is_none())
+        };
+
+        if let Some(output) = &info.output {
+            let configured = config::MONITOR_DESKTOPS
+                .iter()
+                .find(|(name, _)| *name == output.name || *name == output.identity())
+                .map(|(_, desktop)| *desktop);
+            let last = self.last_desktops.get(output.identity()).copied();
+            if let Some(desktop) = configured.filter(hidden).or(last.filter(hidden)) {
+                log::info!("output {} shows desktop {desktop}", output.name);
+                return desktop;
+            }
+        }
+
+        fallback
+            .filter(hidden)
+            .or_else(|| self.desktops.iter().position(|d| d.monitor.is_none()))
+            .expect("too many monitors")
+    }
+
+    /// Lists the active monitors. RandR 1.5 monitors are used if available
+    /// so that mirrored outputs are one monitor and tiled outputs are combined.
+    fn query_monitors(&self) -> Result<Vec<MonitorInfo>> {
+        let mut monitors = Vec::new();
+        if self.randr_monitors {
+            let reply = self
+                .ctx
+                .conn
+                .randr_get_monitors(self.ctx.root, true)?
+                .reply()?;
+            for mon in reply.monitors {
+                log::debug!("Monitor {}: {mon:?}", mon.name);
+                let output = match mon.outputs.first() {
+                    Some(&output) => Some(output::describe_output(&self.ctx, output)?),
+                    None => None,
+                };
+                monitors.push(MonitorInfo {
+                    id: mon.name,
+                    crtc: output.as_ref().map_or(x11rb::NONE, |o| o.crtc),
+                    output,
+                    geometry: Rect {
+                        x: mon.x as i32,
+                        y: mon.y as i32,
+                        w: mon.width as i32,
+                        h: mon.height as i32,
+                    },
+                    scale: dpi_scale(mon.width as i32, mon.width_in_millimeters),
+                });
+            }
+        } else {
+            let crtcs = self
+                .ctx
+                .conn
+                .randr_get_screen_resources_current(self.ctx.root)?
+                .reply()?
+                .crtcs;
+            for crtc in crtcs {
+                let crtc_info = self
+                    .ctx
+                    .conn
+                    .randr_get_crtc_info(crtc, x11rb::CURRENT_TIME)?
+                    .reply()?;
+                log::debug!("Crtc {crtc}: {crtc_info:?}");
+
+                if crtc_info.mode == x11rb::NONE {
+                    // ignore disabled CRTCs
+                    continue;
+                }
+
+                let geometry = Rect {
+                    x: crtc_info.x as i32,
+                    y: crtc_info.y as i32,
+                    w: crtc_info.width as i32,
+                    h: crtc_info.height as i32,
+                };
+                let output = match crtc_info.outputs.first() {
+                    Some(&output) => Some(output::describe_output(&self.ctx, output)?),
+                    None => None,
+                };
+                monitors.push(MonitorInfo {
+                    id: crtc,
+                    crtc,
+                    output,
+                    geometry,
+                    scale: self.monitor_scale(crtc, geometry.w)?,
+                });
+            }
+        }
+        Ok(monitors)
+    }
+
+    /// Adds, updates and removes monitors to match the current configuration.
+    fn refresh_monitors(&mut self) -> Result<()> {
+        let current = self.query_monitors()?;
+
+        // monitor was disabled
+        for monitor in (0..self.monitors.len()).rev() {
+            if !current
+                .iter()
+                .any(|info| info.id == self.monitors[monitor].id)
+            {
+                self.remove_monitor(monitor)?;
+            }
+        }
+
+        for info in current {
+            if let Some(monitor) = self.monitors.iter().position(|mon| mon.id == info.id) {
+                // monitor info was changed
+                let mon = &mut self.monitors[monitor];
+                mon.crtc = info.crtc;
+                mon.identity = info.output.as_ref().map(|o| o.identity().to_owned());
+                mon.output = info.output.map(|o| o.name);
+                if mon.geometry != info.geometry || mon.scale != info.scale {
+                    mon.geometry = info.geometry;
+                    mon.scale = info.scale;
+                    self.update_layout(monitor)?;
+                }
+            } else {
+                // monitor was enabled
+                let desktop = self.desktop_for_monitor(&info, None);
+                self.add_monitor(info, desktop)?;
+            }
+        }
+        self.update_workarea()
+    }
+
+    /// Returns the path of the wallpaper of the desktop, if it has been loaded.
+    fn desktop_wallpaper(&self, desktop: usize) -> Option<&'static str> {
+        config::DESKTOP_WALLPAPERS
+            .iter()
+            .find(|&&(d, _)| d == desktop)
+            .map(|&(_, path)| path)
+            .or(config::WALLPAPER)
+            .filter(|path| self.wallpapers.contains_key(path))
+    }
+
+    /// Draws the wallpapers on the monitors again if they or the desktops shown on them have
+    /// changed.
+    fn update_wallpaper(&mut self) -> Result<()> {
+        if self.wallpapers.is_empty() {
+            return Ok(());
+        }
+        let shown: Vec<(Rect, Option<&'static str>)> = self
+            .monitors
+            .iter()
+            .map(|mon| (mon.geometry, self.desktop_wallpaper(mon.desktop)))
+            .collect();
+        if shown == self.wallpaper_monitors {
+            return Ok(());
+        }
+        let size = |geometry: &Rect| (geometry.w.max(1) as u16, geometry.h.max(1) as u16);
+
+        // pixmaps in sizes no monitor has anymore are freed
+        let sizes: Vec<(u16, u16)> = shown.iter().map(|(geometry, _)| size(geometry)).collect();
+        let unused: Vec<_> = self
+            .wallpaper_pixmaps
+            .keys()
+            .filter(|&&(_, w, h)| !sizes.contains(&(w, h)))
+            .copied()
+            .collect();
+        for key in unused {
+            let pixmap = self.wallpaper_pixmaps.remove(&key).unwrap();
+            self.ctx.conn.free_pixmap(pixmap)?;
+        }
+
+        let mut monitors = Vec::with_capacity(shown.len());
+        for (geometry, path) in &shown {
+            let (w, h) = size(geometry);
+            let pixmap = match path {
+                Some(path) => match self.wallpaper_pixmaps.get(&(path, w, h)) {
+                    Some(&pixmap) => Some(pixmap),
+                    None => {
+                        let pixmap = wallpaper::render_pixmap(
+                            &self.ctx,
+                            &self.wallpapers[path],
+                            w,
+                            h,
+                            config::WALLPAPER_SCALING,
+                            self.theme.wallpaper,
+                        )?;
+                        if let Some(pixmap) = pixmap {
+                            self.wallpaper_pixmaps.insert((path, w, h), pixmap);
+                        }
+                        pixmap
+                    }
+                },
+                None => None,
+            };
+            monitors.push((*geometry, pixmap));
+        }
+        self.root_pixmap = wallpaper::set_root_pixmap(
+            &self.ctx,
+            self.root_pixmap,
+            &monitors,
+            self.theme.wallpaper,
+        )?;
+        self.wallpaper_monitors = shown;
+        Ok(())
+    }
+
+    fn add_monitor(&mut self, info: MonitorInfo, desktop: usize) -> Result<usize> {
+        let geometry = info.geometry;
+        let i = self.monitors.len();
+        if let Some(output) = &info.output {
+            log::info!(
+                "monitor {i}: output {}, EDID {:?}",
+                output.name,
+                output.edid
+            );
+        }
+        let dummy_window = self.ctx.conn.generate_id()?;
+        log::debug!("dummy window for monitor {i}: {dummy_window}");
+
+        let depth = x11rb::COPY_DEPTH_FROM_PARENT;
+        let class = xproto::WindowClass::INPUT_ONLY;
+        let visual = x11rb::COPY_FROM_PARENT;
+        let aux = xproto::CreateWindowAux::new();
+        self.ctx.conn.create_window(
+            depth,
+            dummy_window,
+            self.ctx.root,
+            geometry.x as i16, // x
+            geometry.y as i16, // y
+            1,                 // width
+            1,                 // height
+            0,                 // border-width
+            class,
+            visual,
+            &aux,
+        )?;
+        self.ctx.conn.map_window(dummy_window)?;
+
+        let tab_bar = self.ctx.conn.generate_id()?;
+        let aux = xproto::CreateWindowAux::new()
+            .background_pixel(self.theme.tab_inactive.0)
+            .event_mask(xproto::EventMask::EXPOSURE | xproto::EventMask::BUTTON_PRESS);
+        self.ctx.conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            tab_bar,
+            self.ctx.root,
+            geometry.x as i16,                    // x
+            geometry.y as i16,                    // y
+            geometry.w.max(1) as u16,             // width
+            config::TAB_BAR_HEIGHT.max(1) as u16, // height
+            0,                                    // border-width
+            xproto::WindowClass::INPUT_OUTPUT,
+            x11rb::COPY_FROM_PARENT,
+            &aux,
+        )?;
+
+        self.monitors.push(Monitor {
+            id: info.id,
+            crtc: info.crtc,
+            identity: info.output.as_ref().map(|o| o.identity().to_owned()),
+            output: info.output.map(|o| o.name),
+            desktop,
+            dummy_window,
+            tab_bar,
+            geometry,
+            workarea: Rect {
+                x: 0,
+                y: 0,
+                ..geometry
+            },
+            scale: info.scale,
+            stacking: Vec::new(),
+        });
+        self.show_desktop(desktop, i)?;
+        self.update_workarea()?;
+        Ok(i)
+    }
+
+    /// Computes the scale of a monitor from the DPI of its output.
+    fn monitor_scale(&self, crtc: randr::Crtc, width: i32) -> Result<f64> {
+        if !config::DPI_SCALING {
+            return Ok(1.0);
+        }
+        let info = self
+            .ctx
+            .conn
+            .randr_get_crtc_info(crtc, x11rb::CURRENT_TIME)?
+            .reply()?;
+        let output = match info.outputs.first() {
+            Some(output) => *output,
+            None => return Ok(1.0),
+        };
+        let output_info = self
+            .ctx
+            .conn
+            .randr_get_output_info(output, x11rb::CURRENT_TIME)?
+            .reply()?;
+
+        // the physical width is of the unrotated output
+        let sideways = u16::from(info.rotation)
+            & u16::from(randr::Rotation::ROTATE90 | randr::Rotation::ROTATE270)
+            != 0;
+        let mm = if sideways {
+            output_info.mm_height
+        } else {
+            output_info.mm_width
+        };
+        Ok(dpi_scale(width, mm))
+    }
+
+    fn remove_monitor(&mut self, monitor: usize) -> Result<()> {
+        let desktop = self.monitors[monitor].desktop;
+        if let Some(identity) = self.monitors[monitor].identity.clone() {
+            self.last_desktops.insert(identity, desktop);
+        }
+        self.hide_desktop(desktop)?;
+
+        let dummy_window = self.monitors[monitor].dummy_window;
+        self.ctx.conn.destroy_window(dummy_window)?;
+        self.ctx
+            .conn
+            .destroy_window(self.monitors[monitor].tab_bar)?;
+
+        self.monitors.swap_remove(monitor);
+        if monitor < self.monitors.len() {
+            let desktop = self.monitors[monitor].desktop;
+            self.desktops[desktop].monitor = Some(monitor);
+        }
+        Ok(())
+    }
+
+    /// Manages the viewable windows which are not managed yet, e.g. at startup.
+    fn adopt_windows(&mut self) -> Result<()> {
+        let setup = self.ctx.conn.setup();
+        let (id_base, id_mask) = (setup.resource_id_base, setup.resource_id_mask);
+        let tree = self.ctx.conn.query_tree(self.ctx.root)?.reply()?;
+        // the children are in the stacking order, so parents usually come before their transients
+        for child in tree.children {
+            let own = child & !id_mask == id_base;
+            if own || self.windows.contains_key(&child) || self.docks.contains_key(&child) {
+                continue;
+            }
+            let viewable = match self.ctx.conn.get_window_attributes(child)?.reply() {
+                Ok(attrs) => attrs.map_state == xproto::MapState::VIEWABLE,
+                Err(_) => false,
+            };
+            if viewable {
+                log::info!("adopt window 0x{child:X}");
+                self.manage_window(child)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts managing the window, unless it is an unmanageable one, a dock or a notification.
+    fn manage_window(&mut self, id: xproto::Window) -> Result<()> {
+        // all the requests are sent before waiting for the replies
+        let ctx = self.ctx.clone();
+        let attrs = ctx.conn.get_window_attributes(id)?;
+        let props = utils::ClientPropertiesCookie::new(&ctx, id, self.res, self.shape)?;
+        if !is_manageable(id, attrs.reply())? {
+            log::debug!("window 0x{:X} is mapped without being managed", id);
+            self.ctx.conn.map_window(id)?;
+            return Ok(());
+        }
+        let props = props.reply()?;
+
+        // a dock is _NET_WM_WINDOW_TYPE_DOCK or reserves space with struts
+        let dock = props.window_type == Some(self.ctx.atom._NET_WM_WINDOW_TYPE_DOCK)
+            || props.strut.is_some();
+        if self.docks.contains_key(&id) || dock {
+            self.add_dock(id)?;
+        } else if props.window_type == Some(self.ctx.atom._NET_WM_WINDOW_TYPE_NOTIFICATION) {
+            if !self.notifications.contains(&id) {
+                self.notifications.push(id);
+            }
+            self.ctx.conn.map_window(id)?;
+            self.arrange_notifications()?;
+        } else {
+            let geo = &props.geometry;
+            let stacking_order = self.stacking_counter;
+            self.stacking_counter += 1;
+
+            let monitor = self.focused_monitor().unwrap_or(0);
+            let mon_geo = self.monitors[monitor].geometry;
+            let desktop = self.monitors[monitor].desktop;
+
+            let mut window = Window {
+                id,
+                desktop,
+                mapped: true,
+                floating: false,
+                fullscreen: false,
+                geometry: Rect {
+                    x: (geo.x as i32) - mon_geo.x,
+                    y: (geo.y as i32) - mon_geo.y,
+                    w: geo.width as i32,
+                    h: geo.height as i32,
+                },
+                stacking_order,
+                ignore_unmap_notify: false,
+                swallowed: None,
+                sticky: false,
+                pip: None,
+                above: false,
+                urgent: false,
+                skip_taskbar: false,
+                skip_pager: false,
+                hidden: false,
+                transient_for: None,
+                class: props.class.clone(),
+                title: props.title.clone(),
+                pid: self.local_pid(id, &props),
+                input: props.input,
+                take_focus: props.protocols.contains(&self.ctx.atom.WM_TAKE_FOCUS),
+                delete_window: props.protocols.contains(&self.ctx.atom.WM_DELETE_WINDOW),
+                shaped: props.shaped,
+                borderless: false,
+                weight: 1.0,
+                restore_geometry: None,
+            };
+
+            // place this window at the center of the monitor if it is a dialog
+            if props.window_type == Some(self.ctx.atom._NET_WM_WINDOW_TYPE_DIALOG) {
+                window.floating = true;
+
+                let (center_x, center_y) = (mon_geo.w / 2, mon_geo.h / 2);
+                window.geometry.x = center_x - window.geometry.w / 2;
+                window.geometry.y = center_y - window.geometry.h / 2;
+            }
+
+            // place this window at the center of its parent if it is a transient window
+            window.transient_for = props
+                .transient_for
+                .filter(|parent| self.windows.contains_key(parent));
+            if let Some(parent) = window.transient_for.map(|p| &self.windows[&p]) {
+                window.floating = true;
+                window.desktop = parent.desktop;
+
+                let pg = parent.geometry;
+                window.geometry.x = pg.x + (pg.w - window.geometry.w) / 2;
+                window.geometry.y = pg.y + (pg.h - window.geometry.h) / 2;
+            }
+
+            // place this floating window where the last window of the same application was
+            let saved = window
+                .class
+                .as_ref()
+                .and_then(|class| self.placements.floating.get(class))
+                .filter(|_| window.floating);
+            if let (Some(&[x, y, w, h]), None) = (saved, window.transient_for) {
+                let w = w.min(mon_geo.w);
+                let h = h.min(mon_geo.h);
+                window.geometry = Rect {
+                    x: x.clamp(0, mon_geo.w - w),
+                    y: y.clamp(0, mon_geo.h - h),
+                    w,
+                    h,
+                };
+            }
+            let saved = window
+                .class
+                .as_ref()
+                .and_then(|class| self.placements.desktops.get(class));
+            if let (true, Some(&desktop), None) =
+                (config::REMEMBER_DESKTOPS, saved, window.transient_for)
+            {
+                if desktop < self.desktops.len() {
+                    window.desktop = desktop;
+                }
+            }
+
+            // the dropdown window floats at the top of the monitor, following its desktops
+            let dropdown = self.dropdown.is_none()
+                && window.transient_for.is_none()
+                && window.class.as_deref() == Some(config::DROPDOWN_CLASS);
+            if dropdown {
+                log::debug!("window 0x{id:X} is the dropdown window");
+                self.dropdown = Some(id);
+                self.dropdown_spawned = None;
+                window.desktop = desktop;
+                window.floating = true;
+                window.above = true;
+                window.sticky = true;
+                window.skip_taskbar = true;
+                window.skip_pager = true;
+                window.geometry = dropdown_geometry(&self.monitors[monitor]);
+            }
+
+            // respect the initial state set by the client
+            for &state in &props.net_wm_state {
+                if state == self.ctx.atom._NET_WM_STATE_FULLSCREEN {
+                    window.fullscreen = true;
+                } else if state == self.ctx.atom._NET_WM_STATE_ABOVE {
+                    window.above = true;
+                } else if state == self.ctx.atom._NET_WM_STATE_SKIP_TASKBAR {
+                    window.skip_taskbar = true;
+                } else if state == self.ctx.atom._NET_WM_STATE_SKIP_PAGER {
+                    window.skip_pager = true;
+                }
+            }
+
+            // _NET_WM_ALLOWED_ACTIONS
+            let actions = [self.ctx.atom._NET_WM_ACTION_FULLSCREEN];
+            utils::replace_property(
+                &self.ctx,
+                window.id,
+                self.ctx.atom._NET_WM_ALLOWED_ACTIONS,
+                utils::Property::AtomList(&actions),
+            )?;
+
+            let policy = window
+                .class
+                .as_ref()
+                .and_then(|class| config::FOCUS_POLICIES.iter().find(|(c, _)| c == class))
+                .map_or(config::DEFAULT_FOCUS_POLICY, |(_, policy)| *policy);
+
+            let window_id = window.id;
+            let desktop = window.desktop;
+            log::debug!("window 0x{:X} added on desktop {}", window_id, desktop);
+            let swallowed = self.find_swallowed_terminal(&window)?;
+            self.select_property_change(window_id)?;
+            self.grab_focus_click(window_id)?;
+            self.windows.insert(window_id, window);
+            self.insert_into_order(window_id, desktop);
+            self.update_net_wm_state(window_id)?;
+            self.update_border(window_id)?;
+            if let Some(terminal) = swallowed {
+                self.swallow(window_id, terminal)?;
+            }
+
+            // a transient window of a window on a hidden desktop is mapped later
+            if let Some(monitor) = self.desktops[desktop].monitor {
+                self.update_layout(monitor)?;
+                self.ctx.conn.map_window(window_id)?;
+                if policy != FocusPolicy::Never {
+                    self.change_focus(window_id)?;
+                }
+            } else if policy == FocusPolicy::Always {
+                self.focus_on_any_desktop(window_id)?;
+            }
+
+            if config::WARP_POINTER_ON_MAP && self.focus == window_id {
+                self.warp_pointer(window_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows the dropdown window at the top of the focused monitor, or hides it if it's already
+    /// shown there. The window is spawned if it doesn't exist.
+    fn toggle_dropdown(&mut self) -> Result<()> {
+        let Some(window) = self.dropdown.filter(|id| self.windows.contains_key(id)) else {
+            self.dropdown = None;
+            // a slow application is not spawned twice, unless it seems to have failed
+            let pending = self
+                .dropdown_spawned
+                .is_some_and(|at| at.elapsed().as_millis() < config::DROPDOWN_SPAWN_TIMEOUT_MS);
+            if !pending {
+                self.dropdown_spawned = Some(std::time::Instant::now());
+                let argv = config::DROPDOWN_COMMAND.iter().map(|arg| arg.to_string());
+                self.run_command(Command::Exec(argv.collect()))?;
+            }
+            return Ok(());
+        };
+
+        let monitor = self.focused_monitor().unwrap_or(0);
+        let desktop = self.monitors[monitor].desktop;
+        let win = &self.windows[&window];
+        if !win.hidden && win.desktop == desktop {
+            self.hide_window(window)?;
+            self.update_layout(monitor)?;
+            if self.focus == window {
+                self.focus_any(desktop)?;
+            }
+            return Ok(());
+        }
+
+        let old_monitor = self.desktops[win.desktop].monitor.filter(|_| !win.hidden);
+        if win.hidden {
+            self.show_hidden_window(window, desktop)?;
+        } else {
+            self.windows.get_mut(&window).unwrap().desktop = desktop;
+            if old_monitor.is_none() {
+                self.ctx.conn.map_window(window)?;
+            }
+        }
+        self.windows.get_mut(&window).unwrap().geometry =
+            dropdown_geometry(&self.monitors[monitor]);
+        if let Some(old_monitor) = old_monitor.filter(|m| *m != monitor) {
+            self.update_layout(old_monitor)?;
+        }
+        self.update_layout(monitor)?;
+        self.change_focus(window)
+    }
+
+    /// Stacks the notifications from `config::NOTIFICATION_CORNER` of the focused monitor,
+    /// above all other windows.
+    fn arrange_notifications(&self) -> Result<()> {
+        let monitor = match self.monitors.get(self.focused_monitor().unwrap_or(0)) {
+            Some(monitor) => monitor,
+            None => return Ok(()),
+        };
+        let area = Rect {
+            x: monitor.geometry.x + monitor.workarea.x,
+            y: monitor.geometry.y + monitor.workarea.y,
+            ..monitor.workarea
+        };
+        let margin = monitor.scaled(config::NOTIFICATION_MARGIN);
+
+        let mut offset = margin;
+        for &window in &self.notifications {
+            let geo = self.ctx.conn.get_geometry(window)?.reply()?;
+            let w = geo.width as i32 + geo.border_width as i32 * 2;
+            let h = geo.height as i32 + geo.border_width as i32 * 2;
+            let (left, right) = (area.x + margin, area.right() - margin - w);
+            let (top, bottom) = (area.y + offset, area.bottom() - offset - h);
+            let (x, y) = match config::NOTIFICATION_CORNER {
+                Corner::TopLeft => (left, top),
+                Corner::TopRight => (right, top),
+                Corner::BottomLeft => (left, bottom),
+                Corner::BottomRight => (right, bottom),
+            };
+            offset += h + margin;
+
+            let aux = xproto::ConfigureWindowAux::new()
+                .x(x)
+                .y(y)
+                .stack_mode(xproto::StackMode::ABOVE);
+            self.ctx.conn.configure_window(window, &aux)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the pointer to the center of the window.
+    fn warp_pointer(&self, id: xproto::Window) -> Result<()> {
+        let window = &self.windows[&id];
+        let monitor = match self.desktops[window.desktop].monitor {
+            Some(monitor) => monitor,
+            None => return Ok(()),
+        };
+        let mon_geo = self.monitors[monitor].geometry;
+        let (x, y) = if window.fullscreen {
+            (mon_geo.x + mon_geo.w / 2, mon_geo.y + mon_geo.h / 2)
+        } else {
+            let geo = window.geometry;
+            (mon_geo.x + geo.x + geo.w / 2, mon_geo.y + geo.y + geo.h / 2)
+        };
+        self.ctx
+            .conn
+            .warp_pointer(x11rb::NONE, self.ctx.root, 0, 0, 0, 0, x as i16, y as i16)?;
+        Ok(())
+    }
+
+    /// Maps the dock without managing it, or updates its strut if it is already added.
+    fn add_dock(&mut self, window: xproto::Window) -> Result<()> {
+        let strut = utils::get_net_wm_strut(&self.ctx, window)?
+            .map_or(Strut::default(), |values| Strut::from_cardinals(&values));
+        if self.docks.insert(window, strut).is_none() {
+            log::debug!("dock 0x{window:X} is added: {strut:?}");
+            // unmapping and destruction are notified on the root window
+            let aux = xproto::ChangeWindowAttributesAux::new()
+                .event_mask(xproto::EventMask::PROPERTY_CHANGE);
+            self.ctx.conn.change_window_attributes(window, &aux)?;
+            self.ctx.conn.map_window(window)?;
+        }
+        self.update_workarea()
+    }
+
+    fn remove_dock(&mut self, window: xproto::Window) -> Result<()> {
+        if self.docks.remove(&window).is_some() {
+            log::debug!("dock 0x{window:X} is removed");
+            self.update_workarea()?;
+        }
+        Ok(())
+    }
+
+    /// Recomputes the work areas of the monitors excluding the struts of docks,
+    /// and publishes them as _NET_WORKAREA.
+    fn update_workarea(&mut self) -> Result<()> {
+        let screen = self.ctx.conn.get_geometry(self.ctx.root)?.reply()?;
+        let screen = (screen.width as i32, screen.height as i32);
+        for monitor in 0..self.monitors.len() {
+            let geometry = self.monitors[monitor].geometry;
+            let mut workarea = workarea(geometry, screen, self.docks.values());
+
+            // the padding in the config is left empty in addition to the struts
+            let mon = &self.monitors[monitor];
+            let (top, bottom, left, right) = config::MONITOR_PADDING
+                .iter()
+                .find(|(name, _)| {
+                    mon.output.as_deref() == Some(*name) || mon.identity.as_deref() == Some(*name)
+                })
+                .map_or(config::PADDING, |(_, padding)| *padding);
+            let (top, bottom, left, right) = (top as i32, bottom as i32, left as i32, right as i32);
+            workarea = Rect {
+                x: workarea.x + left,
+                y: workarea.y + top,
+                w: (workarea.w - left - right).max(1),
+                h: (workarea.h - top - bottom).max(1),
+            };
+
+            if self.monitors[monitor].workarea != workarea {
+                log::debug!("workarea of monitor {monitor}: {workarea:?}");
+                self.monitors[monitor].workarea = workarea;
+                self.update_layout(monitor)?;
+            }
+        }
+
+        // _NET_WORKAREA has an area for each desktop,
+        // and hidden desktops are regarded as on the focused monitor
+        let fallback = self.focused_monitor().unwrap_or(0);
+        let mut areas = Vec::new();
+        for desktop in &self.desktops {
+            let monitor = desktop.monitor.unwrap_or(fallback);
+            if let Some(mon) = self.monitors.get(monitor) {
+                let area = mon.workarea;
+                areas.extend([
+                    (mon.geometry.x + area.x) as u32,
+                    (mon.geometry.y + area.y) as u32,
+                    area.w as u32,
+                    area.h as u32,
+                ]);
+            }
+        }
+        utils::replace_property(
+            &self.ctx,
+            self.ctx.root,
+            self.ctx.atom._NET_WORKAREA,
+            utils::Property::CardinalList(&areas),
+        )
+    }
+
+    /// Focuses the most recently focused window still mapped on the desktop.
+    /// If there is no such window, any mapped window (or the monitor itself) is focused instead.
+    fn focus_any(&mut self, desktop: usize) -> Result<()> {
+        let fallback = self.desktops[desktop]
+            .monitor
+            .map(|mon| self.monitors[mon].dummy_window)
+            .unwrap_or(x11rb::NONE);
+        let recently_focused = self.desktops[desktop]
+            .focus_history
+            .iter()
+            .rev()
+            .copied()
+            .find(|id| {
+                self.windows
+                    .get(id)
+                    .is_some_and(|win| win.mapped && win.desktop == desktop && win.focusable())
+            });
+        let under_pointer = match recently_focused {
+            None if config::FOCUS_UNDER_POINTER_FALLBACK => self.window_under_pointer(desktop)?,
+            _ => None,
+        };
+        let any_window_on_desktop: xproto::Window = recently_focused
+            .or(under_pointer)
+            .or_else(|| {
+                mapped_windows!(self, desktop)
+                    .find(|win| win.focusable())
+                    .map(|win| win.id)
+            })
+            .unwrap_or(fallback);
+        self.change_focus(any_window_on_desktop)?;
+        Ok(())
+    }
+
+    /// Returns the topmost focusable window of the desktop under the pointer, if the desktop is
+    /// shown.
+    fn window_under_pointer(&self, desktop: usize) -> Result<Option<xproto::Window>> {
+        let Some(monitor) = self.desktops[desktop].monitor else {
+            return Ok(None);
+        };
+        let pointer = self.ctx.conn.query_pointer(self.ctx.root)?.reply()?;
+        let mon = &self.monitors[monitor];
+        let (x, y) = (
+            pointer.root_x as i32 - mon.geometry.x,
+            pointer.root_y as i32 - mon.geometry.y,
+        );
+        // the children of the root are listed from the bottom of the stack
+        let tree = self.ctx.conn.query_tree(self.ctx.root)?.reply()?;
+        let window = tree.children.iter().rev().copied().find(|id| {
+            self.windows.get(id).is_some_and(|win| {
+                let bwidth = win.border_width(mon);
+                let outer = Rect {
+                    w: win.geometry.w + bwidth * 2,
+                    h: win.geometry.h + bwidth * 2,
+                    ..win.geometry
+                };
+                win.mapped && win.desktop == desktop && win.focusable() && outer.contains(x, y)
+            })
+        });
+        Ok(window)
+    }
+
+    fn cycle_window_focus(&mut self, reverse: bool) -> Result<()> {
+        match config::FOCUS_ORDER {
+            FocusOrder::Tiling => self.cycle_tiling_order(reverse),
+            FocusOrder::MostRecentlyUsed => self.cycle_mru_order(reverse),
+        }
+    }
+
+    /// Focuses the next window of the same class (WM_CLASS) as the focused one,
+    /// ordered by the desktop and then the tiling order.
+    fn cycle_same_class(&mut self) -> Result<()> {
+        let class = match self
+            .windows
+            .get(&self.focus)
+            .and_then(|win| win.class.clone())
+        {
+            Some(class) => class,
+            None => return Ok(()),
+        };
+        let mut windows: Vec<&Window> = self
+            .window_order
+            .iter()
+            .map(|id| &self.windows[id])
+            .filter(|win| win.mapped && win.class.as_ref() == Some(&class))
+            .filter(|win| win.focusable() || win.id == self.focus)
+            .collect();
+        windows.sort_by_key(|win| win.desktop);
+
+        if let Some(pos) = windows.iter().position(|win| win.id == self.focus) {
+            let next_window = windows[(pos + 1) % windows.len()].id;
+            self.focus_on_any_desktop(next_window)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the window matching the condition, preferring windows on the focused desktop.
+    /// If the focused window matches, the next matching one is chosen so that they are cycled.
+    fn find_window(&self, condition: &WindowMatch) -> Option<xproto::Window> {
+        let mut matches = Vec::new();
+        for win in self.window_order.iter().map(|id| &self.windows[id]) {
+            if !win.mapped || !(win.focusable() || win.id == self.focus) {
+                continue;
+            }
+            let matched = match condition {
+                WindowMatch::Class(class) => win
+                    .class
+                    .as_ref()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(class)),
+                WindowMatch::Title(part) => win.title.to_lowercase().contains(&part.to_lowercase()),
+            };
+            if matched {
+                matches.push(win);
+            }
+        }
+
+        let desktop = self.focused_monitor().map(|mon| self.monitors[mon].desktop);
+        matches.sort_by_key(|win| Some(win.desktop) != desktop);
+        let next = match matches.iter().position(|win| win.id == self.focus) {
+            Some(pos) => matches[(pos + 1) % matches.len()],
+            None => matches.first()?,
+        };
+        Some(next.id)
+    }
+
+    /// Focuses the window, showing its desktop on the focused monitor if it is not visible.
+    fn focus_on_any_desktop(&mut self, window: xproto::Window) -> Result<()> {
+        let desktop = self.windows[&window].desktop;
+        if self.desktops[desktop].monitor.is_none() {
+            let monitor = self.focused_monitor().unwrap_or(0);
+            let old_desktop = self.monitors[monitor].desktop;
+            self.carry_sticky_windows(&[(old_desktop, desktop)]);
+            self.hide_desktop(old_desktop)?;
+            self.show_desktop(desktop, monitor)?;
+        }
+        self.change_focus(window)
+    }
+
+    fn cycle_tiling_order(&mut self, reverse: bool) -> Result<()> {
+        if let Some(window) = self.windows.get(&self.focus) {
+            let desktop = window.desktop;
+            let mut windows: Vec<xproto::Window> = mapped_windows!(self, desktop)
+                .filter(|win| win.focusable() || win.id == self.focus)
+                .map(|win| win.id)
+                .collect();
+            if reverse {
+                windows.reverse();
+            }
+
+            if let Some(pos) = windows.iter().position(|id| *id == self.focus) {
+                let next_window = windows[(pos + 1) % windows.len()];
+                self.change_focus(next_window)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cycle_mru_order(&mut self, reverse: bool) -> Result<()> {
+        if self.mru_cycle.is_none() {
+            let desktop = match self.focused_monitor() {
+                Some(monitor) => self.monitors[monitor].desktop,
+                None => return Ok(()),
+            };
+
+            // most recently focused windows first, followed by never-focused ones
+            let mut windows: Vec<xproto::Window> = self.desktops[desktop]
+                .focus_history
+                .iter()
+                .rev()
+                .copied()
+                .filter(|id| {
+                    self.windows
+                        .get(id)
+                        .is_some_and(|win| win.mapped && win.desktop == desktop && win.focusable())
+                })
+                .collect();
+            for win in mapped_windows!(self, desktop).filter(|win| win.focusable()) {
+                if !windows.contains(&win.id) {
+                    windows.push(win.id);
+                }
+            }
+            if windows.len() < 2 {
+                return Ok(());
+            }
+
+            // grab the keyboard so that we can notice the release of the hot key
+            let mapping = self.ctx.conn.get_modifier_mapping()?.reply()?;
+            let per_modifier = mapping.keycodes_per_modifier() as usize;
+            let index = u16::from(config::HOT_KEY.keybutmask()).trailing_zeros() as usize;
+            let release_keys: Vec<u8> = mapping.keycodes
+                [index * per_modifier..(index + 1) * per_modifier]
+                .iter()
+                .copied()
+                .filter(|kc| *kc != 0)
+                .collect();
+            self.grab_keyboard()?;
+
+            let index = windows
+                .iter()
+                .position(|id| *id == self.focus)
+                .unwrap_or(windows.len() - 1);
+            self.mru_cycle = Some(MruCycle {
+                windows,
+                index,
+                release_keys,
+                cells: Vec::new(),
+            });
+            if config::SWITCHER {
+                let monitor = self.focused_monitor().unwrap_or(0);
+                self.open_switcher(monitor)?;
+            }
+        }
+
+        let cycle = self.mru_cycle.as_mut().unwrap();
+        let n = cycle.windows.len();
+        cycle.index = if reverse {
+            (cycle.index + n - 1) % n
+        } else {
+            (cycle.index + 1) % n
+        };
+        let next_window = cycle.windows[cycle.index];
+        if self.windows.contains_key(&next_window) {
+            self.change_focus(next_window)?;
+        }
+        self.draw_switcher()
+    }
+
+    /// Shows the switcher window listing the windows of the MRU cycle at the center of the
+    /// monitor, with the thumbnails sized after the monitor.
+    fn open_switcher(&mut self, monitor: usize) -> Result<()> {
+        let Some(cycle) = &mut self.mru_cycle else {
+            return Ok(());
+        };
+        let mon = &self.monitors[monitor];
+        let n = cycle.windows.len() as i32;
+        let padding = mon.scaled(config::SWITCHER_PADDING);
+        let title_h = RootMenu::item_height();
+        let max_w = (mon.geometry.w - padding * (n + 1)) / n;
+        let thumb_w = mon
+            .scaled(config::SWITCHER_THUMBNAIL_WIDTH)
+            .min(max_w)
+            .max(1);
+        let thumb_h = (thumb_w as f64 * mon.geometry.h as f64 / mon.geometry.w.max(1) as f64)
+            .round()
+            .max(1.0) as i32;
+        cycle.cells = (0..n)
+            .map(|i| Rect {
+                x: padding + i * (thumb_w + padding),
+                y: padding,
+                w: thumb_w,
+                h: thumb_h,
+            })
+            .collect();
+
+        let w = n * (thumb_w + padding) + padding;
+        let h = thumb_h + title_h + padding * 2;
+        let aux = xproto::ConfigureWindowAux::new()
+            .x(mon.geometry.x + (mon.geometry.w - w) / 2)
+            .y(mon.geometry.y + (mon.geometry.h - h) / 2)
+            .width(w as u32)
+            .height(h as u32)
+            .stack_mode(xproto::StackMode::ABOVE);
+        self.ctx.conn.configure_window(self.switcher_window, &aux)?;
+        self.ctx.conn.map_window(self.switcher_window)?;
+        // the thumbnails are live while the switcher is shown
+        if self.switcher_picture.is_some() {
+            self.set_timer(
+                Timer::SwitcherRefresh,
+                std::time::Duration::from_millis(config::SWITCHER_REFRESH_MS),
+            );
+        }
+        Ok(())
+    }
+
+    /// Draws the thumbnails (if available) and the titles of the windows in the switcher,
+    /// highlighting the selected one.
+    fn draw_switcher(&self) -> Result<()> {
+        let Some(cycle) = &self.mru_cycle else {
+            return Ok(());
+        };
+        let font = self.tab_font;
+        let title_h = RootMenu::item_height();
+        let rect = |r: Rect| xproto::Rectangle {
+            x: r.x as i16,
+            y: r.y as i16,
+            width: r.w.max(0) as u16,
+            height: r.h.max(0) as u16,
+        };
+        for (i, (id, cell)) in cycle.windows.iter().zip(&cycle.cells).enumerate() {
+            let (bg, fg) = if i == cycle.index {
+                self.theme.tab_active
+            } else {
+                self.theme.tab_inactive
+            };
+            // the thumbnail and the title are framed by a half of the padding, which is
+            // above the cells
+            let margin = (cell.y / 2).max(1);
+            let frame = Rect {
+                x: cell.x - margin,
+                y: cell.y - margin,
+                w: cell.w + margin * 2,
+                h: cell.h + title_h + margin * 2,
+            };
+            let aux = xproto::ChangeGCAux::new().foreground(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            self.ctx
+                .conn
+                .poly_fill_rectangle(self.switcher_window, font.gc, &[rect(frame)])?;
+            let aux = xproto::ChangeGCAux::new().foreground(self.theme.background);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            self.ctx
+                .conn
+                .poly_fill_rectangle(self.switcher_window, font.gc, &[rect(*cell)])?;
+
+            // core fonts can only draw Latin-1 strings
+            let max_chars = (cell.w / font.char_width).clamp(0, 255) as usize;
+            let title: Vec<u8> = self
+                .windows
+                .get(id)
+                .map_or("", |win| win.title.as_str())
+                .chars()
+                .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+                .take(max_chars)
+                .collect();
+            let baseline = cell.bottom() + (title_h + font.ascent - font.descent) / 2;
+            let aux = xproto::ChangeGCAux::new().foreground(fg).background(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            self.ctx.conn.image_text8(
+                self.switcher_window,
+                font.gc,
+                cell.x as i16,
+                baseline as i16,
+                &title,
+            )?;
+        }
+        // drawn over the cells filled above
+        if let (Some(thumbnailer), Some(picture)) = (&self.thumbnailer, self.switcher_picture) {
+            let windows: Vec<_> = cycle
+                .windows
+                .iter()
+                .copied()
+                .zip(cycle.cells.iter().copied())
+                .collect();
+            thumbnailer.draw_all(&self.ctx, picture, &windows)?;
+        }
+        Ok(())
+    }
+
+    fn finish_mru_cycle(&mut self) -> Result<()> {
+        if self.mru_cycle.take().is_some() {
+            self.ctx.conn.unmap_window(self.switcher_window)?;
+            self.release_keyboard()?;
+            self.push_focus_history(self.focus);
+        }
+        Ok(())
+    }
+
+    /// Suspends the screen saver and the idle actions while a fullscreen window is visible.
+    fn update_idle_inhibit(&mut self) -> Result<()> {
+        if !self.screensaver {
+            return Ok(());
+        }
+        let inhibited = config::FULLSCREEN_INHIBITS_IDLE
+            && !self.idle_inhibit_overridden
+            && self
+                .monitors
+                .iter()
+                .any(|mon| mapped_windows!(self, mon.desktop).any(|win| win.fullscreen));
+        if inhibited != self.idle_inhibited {
+            log::debug!("idle inhibited: {inhibited}");
+            self.ctx.conn.screensaver_suspend(inhibited as u32)?;
+            self.idle_inhibited = inhibited;
+        }
+        Ok(())
+    }
+
+    /// Runs the idle actions whose timeouts have passed since the last input,
+    /// and returns how long to wait before checking the idle time again.
+    /// Nothing is run while a fullscreen window is visible, and the countdown restarts after that.
+    fn check_idle(&mut self, cmdq: &mut VecDeque<Command>) -> Result<Option<std::time::Duration>> {
+        if !self.screensaver || config::IDLE_ACTIONS.is_empty() {
+            return Ok(None);
+        }
+        let reply = self
+            .ctx
+            .conn
+            .screensaver_query_info(self.ctx.root)?
+            .reply()?;
+        let mut idle = reply.ms_since_user_input as u64;
+        if self.idle_inhibited {
+            self.idle_actions_run = 0;
+            self.idle_offset = idle;
+            return Ok(Some(std::time::Duration::from_millis(
+                config::IDLE_POLL_INTERVAL_MS,
+            )));
+        }
+        if idle < self.idle_offset {
+            self.idle_offset = 0;
+        }
+        idle -= self.idle_offset;
+
+        // input after the last action restarts the countdown
+        if let Some(last) = self.idle_actions_run.checked_sub(1) {
+            if idle < config::IDLE_ACTIONS[last].0 * 1000 {
+                self.idle_actions_run = 0;
+            }
+        }
+        while let Some((secs, prog)) = config::IDLE_ACTIONS.get(self.idle_actions_run) {
+            if idle < secs * 1000 {
+                break;
+            }
+            log::info!("idle for {secs} seconds: {prog}");
+            cmdq.push_back(Command::SpawnProcess(prog.to_string()));
+            self.idle_actions_run += 1;
+        }
+
+        let wait = match config::IDLE_ACTIONS.get(self.idle_actions_run) {
+            Some((secs, _)) => secs * 1000 - idle,
+            None => config::IDLE_POLL_INTERVAL_MS,
+        };
+        Ok(Some(std::time::Duration::from_millis(wait)))
+    }
+
+    /// Makes the next `update_layout` restack all windows, after they are restacked elsewhere.
+    fn invalidate_stacking(&mut self) {
+        for monitor in self.monitors.iter_mut() {
+            monitor.stacking.clear();
+        }
+    }
+
+    /// Starts the timer, replacing the pending one of the same kind.
+    fn set_timer(&mut self, timer: Timer, after: std::time::Duration) {
+        self.timers.retain(|(_, t)| *t != timer);
+        self.timers.push((std::time::Instant::now() + after, timer));
+    }
+
+    /// how long the event loop can wait until the earliest timer expires
+    fn next_timer(&self) -> Option<std::time::Duration> {
+        let now = std::time::Instant::now();
+        self.timers
+            .iter()
+            .map(|(deadline, _)| deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    fn run_timers(&mut self, cmdq: &mut VecDeque<Command>) -> Result<()> {
+        let now = std::time::Instant::now();
+        let (expired, pending) = self
+            .timers
+            .drain(..)
+            .partition(|(deadline, _)| *deadline <= now);
+        self.timers = pending;
+        for (_, timer) in expired {
+            log::trace!("timer expired: {timer:?}");
+            match timer {
+                Timer::ChordTimeout => {
+                    if let Some(chord) = self.chord.take() {
+                        log::info!("key chord {:?} timed out", chord.keys);
+                        self.release_keyboard()?;
+                    }
+                }
+                Timer::IdleCheck => {
+                    if let Some(wait) = self.check_idle(cmdq)? {
+                        self.set_timer(Timer::IdleCheck, wait);
+                    }
+                }
+                Timer::SavePlacements => self.placements.save(),
+                Timer::SwitcherRefresh => {
+                    if self.mru_cycle.is_some() {
+                        self.draw_switcher()?;
+                        self.set_timer(
+                            Timer::SwitcherRefresh,
+                            std::time::Duration::from_millis(config::SWITCHER_REFRESH_MS),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_signals(&mut self, cmdq: &mut VecDeque<Command>) {
+        let signals = match &self.signals {
+            Some(signals) => signals.read(),
+            None => return,
+        };
+        for signal in signals {
+            match signal {
+                libc::SIGCHLD => signal::reap_children(),
+                libc::SIGTERM => {
+                    log::info!("received SIGTERM");
+                    cmdq.push_back(Command::Exit);
+                }
+                libc::SIGHUP => {
+                    log::info!("received SIGHUP");
+                    cmdq.push_back(Command::Restart);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Saves the state when the session manager asks, and exits when the session ends.
+    fn handle_session_requests(&mut self, cmdq: &mut VecDeque<Command>) -> Result<()> {
+        let Some(requests) = self.session.as_mut().map(|session| session.read()) else {
+            return Ok(());
+        };
+        let Some(requests) = requests else {
+            log::warn!("the session manager closed the connection");
+            self.session = None;
+            return Ok(());
+        };
+        for request in requests {
+            match request {
+                xsmp::Request::SaveYourself { shutdown } => {
+                    log::info!("the session manager asks to save the state (shutdown: {shutdown})");
+                    let saved = self.save_session();
+                    if let Some(session) = &mut self.session {
+                        session.save_yourself_done(saved);
+                    }
+                }
+                xsmp::Request::Die => {
+                    log::info!("the session ends");
+                    if let Some(mut session) = self.session.take() {
+                        session.close();
+                    }
+                    cmdq.push_back(Command::Exit);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the process ID of the client owning the window if it runs on this host.
+    /// The server knows the PIDs of local clients by X-Resource, even if they don't set
+    /// _NET_WM_PID, which is meaningless if the client runs on the other hosts.
+    fn local_pid(&self, window: xproto::Window, props: &utils::ClientProperties) -> Option<u32> {
+        if props.local_client_pid.is_some() {
+            return props.local_client_pid;
+        }
+        match &props.client_machine {
+            Some(machine) if Some(machine) != self.hostname.as_ref() => {
+                log::debug!("window 0x{window:X} is a client on {machine:?}");
+                None
+            }
+            _ => props.net_wm_pid,
+        }
+    }
+
+    /// Selects property changes of the managed window to keep its title up to date.
+    fn select_property_change(&self, window: xproto::Window) -> Result<()> {
+        let aux =
+            xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::PROPERTY_CHANGE);
+        self.ctx.conn.change_window_attributes(window, &aux)?;
+        Ok(())
+    }
+
+    /// Grabs mouse buttons on the unfocused window to focus it by a click.
+    fn grab_focus_click(&self, window: xproto::Window) -> Result<()> {
+        self.ctx.conn.grab_button(
+            false,
+            window,
+            xproto::EventMask::BUTTON_PRESS,
+            xproto::GrabMode::SYNC,  // pointer
+            xproto::GrabMode::ASYNC, // keyboard
+            x11rb::NONE,
+            x11rb::NONE,
+            xproto::ButtonIndex::ANY,
+            xproto::ModMask::ANY,
+        )?;
+        Ok(())
+    }
+
+    fn grab_keyboard(&self) -> Result<()> {
+        self.ctx
+            .conn
+            .grab_keyboard(
+                false,
+                self.ctx.root,
+                x11rb::CURRENT_TIME,
+                xproto::GrabMode::ASYNC, // pointer
+                xproto::GrabMode::ASYNC, // keyboard
+            )?
+            .reply()?;
+        Ok(())
+    }
+
+    /// Ungrabs the keyboard unless a mode, a key chord, an MRU cycle, the desktop prompt or the
+    /// overview still needs it.
+    fn release_keyboard(&self) -> Result<()> {
+        if self.active_mode.is_none()
+            && self.chord.is_none()
+            && self.mru_cycle.is_none()
+            && self.desktop_prompt.is_none()
+            && self.overview.is_none()
+        {
+            self.ctx.conn.ungrab_keyboard(x11rb::CURRENT_TIME)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the keysyms of all keycodes and the modifier keys,
+    /// and grabs the keys of the keysym bindings.
+    fn load_keyboard_mapping(&mut self) -> Result<()> {
+        // modifier keys pressed in the middle of a key chord
+        let mapping = self.ctx.conn.get_modifier_mapping()?.reply()?;
+        self.modifier_keycodes = mapping
+            .keycodes
+            .into_iter()
+            .filter(|keycode| *keycode != 0)
+            .collect();
+
+        self.keymap.load(&self.ctx)?;
+        self.grab_keysym_bindings()
+    }
+
+    /// Grabs the keycodes currently generating the keysyms of the keysym bindings,
+    /// replacing the ones grabbed for the previous keyboard mapping.
+    fn grab_keysym_bindings(&mut self) -> Result<()> {
+        for key @ (state, keycode) in std::mem::take(&mut self.keysym_grabs) {
+            self.ctx
+                .conn
+                .ungrab_key(keycode, self.ctx.root, state.into())?;
+            self.keybind.remove(&key);
+        }
+
+        for (state, keysym, cmd) in self.keysym_bindings.clone() {
+            let keycodes = self.keymap.keycodes(keysym);
+            if keycodes.is_empty() {
+                log::debug!("no key generates keysym 0x{keysym:X}");
+            }
+            for keycode in keycodes {
+                let key = (state, keycode);
+                if self.keybind.contains_key(&key) {
+                    log::warn!(
+                        "keysym 0x{keysym:X} is ignored since keycode {keycode} is already bound"
+                    );
+                    continue;
+                }
+                if let Err(err) = self.grab_key(state.into(), keycode) {
+                    log::error!("Failed to grab keycode {keycode} for keysym 0x{keysym:X}: {err}");
+                    continue;
+                }
+                log::info!("keysym 0x{keysym:X} is bound to keycode {keycode}: cmd={cmd:?}");
+                self.keybind.insert(key, KeyBinding::Command(cmd.clone()));
+                self.keysym_grabs.push(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the name of the current keyboard layout visible to bars.
+    fn update_keyboard_layout_property(&self) -> Result<()> {
+        utils::replace_property(
+            &self.ctx,
+            self.ctx.root,
+            self.ctx.atom._DAILY2_KEYBOARD_LAYOUT,
+            utils::Property::Utf8String(&self.keymap.group_name()),
+        )?;
+        Ok(())
+    }
+
+    /// the keysym of the key pressed without modifiers
+    fn keysym(&self, keycode: u8) -> xproto::Keysym {
+        self.keymap.keysym(keycode)
+    }
+
+    /// Updates the desktop prompt with the pressed key.
+    /// The prompt is confirmed by Return, or when no more digits can follow.
+    fn handle_prompt_key(&mut self, keycode: u8, cmdq: &mut VecDeque<Command>) -> Result<()> {
+        const XK_0: u32 = 0x0030;
+        const XK_9: u32 = 0x0039;
+        const XK_KP_0: u32 = 0xFFB0;
+        const XK_KP_9: u32 = 0xFFB9;
+        const XK_BACKSPACE: u32 = 0xFF08;
+        const XK_RETURN: u32 = 0xFF0D;
+        const XK_KP_ENTER: u32 = 0xFF8D;
+        const XK_ESCAPE: u32 = 0xFF1B;
+
+        if self.modifier_keycodes.contains(&keycode) {
+            return Ok(());
+        }
+        let keysym = self.keysym(keycode);
+        let digits = match &mut self.desktop_prompt {
+            Some(digits) => digits,
+            None => return Ok(()),
+        };
+
+        let mut confirmed = false;
+        match keysym {
+            XK_0..=XK_9 | XK_KP_0..=XK_KP_9 => {
+                let digit = if keysym >= XK_KP_0 {
+                    keysym - XK_KP_0
+                } else {
+                    keysym - XK_0
+                };
+                digits.push(char::from_digit(digit, 10).unwrap());
+                let number: usize = digits.parse().unwrap_or(0);
+                confirmed = number * 10 > config::NUM_DESKTOPS;
+            }
+            XK_BACKSPACE => {
+                digits.pop();
+            }
+            XK_RETURN | XK_KP_ENTER => confirmed = true,
+            XK_ESCAPE => {
+                digits.clear();
+                confirmed = true;
+            }
+            _ => {}
+        }
+
+        if confirmed {
+            let number: usize = digits.parse().unwrap_or(0);
+            self.desktop_prompt = None;
+            self.ctx
+                .conn
+                .delete_property(self.ctx.root, self.ctx.atom._DAILY2_PROMPT)?;
+            self.release_keyboard()?;
+            // desktops are numbered from 1 in the prompt
+            if (1..=self.desktops.len()).contains(&number) {
+                cmdq.push_back(Command::SwitchDesktop(number - 1));
+            }
+        } else {
+            // make the prompt visible to bars
+            let text = format!("desktop: {digits}");
+            utils::replace_property(
+                &self.ctx,
+                self.ctx.root,
+                self.ctx.atom._DAILY2_PROMPT,
+                utils::Property::Utf8String(&text),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Advances the key chord with the pressed key.
+    fn handle_chord_key(&mut self, key: (u16, u8), cmdq: &mut VecDeque<Command>) -> Result<()> {
+        if self.chord.is_some() && self.modifier_keycodes.contains(&key.1) {
+            return Ok(());
+        }
+
+        let mut keys = match self.chord.take() {
+            Some(chord) => chord.keys,
+            None => Vec::new(),
+        };
+        keys.push(key);
+
+        let mut node = Some(&self.keybind);
+        let mut binding = None;
+        for key in &keys {
+            binding = node.and_then(|n| n.get(key));
+            node = match binding {
+                Some(KeyBinding::Prefix(next)) => Some(next),
+                _ => None,
+            };
+        }
+
+        match binding {
+            Some(KeyBinding::Command(cmd)) => {
+                cmdq.push_back(cmd.clone());
+                self.release_keyboard()?;
+            }
+            Some(KeyBinding::Prefix(_)) => {
+                if key.1 == config::CHORD_ABORT_KEY && keys.len() > 1 {
+                    self.release_keyboard()?;
+                } else {
+                    if self.chord.is_none() {
+                        self.grab_keyboard()?;
+                    }
+                    self.chord = Some(Chord { keys });
+                    let timeout = std::time::Duration::from_millis(config::CHORD_TIMEOUT_MS);
+                    self.set_timer(Timer::ChordTimeout, timeout);
+                }
+            }
+            None => {
+                if keys.len() > 1 {
+                    log::info!("unbound key chord: {keys:?}");
+                }
+                self.release_keyboard()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn push_focus_history(&mut self, window: xproto::Window) {
+        if let Some(win) = self.windows.get(&window) {
+            let history = &mut self.desktops[win.desktop].focus_history;
+            history.retain(|id| *id != window);
+            history.push(window);
+        }
+    }
+
+    /// Returns the pixels of the fill and the border of the preview window. Pixels of 32-bit
+    /// visuals have premultiplied alpha.
+    fn preview_pixels(&self) -> (u32, u32) {
+        let pixel = |color: u32, alpha: Option<u32>| match alpha {
+            Some(alpha) => {
+                let channel = |shift: u32| ((color >> shift & 0xFF) * alpha / 0xFF) << shift;
+                (alpha << 24) | channel(16) | channel(8) | channel(0)
+            }
+            None => color,
+        };
+        let (background, border) = self.theme.preview;
+        (
+            pixel(background, self.preview_alpha),
+            pixel(border, self.preview_alpha.map(|_| 0xFF)),
+        )
+    }
+
+    /// Redraws the borders, the tab bars, the helper windows and the wallpaper in the colors of
+    /// the current theme.
+    fn apply_theme(&mut self) -> Result<()> {
+        let ids: Vec<xproto::Window> = self.windows.keys().copied().collect();
+        for id in ids {
+            self.update_border(id)?;
+        }
+
+        let (background, border) = self.preview_pixels();
+        let aux = xproto::ChangeWindowAttributesAux::new()
+            .background_pixel(background)
+            .border_pixel(border);
+        self.ctx
+            .conn
+            .change_window_attributes(self.preview_window, &aux)?;
+        let aux = xproto::ChangeWindowAttributesAux::new()
+            .background_pixel(self.theme.tab_inactive.0)
+            .border_pixel(self.theme.tab_active.0);
+        for window in [self.menu_window, self.switcher_window] {
+            self.ctx.conn.change_window_attributes(window, &aux)?;
+        }
+        let aux = xproto::ChangeWindowAttributesAux::new()
+            .background_pixel(self.theme.background)
+            .border_pixel(self.theme.tab_active.0);
+        self.ctx
+            .conn
+            .change_window_attributes(self.pager_window, &aux)?;
+        let aux =
+            xproto::ChangeWindowAttributesAux::new().background_pixel(self.theme.tab_inactive.0);
+        let bars = self.monitors.iter().map(|mon| mon.tab_bar);
+        for window in bars.chain(self.title_bars.values().copied()) {
+            self.ctx.conn.change_window_attributes(window, &aux)?;
+        }
+
+        // the windows are redrawn on Expose
+        let tab_bars = self.monitors.iter().map(|mon| mon.tab_bar);
+        let helpers = [
+            self.preview_window,
+            self.menu_window,
+            self.pager_window,
+            self.switcher_window,
+        ];
+        for window in tab_bars.chain(helpers) {
+            self.ctx.conn.clear_area(true, window, 0, 0, 0, 0)?;
+        }
+
+        // the wallpapers are rendered again with the margins in the new color
+        for (_, pixmap) in self.wallpaper_pixmaps.drain() {
+            self.ctx.conn.free_pixmap(pixmap)?;
+        }
+        self.wallpaper_monitors.clear();
+        Ok(())
+    }
+
+    fn update_border(&self, window: xproto::Window) -> Result<()> {
+        if let Some(win) = self.windows.get(&window) {
+            // only the selected window is highlighted in the overview
+            let overview = self
+                .overview
+                .as_ref()
+                .filter(|overview| overview.windows.contains(&window));
+            let (focused, unfocused) = self.theme.borders(win.class.as_deref());
+            let color = if let Some(overview) = overview {
+                if overview.windows[overview.selected] == window {
+                    focused
+                } else {
+                    unfocused
+                }
+            } else if win.id == self.focus {
+                focused
+            } else if win.urgent {
+                self.theme.border_urgent
+            } else {
+                unfocused
+            };
+            let aux = xproto::ChangeWindowAttributesAux::new().border_pixel(color);
+            self.ctx.conn.change_window_attributes(window, &aux)?;
+            // the title bar is highlighted with the border
+            self.draw_title_bar(window)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the axis along which the tiled window is resized by its weight,
+    /// or `None` if the layout doesn't use weights.
+    fn tile_axis(&self, id: xproto::Window) -> Option<SplitDirection> {
+        let window = self.windows.get(&id)?;
+        if window.floating || window.fullscreen {
+            return None;
+        }
+        let desktop = &self.desktops[window.desktop];
+        let axis = match desktop.layout {
+            Layout::Horizontal => SplitDirection::Horizontal,
+            Layout::Manual => desktop.tree.as_ref()?.parent_direction(id)?,
+            _ => return None,
+        };
+        Some(match (axis, desktop.transposed) {
+            (SplitDirection::Horizontal, true) => SplitDirection::Vertical,
+            (SplitDirection::Vertical, true) => SplitDirection::Horizontal,
+            (axis, false) => axis,
+        })
+    }
+
+    /// Moves the preview window over the area (absolute, including the border of the preview),
+    /// outlining it in the outline style.
+    fn place_preview(
+        &mut self,
+        area: Rect,
+        bwidth: i32,
+        stack_mode: xproto::StackMode,
+    ) -> Result<()> {
+        if area == self.preview_geometry {
+            return Ok(());
+        }
+        self.preview_geometry = area;
+        let (w, h) = ((area.w - bwidth * 2).max(1), (area.h - bwidth * 2).max(1));
+        let aux = xproto::ConfigureWindowAux::new()
+            .stack_mode(stack_mode)
+            .x(area.x)
+            .y(area.y)
+            .width(w as u32)
+            .height(h as u32)
+            .border_width(bwidth as u32);
+        self.ctx.conn.configure_window(self.preview_window, &aux)?;
+
+        if config::PREVIEW_STYLE == PreviewStyle::Outline && self.shape {
+            // the bounding shape is relative to the inside of the border
+            let rect = |x: i32, y: i32, w: i32, h: i32| xproto::Rectangle {
+                x: x as i16,
+                y: y as i16,
+                width: w as u16,
+                height: h as u16,
+            };
+            let border = [
+                rect(-bwidth, -bwidth, w + bwidth * 2, bwidth),
+                rect(-bwidth, h, w + bwidth * 2, bwidth),
+                rect(-bwidth, 0, bwidth, h),
+                rect(w, 0, bwidth, h),
+            ];
+            self.ctx.conn.shape_rectangles(
+                shape::SO::SET,
+                shape::SK::BOUNDING,
+                xproto::ClipOrdering::UNSORTED,
+                self.preview_window,
+                0,
+                0,
+                &border,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Shows the area where the next window is opened in the manual layout with the preview
+    /// window, unless it is used for dragging.
+    fn update_preselection_hint(&mut self) -> Result<()> {
+        if self.drag_action.is_some() {
+            return Ok(());
+        }
+        let hint = self.focused_monitor().and_then(|monitor| {
+            let mon = &self.monitors[monitor];
+            let desktop = &self.desktops[mon.desktop];
+            let side = desktop.next_split?;
+            let window = self
+                .windows
+                .get(&self.focus)
+                .filter(|win| desktop.layout == Layout::Manual && win.mapped)
+                .filter(|win| !win.floating && !win.fullscreen)?;
+            let bwidth = window.border_width(mon);
+            let outer = Rect {
+                x: mon.geometry.x + window.geometry.x,
+                y: mon.geometry.y + window.geometry.y,
+                w: window.geometry.w + bwidth * 2,
+                h: window.geometry.h + bwidth * 2,
+            };
+            Some((side.half_of(outer), mon.preview_border_width()))
+        });
+
+        match hint {
+            Some((area, bwidth)) if area != self.preview_geometry => {
+                self.place_preview(area, bwidth, xproto::StackMode::ABOVE)?;
+                self.ctx.conn.map_window(self.preview_window)?;
+            }
+            None if self.preview_geometry != Rect::default() => {
+                self.preview_geometry = Rect::default();
+                self.ctx.conn.unmap_window(self.preview_window)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn change_focus(&mut self, focus: xproto::Window) -> Result<()> {
+        let old_focus = self.focus;
+        let new_focus = focus;
+
+        if old_focus == new_focus {
+            return Ok(());
+        }
+        if self
+            .windows
+            .get(&new_focus)
+            .is_some_and(|win| !win.focusable())
+        {
+            log::debug!("window 0x{new_focus:X} doesn't accept the input focus");
+            return Ok(());
+        }
+        self.focus = new_focus;
+
+        log::debug!("focus on window 0x{:X} ({})", new_focus, new_focus);
+
+        // focusing a window answers its demand for attention
+        if let Some(window) = self.windows.get_mut(&new_focus) {
+            if window.urgent {
+                window.urgent = false;
+                self.update_net_wm_state(new_focus)?;
+            }
+        }
+
+        self.update_border(old_focus)?;
+        self.update_border(new_focus)?;
+
+        // clicks on the focused window are not intercepted
+        if self.windows.contains_key(&old_focus) {
+            self.grab_focus_click(old_focus)?;
+        }
+        if self.windows.contains_key(&new_focus) {
+            self.ctx.conn.ungrab_button(
+                xproto::ButtonIndex::ANY,
+                new_focus,
+                xproto::ModMask::ANY,
+            )?;
+        }
+
+        // a window with the input hint false sets the focus by itself on WM_TAKE_FOCUS
+        let (input, take_focus) = self
+            .windows
+            .get(&new_focus)
+            .map_or((true, false), |win| (win.input, win.take_focus));
+        if input {
+            self.ctx.conn.set_input_focus(
+                xproto::InputFocus::NONE, // revert-to
+                new_focus,
+                x11rb::CURRENT_TIME,
+            )?;
+        }
+        if take_focus {
+            let event = xproto::ClientMessageEvent::new(
+                32,
+                new_focus,
+                self.ctx.atom.WM_PROTOCOLS,
+                [self.ctx.atom.WM_TAKE_FOCUS, x11rb::CURRENT_TIME, 0, 0, 0],
+            );
+            self.ctx
+                .conn
+                .send_event(false, new_focus, xproto::EventMask::NO_EVENT, event)?;
+        }
+
+        // the focus history is updated only once the MRU cycle is finished
+        if self.mru_cycle.is_none() {
+            self.push_focus_history(new_focus);
+        }
+
+        // focusing another tile hidden behind the zoomed window restores the layout
+        if let Some(window) = self.windows.get(&new_focus) {
+            let desktop = window.desktop;
+            let zoomed = self.desktops[desktop].zoomed;
+            if zoomed.is_some_and(|id| id != new_focus) && !window.floating {
+                self.desktops[desktop].zoomed = None;
+                if let Some(monitor) = self.desktops[desktop].monitor {
+                    self.update_layout(monitor)?;
+                }
+            }
+        }
+
+        // bring the newly focused tab to the front
+        if let Some(window) = self.windows.get(&new_focus) {
+            let desktop = window.desktop;
+            if self.desktops[desktop].layout.has_tab_bar() {
+                if let Some(monitor) = self.desktops[desktop].monitor {
+                    self.update_layout(monitor)?;
+                }
+            }
+        }
+
+        self.update_preselection_hint()?;
+        Ok(())
+    }
+
+    /// Inserts the new window into the tiling order according to the insert position
+    /// of the desktop.
+    fn insert_into_order(&mut self, window: xproto::Window, desktop: usize) {
+        let position = config::DESKTOP_INSERT_POSITIONS
+            .iter()
+            .find(|(d, _)| *d == desktop)
+            .map_or(config::INSERT_POSITION, |(_, position)| *position);
+        let focused = self
+            .windows
+            .get(&self.focus)
+            .filter(|win| win.desktop == desktop)
+            .and_then(|win| self.window_order.iter().position(|id| *id == win.id));
+        let index = match (position, focused) {
+            // windows on the other desktops don't matter
+            (InsertPosition::Master, _) => 0,
+            (InsertPosition::AfterFocused, Some(i)) => i + 1,
+            _ => self.window_order.len(),
+        };
+        self.window_order.insert(index, window);
+    }
+
+    /// Returns the terminal that should be swallowed by the new window, that is, the focused
+    /// terminal window owned by one of the ancestor processes of the new window.
+    fn find_swallowed_terminal(&self, window: &Window) -> Result<Option<xproto::Window>> {
+        let terminal = match self.windows.get(&self.focus) {
+            Some(term) if !term.floating && term.swallowed.is_none() => term.id,
+            _ => return Ok(None),
+        };
+
+        let is_terminal = |id| -> Result<bool> {
+            Ok(utils::get_wm_class(&self.ctx, id)?
+                .is_some_and(|class| config::SWALLOW_CLASSES.contains(&class.as_str())))
+        };
+        if window.floating || !is_terminal(terminal)? || is_terminal(window.id)? {
+            return Ok(None);
+        }
+
+        let term_pid = self.windows[&terminal].pid;
+        let mut pid = window.pid;
+        while let Some(p) = pid {
+            if p <= 1 {
+                break;
+            }
+            if Some(p) == term_pid {
+                return Ok(Some(terminal));
+            }
+            pid = utils::get_parent_pid(p);
+        }
+        Ok(None)
+    }
+
+    /// Hides the terminal and gives its tile to the window.
+    fn swallow(&mut self, window: xproto::Window, terminal: xproto::Window) -> Result<()> {
+        log::debug!("window 0x{window:X} swallows terminal 0x{terminal:X}");
+
+        let term = self.windows.get_mut(&terminal).unwrap();
+        term.mapped = false;
+        term.hidden = true;
+        term.ignore_unmap_notify = true;
+        let desktop = term.desktop;
+        self.ctx.conn.unmap_window(terminal)?;
+        self.update_net_wm_state(terminal)?;
+
+        let win = self.windows.get_mut(&window).unwrap();
+        win.swallowed = Some(terminal);
+        win.desktop = desktop;
+
+        self.window_order.retain(|id| *id != window);
+        let pos = self.window_order.iter().position(|id| *id == terminal);
+        self.window_order.insert(pos.map_or(0, |i| i + 1), window);
+        if let Some(tree) = &mut self.desktops[desktop].tree {
+            tree.replace(terminal, window);
+        }
+        Ok(())
+    }
+
+    /// Restores the terminal swallowed by the window, if any.
+    fn release_swallowed(&mut self, window: xproto::Window) -> Result<()> {
+        let (terminal, desktop) = match self.windows.get_mut(&window) {
+            Some(win) => match win.swallowed.take() {
+                Some(terminal) => (terminal, win.desktop),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        if let Some(term) = self.windows.get_mut(&terminal) {
+            log::debug!("terminal 0x{terminal:X} is restored from window 0x{window:X}");
+            term.mapped = true;
+            term.hidden = false;
+            term.desktop = desktop;
+            self.update_net_wm_state(terminal)?;
+
+            self.window_order.retain(|id| *id != terminal);
+            let pos = self.window_order.iter().position(|id| *id == window);
+            self.window_order.insert(pos.unwrap_or(0), terminal);
+            if let Some(tree) = &mut self.desktops[desktop].tree {
+                tree.replace(window, terminal);
+            }
+
+            if let Some(monitor) = self.desktops[desktop].monitor {
+                self.ctx.conn.map_window(terminal)?;
+                self.update_layout(monitor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the window `other` (and its group) to the group of the visible window `window`.
+    fn join_group(&mut self, window: xproto::Window, other: xproto::Window) -> Result<()> {
+        let mut members = vec![window];
+        for id in [window, other] {
+            if let Some(i) = self.window_groups.iter().position(|g| g.contains(&id)) {
+                members.extend(self.window_groups.swap_remove(i));
+            }
+        }
+        members.push(other);
+        let mut group = Vec::new();
+        for id in members {
+            if !group.contains(&id) {
+                group.push(id);
+            }
+        }
+
+        log::debug!("window group: {group:X?}");
+        self.hide_window(other)?;
+        self.window_groups.push(group);
+        Ok(())
+    }
+
+    /// Removes the unmapped window from its group. If the window was the visible member,
+    /// another member takes over its place in the layout.
+    fn leave_group(&mut self, window: xproto::Window) -> Result<()> {
+        let i = match self.window_groups.iter().position(|g| g.contains(&window)) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let group = &mut self.window_groups[i];
+        let pos = group.iter().position(|id| *id == window).unwrap();
+        group.remove(pos);
+        let next = group[pos % group.len()];
+        if group.len() < 2 {
+            self.window_groups.swap_remove(i);
+        }
+
+        // the window has already been unmapped by the client
+        let win = &self.windows[&window];
+        if !win.hidden {
+            let desktop = win.desktop;
+            self.show_in_place_of(next, window)?;
+            if self.focus == window && self.desktops[desktop].monitor.is_some() {
+                self.change_focus(next)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmaps the window and removes it from the layout, keeping it managed.
+    fn hide_window(&mut self, window: xproto::Window) -> Result<()> {
+        let win = self.windows.get_mut(&window).unwrap();
+        if win.mapped {
+            win.mapped = false;
+            win.ignore_unmap_notify = true;
+            self.ctx.conn.unmap_window(window)?;
+        }
+        win.hidden = true;
+        self.update_net_wm_state(window)?;
+        Ok(())
+    }
+
+    /// Maps the window hidden by `hide_window` on the desktop.
+    fn show_hidden_window(&mut self, window: xproto::Window, desktop: usize) -> Result<()> {
+        let win = self.windows.get_mut(&window).unwrap();
+        win.mapped = true;
+        win.hidden = false;
+        win.desktop = desktop;
+        if self.desktops[desktop].monitor.is_some() {
+            self.ctx.conn.map_window(window)?;
+        } else {
+            win.ignore_unmap_notify = false;
+        }
+        self.update_net_wm_state(window)?;
+        Ok(())
+    }
+
+    /// Shows the window in the hidden stack on the desktop of the monitor and focuses it.
+    fn unhide(&mut self, window: xproto::Window, monitor: usize) -> Result<()> {
+        self.take_hidden(window);
+        let desktop = self.monitors[monitor].desktop;
+        self.show_hidden_window(window, desktop)?;
+        self.update_layout(monitor)?;
+        self.change_focus(window)
+    }
+
+    /// Removes the window from the hidden stacks, returning whether it was there.
+    fn take_hidden(&mut self, window: xproto::Window) -> bool {
+        let mut found = false;
+        for desktop in self.desktops.iter_mut() {
+            let len = desktop.hidden_stack.len();
+            desktop.hidden_stack.retain(|id| *id != window);
+            found |= desktop.hidden_stack.len() != len;
+        }
+        found
+    }
+
+    /// Hides the window `old` and shows the hidden window `new` at the same place in the layout.
+    fn replace_window(&mut self, old: xproto::Window, new: xproto::Window) -> Result<()> {
+        self.hide_window(old)?;
+        self.show_in_place_of(new, old)
+    }
+
+    /// Shows the hidden window `new` at the place of the window `old` in the layout.
+    fn show_in_place_of(&mut self, new: xproto::Window, old: xproto::Window) -> Result<()> {
+        let old_win = &self.windows[&old];
+        let (desktop, floating, geometry) = (old_win.desktop, old_win.floating, old_win.geometry);
+
+        let new_win = self.windows.get_mut(&new).unwrap();
+        new_win.floating = floating;
+        new_win.geometry = geometry;
+        self.show_hidden_window(new, desktop)?;
+
+        self.window_order.retain(|id| *id != new);
+        let pos = self.window_order.iter().position(|id| *id == old);
+        self.window_order.insert(pos.unwrap_or(0), new);
+        if let Some(tree) = &mut self.desktops[desktop].tree {
+            tree.replace(old, new);
+        }
+        Ok(())
+    }
+
+    /// Moves or resizes the focused window by the motion of the pointer to (x, y), dragging it
+    /// across monitors and showing the snapping preview.
+    fn drag_to(&mut self, x: i32, y: i32) -> Result<()> {
+        if let Some((prev_x, prev_y)) = self.dnd_position {
+            self.dnd_position = Some((x, y));
+
+            let dx = x - prev_x;
+            let dy = y - prev_y;
+
+            // a window dragged onto the pager stays still while the desktop under the pointer
+            // is highlighted as the drop target (daily2 has no bar with workspace labels to drop
+            // windows onto, so the pager is the only drop target)
+            if self.drag_action == Some(DragAction::Move) {
+                if let Some(pager) = &mut self.pager {
+                    let target = pager.desktop_at(x - pager.origin.0, y - pager.origin.1);
+                    if target != pager.drop_target {
+                        pager.drop_target = target;
+                        self.draw_pager()?;
+                    }
+                    if target.is_some() {
+                        self.dnd_position = Some((prev_x, prev_y));
+                        self.swap_target = None;
+                        self.preview_geometry = Rect::default();
+                        self.ctx.conn.unmap_window(self.preview_window)?;
+                        return Ok(());
+                    }
+                }
+            }
+
+            // resizing a tile changes its share of the split instead of making it floating
+            if self.drag_action == Some(DragAction::Resize) {
+                if let Some(axis) = self.tile_axis(self.focus) {
+                    return self.drag_tile_edge(axis, (prev_x, prev_y), dx, dy);
+                }
+            }
+
+            // a tile stays in the layout while it's dragged over the tiles of its desktop, and
+            // is made floating when it leaves them or enters a snapping area
+            let tiled = self
+                .windows
+                .get(&self.focus)
+                .is_some_and(|win| !win.floating && !win.fullscreen);
+            if self.drag_action == Some(DragAction::Move) && tiled {
+                let snapping = self
+                    .monitors
+                    .iter()
+                    .find(|mon| mon.geometry.contains(x, y))
+                    .is_some_and(|mon| snap(mon, x, y).is_some());
+                if let Some(tile) = self.tile_at(x, y).filter(|_| !snapping) {
+                    // the motion is accumulated, so that the window jumps to the pointer once
+                    // it's made floating
+                    self.dnd_position = Some((prev_x, prev_y));
+                    return self.highlight_swap_target(tile);
+                }
+                self.swap_target = None;
+            }
+
+            if let Some(window) = self.windows.get_mut(&self.focus) {
+                if !window.floating {
+                    window.floating = true;
+                    if let Some(monitor) = self.desktops[window.desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+            }
+
+            if let Some(window) = self.windows.get_mut(&self.focus) {
+                match self.drag_action {
+                    Some(DragAction::Move) => {
+                        window.geometry.x += dx;
+                        window.geometry.y += dy;
+                    }
+                    Some(DragAction::Resize) => {
+                        // the edges are chosen by the position where the drag started
+                        let edges = *self.resize_edges.get_or_insert_with(|| {
+                            let mon = self.desktops[window.desktop].monitor.unwrap();
+                            let mg = self.monitors[mon].geometry;
+                            resize_edges(window.geometry, prev_x - mg.x, prev_y - mg.y)
+                        });
+                        resize_from_edges(&mut window.geometry, edges, dx, dy);
+                    }
+                    None => {}
+                }
+
+                let mon = self.desktops[window.desktop].monitor.unwrap();
+                let mg = self.monitors[mon].geometry;
+                let ax = mg.x + window.geometry.x;
+                let ay = mg.y + window.geometry.y;
+
+                let mut migrated = None;
+                if mg.distance(x, y) > config::EDGE_RESISTANCE {
+                    // went out of the monitor
+
+                    if let Some(new_monitor) =
+                        self.monitors.iter().find(|mon| mon.geometry.contains(x, y))
+                    {
+                        window.desktop = new_monitor.desktop;
+                        window.geometry.x = ax - new_monitor.geometry.x;
+                        window.geometry.y = ay - new_monitor.geometry.y;
+                        let (dx, dy) =
+                            (mg.x - new_monitor.geometry.x, mg.y - new_monitor.geometry.y);
+                        migrated = Some((window.id, window.desktop, dx, dy));
+                    }
+                }
+
+                let mon = self.desktops[window.desktop].monitor.unwrap();
+                let mg = self.monitors[mon].geometry;
+                self.configured.remove(&window.id);
+                self.monitors[mon].stacking.clear();
+                let aux = xproto::ConfigureWindowAux::new()
+                    .x(mg.left() + window.geometry.x)
+                    .y(mg.top() + window.geometry.y)
+                    .width(window.geometry.w as u32)
+                    .height(window.geometry.h as u32)
+                    .stack_mode(xproto::StackMode::BELOW)
+                    .sibling(self.preview_window);
+                self.ctx.conn.configure_window(window.id, &aux)?;
+                if let Some(&bar) = self.title_bars.get(&window.id) {
+                    let abs = Rect {
+                        x: mg.left() + window.geometry.x,
+                        y: mg.top() + window.geometry.y,
+                        ..window.geometry
+                    };
+                    let geo = title_bar_geometry(abs, window.border_width(&self.monitors[mon]));
+                    self.configured.remove(&bar);
+                    let aux = xproto::ConfigureWindowAux::new()
+                        .x(geo.x)
+                        .y(geo.y)
+                        .width(geo.w as u32)
+                        .stack_mode(xproto::StackMode::ABOVE)
+                        .sibling(window.id);
+                    self.ctx.conn.configure_window(bar, &aux)?;
+                }
+
+                // transient windows move together with their parent
+                if let Some((parent, desktop, dx, dy)) = migrated {
+                    for id in self.transients_of(parent).into_iter().skip(1) {
+                        let transient = self.windows.get_mut(&id).unwrap();
+                        transient.desktop = desktop;
+                        transient.geometry.x += dx;
+                        transient.geometry.y += dy;
+                    }
+                    if let Some(monitor) = self.desktops[desktop].monitor {
+                        self.update_layout(monitor)?;
+                    }
+                }
+
+                let mut preview_visible = false;
+                if let Some(monitor) = self.monitors.iter().find(|mon| mon.geometry.contains(x, y))
+                {
+                    if let Some(geometry) = snap(monitor, x, y) {
+                        preview_visible = true;
+                        // the preview covers the snapped window including its border, whose
+                        // position is already the outer one
+                        let bwidth = monitor.border_width();
+                        let area = Rect {
+                            x: geometry.x,
+                            y: geometry.y,
+                            w: geometry.w + bwidth * 2,
+                            h: geometry.h + bwidth * 2,
+                        };
+                        let preview_bwidth = monitor.preview_border_width();
+                        self.place_preview(area, preview_bwidth, xproto::StackMode::TOP_IF)?;
+                    }
+                }
+                // shaped windows aren't snapped, since their edges aren't rectangular
+                let shaped = self.windows.get(&self.focus).is_some_and(|w| w.shaped);
+                if self.drag_action == Some(DragAction::Move) && preview_visible && !shaped {
+                    self.ctx.conn.map_window(self.preview_window)?;
+                } else {
+                    self.preview_geometry = Rect::default();
+                    self.ctx.conn.unmap_window(self.preview_window)?;
+                }
+                // the buttons follow the right end of the resized bar
+                self.draw_title_bar(self.focus)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the tile under the position on the desktop of the focused window, in the layouts
+    /// where tiles don't overlap.
+    fn tile_at(&self, x: i32, y: i32) -> Option<xproto::Window> {
+        let desktop = self.windows.get(&self.focus)?.desktop;
+        if self.desktops[desktop].layout.has_tab_bar() {
+            return None;
+        }
+        let monitor = &self.monitors[self.desktops[desktop].monitor?];
+        let mg = monitor.geometry;
+        mapped_windows!(self, desktop)
+            .filter(|win| !win.floating && !win.fullscreen)
+            .find(|win| {
+                let bwidth = win.border_width(monitor);
+                let g = win.geometry;
+                let outer = Rect {
+                    x: mg.x + g.x,
+                    y: mg.y + g.y,
+                    w: g.w + bwidth * 2,
+                    h: g.h + bwidth * 2,
+                };
+                outer.contains(x, y)
+            })
+            .map(|win| win.id)
+    }
+
+    /// Outlines the tile to be swapped with the dragged one with the preview window,
+    /// or hides the preview when the tile is the dragged one.
+    fn highlight_swap_target(&mut self, tile: xproto::Window) -> Result<()> {
+        if tile == self.focus {
+            self.swap_target = None;
+            self.preview_geometry = Rect::default();
+            self.ctx.conn.unmap_window(self.preview_window)?;
+            return Ok(());
+        }
+        self.swap_target = Some(tile);
+        let win = &self.windows[&tile];
+        let Some(monitor) = self.desktops[win.desktop].monitor else {
+            return Ok(());
+        };
+        let monitor = &self.monitors[monitor];
+        let (mg, bwidth) = (monitor.geometry, win.border_width(monitor));
+        let area = Rect {
+            x: mg.x + win.geometry.x,
+            y: mg.y + win.geometry.y,
+            w: win.geometry.w + bwidth * 2,
+            h: win.geometry.h + bwidth * 2,
+        };
+        let preview_bwidth = monitor.preview_border_width();
+        self.place_preview(area, preview_bwidth, xproto::StackMode::TOP_IF)?;
+        self.ctx.conn.map_window(self.preview_window)?;
+        Ok(())
+    }
+
+    /// Swaps the places of the two tiles on the same desktop in the layout.
+    fn swap_tiles(&mut self, a: xproto::Window, b: xproto::Window) -> Result<()> {
+        let desktop = self.windows[&a].desktop;
+        let ia = self.window_order.iter().position(|id| *id == a);
+        let ib = self.window_order.iter().position(|id| *id == b);
+        if let (Some(ia), Some(ib)) = (ia, ib) {
+            self.window_order.swap(ia, ib);
+        }
+        if let Some(tree) = &mut self.desktops[desktop].tree {
+            // no window has the ID 0
+            tree.replace(a, x11rb::NONE);
+            tree.replace(b, a);
+            tree.replace(x11rb::NONE, b);
+        }
+        // the shares of the places are kept
+        let weight = self.windows[&a].weight;
+        self.windows.get_mut(&a).unwrap().weight = self.windows[&b].weight;
+        self.windows.get_mut(&b).unwrap().weight = weight;
+        log::info!("swapped 0x{a:X} with 0x{b:X}");
+        match self.desktops[desktop].monitor {
+            Some(monitor) => self.update_layout(monitor),
+            None => Ok(()),
+        }
+    }
+
+    /// Resizes the focused tile by the motion of the pointer, scaling its weight as
+    /// `ResizeWindow` does. The dragged edge is chosen by the position where the drag started.
+    fn drag_tile_edge(
+        &mut self,
+        axis: SplitDirection,
+        (prev_x, prev_y): (i32, i32),
+        dx: i32,
+        dy: i32,
+    ) -> Result<()> {
+        let Some(window) = self.windows.get_mut(&self.focus) else {
+            return Ok(());
+        };
+        let Some(monitor) = self.desktops[window.desktop].monitor else {
+            return Ok(());
+        };
+        let mg = self.monitors[monitor].geometry;
+        let (ex, ey) = *self
+            .resize_edges
+            .get_or_insert_with(|| resize_edges(window.geometry, prev_x - mg.x, prev_y - mg.y));
+        // moving the left or top edge outward grows the tile as well
+        if !window.resize_tile(axis, ex * dx, ey * dy) {
+            return Ok(());
+        }
+        self.update_layout(monitor)
+    }
+
+    /// Follows the pointer moved during the drag. Raw motion events only tell that it moved,
+    /// so its position is queried once for all the events handled at a time.
+    fn follow_drag(&mut self) -> Result<()> {
+        if !std::mem::take(&mut self.drag_moved) || self.dnd_position.is_none() {
+            return Ok(());
+        }
+        let pointer = self.ctx.conn.query_pointer(self.ctx.root)?.reply()?;
+        self.drag_to(pointer.root_x as i32, pointer.root_y as i32)
+    }
+
+    /// Grabs the touchpad swipes with the numbers of fingers bound to commands, so that they
+    /// aren't handled by the windows under the pointer.
+    fn grab_swipes(&self) -> Result<()> {
+        if self.swipe_bindings.is_empty() {
+            return Ok(());
+        }
+        if !self.gestures {
+            log::warn!("XInput 2.4 is not available; touchpad swipes are never handled");
+            return Ok(());
+        }
+        let mut fingers: Vec<u32> = self.swipe_bindings.keys().map(|(n, _)| *n).collect();
+        fingers.sort_unstable();
+        fingers.dedup();
+        for n in fingers {
+            let reply = self
+                .ctx
+                .conn
+                .xinput_xi_passive_grab_device(
+                    x11rb::CURRENT_TIME,
+                    self.ctx.root,
+                    x11rb::NONE,
+                    n,
+                    u8::from(xinput::Device::ALL_MASTER),
+                    xinput::GrabType::GESTURE_SWIPE_BEGIN,
+                    xinput::GrabMode22::ASYNC,
+                    xproto::GrabMode::ASYNC,
+                    xinput::GrabOwner::NO_OWNER,
+                    &SWIPE_EVENT_MASK,
+                    &[xinput::ModifierMask::ANY.into()],
+                )?
+                .reply()?;
+            // the modifiers which failed to be grabbed are replied
+            if !reply.modifiers.is_empty() {
+                log::error!("Failed to grab {n}-finger swipes");
+            }
+        }
+        Ok(())
+    }
+
+    /// Selects raw motion events on the root window while dragging.
+    fn select_raw_motion(&self, enabled: bool) -> Result<()> {
+        if !self.xinput {
+            return Ok(());
+        }
+        let mask = if enabled {
+            xinput::XIEventMask::RAW_MOTION
+        } else {
+            0u32.into()
+        };
+        let masks = [xinput::EventMask {
+            deviceid: u8::from(xinput::Device::ALL_MASTER).into(),
+            mask: vec![mask],
+        }];
+        self.ctx
+            .conn
+            .xinput_xi_select_events(self.ctx.root, &masks)?;
+        Ok(())
+    }
+
+    /// Records the geometry of the floating window and the desktop of the window
+    /// for the next window of the same application.
+    fn remember_placement(&mut self, window: xproto::Window) {
+        let win = match self.windows.get(&window) {
+            Some(win) if win.transient_for.is_none() => win,
+            _ => return,
+        };
+        let class = match &win.class {
+            Some(class) => class,
+            None => return,
+        };
+
+        let mut changed = false;
+        if win.floating && !win.fullscreen && win.pip.is_none() && self.dropdown != Some(window) {
+            let g = win.geometry;
+            let geometry = [g.x, g.y, g.w, g.h];
+            if self.placements.floating.get(class) != Some(&geometry) {
+                self.placements.floating.insert(class.clone(), geometry);
+                changed = true;
+            }
+        }
+        if config::REMEMBER_DESKTOPS && !win.sticky {
+            let desktop = win.desktop;
+            if self.placements.desktops.get(class) != Some(&desktop) {
+                self.placements.desktops.insert(class.clone(), desktop);
+                changed = true;
+            }
+        }
+        // written a while after the changes, not on each drag
+        if changed {
+            self.set_timer(Timer::SavePlacements, PLACEMENTS_SAVE_DELAY);
+        }
+    }
+
+    /// Writes the placements changed but not written yet, before exiting.
+    fn flush_placements(&mut self) {
+        let len = self.timers.len();
+        self.timers
+            .retain(|(_, timer)| *timer != Timer::SavePlacements);
+        if self.timers.len() != len {
+            self.placements.save();
+        }
+    }
+
+    fn remove_window(&mut self, window: xproto::Window) -> Result<()> {
+        self.remember_placement(window);
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.mapped = false;
+        }
+        self.release_swallowed(window)?;
+        self.leave_group(window)?;
+        if let Some(window) = self.windows.remove(&window) {
+            self.window_order.retain(|id| *id != window.id);
+            self.configured.remove(&window.id);
+            for desktop in self.desktops.iter_mut() {
+                desktop.focus_history.retain(|id| *id != window.id);
+                desktop.hidden_stack.retain(|id| *id != window.id);
+            }
+            let desktop = window.desktop;
+            log::debug!("window 0x{:X} removed from desktop {}", window.id, desktop);
+            if let Some(monitor) = self.desktops[desktop].monitor {
+                self.update_layout(monitor)?;
+                if self.focus == window.id {
+                    self.focus_any(desktop)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn hide_desktop(&mut self, desktop: usize) -> Result<()> {
+        if self.desktops[desktop].monitor.is_none() {
+            return Ok(());
+        }
+
+        let mut lost_focus = false;
+        for window in mapped_windows_mut!(self, desktop) {
+            if self.focus == window.id {
+                lost_focus = true;
+            }
+            window.ignore_unmap_notify = true;
+            self.ctx.conn.unmap_window(window.id)?;
+        }
+
+        if lost_focus {
+            self.change_focus(x11rb::NONE)?;
+        }
+
+        self.desktops[desktop].monitor = None;
+        Ok(())
+    }
+
+    fn show_desktop(&mut self, desktop: usize, monitor: usize) -> Result<()> {
+        self.desktops[desktop].monitor = Some(monitor);
+        self.monitors[monitor].desktop = desktop;
+        self.update_layout(monitor)?;
+
+        for window in mapped_windows!(self, desktop) {
+            self.ctx.conn.map_window(window.id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the tabs shown on the monitor and the index of the visible one,
+    /// or `None` if the tab bar should not be shown.
+    fn tab_bar_state(&self, monitor: usize) -> Option<(Vec<xproto::Window>, usize)> {
+        let desktop = self.monitors[monitor].desktop;
+        if !self.desktops[desktop].layout.has_tab_bar() {
+            return None;
+        }
+
+        let tabs: Vec<xproto::Window> = mapped_windows!(self, desktop)
+            .filter(|win| !win.floating && !win.fullscreen)
+            .map(|win| win.id)
+            .collect();
+        if tabs.is_empty() {
+            return None;
+        }
+
+        let visible = std::iter::once(&self.focus)
+            .chain(self.desktops[desktop].focus_history.iter().rev())
+            .find_map(|id| tabs.iter().position(|tab| tab == id))
+            .unwrap_or(0);
+        Some((tabs, visible))
+    }
+
+    /// Returns the tab at the position (absolute) on the tab bar of the monitor.
+    fn tab_at(&self, monitor: usize, x: i32, y: i32) -> Option<xproto::Window> {
+        let (tabs, _) = self.tab_bar_state(monitor)?;
+        let desktop = self.monitors[monitor].desktop;
+        let mon_geo = self.monitors[monitor].geometry;
+        let rects = tab_rects(self.desktops[desktop].layout, mon_geo.w, tabs.len());
+        rects
+            .into_iter()
+            .position(|r| r.contains(x - mon_geo.x, y - mon_geo.y))
+            .map(|i| tabs[i])
+    }
+
+    fn draw_tab_bar(&mut self, monitor: usize) -> Result<()> {
+        let (tabs, visible) = match self.tab_bar_state(monitor) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        let tab_bar = self.monitors[monitor].tab_bar;
+        let font = self.tab_font;
+        let desktop = self.monitors[monitor].desktop;
+        let bar_w = self.monitors[monitor].geometry.w;
+        let rects = tab_rects(self.desktops[desktop].layout, bar_w, tabs.len());
+
+        for (i, (tab, r)) in tabs.iter().zip(rects).enumerate() {
+            let (x, y, w, h) = (r.x, r.y, r.w, r.h);
+            let baseline = y + (h + font.ascent - font.descent) / 2;
+            let (bg, fg) = if i == visible {
+                self.theme.tab_active
+            } else {
+                self.theme.tab_inactive
+            };
+
+            let aux = xproto::ChangeGCAux::new().foreground(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            let rect = xproto::Rectangle {
+                x: x as i16,
+                y: y as i16,
+                width: w as u16,
+                height: h as u16,
+            };
+            self.ctx
+                .conn
+                .poly_fill_rectangle(tab_bar, font.gc, &[rect])?;
+
+            // core fonts can only draw Latin-1 strings
+            let padding = 4;
+            let max_chars = ((w - padding * 2) / font.char_width).clamp(0, 255) as usize;
+            let title: Vec<u8> = self.windows[tab]
+                .title
+                .chars()
+                .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+                .take(max_chars)
+                .collect();
+
+            let aux = xproto::ChangeGCAux::new().foreground(fg).background(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            self.ctx.conn.image_text8(
+                tab_bar,
+                font.gc,
+                (x + padding) as i16,
+                baseline as i16,
+                &title,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Arranges the windows of the desktop on the focused monitor, or of all desktops, in a grid
+    /// on the monitor, grabbing the keyboard and the pointer to pick one of them.
+    fn open_overview(&mut self, all_desktops: bool) -> Result<()> {
+        let monitor = self.focused_monitor().unwrap_or(0);
+        let desktop = self.monitors[monitor].desktop;
+        let windows: Vec<xproto::Window> = self
+            .window_order
+            .iter()
+            .map(|id| &self.windows[id])
+            .filter(|win| win.mapped && (all_desktops || win.desktop == desktop))
+            .map(|win| win.id)
+            .collect();
+        if windows.is_empty() {
+            return Ok(());
+        }
+
+        let mut borrowed = Vec::new();
+        for id in &windows {
+            if self.desktops[self.windows[id].desktop].monitor.is_none() {
+                self.ctx.conn.map_window(*id)?;
+                borrowed.push(*id);
+            }
+        }
+        self.grab_keyboard()?;
+        let event_mask = xproto::EventMask::BUTTON_PRESS | xproto::EventMask::POINTER_MOTION;
+        let grab = self
+            .ctx
+            .conn
+            .grab_pointer(
+                false,
+                self.ctx.root,
+                event_mask,
+                xproto::GrabMode::ASYNC, // pointer
+                xproto::GrabMode::ASYNC, // keyboard
+                x11rb::NONE,
+                x11rb::NONE,
+                x11rb::CURRENT_TIME,
+            )?
+            .reply()?;
+        if grab.status != xproto::GrabStatus::SUCCESS {
+            log::warn!(
+                "Failed to grab the pointer for the overview: {:?}",
+                grab.status
+            );
+        }
+
+        let selected = windows.iter().position(|id| *id == self.focus).unwrap_or(0);
+        log::debug!("overview on monitor {monitor}: {windows:X?}");
+        self.overview = Some(Overview {
+            monitor,
+            windows: windows.clone(),
+            selected,
+            borrowed,
+        });
+        self.update_layout(monitor)?;
+        for id in windows {
+            self.update_border(id)?;
+        }
+        Ok(())
+    }
+
+    /// Places the windows of the overview in the grid on its monitor, above the others.
+    fn arrange_overview(&mut self) -> Result<()> {
+        let Some(overview) = &mut self.overview else {
+            return Ok(());
+        };
+        overview.windows.retain(|id| self.windows.contains_key(id));
+        if overview.windows.is_empty() || overview.monitor >= self.monitors.len() {
+            return self.close_overview(None);
+        }
+        overview.selected = overview.selected.min(overview.windows.len() - 1);
+
+        let mon = &self.monitors[overview.monitor];
+        let bwidth = mon.border_width();
+        let cells = layout::grid(mon.workarea, overview.windows.len(), mon.overview_gap());
+        for (id, cell) in overview.windows.iter().zip(cells) {
+            let geo = Rect {
+                x: mon.geometry.x + cell.x,
+                y: mon.geometry.y + cell.y,
+                w: (cell.w - bwidth * 2).max(1),
+                h: (cell.h - bwidth * 2).max(1),
+            };
+            let aux = xproto::ConfigureWindowAux::new()
+                .stack_mode(xproto::StackMode::ABOVE)
+                .x(geo.x)
+                .y(geo.y)
+                .width(geo.w as u32)
+                .height(geo.h as u32)
+                .border_width(bwidth as u32);
+            self.ctx.conn.configure_window(*id, &aux)?;
+            self.configured.insert(*id, (geo, bwidth as u32));
+        }
+        self.ctx.conn.unmap_window(mon.tab_bar)?;
+        // the layout restacks the windows after the overview
+        self.monitors[overview.monitor].stacking = overview.windows.clone();
+        Ok(())
+    }
+
+    /// Closes the overview restoring the layouts, and focuses the picked window if any.
+    fn close_overview(&mut self, picked: Option<xproto::Window>) -> Result<()> {
+        let Some(overview) = self.overview.take() else {
+            return Ok(());
+        };
+        self.ctx.conn.ungrab_pointer(x11rb::CURRENT_TIME)?;
+        self.release_keyboard()?;
+        for id in overview.borrowed {
+            if let Some(win) = self.windows.get_mut(&id) {
+                if win.mapped && self.desktops[win.desktop].monitor.is_none() {
+                    win.ignore_unmap_notify = true;
+                    self.ctx.conn.unmap_window(id)?;
+                }
+            }
+        }
+        for monitor in 0..self.monitors.len() {
+            self.update_layout(monitor)?;
+        }
+        for id in overview.windows {
+            self.update_border(id)?;
+        }
+        match picked.filter(|id| self.windows.contains_key(id)) {
+            Some(window) => self.focus_on_any_desktop(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Highlights the i-th window in the overview.
+    fn select_in_overview(&mut self, i: usize) -> Result<()> {
+        let Some(overview) = &mut self.overview else {
+            return Ok(());
+        };
+        if i == overview.selected || i >= overview.windows.len() {
+            return Ok(());
+        }
+        let old = overview.windows[overview.selected];
+        overview.selected = i;
+        let new = overview.windows[i];
+        self.update_border(old)?;
+        self.update_border(new)
+    }
+
+    /// Moves the selection in the overview grid with the arrow keys (or h/j/k/l) and Tab,
+    /// picks the selected window with Return or Space, and cancels with Escape.
+    fn handle_overview_key(&mut self, keycode: u8) -> Result<()> {
+        const XK_TAB: u32 = 0xFF09;
+        const XK_RETURN: u32 = 0xFF0D;
+        const XK_KP_ENTER: u32 = 0xFF8D;
+        const XK_SPACE: u32 = 0x0020;
+        const XK_ESCAPE: u32 = 0xFF1B;
+        const XK_LEFT: u32 = 0xFF51;
+        const XK_UP: u32 = 0xFF52;
+        const XK_RIGHT: u32 = 0xFF53;
+        const XK_DOWN: u32 = 0xFF54;
+        const XK_H: u32 = 0x0068;
+        const XK_J: u32 = 0x006A;
+        const XK_K: u32 = 0x006B;
+        const XK_L: u32 = 0x006C;
+
+        if self.modifier_keycodes.contains(&keycode) {
+            return Ok(());
+        }
+        let keysym = self.keysym(keycode);
+        let Some(overview) = &self.overview else {
+            return Ok(());
+        };
+        let (n, i) = (overview.windows.len(), overview.selected);
+        let columns = layout::grid_columns(n);
+        let next = match keysym {
+            XK_LEFT | XK_H if i % columns > 0 => i - 1,
+            XK_RIGHT | XK_L if i % columns + 1 < columns && i + 1 < n => i + 1,
+            XK_UP | XK_K if i >= columns => i - columns,
+            XK_DOWN | XK_J if i + columns < n => i + columns,
+            XK_TAB => (i + 1) % n,
+            XK_RETURN | XK_KP_ENTER | XK_SPACE => {
+                let window = overview.windows[i];
+                return self.close_overview(Some(window));
+            }
+            XK_ESCAPE => return self.close_overview(None),
+            _ => return Ok(()),
+        };
+        self.select_in_overview(next)
+    }
+
+    /// Shows the pager at the top of the focused monitor, with a cell for each desktop sized
+    /// after the monitor.
+    fn open_pager(&mut self) -> Result<()> {
+        let monitor = self.focused_monitor().unwrap_or(0);
+        let mon = &self.monitors[monitor];
+        let n = self.desktops.len();
+        let columns = layout::grid_columns(n);
+        let rows = n.div_ceil(columns);
+        let gap = mon.scaled(config::PAGER_GAP);
+        let cell_w = ((mon.geometry.w as f64 * config::PAGER_SCALE) as i32).max(1);
+        let cell_h = ((mon.geometry.h as f64 * config::PAGER_SCALE) as i32).max(1);
+        let cells: Vec<Rect> = (0..n)
+            .map(|i| Rect {
+                x: gap + (i % columns) as i32 * (cell_w + gap),
+                y: gap + (i / columns) as i32 * (cell_h + gap),
+                w: cell_w,
+                h: cell_h,
+            })
+            .collect();
+
+        let w = columns as i32 * (cell_w + gap) + gap;
+        let h = rows as i32 * (cell_h + gap) + gap;
+        let origin = (
+            mon.geometry.x + mon.workarea.x + (mon.workarea.w - w) / 2,
+            mon.geometry.y + mon.workarea.y + gap,
+        );
+        let aux = xproto::ConfigureWindowAux::new()
+            .x(origin.0)
+            .y(origin.1)
+            .width(w as u32)
+            .height(h as u32)
+            .stack_mode(xproto::StackMode::ABOVE);
+        self.ctx.conn.configure_window(self.pager_window, &aux)?;
+        self.ctx.conn.map_window(self.pager_window)?;
+
+        let mut pager = Pager {
+            monitor,
+            cells,
+            view: PagerView::default(),
+            pressed: None,
+            origin,
+            drop_target: None,
+        };
+        pager.view = self.pager_view(&pager);
+        self.pager = Some(pager);
+        self.draw_pager()
+    }
+
+    /// Returns the miniatures of the windows on the desktops, scaled from the monitors showing
+    /// them (or the monitor of the pager for hidden desktops) into the cells.
+    fn pager_view(&self, pager: &Pager) -> PagerView {
+        let windows = pager
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(desktop, cell)| {
+                let monitor = self.desktops[desktop].monitor.unwrap_or(pager.monitor);
+                let mon_geo = self.monitors[monitor].geometry;
+                let scale = cell.w as f64 / mon_geo.w.max(1) as f64;
+                let mut windows: Vec<&Window> = mapped_windows!(self, desktop).collect();
+                // tiled windows are below the floating ones, which are below fullscreen ones
+                windows.sort_by_key(|win| (win.fullscreen, win.floating));
+                windows
+                    .into_iter()
+                    .map(|win| {
+                        let geo = if win.fullscreen {
+                            Rect {
+                                x: 0,
+                                y: 0,
+                                ..mon_geo
+                            }
+                        } else {
+                            win.geometry
+                        };
+                        let x0 =
+                            (cell.x + (geo.x as f64 * scale) as i32).clamp(cell.x, cell.right());
+                        let y0 =
+                            (cell.y + (geo.y as f64 * scale) as i32).clamp(cell.y, cell.bottom());
+                        let x1 =
+                            (cell.x + (geo.right() as f64 * scale) as i32).clamp(x0, cell.right());
+                        let y1 = (cell.y + (geo.bottom() as f64 * scale) as i32)
+                            .clamp(y0, cell.bottom());
+                        let mini = Rect {
+                            x: x0,
+                            y: y0,
+                            w: x1 - x0,
+                            h: y1 - y0,
+                        };
+                        (win.id, mini)
+                    })
+                    .collect()
+            })
+            .collect();
+        PagerView {
+            windows,
+            focused_desktop: self.focused_monitor().map(|i| self.monitors[i].desktop),
+            focus: self.focus,
+        }
+    }
+
+    /// Redraws the pager if the windows or the focus have changed.
+    /// Returns whether the window is shown with a title bar.
+    fn wants_title_bar(&self, win: &Window) -> bool {
+        config::TITLE_BARS
+            && win.floating
+            && win.mapped
+            && !win.fullscreen
+            && !win.hidden
+            && !win.shaped
+            && win.pip.is_none()
+            && self.dropdown != Some(win.id)
+            && self.desktops[win.desktop].monitor.is_some()
+            && !self
+                .overview
+                .as_ref()
+                .is_some_and(|overview| overview.windows.contains(&win.id))
+    }
+
+    /// Returns the window which has the title bar.
+    fn title_bar_owner(&self, bar: xproto::Window) -> Option<xproto::Window> {
+        self.title_bars
+            .iter()
+            .find(|(_, b)| **b == bar)
+            .map(|(window, _)| *window)
+    }
+
+    /// Returns the width of the title bar of the window, which spans its border.
+    fn title_bar_width(&self, window: xproto::Window) -> i32 {
+        let Some(win) = self.windows.get(&window) else {
+            return 0;
+        };
+        let bwidth = match self.desktops[win.desktop].monitor {
+            Some(monitor) => win.border_width(&self.monitors[monitor]),
+            None => 0,
+        };
+        win.geometry.w + bwidth * 2
+    }
+
+    /// Creates the title bars of the floating windows shown on monitors, and destroys those of
+    /// the others.
+    fn update_title_bars(&mut self) -> Result<()> {
+        let unwanted: Vec<(xproto::Window, xproto::Window)> = self
+            .title_bars
+            .iter()
+            .filter(|(id, _)| {
+                !self
+                    .windows
+                    .get(id)
+                    .is_some_and(|win| self.wants_title_bar(win))
+            })
+            .map(|(id, bar)| (*id, *bar))
+            .collect();
+        for (id, bar) in unwanted {
+            self.title_bars.remove(&id);
+            self.configured.remove(&bar);
+            self.ctx.conn.destroy_window(bar)?;
+            if self.title_bar_hover.is_some_and(|(b, _)| b == bar) {
+                self.title_bar_hover = None;
+            }
+            if self.title_bar_pressed.is_some_and(|(b, _)| b == bar) {
+                self.title_bar_pressed = None;
+            }
+        }
+
+        let wanted: Vec<xproto::Window> = self
+            .windows
+            .values()
+            .filter(|win| !self.title_bars.contains_key(&win.id) && self.wants_title_bar(win))
+            .map(|win| win.id)
+            .collect();
+        let mut monitors = Vec::new();
+        let mut bars = Vec::new();
+        for id in wanted {
+            let bar = self.ctx.conn.generate_id()?;
+            let event_mask = xproto::EventMask::EXPOSURE
+                | xproto::EventMask::BUTTON_PRESS
+                | xproto::EventMask::BUTTON_RELEASE
+                | xproto::EventMask::POINTER_MOTION
+                | xproto::EventMask::LEAVE_WINDOW;
+            let aux = xproto::CreateWindowAux::new()
+                .override_redirect(1)
+                .background_pixel(self.theme.tab_inactive.0)
+                .event_mask(event_mask);
+            self.ctx.conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                bar,
+                self.ctx.root,
+                0, // x
+                0, // y
+                1, // width
+                1, // height
+                0, // border-width
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &aux,
+            )?;
+            self.title_bars.insert(id, bar);
+            bars.push(bar);
+            let monitor = self.desktops[self.windows[&id].desktop].monitor.unwrap();
+            if !monitors.contains(&monitor) {
+                monitors.push(monitor);
+            }
+        }
+        // the bars are mapped once they are placed above their windows
+        for monitor in monitors {
+            self.update_layout(monitor)?;
+        }
+        for bar in bars {
+            self.ctx.conn.map_window(bar)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the title bar of the window with its title and the buttons, if it has one.
+    fn draw_title_bar(&self, window: xproto::Window) -> Result<()> {
+        let (Some(&bar), Some(win)) = (self.title_bars.get(&window), self.windows.get(&window))
+        else {
+            return Ok(());
+        };
+        let font = self.tab_font;
+        let (w, h) = (self.title_bar_width(window), config::TAB_BAR_HEIGHT as i32);
+        let (bg, fg) = if window == self.focus {
+            self.theme.tab_active
+        } else {
+            self.theme.tab_inactive
+        };
+        let rect = |r: Rect| xproto::Rectangle {
+            x: r.x as i16,
+            y: r.y as i16,
+            width: r.w.max(0) as u16,
+            height: r.h.max(0) as u16,
+        };
+
+        let aux = xproto::ChangeGCAux::new().foreground(bg);
+        self.ctx.conn.change_gc(font.gc, &aux)?;
+        self.ctx
+            .conn
+            .poly_fill_rectangle(bar, font.gc, &[rect(Rect { x: 0, y: 0, w, h })])?;
+
+        // core fonts can only draw Latin-1 strings
+        let padding = 4;
+        let buttons_w = h * TitleButton::ALL.len() as i32;
+        let max_chars = ((w - buttons_w - padding * 2) / font.char_width).clamp(0, 255) as usize;
+        let title: Vec<u8> = win
+            .title
+            .chars()
+            .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+            .take(max_chars)
+            .collect();
+        let baseline = (h + font.ascent - font.descent) / 2;
+        let aux = xproto::ChangeGCAux::new().foreground(fg).background(bg);
+        self.ctx.conn.change_gc(font.gc, &aux)?;
+        self.ctx
+            .conn
+            .image_text8(bar, font.gc, padding as i16, baseline as i16, &title)?;
+
+        for button in TitleButton::ALL {
+            let r = button.rect(w);
+            if self.title_bar_hover == Some((bar, button)) {
+                let aux = xproto::ChangeGCAux::new().foreground(self.theme.button_hover);
+                self.ctx.conn.change_gc(font.gc, &aux)?;
+                self.ctx
+                    .conn
+                    .poly_fill_rectangle(bar, font.gc, &[rect(r)])?;
+            }
+            let aux = xproto::ChangeGCAux::new().foreground(fg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            // the symbol fills the middle third of the button
+            let m = r.w / 3;
+            let (x0, y0, x1, y1) = (r.x + m, r.y + m, r.x + r.w - m - 1, r.y + r.h - m - 1);
+            match button {
+                TitleButton::Close => {
+                    let segment = |x1: i32, y1: i32, x2: i32, y2: i32| xproto::Segment {
+                        x1: x1 as i16,
+                        y1: y1 as i16,
+                        x2: x2 as i16,
+                        y2: y2 as i16,
+                    };
+                    let segments = [segment(x0, y0, x1, y1), segment(x0, y1, x1, y0)];
+                    self.ctx.conn.poly_segment(bar, font.gc, &segments)?;
+                }
+                TitleButton::Maximize => {
+                    let r = Rect {
+                        x: x0,
+                        y: y0,
+                        w: x1 - x0,
+                        h: y1 - y0,
+                    };
+                    self.ctx.conn.poly_rectangle(bar, font.gc, &[rect(r)])?;
+                }
+                TitleButton::Float => {
+                    // two tiles side by side
+                    let half = (x1 - x0) / 2;
+                    let tiles = [
+                        Rect {
+                            x: x0,
+                            y: y0,
+                            w: half - 1,
+                            h: y1 - y0,
+                        },
+                        Rect {
+                            x: x0 + half + 1,
+                            y: y0,
+                            w: x1 - x0 - half - 1,
+                            h: y1 - y0,
+                        },
+                    ];
+                    self.ctx
+                        .conn
+                        .poly_rectangle(bar, font.gc, &tiles.map(rect))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Highlights the button under the pointer at (x, y) on the title bar, if any.
+    fn hover_title_bar(&mut self, bar: Option<xproto::Window>, x: i32, y: i32) -> Result<()> {
+        let h = config::TAB_BAR_HEIGHT as i32;
+        // nothing is highlighted during drags
+        let hover = bar
+            .filter(|_| self.dnd_position.is_none() && (0..h).contains(&y))
+            .and_then(|bar| {
+                let window = self.title_bar_owner(bar)?;
+                Some((bar, TitleButton::at(self.title_bar_width(window), x)?))
+            });
+        if hover == self.title_bar_hover {
+            return Ok(());
+        }
+        let old = std::mem::replace(&mut self.title_bar_hover, hover);
+        for (bar, _) in [old, hover].into_iter().flatten() {
+            if let Some(window) = self.title_bar_owner(bar) {
+                self.draw_title_bar(window)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Focuses and raises the window whose title bar is pressed, and starts moving it unless a
+    /// button is pressed.
+    fn press_title_bar(
+        &mut self,
+        window: xproto::Window,
+        press: &xproto::ButtonPressEvent,
+    ) -> Result<()> {
+        const MOUSE_L: u8 = 1;
+        if press.detail != MOUSE_L || self.dnd_position.is_some() {
+            return Ok(());
+        }
+        self.change_focus(window)?;
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.stacking_order = self.stacking_counter;
+            self.stacking_counter += 1;
+            if let Some(monitor) = self.desktops[win.desktop].monitor {
+                self.update_layout(monitor)?;
+            }
         }
 
-        let mut lost_focus = false;
-        for window in mapped_windows_mut!(self, desktop) {
-            if self.focus == window.id {
-                lost_focus = true;
+        let bar = self.title_bars[&window];
+        match TitleButton::at(self.title_bar_width(window), press.event_x as i32) {
+            Some(button) => self.title_bar_pressed = Some((bar, button)),
+            // the bar keeps getting the motion while the button is held
+            None if self.focus == window => {
+                self.select_raw_motion(true)?;
+                self.dnd_position = Some((press.root_x as i32, press.root_y as i32));
+                self.drag_action = Some(DragAction::Move);
+                self.button_count += 1;
+                self.hover_title_bar(None, 0, 0)?;
             }
-            window.ignore_unmap_notify = true;
-            self.ctx.conn.unmap_window(window.id)?;
+            None => {}
         }
-        self.ctx.conn.flush()?;
+        Ok(())
+    }
 
-        if lost_focus {
-            self.change_focus(x11rb::NONE)?;
+    fn click_title_button(&mut self, window: xproto::Window, button: TitleButton) -> Result<()> {
+        let Some(monitor) = self
+            .windows
+            .get(&window)
+            .and_then(|win| self.desktops[win.desktop].monitor)
+        else {
+            return Ok(());
+        };
+        match button {
+            TitleButton::Close => self.close_window(window)?,
+            TitleButton::Maximize => {
+                // the maximized window leaves room for its title bar
+                let mon = &self.monitors[monitor];
+                let mut full = snap_geometry(mon, SnapRegion::Full);
+                full.x -= mon.geometry.x;
+                full.y -= mon.geometry.y - config::TAB_BAR_HEIGHT as i32;
+                full.h -= config::TAB_BAR_HEIGHT as i32;
+                let win = self.windows.get_mut(&window).unwrap();
+                match win.restore_geometry.take() {
+                    Some(geometry) if win.geometry == full => win.geometry = geometry,
+                    _ => win.restore_geometry = Some(std::mem::replace(&mut win.geometry, full)),
+                }
+                self.update_layout(monitor)?;
+                self.remember_placement(window);
+            }
+            TitleButton::Float => {
+                self.remember_placement(window);
+                self.windows.get_mut(&window).unwrap().floating = false;
+                self.update_layout(monitor)?;
+            }
         }
+        Ok(())
+    }
 
-        self.desktops[desktop].monitor = None;
+    /// Asks the client to close the window with WM_DELETE_WINDOW, or disconnects the client if
+    /// it doesn't support that.
+    fn close_window(&self, window: xproto::Window) -> Result<()> {
+        if self
+            .windows
+            .get(&window)
+            .is_some_and(|win| win.delete_window)
+        {
+            let event = xproto::ClientMessageEvent::new(
+                32,
+                window,
+                self.ctx.atom.WM_PROTOCOLS,
+                [self.ctx.atom.WM_DELETE_WINDOW, x11rb::CURRENT_TIME, 0, 0, 0],
+            );
+            self.ctx
+                .conn
+                .send_event(false, window, xproto::EventMask::NO_EVENT, event)?;
+        } else {
+            self.ctx.conn.kill_client(window)?;
+        }
         Ok(())
     }
 
-    fn show_desktop(&mut self, desktop: usize, monitor: usize) -> Result<()> {
-        self.desktops[desktop].monitor = Some(monitor);
-        self.monitors[monitor].desktop = desktop;
-        self.update_layout(monitor)?;
+    fn update_pager(&mut self) -> Result<()> {
+        let Some(pager) = &self.pager else {
+            return Ok(());
+        };
+        if pager.monitor >= self.monitors.len() {
+            self.pager = None;
+            self.ctx.conn.unmap_window(self.pager_window)?;
+            return Ok(());
+        }
+        let view = self.pager_view(pager);
+        if view != pager.view {
+            self.pager.as_mut().unwrap().view = view;
+            self.draw_pager()?;
+        }
+        Ok(())
+    }
 
-        for window in mapped_windows!(self, desktop) {
-            self.ctx.conn.map_window(window.id)?;
+    fn draw_pager(&self) -> Result<()> {
+        let Some(pager) = &self.pager else {
+            return Ok(());
+        };
+        let font = self.tab_font;
+        let gc = font.gc;
+        let rect = |r: &Rect| xproto::Rectangle {
+            x: r.x as i16,
+            y: r.y as i16,
+            width: r.w.max(0) as u16,
+            height: r.h.max(0) as u16,
+        };
+        for (desktop, cell) in pager.cells.iter().enumerate() {
+            let (bg, fg) = if pager.drop_target == Some(desktop) {
+                (self.theme.button_hover, self.theme.tab_active.1)
+            } else if pager.view.focused_desktop == Some(desktop) {
+                self.theme.tab_active
+            } else {
+                self.theme.tab_inactive
+            };
+            let aux = xproto::ChangeGCAux::new().foreground(bg).background(bg);
+            self.ctx.conn.change_gc(gc, &aux)?;
+            self.ctx
+                .conn
+                .poly_fill_rectangle(self.pager_window, gc, &[rect(cell)])?;
+
+            for (id, mini) in &pager.view.windows[desktop] {
+                let color = if *id == pager.view.focus {
+                    self.theme.tab_active.1
+                } else {
+                    self.theme.tab_inactive.1
+                };
+                let aux = xproto::ChangeGCAux::new().foreground(color);
+                self.ctx.conn.change_gc(gc, &aux)?;
+                self.ctx
+                    .conn
+                    .poly_fill_rectangle(self.pager_window, gc, &[rect(mini)])?;
+                // the outline separates overlapping windows
+                let aux = xproto::ChangeGCAux::new().foreground(bg);
+                self.ctx.conn.change_gc(gc, &aux)?;
+                let outline = Rect {
+                    w: mini.w - 1,
+                    h: mini.h - 1,
+                    ..*mini
+                };
+                self.ctx
+                    .conn
+                    .poly_rectangle(self.pager_window, gc, &[rect(&outline)])?;
+            }
+
+            // desktops are numbered from 1 like in the prompt
+            let label = (desktop + 1).to_string();
+            let aux = xproto::ChangeGCAux::new().foreground(fg).background(bg);
+            self.ctx.conn.change_gc(gc, &aux)?;
+            self.ctx.conn.image_text8(
+                self.pager_window,
+                gc,
+                (cell.x + 2) as i16,
+                (cell.y + font.ascent + 1) as i16,
+                label.as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Opens the root menu next to the pointer at (x, y), listing the desktops displayed or
+    /// having windows, and the actions.
+    fn open_root_menu(&mut self, x: i32, y: i32) -> Result<()> {
+        let current = self.focused_monitor().map(|mon| self.monitors[mon].desktop);
+        let mut items: Vec<(String, Command)> = (0..self.desktops.len())
+            .filter(|&i| {
+                self.desktops[i].monitor.is_some() || self.windows.values().any(|w| w.desktop == i)
+            })
+            .map(|i| {
+                let mark = if Some(i) == current { '*' } else { ' ' };
+                (
+                    format!("{mark} desktop {}", i + 1),
+                    Command::SwitchDesktop(i),
+                )
+            })
+            .collect();
+        items.extend(
+            self.menu_actions
+                .iter()
+                .map(|(label, cmd)| (format!("  {label}"), cmd.clone())),
+        );
+
+        let padding = 4;
+        let chars = items
+            .iter()
+            .map(|(label, _)| label.len())
+            .max()
+            .unwrap_or(0);
+        let w = chars as i32 * self.tab_font.char_width + padding * 2;
+        let h = RootMenu::item_height() * items.len() as i32;
+
+        // the menu is kept inside the monitor, just off the pointer so that releasing the button
+        // right away doesn't pick an item
+        let mg = self
+            .monitors
+            .iter()
+            .find(|mon| mon.geometry.contains(x, y))
+            .map_or(Rect { x, y, w, h }, |mon| mon.geometry);
+        let menu_x = (x + 1).min(mg.right() - w - 2).max(mg.x);
+        let menu_y = (y + 1).min(mg.bottom() - h - 2).max(mg.y);
+        let aux = xproto::ConfigureWindowAux::new()
+            .x(menu_x)
+            .y(menu_y)
+            .width(w.max(1) as u32)
+            .height(h.max(1) as u32)
+            .stack_mode(xproto::StackMode::ABOVE);
+        self.ctx.conn.configure_window(self.menu_window, &aux)?;
+        self.ctx.conn.map_window(self.menu_window)?;
+
+        let event_mask = xproto::EventMask::BUTTON_PRESS
+            | xproto::EventMask::BUTTON_RELEASE
+            | xproto::EventMask::POINTER_MOTION;
+        let grab = self
+            .ctx
+            .conn
+            .grab_pointer(
+                false,
+                self.menu_window,
+                event_mask,
+                xproto::GrabMode::ASYNC, // pointer
+                xproto::GrabMode::ASYNC, // keyboard
+                x11rb::NONE,
+                x11rb::NONE,
+                x11rb::CURRENT_TIME,
+            )?
+            .reply()?;
+        if grab.status != xproto::GrabStatus::SUCCESS {
+            // the menu couldn't be closed by clicking outside it
+            log::warn!(
+                "Failed to grab the pointer for the root menu: {:?}",
+                grab.status
+            );
+            self.ctx.conn.unmap_window(self.menu_window)?;
+            return Ok(());
+        }
+
+        self.menu = Some(RootMenu {
+            items,
+            selected: None,
+            width: w,
+        });
+        self.draw_root_menu()
+    }
+
+    fn close_root_menu(&mut self) -> Result<()> {
+        self.menu = None;
+        self.ctx.conn.ungrab_pointer(x11rb::CURRENT_TIME)?;
+        self.ctx.conn.unmap_window(self.menu_window)?;
+        Ok(())
+    }
+
+    /// Highlights the item under the pointer at (x, y) relative to the menu.
+    fn highlight_menu_item(&mut self, x: i32, y: i32) -> Result<()> {
+        let Some(menu) = &mut self.menu else {
+            return Ok(());
+        };
+        let selected = menu.item_at(x, y);
+        if selected != menu.selected {
+            menu.selected = selected;
+            self.draw_root_menu()?;
+        }
+        Ok(())
+    }
+
+    fn draw_root_menu(&self) -> Result<()> {
+        let Some(menu) = &self.menu else {
+            return Ok(());
+        };
+        let font = self.tab_font;
+        let item_h = RootMenu::item_height();
+        for (i, (label, _)) in menu.items.iter().enumerate() {
+            let y = item_h * i as i32;
+            let (bg, fg) = if menu.selected == Some(i) {
+                self.theme.tab_active
+            } else {
+                self.theme.tab_inactive
+            };
+
+            let aux = xproto::ChangeGCAux::new().foreground(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            let rect = xproto::Rectangle {
+                x: 0,
+                y: y as i16,
+                width: menu.width as u16,
+                height: item_h as u16,
+            };
+            self.ctx
+                .conn
+                .poly_fill_rectangle(self.menu_window, font.gc, &[rect])?;
+
+            // core fonts can only draw Latin-1 strings
+            let text: Vec<u8> = label
+                .chars()
+                .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+                .take(255)
+                .collect();
+            let baseline = y + (item_h + font.ascent - font.descent) / 2;
+            let aux = xproto::ChangeGCAux::new().foreground(fg).background(bg);
+            self.ctx.conn.change_gc(font.gc, &aux)?;
+            self.ctx
+                .conn
+                .image_text8(self.menu_window, font.gc, 4, baseline as i16, &text)?;
         }
-        self.ctx.conn.flush()?;
         Ok(())
     }
 
     fn update_layout(&mut self, monitor: usize) -> Result<()> {
         log::trace!("update_layout: {monitor}");
+        self.metrics.record_layout();
+
+        if self.overview.as_ref().is_some_and(|o| o.monitor == monitor) {
+            return self.arrange_overview();
+        }
 
         let desktop = self.monitors[monitor].desktop;
         let mon_geo = self.monitors[monitor].geometry;
-        let bwidth = config::WINDOW_BORDER_WIDTH as i32;
 
         // normal windows
 
-        let sinked_windows: Vec<xproto::Window> = mapped_windows!(self, desktop)
+        let mut sinked_windows: Vec<xproto::Window> = mapped_windows!(self, desktop)
             .filter(|win| !win.floating && !win.fullscreen)
             .map(|win| win.id)
             .collect();
 
-        // NOTE: horizontal layout
-        if !sinked_windows.is_empty() {
-            let n = sinked_windows.len();
-            let each_w = mon_geo.w / n as i32;
-            let last_w = mon_geo.w - (n as i32 - 1) * each_w;
-            let each_h = mon_geo.h;
-
-            for (i, win) in sinked_windows.into_iter().enumerate() {
-                let x = each_w * (i as i32);
-                let y = 0;
-                let w = if i < n - 1 { each_w } else { last_w };
-
-                let geo = Rect {
-                    x,
-                    y,
-                    w: w - bwidth * 2,
-                    h: each_h - bwidth * 2,
-                };
-                self.windows.get_mut(&win).unwrap().geometry = geo;
+        let area = self.monitors[monitor].workarea;
+        let layout = self.desktops[desktop].layout;
+        let n = sinked_windows.len();
 
-                let aux = xproto::ConfigureWindowAux::new()
-                    .stack_mode(xproto::StackMode::ABOVE)
-                    .x(mon_geo.x + geo.x)
-                    .y(mon_geo.y + geo.y)
-                    .width(geo.w as u32)
-                    .height(geo.h as u32)
-                    .border_width(bwidth as u32);
-                self.ctx.conn.configure_window(win, &aux)?;
+        let orientation = if layout.has_tab_bar() {
+            Orientation::default()
+        } else {
+            let desk = &self.desktops[desktop];
+            Orientation {
+                transposed: desk.transposed,
+                mirrored: desk.mirrored,
+            }
+        };
+
+        let windows = &self.windows;
+        let weight = |id| windows.get(&id).map_or(1.0, |win: &Window| win.weight);
+        let arrangement = match layout {
+            Layout::Manual => {
+                let geometries = manual_layout(
+                    &mut self.desktops[desktop],
+                    self.focus,
+                    &sinked_windows,
+                    &weight,
+                    orientation.area(area),
+                );
+                sinked_windows = geometries.iter().map(|(id, _)| *id).collect();
+                Arrangement::Given(geometries.into_iter().map(|(_, geo)| geo).collect())
+            }
+            Layout::Horizontal => {
+                Arrangement::Weighted(sinked_windows.iter().map(|id| weight(*id)).collect())
+            }
+            Layout::Spiral => Arrangement::Spiral,
+            Layout::External => {
+                let tiles: Vec<layout_program::Tile> = sinked_windows
+                    .iter()
+                    .map(|id| layout_program::Tile {
+                        id: *id,
+                        weight: weight(*id),
+                        class: self.windows[id].class.as_deref(),
+                    })
+                    .collect();
+                let envs = vec![
+                    ("DAILY_DESKTOP", desktop.to_string()),
+                    ("DAILY_MONITOR", monitor.to_string()),
+                ];
+                // the horizontal layout is used while the program doesn't work
+                let cache = &mut self.layout_program_cache;
+                let geometries = config::LAYOUT_PROGRAM.and_then(|program| {
+                    let area = orientation.area(area);
+                    layout_program::run(cache, desktop, program, area, &tiles, envs)
+                });
+                match geometries {
+                    Some(geometries) => Arrangement::Given(geometries),
+                    None => Arrangement::Weighted(tiles.iter().map(|t| t.weight).collect()),
+                }
+            }
+            Layout::Columns => Arrangement::Columns(self.desktops[desktop].columns),
+            Layout::Tabbed | Layout::Stacking => {
+                // the visible tab is stacked on the top
+                if let Some((_, active)) = self.tab_bar_state(monitor) {
+                    let visible = sinked_windows.remove(active);
+                    sinked_windows.push(visible);
+                }
+                Arrangement::Titled(tab_bar_height(layout, n, area.h))
             }
+        };
+        let geometries = layout::tile(area, n, &arrangement, orientation);
+
+        let mut tiles: Vec<(xproto::Window, Rect)> =
+            sinked_windows.into_iter().zip(geometries).collect();
+
+        // the zoomed window covers the whole area above the others
+        let zoomed = self.desktops[desktop]
+            .zoomed
+            .and_then(|id| tiles.iter().position(|(win, _)| *win == id));
+        if let Some(i) = zoomed {
+            let (win, _) = tiles.remove(i);
+            tiles.push((win, area));
+        } else {
+            self.desktops[desktop].zoomed = None;
+        }
+
+        // the border makes no sense when only one tiled window is visible
+        let smart_borderless = config::SMART_BORDERS
+            && (n == 1 || zoomed.is_some() || matches!(layout, Layout::Tabbed | Layout::Stacking));
+
+        // the windows are configured from the bottom, each of them stacked on the top
+        let mut stack: Vec<(xproto::Window, Option<(Rect, u32)>)> = Vec::new();
+        for (win, outer) in tiles {
+            let bwidth = if smart_borderless {
+                0
+            } else {
+                self.windows[&win].border_width(&self.monitors[monitor])
+            };
+            let geo = layout::inside_border(outer, bwidth);
+            self.windows.get_mut(&win).unwrap().geometry = geo;
+            let abs = Rect {
+                x: mon_geo.x + geo.x,
+                y: mon_geo.y + geo.y,
+                ..geo
+            };
+            stack.push((win, Some((abs, bwidth as u32))));
+        }
+
+        // tab bar
+
+        let tab_bar = self.monitors[monitor].tab_bar;
+        let tab_bar_visible = self.tab_bar_state(monitor).is_some();
+        if tab_bar_visible {
+            let geo = Rect {
+                x: mon_geo.x + area.x,
+                y: mon_geo.y + area.y,
+                w: area.w,
+                h: tab_bar_height(layout, n, area.h).max(1),
+            };
+            stack.push((tab_bar, Some((geo, 0))));
         }
 
         // floating windows
@@ -1153,17 +7667,34 @@ impl Daily {
             .filter(|win| win.floating && !win.fullscreen)
             .cloned()
             .collect();
-        floating_windows.sort_by_key(|win| win.stacking_order);
+        // windows with _NET_WM_STATE_ABOVE are always on the top,
+        // and transient windows are stacked right above their parent
+        let parent_order = |win: &Window| {
+            win.transient_for
+                .and_then(|parent| self.windows.get(&parent))
+                .map_or(win.stacking_order, |parent| parent.stacking_order)
+        };
+        floating_windows.sort_by_key(|win| {
+            (
+                win.above,
+                parent_order(win),
+                win.transient_for.is_some(),
+                win.stacking_order,
+            )
+        });
 
-        for win in floating_windows {
-            let aux = xproto::ConfigureWindowAux::new()
-                .stack_mode(xproto::StackMode::ABOVE)
-                .x(mon_geo.x + win.geometry.x)
-                .y(mon_geo.y + win.geometry.y)
-                .width(win.geometry.w as u32)
-                .height(win.geometry.h as u32)
-                .border_width(bwidth as u32);
-            self.ctx.conn.configure_window(win.id, &aux)?;
+        for win in &floating_windows {
+            let abs = Rect {
+                x: mon_geo.x + win.geometry.x,
+                y: mon_geo.y + win.geometry.y,
+                ..win.geometry
+            };
+            let bwidth = win.border_width(&self.monitors[monitor]) as u32;
+            stack.push((win.id, Some((abs, bwidth))));
+            if let Some(&bar) = self.title_bars.get(&win.id) {
+                let geo = title_bar_geometry(abs, bwidth as i32);
+                stack.push((bar, Some((geo, 0))));
+            }
         }
 
         // fullscreen windows
@@ -1173,87 +7704,649 @@ impl Daily {
             .cloned()
             .collect();
         fullscreen_windows.sort_by_key(|win| win.stacking_order);
+        stack.extend(
+            fullscreen_windows
+                .iter()
+                .map(|win| (win.id, Some((mon_geo, 0)))),
+        );
 
-        for win in fullscreen_windows {
-            let aux = xproto::ConfigureWindowAux::new()
-                .stack_mode(xproto::StackMode::ABOVE)
-                .x(mon_geo.x)
-                .y(mon_geo.y)
-                .width(mon_geo.w as u32)
-                .height(mon_geo.h as u32)
-                .border_width(0);
-            self.ctx.conn.configure_window(win.id, &aux)?;
+        stack.extend(self.notifications.iter().map(|id| (*id, None)));
+        stack.push((self.preview_window, None));
+        if self.pager.is_some() {
+            stack.push((self.pager_window, None));
+        }
+        // windows of other monitors in the overview stay in its grid
+        if let Some(overview) = &self.overview {
+            stack.retain(|(id, _)| !overview.windows.contains(id));
         }
 
-        let aux = xproto::ConfigureWindowAux::new().stack_mode(xproto::StackMode::ABOVE);
-        self.ctx.conn.configure_window(self.preview_window, &aux)?;
+        // only the changes are applied, since configuring a window makes the client redraw it
+        let order: Vec<xproto::Window> = stack.iter().map(|(id, _)| *id).collect();
+        let restack = self.monitors[monitor].stacking != order;
+        for (id, geometry) in stack {
+            let mut aux = xproto::ConfigureWindowAux::new();
+            if restack {
+                aux = aux.stack_mode(xproto::StackMode::ABOVE);
+            }
+            if let Some((geo, bwidth)) = geometry {
+                if self.configured.get(&id) != Some(&(geo, bwidth)) {
+                    aux = aux
+                        .x(geo.x)
+                        .y(geo.y)
+                        .width(geo.w as u32)
+                        .height(geo.h as u32)
+                        .border_width(bwidth);
+                    self.configured.insert(id, (geo, bwidth));
+                }
+            }
+            if aux != xproto::ConfigureWindowAux::new() {
+                self.ctx.conn.configure_window(id, &aux)?;
+            }
+        }
+        self.monitors[monitor].stacking = order;
 
-        self.ctx.conn.flush()?;
-        Ok(())
+        for win in &floating_windows {
+            self.draw_title_bar(win.id)?;
+        }
+        if tab_bar_visible {
+            self.ctx.conn.map_window(tab_bar)?;
+            self.draw_tab_bar(monitor)?;
+        } else {
+            self.ctx.conn.unmap_window(tab_bar)?;
+        }
+
+        self.update_preselection_hint()
     }
 }
 
-fn snap(monitor_geometry: Rect, x: i32, y: i32) -> Option<Rect> {
-    let mg = monitor_geometry;
-    let d = config::SNAPPING_WIDTH as i32;
-    let bwidth = config::WINDOW_BORDER_WIDTH as i32;
-
-    let left = mg.left() <= x && x < mg.left() + d;
-    let right = mg.right() - d <= x && x < mg.right();
-    let top = mg.top() <= y && y < mg.top() + d;
-    let bottom = mg.bottom() - d <= y && y < mg.bottom();
-    let x_center = mg.x + mg.w / 2 - d <= x && x <= mg.x + mg.w / 2 + d;
-    let y_center = mg.y + mg.h / 2 - d <= y && y <= mg.y + mg.h / 2 + d;
-
-    let mut geometry = Rect::default();
-    if left && top {
-        geometry.x = mg.x;
-        geometry.y = mg.y;
-        geometry.w = mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h / 2 - bwidth * 2;
-    } else if left && bottom {
-        geometry.x = mg.x;
-        geometry.y = mg.y + mg.h / 2;
-        geometry.w = mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h - mg.h / 2 - bwidth * 2;
-    } else if right && top {
-        geometry.x = mg.x + mg.w / 2;
-        geometry.y = mg.y;
-        geometry.w = mg.w - mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h / 2 - bwidth * 2;
-    } else if right && bottom {
-        geometry.x = mg.x + mg.w / 2;
-        geometry.y = mg.y + mg.h / 2;
-        geometry.w = mg.w - mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h - mg.h / 2 - bwidth * 2;
-    } else if left {
-        geometry.x = mg.x;
-        geometry.y = mg.y;
-        geometry.w = mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h - bwidth * 2;
-    } else if right {
-        geometry.x = mg.x + mg.w / 2;
-        geometry.y = mg.y;
-        geometry.w = mg.w - mg.w / 2 - bwidth * 2;
-        geometry.h = mg.h - bwidth * 2;
-    } else if top {
-        geometry.x = mg.x;
-        geometry.y = mg.y;
-        geometry.w = mg.w - bwidth * 2;
-        geometry.h = mg.h / 2 - bwidth * 2;
-    } else if bottom {
-        geometry.x = mg.x;
-        geometry.y = mg.y + mg.h / 2;
-        geometry.w = mg.w - bwidth * 2;
-        geometry.h = mg.h - mg.h / 2 - bwidth * 2;
-    } else if x_center && y_center {
-        geometry.x = mg.x;
-        geometry.y = mg.y;
-        geometry.w = mg.w - bwidth * 2;
-        geometry.h = mg.h - bwidth * 2;
-    } else {
+/// the name of the kind of the event for the metrics
+fn event_kind(event: &Event) -> &'static str {
+    match event {
+        Event::KeyPress(_) => "KeyPress",
+        Event::KeyRelease(_) => "KeyRelease",
+        Event::ButtonPress(_) => "ButtonPress",
+        Event::ButtonRelease(_) => "ButtonRelease",
+        Event::MotionNotify(_) => "MotionNotify",
+        Event::Expose(_) => "Expose",
+        Event::LeaveNotify(_) => "LeaveNotify",
+        Event::MapRequest(_) => "MapRequest",
+        Event::UnmapNotify(_) => "UnmapNotify",
+        Event::DestroyNotify(_) => "DestroyNotify",
+        Event::ConfigureRequest(_) => "ConfigureRequest",
+        Event::ConfigureNotify(_) => "ConfigureNotify",
+        Event::PropertyNotify(_) => "PropertyNotify",
+        Event::ClientMessage(_) => "ClientMessage",
+        Event::MappingNotify(_) => "MappingNotify",
+        Event::RandrNotify(_) => "RandrNotify",
+        Event::RandrScreenChangeNotify(_) => "RandrScreenChangeNotify",
+        Event::ShapeNotify(_) => "ShapeNotify",
+        Event::XkbStateNotify(_) => "XkbStateNotify",
+        Event::XkbMapNotify(_) => "XkbMapNotify",
+        Event::XkbNewKeyboardNotify(_) => "XkbNewKeyboardNotify",
+        Event::XinputRawMotion(_) => "XinputRawMotion",
+        Event::XinputGestureSwipeBegin(_) => "XinputGestureSwipeBegin",
+        Event::XinputGestureSwipeUpdate(_) => "XinputGestureSwipeUpdate",
+        Event::XinputGestureSwipeEnd(_) => "XinputGestureSwipeEnd",
+        Event::Error(_) => "Error",
+        _ => "other",
+    }
+}
+
+/// Override-redirect windows (e.g. menus and tooltips) and InputOnly windows are not managed.
+/// A window already destroyed is not manageable either.
+fn is_manageable(
+    window: xproto::Window,
+    attrs: std::result::Result<xproto::GetWindowAttributesReply, x11rb::errors::ReplyError>,
+) -> Result<bool> {
+    match attrs {
+        Ok(attrs) => Ok(!attrs.override_redirect && attrs.class != xproto::WindowClass::INPUT_ONLY),
+        Err(x11rb::errors::ReplyError::X11Error(err)) => {
+            log::debug!("window 0x{window:X} is not manageable: {err:?}");
+            Ok(false)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Chooses the edges nearest to the point, dividing the window into thirds.
+/// A point in the middle resizes from the bottom-right corner.
+fn resize_edges(geometry: Rect, x: i32, y: i32) -> (i32, i32) {
+    let edge = |pos: i32, start: i32, len: i32| {
+        if pos < start + len / 3 {
+            -1
+        } else if pos >= start + len - len / 3 {
+            1
+        } else {
+            0
+        }
+    };
+    match (
+        edge(x, geometry.x, geometry.w),
+        edge(y, geometry.y, geometry.h),
+    ) {
+        (0, 0) => (1, 1),
+        edges => edges,
+    }
+}
+
+fn resize_from_edges(geometry: &mut Rect, (ex, ey): (i32, i32), dx: i32, dy: i32) {
+    match ex {
+        -1 => {
+            let dx = dx.min(geometry.w - 1);
+            geometry.x += dx;
+            geometry.w -= dx;
+        }
+        1 => geometry.w = (geometry.w + dx).max(1),
+        _ => {}
+    }
+    match ey {
+        -1 => {
+            let dy = dy.min(geometry.h - 1);
+            geometry.y += dy;
+            geometry.h -= dy;
+        }
+        1 => geometry.h = (geometry.h + dy).max(1),
+        _ => {}
+    }
+}
+
+/// Computes the scale for the DPI, rounded to a multiple of 0.25.
+/// Monitors without the physical size are not scaled.
+fn dpi_scale(width: i32, mm: u32) -> f64 {
+    if !config::DPI_SCALING || mm == 0 {
+        return 1.0;
+    }
+    let dpi = width as f64 * 25.4 / mm as f64;
+    let scale = ((dpi / config::BASE_DPI) * 4.0).round() / 4.0;
+    log::info!("{dpi:.0} DPI, scale {scale}");
+    scale.clamp(0.5, 4.0)
+}
+
+/// Finds the drag action bound to the button pressed with the modifiers.
+fn drag_action(state: xproto::KeyButMask, button: u8) -> Option<DragAction> {
+    let state = button_modifiers(state);
+    config::DRAG_BUTTONS
+        .iter()
+        .find(|(mods, b, _)| *b == button && u16::from(combined_modmask(mods)) == state)
+        .map(|(_, _, action)| *action)
+}
+
+/// the wheel buttons
+/// how long the placements are kept unwritten after a change
+const PLACEMENTS_SAVE_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+const SCROLL_UP: u8 = 4;
+const SCROLL_DOWN: u8 = 5;
+
+/// Finds the command of the wheel scrolled on the root window: on the background
+/// (`child` is none), or anywhere with ROOT_SCROLL_MODIFIERS if any.
+fn root_scroll(state: xproto::KeyButMask, button: u8, child: xproto::Window) -> Option<Command> {
+    let modifiers = config::ROOT_SCROLL_MODIFIERS?;
+    if button_modifiers(state) != u16::from(combined_modmask(modifiers)) {
+        return None;
+    }
+    if modifiers.is_empty() && child != x11rb::NONE {
+        return None;
+    }
+    match button {
+        SCROLL_UP => Some(Command::SwitchPrevDesktop),
+        SCROLL_DOWN => Some(Command::SwitchNextDesktop),
+        _ => None,
+    }
+}
+
+/// the modifiers which mouse bindings are distinguished by, in the state of button events
+fn button_modifiers(state: xproto::KeyButMask) -> u16 {
+    let modifiers = xproto::KeyButMask::SHIFT
+        | xproto::KeyButMask::CONTROL
+        | xproto::KeyButMask::MOD1
+        | xproto::KeyButMask::MOD4;
+    u16::from(state) & u16::from(modifiers)
+}
+
+/// the event mask of the swipe gestures, which x11rb doesn't define
+/// (XI_GestureSwipeBegin = 30, XI_GestureSwipeUpdate = 31 and XI_GestureSwipeEnd = 32)
+const SWIPE_EVENT_MASK: [u32; 2] = [1 << 30 | 1 << 31, 1];
+
+/// Returns the direction of the swipe which traveled (dx, dy), or None if it is too short.
+fn swipe_direction(dx: f64, dy: f64) -> Option<Direction> {
+    if dx.abs().max(dy.abs()) < config::SWIPE_THRESHOLD {
         return None;
     }
+    let direction = if dx.abs() >= dy.abs() {
+        if dx < 0.0 {
+            Direction::Left
+        } else {
+            Direction::Right
+        }
+    } else if dy < 0.0 {
+        Direction::Up
+    } else {
+        Direction::Down
+    };
+    Some(direction)
+}
+
+/// Shift, Lock, Control and Mod1-Mod5 in the state of key events
+const MODIFIER_BITS: u16 = 0x00FF;
+
+pub fn combined_modmask(modifiers: &[Modifier]) -> xproto::ModMask {
+    let mut modmask = xproto::ModMask::default();
+    for m in modifiers {
+        modmask = modmask | m.modmask();
+    }
+    modmask
+}
+
+const NET_WM_STATE_REMOVE: u32 = 0;
+const NET_WM_STATE_ADD: u32 = 1;
+const NET_WM_STATE_TOGGLE: u32 = 2;
+
+/// Returns the new value of a _NET_WM_STATE flag changed by the action of a client message.
+fn apply_state_action(action: u32, current: bool) -> bool {
+    match action {
+        NET_WM_STATE_REMOVE => false,
+        NET_WM_STATE_ADD => true,
+        NET_WM_STATE_TOGGLE => !current,
+        _ => current,
+    }
+}
+
+/// Returns the geometry (absolute) of the title bar above a floating window at `abs` (absolute,
+/// not including borders).
+fn title_bar_geometry(abs: Rect, bwidth: i32) -> Rect {
+    let h = config::TAB_BAR_HEIGHT as i32;
+    Rect {
+        x: abs.x,
+        y: abs.y - h,
+        w: abs.w + bwidth * 2,
+        h,
+    }
+}
+
+/// Returns the geometry of the dropdown window (relative to the monitor), spanning the width of
+/// the work area from its top.
+fn dropdown_geometry(monitor: &Monitor) -> Rect {
+    let area = monitor.workarea;
+    let bwidth = monitor.border_width();
+    let h = (area.h as f64 * config::DROPDOWN_HEIGHT).round() as i32;
+    Rect {
+        x: area.x,
+        y: area.y,
+        w: (area.w - bwidth * 2).max(1),
+        h: (h - bwidth * 2).max(1),
+    }
+}
+
+/// Returns the geometry of a picture-in-picture window (relative to the monitor).
+fn pip_geometry(monitor: &Monitor) -> Rect {
+    let mg = monitor.geometry;
+    let bwidth = monitor.border_width();
+    let margin = config::PIP_MARGIN as i32;
+    let (w, h) = (config::PIP_SIZE.0 as i32, config::PIP_SIZE.1 as i32);
+
+    let left = margin;
+    let right = mg.w - margin - w - bwidth * 2;
+    let top = margin;
+    let bottom = mg.h - margin - h - bwidth * 2;
+    let (x, y) = match config::PIP_CORNER {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    Rect { x, y, w, h }
+}
+
+/// Windows are placed according to the container tree of the desktop.
+/// The tree is synchronized with `tiles` first: windows no longer tiled are removed, and
+/// new windows are inserted next to the focused window.
+fn manual_layout(
+    desktop: &mut Desktop,
+    focus: xproto::Window,
+    tiles: &[xproto::Window],
+    weight: &impl Fn(xproto::Window) -> f64,
+    area: Rect,
+) -> Vec<(xproto::Window, Rect)> {
+    let mut tree = desktop
+        .tree
+        .take()
+        .and_then(|tree| tree.retain(&|id| tiles.contains(&id)));
+
+    // the preselected side is on the screen, which the tree is transformed to
+    let mut split = desktop.next_split;
+    if desktop.transposed {
+        split = split.map(Direction::transposed);
+    }
+    if desktop.mirrored {
+        split = split.map(Direction::mirrored);
+    }
+
+    for &window in tiles {
+        match &mut tree {
+            Some(tree) if tree.contains(window) => {}
+            Some(tree) => {
+                if !tree.insert(focus, window, split) {
+                    tree.push(window);
+                }
+                desktop.next_split = None;
+            }
+            None => tree = Some(Container::Window(window)),
+        }
+    }
 
-    Some(geometry)
+    let mut geometries = Vec::with_capacity(tiles.len());
+    if let Some(tree) = &tree {
+        tree.geometries(area, weight, &mut geometries);
+    }
+    desktop.tree = tree;
+    geometries
+}
+
+/// Returns the height of the tab bar showing `n` titles in the container of `container_h`.
+/// A pixel is left below the bar for the windows when the titles don't fit.
+fn tab_bar_height(layout: Layout, n: usize, container_h: i32) -> i32 {
+    let title_h = config::TAB_BAR_HEIGHT as i32;
+    let bar_h = match layout {
+        Layout::Stacking => title_h.saturating_mul(i32::try_from(n).unwrap_or(i32::MAX)),
+        _ => title_h,
+    };
+    bar_h.min(container_h - 1).max(0)
+}
+
+/// Returns the regions of `n` titles on the tab bar (relative to the tab bar).
+fn tab_rects(layout: Layout, bar_w: i32, n: usize) -> Vec<Rect> {
+    let title_h = config::TAB_BAR_HEIGHT as i32;
+    let bar = Rect {
+        x: 0,
+        y: 0,
+        w: bar_w,
+        h: title_h,
+    };
+    match layout {
+        // titles are listed vertically
+        Layout::Stacking => (0..n)
+            .map(|i| Rect {
+                x: 0,
+                y: title_h * i as i32,
+                w: bar_w,
+                h: title_h,
+            })
+            .collect(),
+        _ => layout::horizontal(bar, n),
+    }
+}
+
+fn snap(monitor: &Monitor, x: i32, y: i32) -> Option<Rect> {
+    layout::snap_region(monitor.geometry, monitor.snapping_width(), x, y)
+        .map(|region| snap_geometry(monitor, region))
+}
+
+/// Returns the geometry (absolute, not including borders) of the region of the work area of the
+/// monitor.
+fn snap_geometry(monitor: &Monitor, region: SnapRegion) -> Rect {
+    let area = Rect {
+        x: monitor.geometry.x + monitor.workarea.x,
+        y: monitor.geometry.y + monitor.workarea.y,
+        ..monitor.workarea
+    };
+    layout::snap_geometry(area, monitor.border_width(), region)
+}
+
+fn rect_json(rect: Rect) -> Json {
+    Json::Object(vec![
+        ("x", rect.x.into()),
+        ("y", rect.y.into()),
+        ("width", rect.w.into()),
+        ("height", rect.h.into()),
+    ])
+}
+
+/// Splits the payload of RUN_COMMAND into the commands, each with the criteria (like
+/// `[class="Firefox"]`, or empty) applied to it. As in i3, the criteria also apply to the
+/// commands chained after it with `,`, while `;` starts over without them. The separators in
+/// double quotes or in the criteria don't split the commands.
+fn split_i3_commands(payload: &str) -> Vec<(String, String)> {
+    let mut commands = Vec::new();
+    let mut criteria = String::new();
+    let mut rest = payload;
+    loop {
+        // the end of the command and the separator after it
+        let (mut quoted, mut escaped, mut bracket) = (false, false, false);
+        let end = rest.char_indices().find(|&(_, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                '[' if !quoted => bracket = true,
+                ']' if !quoted => bracket = false,
+                ';' | ',' if !quoted && !bracket => return true,
+                _ => {}
+            }
+            false
+        });
+        let (chunk, separator) = match end {
+            Some((i, separator)) => (&rest[..i], Some(separator)),
+            None => (rest, None),
+        };
+        let mut cmd = chunk.trim();
+        if cmd.starts_with('[') {
+            let (own, after) = cmd.split_at(cmd.find(']').map_or(cmd.len(), |i| i + 1));
+            criteria = own.to_owned();
+            cmd = after.trim();
+        }
+        if !cmd.is_empty() {
+            commands.push((criteria.clone(), cmd.to_owned()));
+        }
+        match separator {
+            Some(separator) => {
+                if separator == ';' {
+                    criteria.clear();
+                }
+                rest = &rest[chunk.len() + 1..];
+            }
+            None => return commands,
+        }
+    }
+}
+
+/// Translates an i3 command (e.g. `workspace number 3`) into a command.
+/// Commands of daily2 are also accepted. Of the criteria, only a class or a title is accepted,
+/// for `focus`.
+fn parse_i3_command(criteria: &str, cmd: &str) -> std::result::Result<Command, String> {
+    let words = command::split_words(cmd).ok_or("unbalanced quotes")?;
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    if !criteria.is_empty() {
+        let inner = criteria.trim_start_matches('[').trim_end_matches(']');
+        let inner = command::split_words(inner).ok_or("unbalanced quotes")?;
+        let matching = match inner.as_slice() {
+            [criterion] => match criterion.split_once('=') {
+                Some(("class", class)) => Some(WindowMatch::Class(class.to_owned())),
+                Some(("title", title)) => Some(WindowMatch::Title(title.to_owned())),
+                _ => None,
+            },
+            _ => None,
+        };
+        return match (matching, &words[..]) {
+            (Some(matching), ["focus"]) => Ok(Command::FocusMatching(matching)),
+            _ => Err(format!("unsupported criteria for {cmd:?}: {criteria}")),
+        };
+    }
+    // workspace names like "3" or "3:web" are numbered from 1
+    let workspace = |name: &str| -> std::result::Result<usize, String> {
+        let digits: String = name.chars().take_while(char::is_ascii_digit).collect();
+        match digits.parse::<usize>() {
+            Ok(n) if (1..=config::NUM_DESKTOPS).contains(&n) => Ok(n - 1),
+            _ => Err(format!("no such workspace: {name:?}")),
+        }
+    };
+    match words[..] {
+        ["workspace", "number", name] | ["workspace", name] => {
+            workspace(name).map(Command::SwitchDesktop)
+        }
+        ["move", .., "workspace", "number", name] | ["move", .., "workspace", name] => {
+            workspace(name).map(Command::MoveWindow)
+        }
+        ["mode", "default"] => Ok(Command::ExitMode),
+        ["mode", name] => Ok(Command::EnterMode(name.to_owned())),
+        ["exec", ..] => {
+            let cmdline = cmd.trim().strip_prefix("exec").unwrap().trim();
+            let cmdline = cmdline.strip_prefix("--no-startup-id").unwrap_or(cmdline);
+            Ok(Command::SpawnProcess(cmdline.trim().to_owned()))
+        }
+        ["floating", "toggle"] => Ok(Command::ToggleFloating),
+        ["focus", "output", "next"] => Ok(Command::FocusNextMonitor),
+        ["focus", "output", ..] => Err("only \"focus output next\" is supported".to_owned()),
+        ["focus", "right" | "down"] => Ok(Command::FocusNextWindow),
+        ["focus", "left" | "up"] => Ok(Command::FocusPrevWindow),
+        ["layout", "tabbed"] => Ok(Command::SetLayout(Layout::Tabbed)),
+        ["layout", "stacking"] => Ok(Command::SetLayout(Layout::Stacking)),
+        ["layout", "splith" | "splitv" | "toggle"] => Ok(Command::NextLayout),
+        ["split", "h" | "horizontal"] => Ok(Command::SplitHorizontal),
+        ["split", "v" | "vertical"] => Ok(Command::SplitVertical),
+        _ => cmd
+            .parse()
+            .map_err(|err: command::ParseError| err.to_string()),
+    }
+}
+
+/// the region of the monitor not reserved by the struts (relative to the monitor)
+fn workarea<'a>(
+    geometry: Rect,
+    screen: (i32, i32),
+    struts: impl Iterator<Item = &'a Strut>,
+) -> Rect {
+    let (mut left, mut right) = (geometry.left(), geometry.right());
+    let (mut top, mut bottom) = (geometry.top(), geometry.bottom());
+    // whether the range (with the end inclusive) overlaps [lo, hi)
+    let overlaps = |(start, end): (i32, i32), lo: i32, hi: i32| start < hi && lo <= end;
+    for strut in struts {
+        let vertical = (geometry.top(), geometry.bottom());
+        let horizontal = (geometry.left(), geometry.right());
+        if strut.left > 0 && overlaps(strut.ranges[0], vertical.0, vertical.1) {
+            left = left.max(strut.left.min(geometry.right()));
+        }
+        if strut.right > 0 && overlaps(strut.ranges[1], vertical.0, vertical.1) {
+            right = right.min((screen.0 - strut.right).max(geometry.left()));
+        }
+        if strut.top > 0 && overlaps(strut.ranges[2], horizontal.0, horizontal.1) {
+            top = top.max(strut.top.min(geometry.bottom()));
+        }
+        if strut.bottom > 0 && overlaps(strut.ranges[3], horizontal.0, horizontal.1) {
+            bottom = bottom.min((screen.1 - strut.bottom).max(geometry.top()));
+        }
+    }
+    Rect {
+        x: left - geometry.x,
+        y: top - geometry.y,
+        w: (right - left).max(1),
+        h: (bottom - top).max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(payload: &str) -> Vec<String> {
+        split_i3_commands(payload)
+            .into_iter()
+            .map(|(criteria, cmd)| format!("{criteria}|{cmd}"))
+            .collect()
+    }
+
+    #[test]
+    fn i3_commands_are_split_outside_quotes() {
+        assert_eq!(
+            commands(r#"workspace 2; exec sh -c "a; b""#),
+            ["|workspace 2", r#"|exec sh -c "a; b""#]
+        );
+        assert_eq!(
+            commands(r#"exec notify-send "a, \"b; c\"" ,, mode default;"#),
+            [r#"|exec notify-send "a, \"b; c\"""#, "|mode default"]
+        );
+        // single quotes are not quotes in i3
+        assert_eq!(commands("exec 'a; b'"), ["|exec 'a", "|b'"]);
+        assert!(commands("  ;  ").is_empty());
+    }
+
+    #[test]
+    fn i3_criteria_apply_until_semicolon() {
+        assert_eq!(
+            commands(r#"[class="a;b"] focus, floating toggle; focus left"#),
+            [
+                r#"[class="a;b"]|focus"#,
+                r#"[class="a;b"]|floating toggle"#,
+                "|focus left",
+            ]
+        );
+        assert_eq!(
+            commands("[title=x] focus, [class=y] focus"),
+            ["[title=x]|focus", "[class=y]|focus"]
+        );
+    }
+
+    #[test]
+    fn stacked_titles_leave_room_for_the_windows() {
+        let title_h = config::TAB_BAR_HEIGHT as i32;
+        assert_eq!(tab_bar_height(Layout::Tabbed, 100, 1000), title_h);
+        assert_eq!(tab_bar_height(Layout::Stacking, 3, 1000), title_h * 3);
+        for (n, container_h) in [(100, title_h * 10), (usize::MAX, 1000), (1, 1), (5, 0)] {
+            let bar_h = tab_bar_height(Layout::Stacking, n, container_h);
+            assert!(
+                bar_h >= 0 && bar_h < container_h.max(1),
+                "{bar_h} for {n} titles"
+            );
+            assert!(bar_h > 0 || container_h <= 1);
+            let windows = layout::titled(
+                Rect {
+                    x: 0,
+                    y: 0,
+                    w: 100,
+                    h: container_h,
+                },
+                bar_h,
+                1,
+            );
+            assert!(windows[0].h >= 1 || container_h < 1, "{:?}", windows[0]);
+        }
+    }
+
+    #[test]
+    fn i3_commands_are_translated() {
+        let parse = |cmd| parse_i3_command("", cmd).map(|cmd| cmd.to_string());
+        assert_eq!(parse("workspace number 2").unwrap(), "switch-desktop 1");
+        assert_eq!(parse(r#"workspace "3: web""#).unwrap(), "switch-desktop 2");
+        assert_eq!(
+            parse("move container to workspace 1").unwrap(),
+            "move-window 0"
+        );
+        assert_eq!(parse(r#"mode "default""#).unwrap(), "exit-mode");
+        assert_eq!(parse(r#"mode "resize""#).unwrap(), "enter-mode resize");
+        assert_eq!(
+            parse("exec --no-startup-id sh -c 'echo hi'").unwrap(),
+            "spawn sh -c 'echo hi'"
+        );
+        assert_eq!(parse("layout tabbed").unwrap(), "set-layout tabbed");
+        assert_eq!(parse("focus output next").unwrap(), "focus-next-monitor");
+        assert!(parse("focus output HDMI-1").is_err());
+        // commands of daily2
+        assert_eq!(parse("toggle-zoom").unwrap(), "toggle-zoom");
+        assert!(parse("workspace 0").is_err());
+        assert!(parse("workspace web").is_err());
+        assert!(parse(r#"mode "open"#).is_err());
+        assert!(parse("bogus").is_err());
+
+        let focus = |criteria| parse_i3_command(criteria, "focus").map(|cmd| cmd.to_string());
+        assert_eq!(
+            focus(r#"[class="Firefox"]"#).unwrap(),
+            "focus --class Firefox"
+        );
+        assert_eq!(
+            focus(r#"[title="a title"]"#).unwrap(),
+            "focus --title a title"
+        );
+        assert!(focus("[instance=x]").is_err());
+        assert!(parse_i3_command("[class=x]", "kill").is_err());
+    }
 }
@@ -5,6 +5,9 @@ pub enum Error {
     #[error("interrupted by user")]
     Interrupted { restart: bool },
 
+    #[error("the key is already grabbed by another client")]
+    KeyAlreadyGrabbed,
+
     #[error(transparent)]
     X11(ReplyOrIdError),
 }
@@ -0,0 +1,269 @@
+use std::io::{Read as _, Write as _};
+use std::os::unix::io::{AsRawFd as _, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::ipc::Json;
+
+// A subset of the i3 IPC protocol (https://i3wm.org/docs/ipc.html), enough for i3bar and
+// the i3 modules of bars like polybar. Each message consists of the magic string "i3-ipc",
+// the length of the payload, the message type (both 32-bit native endian) and the payload.
+
+const MAGIC: &[u8] = b"i3-ipc";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+/// the longest payload accepted, above which the client is disconnected
+const MAX_PAYLOAD_LEN: usize = 1 << 20;
+
+pub const RUN_COMMAND: u32 = 0;
+pub const GET_WORKSPACES: u32 = 1;
+pub const SUBSCRIBE: u32 = 2;
+pub const GET_OUTPUTS: u32 = 3;
+pub const GET_TREE: u32 = 4;
+pub const GET_MARKS: u32 = 5;
+pub const GET_BAR_CONFIG: u32 = 6;
+pub const GET_VERSION: u32 = 7;
+pub const GET_BINDING_MODES: u32 = 8;
+pub const GET_CONFIG: u32 = 9;
+pub const SEND_TICK: u32 = 10;
+
+/// the kinds of events clients can subscribe to, and their message types
+pub const EVENT_WORKSPACE: (&str, u32) = ("workspace", 0x8000_0000);
+pub const EVENT_OUTPUT: (&str, u32) = ("output", 0x8000_0001);
+pub const EVENT_MODE: (&str, u32) = ("mode", 0x8000_0002);
+pub const EVENT_WINDOW: (&str, u32) = ("window", 0x8000_0003);
+
+/// a message received from a client
+pub struct Message {
+    pub client: usize,
+    pub kind: u32,
+    pub payload: String,
+}
+
+struct Client {
+    stream: UnixStream,
+    /// bytes received but not forming a whole message yet
+    buffer: Vec<u8>,
+    /// the kinds of the subscribed events
+    events: Vec<String>,
+}
+
+/// a listening socket speaking the i3 IPC protocol, and connected clients
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Option<Client>>,
+}
+
+impl Server {
+    /// Creates the socket and tells its path to i3 clients by `$I3SOCK`.
+    /// The path is also to be set to the `I3_SOCKET_PATH` property of the root window.
+    pub fn bind() -> Option<Server> {
+        let path = socket_path();
+        let _ = std::fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("Failed to create the i3 IPC socket {path:?}: {err}");
+                return None;
+            }
+        };
+        if let Err(err) = listener.set_nonblocking(true) {
+            log::error!("Failed to make the i3 IPC socket non-blocking: {err}");
+            return None;
+        }
+        log::info!("i3 IPC socket: {path:?}");
+        std::env::set_var("I3SOCK", &path);
+        Some(Server {
+            listener,
+            path,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// the listening socket followed by the connected clients
+    pub fn fds(&self) -> Vec<RawFd> {
+        let mut fds = vec![self.listener.as_raw_fd()];
+        fds.extend(
+            self.clients
+                .iter()
+                .flatten()
+                .map(|client| client.stream.as_raw_fd()),
+        );
+        fds
+    }
+
+    /// Accepts new clients and reads the messages from the readable ones,
+    /// given in the same order as `fds`.
+    pub fn receive(&mut self, readable: &[bool]) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let connected: Vec<usize> = (0..self.clients.len())
+            .filter(|i| self.clients[*i].is_some())
+            .collect();
+        for (&i, _) in connected
+            .iter()
+            .zip(readable.iter().skip(1))
+            .filter(|(_, readable)| **readable)
+        {
+            if !self.read_client(i, &mut messages) {
+                log::debug!("i3 IPC client {i} disconnected");
+                self.clients[i] = None;
+            }
+        }
+
+        if readable.first() == Some(&true) {
+            self.accept();
+        }
+        messages
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::warn!("Failed to accept an i3 IPC connection: {err}");
+                    break;
+                }
+            };
+            // reads are done only when the socket is readable, so they don't block
+            let setup = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(Some(Duration::from_millis(100))));
+            if let Err(err) = setup {
+                log::warn!("Failed to set up an i3 IPC connection: {err}");
+                continue;
+            }
+            let client = Client {
+                stream,
+                buffer: Vec::new(),
+                events: Vec::new(),
+            };
+            match self.clients.iter().position(Option::is_none) {
+                Some(i) => self.clients[i] = Some(client),
+                None => self.clients.push(Some(client)),
+            }
+        }
+    }
+
+    /// Returns false if the client is disconnected or sends a malformed message.
+    fn read_client(&mut self, i: usize, messages: &mut Vec<Message>) -> bool {
+        let client = self.clients[i].as_mut().unwrap();
+        let mut buf = [0; 4096];
+        match client.stream.read(&mut buf) {
+            Ok(0) | Err(_) => return false,
+            Ok(n) => client.buffer.extend_from_slice(&buf[..n]),
+        }
+
+        while client.buffer.len() >= HEADER_LEN {
+            if &client.buffer[..MAGIC.len()] != MAGIC {
+                log::warn!("i3 IPC client {i} sent a malformed message");
+                return false;
+            }
+            let field = |offset: usize| {
+                let bytes = &client.buffer[MAGIC.len() + offset..MAGIC.len() + offset + 4];
+                u32::from_ne_bytes(bytes.try_into().unwrap())
+            };
+            let (len, kind) = (field(0) as usize, field(4));
+            if len > MAX_PAYLOAD_LEN {
+                log::warn!("i3 IPC client {i} sent a message of {len} bytes");
+                return false;
+            }
+            if client.buffer.len() < HEADER_LEN + len {
+                break;
+            }
+            let payload: Vec<u8> = client.buffer.drain(..HEADER_LEN + len).collect();
+            messages.push(Message {
+                client: i,
+                kind,
+                payload: String::from_utf8_lossy(&payload[HEADER_LEN..]).into_owned(),
+            });
+        }
+        true
+    }
+
+    pub fn reply(&mut self, client: usize, kind: u32, payload: &Json) {
+        self.send(client, kind, &payload.to_string());
+    }
+
+    /// Returns whether any client subscribes to events.
+    pub fn has_subscribers(&self) -> bool {
+        self.clients
+            .iter()
+            .flatten()
+            .any(|client| !client.events.is_empty())
+    }
+
+    /// Adds the events to the subscription of the client.
+    pub fn subscribe(&mut self, client: usize, events: Vec<String>) {
+        if let Some(Some(client)) = self.clients.get_mut(client) {
+            client.events.extend(events);
+        }
+    }
+
+    /// Sends the event to the clients subscribing it.
+    pub fn broadcast(&mut self, (event, kind): (&str, u32), payload: &Json) {
+        let payload = payload.to_string();
+        for i in 0..self.clients.len() {
+            let subscribed = self.clients[i]
+                .as_ref()
+                .is_some_and(|client| client.events.iter().any(|e| e == event));
+            if subscribed {
+                self.send(i, kind, &payload);
+            }
+        }
+    }
+
+    fn send(&mut self, i: usize, kind: u32, payload: &str) {
+        let client = match self.clients.get_mut(i) {
+            Some(Some(client)) => client,
+            _ => return,
+        };
+        let mut message = MAGIC.to_vec();
+        message.extend((payload.len() as u32).to_ne_bytes());
+        message.extend(kind.to_ne_bytes());
+        message.extend(payload.as_bytes());
+        if let Err(err) = client.stream.write_all(&message) {
+            log::warn!("Failed to send an i3 IPC message to client {i}: {err}");
+            self.clients[i] = None;
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// the strings in a JSON array like `["workspace","mode"]`, which is the payload of SUBSCRIBE
+pub fn parse_string_array(payload: &str) -> Option<Vec<String>> {
+    let inner = payload.trim().strip_prefix('[')?.strip_suffix(']')?.trim();
+    if inner.is_empty() {
+        return Some(Vec::new());
+    }
+    inner
+        .split(',')
+        .map(|item| {
+            let item = item.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some(item.to_owned())
+        })
+        .collect()
+}
+
+/// `$XDG_RUNTIME_DIR/daily2-i3.<pid>.sock`, or `/tmp/daily2-i3-<uid>.<pid>.sock`
+fn socket_path() -> PathBuf {
+    let pid = std::process::id();
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(format!("daily2-i3.{pid}.sock")),
+        _ => {
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/daily2-i3-{uid}.{pid}.sock"))
+        }
+    }
+}
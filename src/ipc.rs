@@ -0,0 +1,337 @@
+use std::fmt;
+use std::io::{Read as _, Write as _};
+use std::os::unix::fs::PermissionsExt as _;
+use std::os::unix::io::{AsRawFd as _, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Clients connect to the socket, write a request terminated by a newline and read the reply
+// until the connection is closed, e.g.
+//
+//   $ echo get_focus | socat - UNIX-CONNECT:$DAILY2_SOCKET
+//   {"window":4194305,"monitor":0,"desktop":1}
+//
+// A request is either a query (`get_windows`, `get_desktops`, `get_monitors`, `get_focus`,
+// `get_metrics`) replied with JSON, or a command (e.g. `move-window 3`) replied with
+// {"success":...}.
+//
+// The requests are read without blocking as they arrive, so a slow client doesn't stop the WM.
+
+/// the longest request accepted, above which the client is disconnected
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// a listening socket accepting IPC requests
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    /// the connected clients whose request hasn't been read wholly yet
+    pending: Vec<Pending>,
+}
+
+struct Pending {
+    stream: UnixStream,
+    /// bytes received before the newline
+    buffer: Vec<u8>,
+}
+
+impl Server {
+    /// Creates the socket and exports its path as `$DAILY2_SOCKET` to child processes.
+    pub fn bind() -> Option<Server> {
+        let path = socket_path();
+        // a socket left by a previous process which didn't exit cleanly (or restarted)
+        let _ = std::fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("Failed to create the IPC socket {path:?}: {err}");
+                return None;
+            }
+        };
+        // not to be connected by other users, e.g. in /tmp
+        let permissions = std::fs::Permissions::from_mode(0o600);
+        if let Err(err) = std::fs::set_permissions(&path, permissions) {
+            log::error!("Failed to restrict the permissions of the IPC socket {path:?}: {err}");
+            return None;
+        }
+        if let Err(err) = listener.set_nonblocking(true) {
+            log::error!("Failed to make the IPC socket non-blocking: {err}");
+            return None;
+        }
+        log::info!("IPC socket: {path:?}");
+        std::env::set_var("DAILY2_SOCKET", &path);
+        Some(Server {
+            listener,
+            path,
+            pending: Vec::new(),
+        })
+    }
+
+    /// the listening socket followed by the clients being read
+    pub fn fds(&self) -> Vec<RawFd> {
+        let mut fds = vec![self.listener.as_raw_fd()];
+        fds.extend(
+            self.pending
+                .iter()
+                .map(|pending| pending.stream.as_raw_fd()),
+        );
+        fds
+    }
+
+    /// Reads the readable clients (given in the same order as `fds`) and accepts new ones, and
+    /// returns the requests read wholly.
+    pub fn receive(&mut self, readable: &[bool]) -> Vec<Request> {
+        let mut requests = Vec::new();
+        for (i, client) in std::mem::take(&mut self.pending).into_iter().enumerate() {
+            if readable.get(i + 1) != Some(&true) {
+                self.pending.push(client);
+                continue;
+            }
+            match client.read() {
+                Progress::Reading(client) => self.pending.push(client),
+                Progress::Done(request) => requests.push(request),
+                Progress::Closed => log::debug!("IPC client {i} disconnected"),
+            }
+        }
+
+        if readable.first() == Some(&true) {
+            self.accept();
+        }
+        requests
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::warn!("Failed to accept an IPC connection: {err}");
+                    break;
+                }
+            };
+            if let Err(err) = stream.set_nonblocking(true) {
+                log::warn!("Failed to set up an IPC connection: {err}");
+                continue;
+            }
+            self.pending.push(Pending {
+                stream,
+                buffer: Vec::new(),
+            });
+        }
+    }
+}
+
+/// what reading a client results in
+enum Progress {
+    /// the request is not complete yet
+    Reading(Pending),
+    Done(Request),
+    /// the client is disconnected or misbehaves
+    Closed,
+}
+
+impl Pending {
+    /// Reads what has arrived.
+    fn read(mut self) -> Progress {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                // a request without the newline at EOF is taken as it is
+                Ok(0) if self.buffer.is_empty() => return Progress::Closed,
+                Ok(0) => return self.into_request(),
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::warn!("Failed to read an IPC request: {err}");
+                    return Progress::Closed;
+                }
+            }
+            if self.buffer.contains(&b'\n') {
+                return self.into_request();
+            }
+            if self.buffer.len() > MAX_REQUEST_LEN {
+                log::warn!("an IPC request is longer than {MAX_REQUEST_LEN} bytes");
+                return Progress::Closed;
+            }
+        }
+        Progress::Reading(self)
+    }
+
+    fn into_request(self) -> Progress {
+        let end = self.buffer.iter().position(|&b| b == b'\n');
+        let line = String::from_utf8_lossy(&self.buffer[..end.unwrap_or(self.buffer.len())]);
+        // the reply is small enough, but a client not reading it mustn't block the WM
+        let setup = self.stream.set_nonblocking(false).and_then(|()| {
+            self.stream
+                .set_write_timeout(Some(Duration::from_millis(100)))
+        });
+        if let Err(err) = setup {
+            log::warn!("Failed to set up an IPC connection: {err}");
+            return Progress::Closed;
+        }
+        Progress::Done(Request {
+            line: line.trim().to_owned(),
+            stream: self.stream,
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// a request line read from a client, which waits for the reply
+pub struct Request {
+    pub line: String,
+    stream: UnixStream,
+}
+
+impl Request {
+    pub fn reply(mut self, reply: &Json) {
+        if let Err(err) = writeln!(self.stream, "{reply}") {
+            log::warn!("Failed to reply to an IPC request: {err}");
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/daily2.<display>.sock`, or `/tmp/daily2-<uid>.<display>.sock`
+fn socket_path() -> PathBuf {
+    let display: String = std::env::var("DISPLAY")
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '.')
+        .collect();
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(format!("daily2.{display}.sock")),
+        _ => {
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/daily2-{uid}.{display}.sock"))
+        }
+    }
+}
+
+/// a JSON value written in a single line
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        Json::Int(n as i64)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Json {
+        Json::Int(n as i64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Json {
+        Json::Int(n.min(i64::MAX as u64) as i64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Int(n as i64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Json {
+        Json::Float(x)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Json {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Int(n) => write!(f, "{n}"),
+            Json::Float(x) if x.is_finite() => write!(f, "{x}"),
+            Json::Float(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
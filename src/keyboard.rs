@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use x11rb::connection::{Connection as _, RequestConnection as _};
+use x11rb::protocol::{xkb, xproto};
+
+use xkb::ConnectionExt as _;
+use xproto::ConnectionExt as _;
+
+use crate::error::Result;
+use crate::utils;
+
+/// keysyms of each key, taking the keyboard layout group (e.g. US or JP) into account
+#[derive(Debug, Default)]
+pub struct Keymap {
+    /// whether the XKB extension is available
+    xkb: bool,
+    /// whether auto-repeated keys are sent without releases in between
+    detectable_auto_repeat: bool,
+    /// the active group
+    group: u8,
+    /// keysyms of each keycode, indexed by the group and then the shift level
+    keysyms: HashMap<u8, Vec<Vec<xproto::Keysym>>>,
+    /// names of the groups (e.g. "English (US)")
+    group_names: Vec<String>,
+}
+
+impl Keymap {
+    /// Enables XKB if available, so that group changes are notified.
+    pub fn init(&mut self, ctx: &utils::Context) -> Result<()> {
+        let supported = ctx.conn.extension_information(xkb::X11_EXTENSION_NAME)?;
+        self.xkb = match supported {
+            Some(_) => ctx.conn.xkb_use_extension(1, 0)?.reply()?.supported,
+            None => false,
+        };
+        if self.xkb {
+            let flag = xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT;
+            let reply = ctx
+                .conn
+                .xkb_per_client_flags(
+                    xkb::ID::USE_CORE_KBD.into(),
+                    flag,
+                    flag,
+                    xkb::BoolCtrl::from(0u32),
+                    xkb::BoolCtrl::from(0u32),
+                    xkb::BoolCtrl::from(0u32),
+                )?
+                .reply()?;
+            self.detectable_auto_repeat = u32::from(reply.value) & u32::from(flag) != 0;
+
+            let events = xkb::EventType::STATE_NOTIFY
+                | xkb::EventType::MAP_NOTIFY
+                | xkb::EventType::NEW_KEYBOARD_NOTIFY;
+            ctx.conn.xkb_select_events(
+                xkb::ID::USE_CORE_KBD.into(),
+                xkb::EventType::from(0u16),
+                events,
+                xkb::MapPart::from(0u16),
+                xkb::MapPart::from(0u16),
+                &xkb::SelectEventsAux::new(),
+            )?;
+            let state = ctx
+                .conn
+                .xkb_get_state(xkb::ID::USE_CORE_KBD.into())?
+                .reply()?;
+            self.group = u8::from(state.group);
+        } else {
+            log::warn!("XKB is not available; keysyms are resolved with the core keymap");
+        }
+        self.load(ctx)
+    }
+
+    /// Reads the keysyms of all keycodes.
+    pub fn load(&mut self, ctx: &utils::Context) -> Result<()> {
+        let setup = ctx.conn.setup();
+        let (min, max) = (setup.min_keycode, setup.max_keycode);
+        self.keysyms.clear();
+
+        if self.xkb {
+            let part = xkb::MapPart::KEY_SYMS;
+            let reply = ctx
+                .conn
+                .xkb_get_map(
+                    xkb::ID::USE_CORE_KBD.into(),
+                    part,
+                    xkb::MapPart::from(0u16),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    xkb::VMod::from(0u16),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                )?
+                .reply()?;
+            let names = ctx
+                .conn
+                .xkb_get_names(xkb::ID::USE_CORE_KBD.into(), xkb::NameDetail::GROUP_NAMES)?
+                .reply()?;
+            self.group_names.clear();
+            for atom in names.value_list.groups.unwrap_or_default() {
+                if atom != x11rb::NONE {
+                    self.group_names.push(utils::get_atom_name(ctx, atom)?);
+                }
+            }
+
+            let syms = reply.map.syms_rtrn.unwrap_or_default();
+            for (keycode, sym_map) in (reply.first_key_sym..=reply.max_key_code).zip(syms) {
+                // the number of groups is in the lower 4 bits
+                let groups = (sym_map.group_info & 0x0F) as usize;
+                let width = (sym_map.width as usize).max(1);
+                let keysyms = sym_map
+                    .syms
+                    .chunks(width)
+                    .take(groups)
+                    .map(|levels| levels.to_vec())
+                    .collect();
+                self.keysyms.insert(keycode, keysyms);
+            }
+        } else {
+            let mapping = ctx.conn.get_keyboard_mapping(min, max - min + 1)?.reply()?;
+            let per_keycode = (mapping.keysyms_per_keycode as usize).max(1);
+            for (keysyms, keycode) in mapping.keysyms.chunks(per_keycode).zip(min..=max) {
+                // two groups of two levels in the core keymap
+                let groups = keysyms
+                    .chunks(2)
+                    .take(2)
+                    .filter(|levels| levels.iter().any(|sym| *sym != x11rb::NO_SYMBOL))
+                    .map(|levels| levels.to_vec())
+                    .collect();
+                self.keysyms.insert(keycode, groups);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn detectable_auto_repeat(&self) -> bool {
+        self.detectable_auto_repeat
+    }
+
+    /// Returns true if the group is changed.
+    pub fn set_group(&mut self, group: u8) -> bool {
+        if self.group != group {
+            log::debug!("keyboard group: {group}");
+            self.group = group;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// the name of the active group, or its number if the name is unknown
+    pub fn group_name(&self) -> String {
+        match self.group_names.get(self.group as usize) {
+            Some(name) => name.clone(),
+            None => format!("group {}", self.group + 1),
+        }
+    }
+
+    /// Locks the next group. The change is notified by a state notify event.
+    pub fn lock_next_group(&self, ctx: &utils::Context) -> Result<()> {
+        if !self.xkb {
+            log::warn!("keyboard groups can't be switched without XKB");
+            return Ok(());
+        }
+        let n = self.group_names.len().max(1);
+        let next = (self.group as usize + 1) % n;
+        ctx.conn.xkb_latch_lock_state(
+            xkb::ID::USE_CORE_KBD.into(),
+            xproto::ModMask::from(0u16),
+            xproto::ModMask::from(0u16),
+            true,
+            xkb::Group::from(next as u8),
+            xproto::ModMask::from(0u16),
+            false,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// keycodes generating the keysym without modifiers in any group
+    pub fn keycodes(&self, keysym: xproto::Keysym) -> Vec<u8> {
+        let mut keycodes: Vec<u8> = self
+            .keysyms
+            .iter()
+            .filter(|(_, groups)| groups.iter().any(|levels| levels.first() == Some(&keysym)))
+            .map(|(keycode, _)| *keycode)
+            .collect();
+        keycodes.sort_unstable();
+        keycodes
+    }
+
+    /// the keysym of the key pressed without modifiers in the active group
+    pub fn keysym(&self, keycode: u8) -> xproto::Keysym {
+        let groups = match self.keysyms.get(&keycode) {
+            Some(groups) if !groups.is_empty() => groups,
+            _ => return x11rb::NO_SYMBOL,
+        };
+        // out-of-range groups wrap around
+        let group = &groups[self.group as usize % groups.len()];
+        group.first().copied().unwrap_or(x11rb::NO_SYMBOL)
+    }
+}
@@ -0,0 +1,506 @@
+// The geometry of tiled and snapped windows, computed from the area and the parameters of the
+// layout alone. Nothing here talks to the X server.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    pub fn top(&self) -> i32 {
+        self.y
+    }
+    pub fn bottom(&self) -> i32 {
+        self.y + self.h
+    }
+    pub fn left(&self) -> i32 {
+        self.x
+    }
+    pub fn right(&self) -> i32 {
+        self.x + self.w
+    }
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.left() <= x && x < self.right() && self.top() <= y && y < self.bottom()
+    }
+    /// the distance from the point to this rectangle (0 if the point is inside)
+    pub fn distance(&self, x: i32, y: i32) -> i32 {
+        let dx = (self.left() - x).max(x - (self.right() - 1)).max(0);
+        let dy = (self.top() - y).max(y - (self.bottom() - 1)).max(0);
+        dx.max(dy)
+    }
+    /// swaps the x-axis and the y-axis
+    pub fn transposed(&self) -> Rect {
+        Rect {
+            x: self.y,
+            y: self.x,
+            w: self.h,
+            h: self.w,
+        }
+    }
+}
+
+/// tiles can't be shrunk below this weight, so that they stay visible
+pub const MIN_TILE_WEIGHT: f64 = 0.05;
+
+/// how tiles are arranged in the area
+#[derive(Debug, Clone)]
+pub enum Arrangement {
+    /// side by side, sharing the width in proportion to the weights
+    Weighted(Vec<f64>),
+    Spiral,
+    /// in the number of columns
+    Columns(usize),
+    /// all below the tab bar of the height
+    Titled(i32),
+    /// computed elsewhere in the oriented area (e.g. from the container tree)
+    Given(Vec<Rect>),
+}
+
+/// A rotated layout is computed in the transposed area and transposed back,
+/// and a mirrored one is flipped before that.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Orientation {
+    pub transposed: bool,
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    /// Returns the area in which the layout is computed.
+    pub fn area(self, area: Rect) -> Rect {
+        if self.transposed {
+            area.transposed()
+        } else {
+            area
+        }
+    }
+
+    /// Maps the geometry computed in `self.area(area)` back onto the area.
+    pub fn restore(self, area: Rect, mut geometry: Rect) -> Rect {
+        let area = self.area(area);
+        if self.mirrored {
+            geometry.x = area.x + area.right() - geometry.right();
+        }
+        if self.transposed {
+            geometry = geometry.transposed();
+        }
+        geometry
+    }
+}
+
+/// Returns the geometries (including borders) of `n` tiles in the area.
+pub fn tile(
+    area: Rect,
+    n: usize,
+    arrangement: &Arrangement,
+    orientation: Orientation,
+) -> Vec<Rect> {
+    let oriented = orientation.area(area);
+    let geometries = match arrangement {
+        Arrangement::Weighted(weights) => weighted_horizontal(oriented, weights),
+        Arrangement::Spiral => spiral(oriented, n),
+        Arrangement::Columns(columns) => columns_layout(oriented, *columns, n),
+        Arrangement::Titled(bar_h) => titled(oriented, *bar_h, n),
+        Arrangement::Given(geometries) => geometries.clone(),
+    };
+    geometries
+        .into_iter()
+        .map(|geometry| orientation.restore(area, geometry))
+        .collect()
+}
+
+/// Windows are placed side by side, sharing the width of the area equally.
+pub fn horizontal(area: Rect, n: usize) -> Vec<Rect> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let each_w = area.w / n as i32;
+    let last_w = area.w - (n as i32 - 1) * each_w;
+    (0..n)
+        .map(|i| Rect {
+            x: area.x + each_w * (i as i32),
+            y: area.y,
+            w: if i < n - 1 { each_w } else { last_w },
+            h: area.h,
+        })
+        .collect()
+}
+
+/// Windows are placed side by side, sharing the width of the area in proportion to the weights.
+pub fn weighted_horizontal(area: Rect, weights: &[f64]) -> Vec<Rect> {
+    let total: f64 = weights.iter().sum();
+    if weights.is_empty() || total <= 0.0 {
+        return horizontal(area, weights.len());
+    }
+
+    // the edges are rounded so that the windows cover the area without gaps
+    let mut acc = 0.0;
+    let mut left = area.x;
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| {
+            acc += weight;
+            let right = if i == weights.len() - 1 {
+                area.right()
+            } else {
+                area.x + (area.w as f64 * acc / total).round() as i32
+            };
+            let rect = Rect {
+                x: left,
+                y: area.y,
+                w: right - left,
+                h: area.h,
+            };
+            left = right;
+            rect
+        })
+        .collect()
+}
+
+/// Windows are placed one above the other, sharing the height of the area in proportion to
+/// the weights.
+pub fn weighted_vertical(area: Rect, weights: &[f64]) -> Vec<Rect> {
+    weighted_horizontal(area.transposed(), weights)
+        .into_iter()
+        .map(|r| r.transposed())
+        .collect()
+}
+
+/// Each window takes half of the remaining area, alternating the split direction
+/// (left, top, right, bottom, left, ...) like dwm's fibonacci layout.
+pub fn spiral(area: Rect, n: usize) -> Vec<Rect> {
+    let mut rest = area;
+    let mut geometries = Vec::with_capacity(n);
+    for i in 0..n {
+        if i == n - 1 {
+            geometries.push(rest);
+            break;
+        }
+
+        let mut geometry = rest;
+        if i % 2 == 0 {
+            geometry.w = rest.w / 2;
+            rest.w -= geometry.w;
+            if i % 4 == 0 {
+                rest.x += geometry.w;
+            } else {
+                geometry.x += rest.w;
+            }
+        } else {
+            geometry.h = rest.h / 2;
+            rest.h -= geometry.h;
+            if i % 4 == 1 {
+                rest.y += geometry.h;
+            } else {
+                geometry.y += rest.h;
+            }
+        }
+        geometries.push(geometry);
+    }
+    geometries
+}
+
+/// Windows fill `columns` columns from top to bottom, left to right.
+pub fn columns_layout(area: Rect, columns: usize, n: usize) -> Vec<Rect> {
+    let columns = columns.clamp(1, n.max(1));
+    let mut geometries = Vec::with_capacity(n);
+    for (j, column) in horizontal(area, columns).into_iter().enumerate() {
+        let rows = n / columns + usize::from(j < n % columns);
+        let each_h = column.h / rows.max(1) as i32;
+        for i in 0..rows {
+            let y = column.y + each_h * i as i32;
+            geometries.push(Rect {
+                x: column.x,
+                y,
+                w: column.w,
+                h: if i < rows - 1 {
+                    each_h
+                } else {
+                    column.bottom() - y
+                },
+            });
+        }
+    }
+    geometries
+}
+
+/// All windows occupy the area below the tab bar.
+/// The tab bar may be taller than the area, which leaves no room for the windows.
+pub fn titled(area: Rect, bar_h: i32, n: usize) -> Vec<Rect> {
+    let bar_h = bar_h.clamp(0, area.h.max(0));
+    let geometry = Rect {
+        x: area.x,
+        y: area.y + bar_h,
+        w: area.w,
+        h: area.h - bar_h,
+    };
+    vec![geometry; n]
+}
+
+/// Returns the geometry of a window inside its border when the whole of it takes `outer`.
+/// Tiles too small for the border (e.g. below a tall tab bar) keep a pixel, since X windows
+/// can't be empty.
+pub fn inside_border(outer: Rect, bwidth: i32) -> Rect {
+    Rect {
+        x: outer.x,
+        y: outer.y,
+        w: (outer.w - bwidth * 2).max(1),
+        h: (outer.h - bwidth * 2).max(1),
+    }
+}
+
+/// a half or a quarter of a monitor, or the whole of it, into which windows are snapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapRegion {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Full,
+}
+
+/// the number of columns of the overview grid of `n` windows, which is as square as possible
+pub fn grid_columns(n: usize) -> usize {
+    (1..).find(|c| c * c >= n).unwrap()
+}
+
+/// Arranges `n` cells in rows of `grid_columns(n)` in the area, each of them separated from the
+/// others by `gap` pixels if they are large enough.
+pub fn grid(area: Rect, n: usize, gap: i32) -> Vec<Rect> {
+    let columns = grid_columns(n);
+    let rows = n.div_ceil(columns.max(1));
+    let edge = |start: i32, len: i32, i: usize, count: usize| {
+        start + (len as i64 * i as i64 / count as i64) as i32
+    };
+    (0..n)
+        .map(|i| {
+            let (row, column) = (i / columns, i % columns);
+            let x = edge(area.x, area.w, column, columns);
+            let y = edge(area.y, area.h, row, rows);
+            let w = edge(area.x, area.w, column + 1, columns) - x;
+            let h = edge(area.y, area.h, row + 1, rows) - y;
+            // a half of the gap is left on each side of the cell, keeping at least a pixel
+            let inset = (gap / 2).min((w - 1) / 2).min((h - 1) / 2).max(0);
+            Rect {
+                x: x + inset,
+                y: y + inset,
+                w: w - inset * 2,
+                h: h - inset * 2,
+            }
+        })
+        .collect()
+}
+
+/// Returns the region into which a window dropped at (x, y) is snapped: the edges and corners
+/// within `d` pixels of the geometry, or the whole of it around the center.
+pub fn snap_region(geometry: Rect, d: i32, x: i32, y: i32) -> Option<SnapRegion> {
+    let g = geometry;
+    let left = g.left() <= x && x < g.left() + d;
+    let right = g.right() - d <= x && x < g.right();
+    let top = g.top() <= y && y < g.top() + d;
+    let bottom = g.bottom() - d <= y && y < g.bottom();
+    let x_center = g.x + g.w / 2 - d <= x && x <= g.x + g.w / 2 + d;
+    let y_center = g.y + g.h / 2 - d <= y && y <= g.y + g.h / 2 + d;
+
+    let region = if left && top {
+        SnapRegion::TopLeft
+    } else if left && bottom {
+        SnapRegion::BottomLeft
+    } else if right && top {
+        SnapRegion::TopRight
+    } else if right && bottom {
+        SnapRegion::BottomRight
+    } else if left {
+        SnapRegion::Left
+    } else if right {
+        SnapRegion::Right
+    } else if top {
+        SnapRegion::Top
+    } else if bottom {
+        SnapRegion::Bottom
+    } else if x_center && y_center {
+        SnapRegion::Full
+    } else {
+        return None;
+    };
+    Some(region)
+}
+
+/// Returns the geometry (not including borders) of the region of `geometry`.
+pub fn snap_geometry(geometry: Rect, bwidth: i32, region: SnapRegion) -> Rect {
+    let g = geometry;
+    let (x, w) = match region {
+        SnapRegion::Left | SnapRegion::TopLeft | SnapRegion::BottomLeft => (g.x, g.w / 2),
+        SnapRegion::Right | SnapRegion::TopRight | SnapRegion::BottomRight => {
+            (g.x + g.w / 2, g.w - g.w / 2)
+        }
+        _ => (g.x, g.w),
+    };
+    let (y, h) = match region {
+        SnapRegion::Top | SnapRegion::TopLeft | SnapRegion::TopRight => (g.y, g.h / 2),
+        SnapRegion::Bottom | SnapRegion::BottomLeft | SnapRegion::BottomRight => {
+            (g.y + g.h / 2, g.h - g.h / 2)
+        }
+        _ => (g.y, g.h),
+    };
+    Rect {
+        x,
+        y,
+        w: w - bwidth * 2,
+        h: h - bwidth * 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn area() -> impl Strategy<Value = Rect> {
+        (-2000..=2000, -2000..=2000, 1..=4000, 1..=3000).prop_map(|(x, y, w, h)| Rect {
+            x,
+            y,
+            w,
+            h,
+        })
+    }
+
+    fn orientation() -> impl Strategy<Value = Orientation> {
+        any::<(bool, bool)>().prop_map(|(transposed, mirrored)| Orientation {
+            transposed,
+            mirrored,
+        })
+    }
+
+    /// the arrangements which divide the area among `n` tiles
+    fn partition(n: usize) -> impl Strategy<Value = Arrangement> {
+        prop_oneof![
+            prop::collection::vec(MIN_TILE_WEIGHT..5.0, n).prop_map(Arrangement::Weighted),
+            Just(Arrangement::Spiral),
+            (0..=10usize).prop_map(Arrangement::Columns),
+        ]
+    }
+
+    fn area_of(r: &Rect) -> i64 {
+        r.w as i64 * r.h as i64
+    }
+
+    fn overlaps(a: &Rect, b: &Rect) -> bool {
+        a.left() < b.right() && b.left() < a.right() && a.top() < b.bottom() && b.top() < a.bottom()
+    }
+
+    fn inside(inner: &Rect, outer: &Rect) -> bool {
+        outer.left() <= inner.left()
+            && inner.right() <= outer.right()
+            && outer.top() <= inner.top()
+            && inner.bottom() <= outer.bottom()
+    }
+
+    /// Checks that the windows placed in the tiles with the border are never empty.
+    fn assert_windows_fit(tiles: &[Rect], bwidth: i32) -> Result<(), TestCaseError> {
+        for tile in tiles {
+            let window = inside_border(*tile, bwidth);
+            prop_assert!(window.w >= 1 && window.h >= 1, "{tile:?}: {window:?}");
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2000))]
+
+        #[test]
+        fn partitions_cover_the_area_without_overlaps(
+            (area, n, arrangement) in (area(), 1..=16usize)
+                .prop_flat_map(|(area, n)| (Just(area), Just(n), partition(n))),
+            orientation in orientation(),
+            bwidth in 0..=8,
+        ) {
+            let geometries = tile(area, n, &arrangement, orientation);
+            prop_assert_eq!(geometries.len(), n);
+            for (i, a) in geometries.iter().enumerate() {
+                prop_assert!(a.w >= 0 && a.h >= 0, "negative size {:?}", a);
+                prop_assert!(inside(a, &area), "{:?} is out of the area", a);
+                for b in &geometries[i + 1..] {
+                    prop_assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+                }
+            }
+            // disjoint tiles inside the area fill it exactly when their areas sum up to it
+            let covered: i64 = geometries.iter().map(area_of).sum();
+            prop_assert_eq!(covered, area_of(&area), "gaps are left");
+            assert_windows_fit(&geometries, bwidth)?;
+        }
+
+        #[test]
+        fn titled_windows_share_the_area_below_the_bar(
+            area in area(),
+            n in 1..=16usize,
+            // the stacking layout has a title per window, which may not fit in the area
+            title_h in 0..=20,
+            orientation in orientation(),
+            bwidth in 0..=8,
+        ) {
+            let bar_h = title_h * n as i32;
+            let geometries = tile(area, n, &Arrangement::Titled(bar_h), orientation);
+            prop_assert_eq!(geometries.len(), n);
+            for geometry in &geometries {
+                prop_assert_eq!(*geometry, geometries[0]);
+                prop_assert!(geometry.w >= 0 && geometry.h >= 0, "{:?}", geometry);
+                prop_assert!(inside(geometry, &area), "{:?}", geometry);
+            }
+            assert_windows_fit(&geometries, bwidth)?;
+        }
+
+        #[test]
+        fn grid_cells_are_separate_in_the_area(area in area(), n in 0..=30usize, gap in 0..=64) {
+            let cells = grid(area, n, gap);
+            prop_assert_eq!(cells.len(), n);
+            for (i, a) in cells.iter().enumerate() {
+                prop_assert!(a.w >= 0 && a.h >= 0, "negative size {:?}", a);
+                prop_assert!(inside(a, &area), "{:?} is out of the area", a);
+                for b in &cells[i + 1..] {
+                    prop_assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+                }
+            }
+        }
+
+        #[test]
+        fn snap_regions_tile_the_monitor(monitor in area()) {
+            use SnapRegion::*;
+            for regions in [
+                &[Left, Right][..],
+                &[Top, Bottom],
+                &[TopLeft, TopRight, BottomLeft, BottomRight],
+                &[Full],
+            ] {
+                let geometries: Vec<Rect> = regions
+                    .iter()
+                    .map(|region| snap_geometry(monitor, 0, *region))
+                    .collect();
+                for (i, a) in geometries.iter().enumerate() {
+                    prop_assert!(a.w >= 0 && a.h >= 0, "{:?}", a);
+                    prop_assert!(inside(a, &monitor), "{:?}", a);
+                    for b in &geometries[i + 1..] {
+                        prop_assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+                    }
+                }
+                let covered: i64 = geometries.iter().map(area_of).sum();
+                prop_assert_eq!(covered, area_of(&monitor), "{:?}", regions);
+            }
+
+            // the corners snap into the quarters, which are separate on monitors of more than
+            // a pixel
+            prop_assume!(monitor.w >= 2 && monitor.h >= 2);
+            let (right, bottom) = (monitor.right() - 1, monitor.bottom() - 1);
+            prop_assert_eq!(snap_region(monitor, 1, monitor.x, monitor.y), Some(TopLeft));
+            prop_assert_eq!(snap_region(monitor, 1, right, bottom), Some(BottomRight));
+        }
+    }
+}
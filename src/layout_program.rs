@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io::{Read, Write as _};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt as _;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::layout::Rect;
+use crate::signal;
+use crate::utils;
+
+// The external layout runs LAYOUT_PROGRAM in shell on each relayout, and writes the area
+// (relative to the monitor) and the tiled windows to its stdin:
+//
+//   area 0 20 1920 1060
+//   window 0x1400003 1.0 Firefox
+//   window 0x1600008 0.5 XTerm
+//
+// where each window line has the id, its weight and its class ("-" if unknown). The program
+// prints a line "<x> <y> <width> <height>" (including borders, in the same coordinates as the
+// area) for each window in the same order, and exits. The desktop and the monitor being laid out
+// are given by $DAILY_DESKTOP and $DAILY_MONITOR.
+//
+// The event loop waits for the program, so it isn't run again while the input of the desktop is
+// unchanged, and the last output is reused if it fails with the same number of windows. The focus
+// isn't given, so that moving it among the windows doesn't run the program.
+
+/// a tiled window given to the program
+pub struct Tile<'a> {
+    pub id: u32,
+    pub weight: f64,
+    pub class: Option<&'a str>,
+}
+
+/// the input and the output of the last successful run for each desktop
+#[derive(Debug, Default)]
+pub struct Cache {
+    last: HashMap<usize, (String, Vec<Rect>)>,
+}
+
+/// Runs the program for the desktop and returns the geometries of the tiles, or None if it
+/// fails, takes too long, or prints a malformed output without a usable cached result.
+pub fn run(
+    cache: &mut Cache,
+    desktop: usize,
+    program: &str,
+    area: Rect,
+    tiles: &[Tile],
+    envs: Vec<(&'static str, String)>,
+) -> Option<Vec<Rect>> {
+    let input = input(area, tiles);
+    let key = format!("{envs:?}\n{input}");
+    let last = cache.last.get(&desktop);
+    if let Some((_, geometries)) = last.filter(|(last, _)| *last == key) {
+        return Some(geometries.clone());
+    }
+    match run_program(program, &input, tiles.len(), envs) {
+        Some(geometries) => {
+            cache.last.insert(desktop, (key, geometries.clone()));
+            Some(geometries)
+        }
+        None => {
+            let (_, geometries) = last.filter(|(_, last)| last.len() == tiles.len())?;
+            log::info!("The last output of the layout program is used");
+            Some(geometries.clone())
+        }
+    }
+}
+
+fn run_program(
+    program: &str,
+    input: &str,
+    n: usize,
+    envs: Vec<(&'static str, String)>,
+) -> Option<Vec<Rect>> {
+    let mut command = Command::new("/bin/sh");
+    command
+        .arg("-c")
+        .arg(program)
+        .envs(envs)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        // in a new process group, so that the processes started by the shell are killed with it
+        .process_group(0);
+    let mut child = match signal::unblock_in_child(&mut command).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            log::error!("Failed to run the layout program: {err}");
+            return None;
+        }
+    };
+
+    // the input is small enough to fit in the pipe, and a program ignoring it is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input.as_bytes());
+    }
+
+    let output = read_with_timeout(
+        child.stdout.take().unwrap(),
+        Duration::from_millis(config::LAYOUT_PROGRAM_TIMEOUT_MS),
+    );
+    // the output is complete at EOF, and the programs still running after that are killed
+    unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) };
+    let _ = child.wait();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            log::warn!("The layout program failed: {err}");
+            return None;
+        }
+    };
+    let geometries = parse_output(&String::from_utf8_lossy(&output), n);
+    if geometries.is_none() {
+        log::warn!("The layout program printed a malformed output: {output:?}");
+    }
+    geometries
+}
+
+fn input(area: Rect, tiles: &[Tile]) -> String {
+    let mut input = format!("area {} {} {} {}\n", area.x, area.y, area.w, area.h);
+    for tile in tiles {
+        // classes may contain spaces, so the class is the rest of the line
+        input += &format!(
+            "window 0x{:X} {:?} {}\n",
+            tile.id,
+            tile.weight,
+            tile.class.unwrap_or("-")
+        );
+    }
+    input
+}
+
+/// Reads the pipe until EOF, giving up when the timeout expires.
+fn read_with_timeout(mut pipe: impl Read + AsRawFd, timeout: Duration) -> std::io::Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        if !utils::wait_readable(&[pipe.as_raw_fd()], Some(remaining))?[0] {
+            continue;
+        }
+        match pipe.read(&mut buf) {
+            Ok(0) => return Ok(output),
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Parses the geometries of `n` tiles. Empty lines are skipped.
+fn parse_output(output: &str, n: usize) -> Option<Vec<Rect>> {
+    let mut geometries = Vec::with_capacity(n);
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let values: Vec<i32> = line
+            .split_whitespace()
+            .map(|v| v.parse().ok())
+            .collect::<Option<_>>()?;
+        let [x, y, w, h] = values[..] else {
+            return None;
+        };
+        if w < 1 || h < 1 {
+            return None;
+        }
+        geometries.push(Rect { x, y, w, h });
+    }
+    (geometries.len() == n).then_some(geometries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_has_a_geometry_for_each_tile() {
+        let rect = |x, y, w, h| Rect { x, y, w, h };
+        assert_eq!(
+            parse_output("0 0 960 1080\n\n  960 0 960 1080  \n", 2),
+            Some(vec![rect(0, 0, 960, 1080), rect(960, 0, 960, 1080)])
+        );
+        assert_eq!(
+            parse_output("-10 -20 5 5", 1),
+            Some(vec![rect(-10, -20, 5, 5)])
+        );
+        assert_eq!(parse_output("", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn malformed_output_is_rejected() {
+        // too few or too many lines
+        assert_eq!(parse_output("0 0 10 10", 2), None);
+        assert_eq!(parse_output("0 0 10 10\n0 0 10 10", 1), None);
+        // not 4 integers
+        assert_eq!(parse_output("0 0 10", 1), None);
+        assert_eq!(parse_output("0 0 10 10 10", 1), None);
+        assert_eq!(parse_output("0 0 10.5 10", 1), None);
+        assert_eq!(parse_output("x 0 10 10", 1), None);
+        // empty tiles
+        assert_eq!(parse_output("0 0 0 10", 1), None);
+        assert_eq!(parse_output("0 0 10 -1", 1), None);
+    }
+}
@@ -1,27 +1,104 @@
+use std::os::unix::process::CommandExt as _;
+
+use clap::Parser as _;
+
+mod check;
+mod cli;
+mod command;
 mod config;
 mod daily;
 mod error;
+mod i3ipc;
+mod ipc;
+mod keyboard;
+mod layout;
+mod layout_program;
+mod metrics;
+mod output;
+mod placement;
+mod signal;
+mod state;
+mod theme;
+mod thumbnail;
 mod utils;
+mod wallpaper;
+mod xsmp;
 
 fn main() {
-    env_logger::init();
+    // --help and --version exit here
+    let args = cli::Args::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(filters) = &args.log_level {
+        logger.parse_filters(filters);
+    }
+    logger.init();
+    if let Some(display) = &args.display {
+        // inherited by the clients too
+        std::env::set_var("DISPLAY", display);
+    }
+    if let Some(path) = args.config {
+        cli::set_config_file(path);
+    }
+    if args.check_config {
+        std::process::exit(if check::check_config() { 0 } else { 1 });
+    }
+    // the message goes to the log with a backtrace, before the grabs are released by `start`
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        log::error!("{info}\n{backtrace}");
+    }));
 
     let mut daily = daily::Daily::new().expect("failed to initialize daily");
+    daily.set_replace(args.replace);
 
     for (modifiers, keycode, command) in config::keybindings() {
         daily
             .bind_key(modifiers, keycode, command.clone())
             .unwrap_or_else(|err| {
                 log::error!(
-                    "Failed to add a keybinding: modifiers:{:?}, keycode:{}, command:{:?}",
+                    "Failed to add a keybinding: modifiers:{:?}, keycode:{}, command:{:?}: {}",
                     modifiers,
                     keycode,
-                    command
+                    command,
+                    err
+                );
+                log::debug!("detail: {err:?}");
+            });
+    }
+
+    for (keys, command) in config::key_chords() {
+        daily
+            .bind_chord(&keys, command.clone())
+            .unwrap_or_else(|err| {
+                log::error!(
+                    "Failed to add a key chord: keys:{:?}, command:{:?}: {}",
+                    keys,
+                    command,
+                    err
                 );
                 log::debug!("detail: {err:?}");
             });
     }
 
+    for (modifiers, keysym, command) in config::keysym_bindings() {
+        daily.bind_keysym(modifiers, keysym, command);
+    }
+
+    for (fingers, direction, command) in config::swipe_gestures() {
+        daily.bind_swipe(fingers, direction, command);
+    }
+
+    for binding in config::mode_keybindings() {
+        daily.bind_mode_key(
+            binding.mode,
+            binding.oneshot,
+            binding.modifiers,
+            binding.keycode,
+            binding.command,
+        );
+    }
+
     log::info!("start");
     match daily.start() {
         Ok(()) => {}
@@ -29,6 +106,11 @@ fn main() {
         Err(error::Error::Interrupted { restart }) => {
             if restart {
                 log::info!("try to restart");
+                // the X connection is already closed, and the state descriptor is inherited
+                let mut args = std::env::args_os();
+                let program = args.next().unwrap_or_else(|| "daily2".into());
+                let err = std::process::Command::new(program).args(args).exec();
+                log::error!("failed to restart: {err}");
                 std::process::exit(2);
             }
         }
@@ -37,6 +119,11 @@ fn main() {
             log::error!("{x11_err:?}");
             std::process::exit(1);
         }
+
+        Err(err @ error::Error::KeyAlreadyGrabbed) => {
+            log::error!("{err}");
+            std::process::exit(1);
+        }
     }
     log::info!("stop");
 }
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::ipc::Json;
+
+/// counters of the work done since the start, replied to the `get_metrics` query
+pub struct Metrics {
+    started: Instant,
+    /// the number of events handled by their kinds
+    events: HashMap<&'static str, u64>,
+    /// the number of times the layout of a monitor is computed and applied
+    layouts: u64,
+    /// the number of X11 errors received as events
+    x11_errors: u64,
+    /// the number of executed commands and the time taken by them, by their names
+    commands: HashMap<String, CommandStats>,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            started: Instant::now(),
+            events: HashMap::new(),
+            layouts: 0,
+            x11_errors: 0,
+            commands: HashMap::new(),
+        }
+    }
+
+    pub fn record_event(&mut self, kind: &'static str) {
+        *self.events.entry(kind).or_default() += 1;
+    }
+
+    pub fn record_layout(&mut self) {
+        self.layouts += 1;
+    }
+
+    pub fn record_x11_error(&mut self) {
+        self.x11_errors += 1;
+    }
+
+    pub fn record_command(&mut self, name: String, elapsed: Duration) {
+        let stats = self.commands.entry(name).or_default();
+        stats.count += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+    }
+
+    /// Latencies are in microseconds. Members are sorted by their names to make them stable.
+    pub fn to_json(&self) -> Json {
+        let mut events: Vec<(&str, u64)> = self.events.iter().map(|(k, n)| (*k, *n)).collect();
+        events.sort_unstable();
+        let events = events
+            .into_iter()
+            .map(|(kind, count)| Json::Object(vec![("kind", kind.into()), ("count", count.into())]))
+            .collect();
+
+        let mut commands: Vec<(&String, &CommandStats)> = self.commands.iter().collect();
+        commands.sort_unstable_by_key(|(name, _)| *name);
+        let commands = commands
+            .into_iter()
+            .map(|(name, stats)| {
+                let average = stats.total / stats.count.max(1) as u32;
+                Json::Object(vec![
+                    ("name", name.as_str().into()),
+                    ("count", stats.count.into()),
+                    ("average_us", (average.as_micros() as u64).into()),
+                    ("max_us", (stats.max.as_micros() as u64).into()),
+                ])
+            })
+            .collect();
+
+        Json::Object(vec![
+            ("uptime", self.started.elapsed().as_secs().into()),
+            ("events", Json::Array(events)),
+            ("layouts", self.layouts.into()),
+            ("x11_errors", self.x11_errors.into()),
+            ("commands", Json::Array(commands)),
+        ])
+    }
+}
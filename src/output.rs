@@ -0,0 +1,272 @@
+use x11rb::protocol::{randr, xproto};
+
+use randr::ConnectionExt as _;
+use xproto::ConnectionExt as _;
+
+use crate::error::Result;
+use crate::utils;
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Normal,
+    Left,
+    Inverted,
+    Right,
+}
+
+impl Rotation {
+    fn randr_rotation(&self) -> randr::Rotation {
+        match self {
+            Rotation::Normal => randr::Rotation::ROTATE0,
+            Rotation::Left => randr::Rotation::ROTATE90,
+            Rotation::Inverted => randr::Rotation::ROTATE180,
+            Rotation::Right => randr::Rotation::ROTATE270,
+        }
+    }
+
+    fn is_sideways(&self) -> bool {
+        matches!(self, Rotation::Left | Rotation::Right)
+    }
+}
+
+/// how a connected output is configured
+#[derive(Debug, Clone, Copy)]
+pub struct OutputConfig {
+    /// the output name reported by RandR (e.g. "HDMI-1")
+    pub name: &'static str,
+    /// the position of the top-left corner on the screen
+    pub position: (i16, i16),
+    pub rotation: Rotation,
+    pub primary: bool,
+}
+
+/// Enables the connected outputs listed in the layout with their preferred modes
+/// and disables the disconnected ones. Outputs not listed are left untouched.
+pub fn apply_layout(ctx: &utils::Context, layout: &[OutputConfig]) -> Result<()> {
+    let resources = ctx
+        .conn
+        .randr_get_screen_resources_current(ctx.root)?
+        .reply()?;
+    let timestamp = resources.config_timestamp;
+
+    struct Plan {
+        output: randr::Output,
+        crtc: randr::Crtc,
+        mode: randr::Mode,
+        config: OutputConfig,
+        width: i32,
+        height: i32,
+    }
+
+    let mut enabled = Vec::new();
+    let mut disabled = Vec::new();
+    let mut used_crtcs = Vec::new();
+    let mut free_crtcs = Vec::new();
+    for &crtc in &resources.crtcs {
+        let info = ctx.conn.randr_get_crtc_info(crtc, timestamp)?.reply()?;
+        if info.outputs.is_empty() {
+            free_crtcs.push(crtc);
+        }
+    }
+
+    for &output in &resources.outputs {
+        let info = ctx.conn.randr_get_output_info(output, timestamp)?.reply()?;
+        let name = String::from_utf8_lossy(&info.name);
+        let config = match layout.iter().find(|conf| conf.name == name) {
+            Some(config) => *config,
+            None => continue,
+        };
+
+        if info.connection != randr::Connection::CONNECTED {
+            if info.crtc != x11rb::NONE {
+                disabled.push(info.crtc);
+            }
+            continue;
+        }
+
+        // the first num_preferred modes are preferred by the monitor, and otherwise the largest
+        // one is chosen
+        let mode_info = |mode: &randr::Mode| resources.modes.iter().find(|m| m.id == *mode);
+        let preferred = info
+            .modes
+            .get(..usize::from(info.num_preferred))
+            .unwrap_or_default();
+        let mode = match preferred.first() {
+            Some(mode) => Some(*mode),
+            None => info
+                .modes
+                .iter()
+                .filter_map(|mode| Some((*mode, mode_info(mode)?)))
+                .max_by_key(|(_, m)| (u32::from(m.width) * u32::from(m.height), m.dot_clock))
+                .map(|(mode, _)| mode),
+        };
+        let Some(mode) = mode else {
+            log::warn!("output {name} has no modes");
+            continue;
+        };
+        let crtc = if info.crtc != x11rb::NONE {
+            info.crtc
+        } else if let Some(i) = free_crtcs.iter().position(|c| info.crtcs.contains(c)) {
+            free_crtcs.remove(i)
+        } else {
+            log::warn!("no CRTC is available for output {name}");
+            continue;
+        };
+        used_crtcs.push(crtc);
+
+        let mode_info = mode_info(&mode).unwrap();
+        let (mut width, mut height) = (mode_info.width as i32, mode_info.height as i32);
+        if config.rotation.is_sideways() {
+            std::mem::swap(&mut width, &mut height);
+        }
+        log::info!(
+            "output {name}: mode {width}x{height} at {:?}",
+            config.position
+        );
+        enabled.push(Plan {
+            output,
+            crtc,
+            mode,
+            config,
+            width,
+            height,
+        });
+    }
+
+    for crtc in disabled {
+        ctx.conn
+            .randr_set_crtc_config(
+                crtc,
+                x11rb::CURRENT_TIME,
+                timestamp,
+                0,
+                0,
+                x11rb::NONE,
+                randr::Rotation::ROTATE0,
+                &[],
+            )?
+            .reply()?;
+    }
+
+    // the screen must be large enough to contain all CRTCs before they are configured
+    let (mut screen_w, mut screen_h) = (0, 0);
+    for plan in &enabled {
+        screen_w = screen_w.max(plan.config.position.0 as i32 + plan.width);
+        screen_h = screen_h.max(plan.config.position.1 as i32 + plan.height);
+    }
+    for &crtc in &resources.crtcs {
+        if used_crtcs.contains(&crtc) {
+            continue;
+        }
+        let info = ctx.conn.randr_get_crtc_info(crtc, timestamp)?.reply()?;
+        if info.mode != x11rb::NONE {
+            screen_w = screen_w.max(info.x as i32 + info.width as i32);
+            screen_h = screen_h.max(info.y as i32 + info.height as i32);
+        }
+    }
+    let current = ctx.conn.get_geometry(ctx.root)?.reply()?;
+    if screen_w > current.width as i32 || screen_h > current.height as i32 {
+        let (w, h) = (
+            screen_w.max(current.width as i32),
+            screen_h.max(current.height as i32),
+        );
+        set_screen_size(ctx, w, h)?;
+    }
+
+    for plan in &enabled {
+        let (x, y) = plan.config.position;
+        ctx.conn
+            .randr_set_crtc_config(
+                plan.crtc,
+                x11rb::CURRENT_TIME,
+                timestamp,
+                x,
+                y,
+                plan.mode,
+                plan.config.rotation.randr_rotation(),
+                &[plan.output],
+            )?
+            .reply()?;
+        if plan.config.primary {
+            ctx.conn.randr_set_output_primary(ctx.root, plan.output)?;
+        }
+    }
+
+    if screen_w > 0 && (screen_w != current.width as i32 || screen_h != current.height as i32) {
+        set_screen_size(ctx, screen_w, screen_h)?;
+    }
+    Ok(())
+}
+
+/// Resizes the screen keeping its DPI.
+fn set_screen_size(ctx: &utils::Context, width: i32, height: i32) -> Result<()> {
+    let screen = ctx.screen();
+    let mm_w = screen.width_in_millimeters as i32 * width / screen.width_in_pixels.max(1) as i32;
+    let mm_h = screen.height_in_millimeters as i32 * height / screen.height_in_pixels.max(1) as i32;
+    ctx.conn.randr_set_screen_size(
+        ctx.root,
+        width as u16,
+        height as u16,
+        mm_w as u32,
+        mm_h as u32,
+    )?;
+    Ok(())
+}
+
+/// the name and the identity of the monitor connected to an output
+#[derive(Debug, Clone)]
+pub struct OutputDescription {
+    pub name: String,
+    pub crtc: randr::Crtc,
+    /// the manufacturer, product code and serial number read from EDID (e.g. "DEL-40F1-4C385A30")
+    pub edid: Option<String>,
+}
+
+impl OutputDescription {
+    /// a string identifying the monitor, which doesn't change across CRTC renumbering
+    pub fn identity(&self) -> &str {
+        self.edid.as_deref().unwrap_or(&self.name)
+    }
+}
+
+pub fn describe_output(ctx: &utils::Context, output: randr::Output) -> Result<OutputDescription> {
+    let info = ctx
+        .conn
+        .randr_get_output_info(output, x11rb::CURRENT_TIME)?
+        .reply()?;
+    let edid = ctx
+        .conn
+        .randr_get_output_property(
+            output,
+            ctx.atom.EDID,
+            xproto::AtomEnum::ANY,
+            0,
+            32, // in 4-byte units
+            false,
+            false,
+        )?
+        .reply()?
+        .data;
+    Ok(OutputDescription {
+        name: String::from_utf8_lossy(&info.name).into_owned(),
+        crtc: info.crtc,
+        edid: parse_edid(&edid),
+    })
+}
+
+fn parse_edid(edid: &[u8]) -> Option<String> {
+    const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+    if edid.len() < 16 || edid[..8] != HEADER {
+        return None;
+    }
+    // three letters packed in 5 bits each
+    let vendor = u16::from_be_bytes([edid[8], edid[9]]);
+    let vendor: String = [10, 5, 0]
+        .iter()
+        .map(|shift| (b'A' - 1 + ((vendor >> shift) & 0x1F) as u8) as char)
+        .collect();
+    let product = u16::from_le_bytes([edid[10], edid[11]]);
+    let serial = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+    Some(format!("{vendor}-{product:04X}-{serial:08X}"))
+}
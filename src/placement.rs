@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// where windows of each application (WM_CLASS) were placed last time,
+/// kept in a file across sessions
+#[derive(Debug, Default)]
+pub struct Placements {
+    /// x, y, width and height (relative to the monitor) of floating windows
+    pub floating: HashMap<String, [i32; 4]>,
+    /// the desktops on which windows were last used
+    pub desktops: HashMap<String, usize>,
+}
+
+// The file consists of lines like this:
+//
+//   floating Firefox 100,80,1280,720
+//   desktop Firefox 2
+
+impl Placements {
+    /// Loads the placements. A missing or unreadable file gives empty placements.
+    pub fn load() -> Placements {
+        let mut placements = Placements::default();
+        let path = match file_path() {
+            Some(path) => path,
+            None => return placements,
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return placements,
+        };
+        for line in text.lines() {
+            if placements.parse_line(line).is_none() {
+                log::warn!("ignored a malformed line in {path:?}: {line:?}");
+            }
+        }
+        placements
+    }
+
+    pub fn save(&self) {
+        let path = match file_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut out = String::new();
+        let mut classes: Vec<_> = self.floating.iter().collect();
+        classes.sort();
+        for (class, [x, y, w, h]) in classes {
+            out += &format!("floating {} {x},{y},{w},{h}\n", escape(class));
+        }
+        let mut classes: Vec<_> = self.desktops.iter().collect();
+        classes.sort();
+        for (class, desktop) in classes {
+            out += &format!("desktop {} {desktop}\n", escape(class));
+        }
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, out));
+        if let Err(err) = result {
+            log::error!("Failed to save placements to {path:?}: {err}");
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let mut words = line.split_whitespace();
+        let kind = words.next()?;
+        let class = unescape(words.next()?);
+        match kind {
+            "floating" => {
+                let geometry: Vec<i32> = words
+                    .next()?
+                    .split(',')
+                    .map(|v| v.parse().ok())
+                    .collect::<Option<_>>()?;
+                self.floating.insert(class, geometry.try_into().ok()?);
+            }
+            "desktop" => {
+                self.desktops.insert(class, words.next()?.parse().ok()?);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// `$XDG_STATE_HOME/daily2/placements`, or `~/.local/state/daily2/placements`
+fn file_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(dir.join("daily2/placements"))
+}
+
+/// Class names may contain spaces, which are written as `%20` (and `%` as `%25`).
+fn escape(class: &str) -> String {
+    class.replace('%', "%25").replace(' ', "%20")
+}
+
+fn unescape(class: &str) -> String {
+    class.replace("%20", " ").replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes_are_escaped_into_a_word() {
+        for class in ["Firefox", "Google Chrome", "100%", "%20", "% %25 ", ""] {
+            let escaped = escape(class);
+            assert!(!escaped.contains(' '), "{escaped:?}");
+            assert_eq!(unescape(&escaped), class);
+        }
+        assert_eq!(escape("Google Chrome"), "Google%20Chrome");
+        assert_eq!(escape("100%"), "100%25");
+    }
+
+    #[test]
+    fn lines_are_parsed() {
+        let mut placements = Placements::default();
+        assert!(placements
+            .parse_line("floating Firefox 100,80,1280,720")
+            .is_some());
+        assert!(placements
+            .parse_line("floating Google%20Chrome -5,0,640,480")
+            .is_some());
+        assert!(placements.parse_line("desktop Firefox 2").is_some());
+        assert_eq!(placements.floating["Firefox"], [100, 80, 1280, 720]);
+        assert_eq!(placements.floating["Google Chrome"], [-5, 0, 640, 480]);
+        assert_eq!(placements.desktops["Firefox"], 2);
+
+        for line in [
+            "",
+            "floating",
+            "floating Firefox",
+            "floating Firefox 1,2,3",
+            "floating Firefox 1,2,3,4,5",
+            "floating Firefox 1,2,x,4",
+            "desktop Firefox",
+            "desktop Firefox -1",
+            "window Firefox 1",
+        ] {
+            assert!(
+                placements.parse_line(line).is_none(),
+                "{line:?} is accepted"
+            );
+        }
+        assert_eq!(placements.floating.len(), 2);
+        assert_eq!(placements.desktops.len(), 1);
+    }
+}
@@ -0,0 +1,93 @@
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt as _;
+
+// Signals handled by the WM are blocked and read from a signalfd in the event loop instead of
+// interrupting it. The mask is inherited by child processes, so they unblock the signals
+// before executing the program.
+
+/// the signals read from the signalfd
+const SIGNALS: &[libc::c_int] = &[libc::SIGCHLD, libc::SIGTERM, libc::SIGHUP];
+
+/// a non-blocking signalfd receiving SIGNALS
+pub struct SignalFd {
+    fd: RawFd,
+}
+
+impl SignalFd {
+    /// Blocks the signals and creates the signalfd.
+    /// This must be done before spawning threads, which inherit the signal mask.
+    pub fn new() -> std::io::Result<SignalFd> {
+        let mask = signal_set();
+        let mut old: libc::sigset_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sigprocmask(libc::SIG_BLOCK, &mask, &mut old) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            let err = std::io::Error::last_os_error();
+            // the signals would never be handled while blocked without the signalfd
+            unsafe { libc::sigprocmask(libc::SIG_SETMASK, &old, std::ptr::null_mut()) };
+            return Err(err);
+        }
+        Ok(SignalFd { fd })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Reads the pending signals. Signals of the same kind received in between are merged.
+    pub fn read(&self) -> Vec<libc::c_int> {
+        let mut signals = Vec::new();
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+            let size = std::mem::size_of::<libc::signalfd_siginfo>();
+            let n = unsafe { libc::read(self.fd, &mut info as *mut _ as *mut libc::c_void, size) };
+            if n != size as isize {
+                break;
+            }
+            signals.push(info.ssi_signo as libc::c_int);
+        }
+        signals
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Makes the command run with the signals unblocked.
+pub fn unblock_in_child(command: &mut std::process::Command) -> &mut std::process::Command {
+    // sigprocmask is async-signal-safe, so it can be called between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            let mask = signal_set();
+            libc::sigprocmask(libc::SIG_UNBLOCK, &mask, std::ptr::null_mut());
+            Ok(())
+        })
+    }
+}
+
+/// Reaps all exited child processes.
+pub fn reap_children() {
+    loop {
+        let pid = unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) };
+        if pid <= 0 {
+            break;
+        }
+        log::debug!("child process {pid} exited");
+    }
+}
+
+fn signal_set() -> libc::sigset_t {
+    unsafe {
+        let mut mask: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut mask);
+        for &signal in SIGNALS {
+            libc::sigaddset(&mut mask, signal);
+        }
+        mask
+    }
+}
@@ -0,0 +1,481 @@
+use std::fs::File;
+use std::io::{Read as _, Seek as _, Write as _};
+use std::os::fd::{FromRawFd as _, IntoRawFd as _, RawFd};
+use std::path::PathBuf;
+
+use crate::daily::{Container, Layout, SplitDirection};
+
+// On restart, the serialized state is written into a memfd which is left open across exec,
+// and its descriptor is told to the next process with $DAILY2_STATE_FD. Nothing is left
+// behind in the X server or the file system if the next process fails to start.
+const FD_VAR: &str = "DAILY2_STATE_FD";
+
+// When the session manager asks to save the state (SaveYourself), it is written into a file
+// instead, since the next session runs on another X server. Window IDs are meaningless there,
+// so only the monitors and the desktops are restored from it.
+const SESSION_FILE: &str = "daily2/session";
+
+/// the WM state carried over to the next process on restart
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub monitors: Vec<MonitorState>,
+    pub desktops: Vec<DesktopState>,
+    /// managed windows in the tiling order
+    pub windows: Vec<WindowState>,
+    pub focus: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MonitorState {
+    pub crtc: u32,
+    /// the EDID or the output name of the monitor
+    pub identity: Option<String>,
+    pub desktop: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DesktopState {
+    pub index: usize,
+    pub layout: Layout,
+    pub columns: usize,
+    pub tree: Option<Container>,
+    pub transposed: bool,
+    pub mirrored: bool,
+    /// the windows hidden by HideWindow, the last one is shown first
+    pub hidden: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowState {
+    pub id: u32,
+    pub desktop: usize,
+    pub floating: bool,
+    pub fullscreen: bool,
+    /// x, y, width and height (relative to the monitor)
+    pub geometry: [i32; 4],
+    pub weight: f64,
+    /// hidden by HideWindow, a window group or swallowing
+    pub hidden: bool,
+}
+
+// The state is serialized into a line-oriented text like this:
+//
+//   monitor 0x3f desktop=1 identity=DEL-40F1-4C385A30
+//   desktop 1 layout=manual columns=3 transposed=0 mirrored=1 tree=h[0x400001,v[0x600001]]
+//     hidden=0x800001,0xA00001
+//   window 0x400001 desktop=1 floating=0 fullscreen=0 geometry=0,0,958,1078 weight=1 hidden=0
+//
+// (the desktop line is wrapped here)
+//   focus 0x400001
+
+impl State {
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        for mon in &self.monitors {
+            out += &format!("monitor 0x{:X} desktop={}", mon.crtc, mon.desktop);
+            if let Some(identity) = &mon.identity {
+                out += &format!(" identity={identity}");
+            }
+            out += "\n";
+        }
+        for desktop in &self.desktops {
+            out += &format!(
+                "desktop {} layout={} columns={} transposed={} mirrored={}",
+                desktop.index,
+                desktop.layout.name(),
+                desktop.columns,
+                desktop.transposed as u8,
+                desktop.mirrored as u8,
+            );
+            if let Some(tree) = &desktop.tree {
+                out += " tree=";
+                write_tree(tree, &mut out);
+            }
+            if !desktop.hidden.is_empty() {
+                let ids: Vec<String> = desktop
+                    .hidden
+                    .iter()
+                    .map(|id| format!("0x{id:X}"))
+                    .collect();
+                out += &format!(" hidden={}", ids.join(","));
+            }
+            out += "\n";
+        }
+        for win in &self.windows {
+            let [x, y, w, h] = win.geometry;
+            out += &format!(
+                "window 0x{:X} desktop={} floating={} fullscreen={} geometry={x},{y},{w},{h}",
+                win.id, win.desktop, win.floating as u8, win.fullscreen as u8,
+            );
+            out += &format!(" weight={} hidden={}\n", win.weight, win.hidden as u8);
+        }
+        if let Some(focus) = self.focus {
+            out += &format!("focus 0x{focus:X}\n");
+        }
+        out
+    }
+
+    /// Parses the serialized state. Malformed lines are ignored.
+    pub fn deserialize(text: &str) -> State {
+        let mut state = State::default();
+        for line in text.lines() {
+            if parse_line(&mut state, line).is_none() {
+                log::warn!("ignored a malformed line in the saved state: {line:?}");
+            }
+        }
+        state
+    }
+}
+
+fn parse_line(state: &mut State, line: &str) -> Option<()> {
+    let mut words = line.split_whitespace();
+    let kind = words.next()?;
+    let id = words.next()?;
+    let attr = |key: &str| -> Option<&str> {
+        line.split_whitespace()
+            .skip(2)
+            .find_map(|word| word.strip_prefix(key)?.strip_prefix('='))
+    };
+
+    match kind {
+        "monitor" => state.monitors.push(MonitorState {
+            crtc: parse_id(id)?,
+            identity: attr("identity").map(str::to_owned),
+            desktop: attr("desktop")?.parse().ok()?,
+        }),
+        "desktop" => state.desktops.push(DesktopState {
+            index: id.parse().ok()?,
+            layout: Layout::from_name(attr("layout")?)?,
+            columns: attr("columns")?.parse().ok()?,
+            tree: match attr("tree") {
+                Some(tree) => Some(parse_tree(&mut tree.chars().peekable())?),
+                None => None,
+            },
+            // missing in the state saved by older versions
+            transposed: attr("transposed") == Some("1"),
+            mirrored: attr("mirrored") == Some("1"),
+            hidden: match attr("hidden") {
+                Some(ids) => ids.split(',').map(parse_id).collect::<Option<_>>()?,
+                None => Vec::new(),
+            },
+        }),
+        "window" => {
+            let geometry: Vec<i32> = attr("geometry")?
+                .split(',')
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?;
+            state.windows.push(WindowState {
+                id: parse_id(id)?,
+                desktop: attr("desktop")?.parse().ok()?,
+                floating: attr("floating")? == "1",
+                fullscreen: attr("fullscreen")? == "1",
+                geometry: geometry.try_into().ok()?,
+                weight: match attr("weight") {
+                    Some(weight) => weight.parse().ok()?,
+                    None => 1.0,
+                },
+                hidden: attr("hidden") == Some("1"),
+            });
+        }
+        "focus" => state.focus = Some(parse_id(id)?),
+        _ => return None,
+    }
+    Some(())
+}
+
+fn parse_id(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+fn write_tree(tree: &Container, out: &mut String) {
+    match tree {
+        Container::Window(id) => *out += &format!("0x{id:X}"),
+        Container::Split {
+            direction,
+            children,
+        } => {
+            out.push(match direction {
+                SplitDirection::Horizontal => 'h',
+                SplitDirection::Vertical => 'v',
+            });
+            out.push('[');
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_tree(child, out);
+            }
+            out.push(']');
+        }
+    }
+}
+
+fn parse_tree(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<Container> {
+    let direction = match chars.peek()? {
+        'h' => SplitDirection::Horizontal,
+        'v' => SplitDirection::Vertical,
+        _ => {
+            let id: String =
+                std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_alphanumeric())).collect();
+            return Some(Container::Window(parse_id(&id)?));
+        }
+    };
+    chars.next();
+
+    if chars.next()? != '[' {
+        return None;
+    }
+    let mut children = Vec::new();
+    loop {
+        children.push(parse_tree(chars)?);
+        match chars.next()? {
+            ',' => continue,
+            ']' => break,
+            _ => return None,
+        }
+    }
+    Some(Container::Split {
+        direction,
+        children,
+    })
+}
+
+/// Writes the serialized state into a memory file inherited by the process executed next.
+pub fn hand_over(text: &str) -> std::io::Result<()> {
+    // without MFD_CLOEXEC
+    let fd = unsafe { libc::memfd_create(c"daily2-state".as_ptr(), 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(text.as_bytes())?;
+    std::env::set_var(FD_VAR, file.into_raw_fd().to_string());
+    Ok(())
+}
+
+/// Reads the serialized state handed over by the previous process, if any. The descriptor is
+/// closed so that it isn't inherited by the clients spawned later.
+pub fn take_over() -> Option<String> {
+    let fd: RawFd = std::env::var(FD_VAR).ok()?.parse().ok()?;
+    std::env::remove_var(FD_VAR);
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        log::warn!("the state descriptor {fd} is not open");
+        return None;
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut text = String::new();
+    let read = file.rewind().and_then(|()| file.read_to_string(&mut text));
+    match read {
+        Ok(_) => Some(text),
+        Err(err) => {
+            log::error!("failed to read the handed over state: {err}");
+            None
+        }
+    }
+}
+
+/// `$XDG_STATE_HOME/daily2/session` (`~/.local/state/daily2/session`)
+fn session_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(dir.join(SESSION_FILE))
+}
+
+/// Writes the serialized state for the next session.
+pub fn save_session(text: &str) -> std::io::Result<()> {
+    let path = session_path()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "$HOME is not set"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // replaced at once not to leave a half-written file if the session ends meanwhile
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, text)?;
+    std::fs::rename(&temporary, &path)
+}
+
+/// Reads the state saved by `save_session` in the previous session, without the windows.
+pub fn load_session() -> Option<State> {
+    let path = session_path()?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            log::error!("failed to read {}: {err}", path.display());
+            return None;
+        }
+    };
+    let mut state = State::deserialize(&text);
+    state.windows.clear();
+    state.focus = None;
+    for desktop in &mut state.desktops {
+        desktop.tree = None;
+        desktop.hidden.clear();
+    }
+    Some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> State {
+        let window = Container::Window;
+        State {
+            monitors: vec![
+                MonitorState {
+                    crtc: 0x3f,
+                    identity: Some("DEL-40F1-4C385A30".to_owned()),
+                    desktop: 1,
+                },
+                MonitorState {
+                    crtc: 0x40,
+                    identity: None,
+                    desktop: 0,
+                },
+            ],
+            desktops: vec![
+                DesktopState {
+                    index: 0,
+                    layout: Layout::Horizontal,
+                    columns: 2,
+                    tree: None,
+                    transposed: false,
+                    mirrored: false,
+                    hidden: Vec::new(),
+                },
+                DesktopState {
+                    index: 1,
+                    layout: Layout::Manual,
+                    columns: 3,
+                    tree: Some(Container::Split {
+                        direction: SplitDirection::Horizontal,
+                        children: vec![
+                            window(0x400001),
+                            Container::Split {
+                                direction: SplitDirection::Vertical,
+                                children: vec![
+                                    window(0x600001),
+                                    Container::Split {
+                                        direction: SplitDirection::Horizontal,
+                                        children: vec![window(0x600002), window(0x600003)],
+                                    },
+                                ],
+                            },
+                        ],
+                    }),
+                    transposed: true,
+                    mirrored: true,
+                    hidden: vec![0x800001, 0xA00001],
+                },
+            ],
+            windows: vec![
+                WindowState {
+                    id: 0x400001,
+                    desktop: 1,
+                    floating: false,
+                    fullscreen: false,
+                    geometry: [0, 0, 958, 1078],
+                    weight: 1.0,
+                    hidden: false,
+                },
+                WindowState {
+                    id: 0x800001,
+                    desktop: 1,
+                    floating: true,
+                    fullscreen: true,
+                    geometry: [-10, 20, 300, 200],
+                    weight: 0.25,
+                    hidden: true,
+                },
+            ],
+            focus: Some(0x400001),
+        }
+    }
+
+    #[test]
+    fn state_is_restored_as_serialized() {
+        let state = sample();
+        let text = state.serialize();
+        let restored = State::deserialize(&text);
+        assert_eq!(restored.serialize(), text);
+
+        assert_eq!(restored.monitors.len(), 2);
+        assert_eq!(restored.monitors[0].crtc, 0x3f);
+        assert_eq!(restored.monitors[0].identity, state.monitors[0].identity);
+        assert_eq!(restored.monitors[1].identity, None);
+
+        assert_eq!(restored.desktops.len(), 2);
+        let (empty, manual) = (&restored.desktops[0], &restored.desktops[1]);
+        assert_eq!(empty.layout, Layout::Horizontal);
+        assert_eq!(empty.tree, None);
+        assert!(empty.hidden.is_empty());
+        assert_eq!(manual.layout, Layout::Manual);
+        assert_eq!(manual.columns, 3);
+        assert!(manual.transposed && manual.mirrored);
+        assert_eq!(manual.tree, state.desktops[1].tree);
+        assert_eq!(manual.hidden, [0x800001, 0xA00001]);
+
+        assert_eq!(restored.windows.len(), 2);
+        let window = &restored.windows[1];
+        assert_eq!(window.id, 0x800001);
+        assert!(window.floating && window.fullscreen && window.hidden);
+        assert_eq!(window.geometry, [-10, 20, 300, 200]);
+        assert_eq!(window.weight, 0.25);
+        assert_eq!(restored.focus, Some(0x400001));
+    }
+
+    #[test]
+    fn empty_state_is_empty_text() {
+        let state = State::default();
+        assert_eq!(state.serialize(), "");
+        let restored = State::deserialize("");
+        assert!(restored.monitors.is_empty() && restored.desktops.is_empty());
+        assert!(restored.windows.is_empty() && restored.focus.is_none());
+    }
+
+    #[test]
+    fn malformed_lines_are_ignored() {
+        let text = "\
+monitor 0x3f
+monitor 3f desktop=1
+desktop 1 layout=bogus columns=1
+desktop 2 layout=manual columns=1 tree=h[0x1,v[0x2]
+desktop 3 layout=manual columns=1 tree=h[0x1;0x2]
+desktop 4 layout=manual columns=1 tree=x[0x1]
+desktop 5 layout=tabbed columns=1 hidden=0x1,2
+window 0x1 desktop=0 floating=0 fullscreen=0 geometry=0,0,10
+window 0x2 desktop=0 floating=0 fullscreen=0 geometry=0,0,10,10 weight=heavy
+focus
+bogus 0x1
+
+desktop 6 layout=tabbed columns=1
+window 0x3 desktop=6 floating=1 fullscreen=0 geometry=1,2,3,4
+";
+        let state = State::deserialize(text);
+        assert!(state.monitors.is_empty());
+        assert_eq!(state.desktops.len(), 1);
+        assert_eq!(state.desktops[0].index, 6);
+        // missing in the state saved by older versions
+        assert!(!state.desktops[0].transposed && state.desktops[0].hidden.is_empty());
+        assert_eq!(state.windows.len(), 1);
+        assert_eq!(state.windows[0].weight, 1.0);
+        assert!(state.windows[0].floating && !state.windows[0].hidden);
+        assert_eq!(state.focus, None);
+    }
+
+    #[test]
+    fn trees_are_parsed_as_written() {
+        let parse = |text: &str| parse_tree(&mut text.chars().peekable());
+        let tree = parse("v[h[0x1,0x2],0x3,v[v[0xABC]]]").unwrap();
+        let mut text = String::new();
+        write_tree(&tree, &mut text);
+        assert_eq!(text, "v[h[0x1,0x2],0x3,v[v[0xABC]]]");
+        assert_eq!(parse("0x10"), Some(Container::Window(0x10)));
+        for malformed in ["", "h[]", "h[0x1", "h0x1]", "h[0x1,]", "10", "0xZZ"] {
+            assert_eq!(parse(malformed), None, "{malformed:?} is accepted");
+        }
+    }
+}
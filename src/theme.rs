@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use crate::{cli, config};
+
+// The theme is a table of named colors, each given as "#RRGGBB" or by another name, so that
+// the elements can share a palette. The table of config::THEME is overridden (or extended) by
+// the lines of the theme file:
+//
+//   # comments start with "# " at the beginning of the line
+//   blue #3465A4
+//   border-focused blue
+
+/// the colors (0xRRGGBB) of the elements drawn by the WM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub border_focused: u32,
+    pub border_unfocused: u32,
+    pub border_urgent: u32,
+    /// the fill and the border of the preview window
+    pub preview: (u32, u32),
+    /// the margins around the wallpaper
+    pub wallpaper: u32,
+    /// behind thumbnails in the switcher and desktops in the pager
+    pub background: u32,
+    /// tab bars, the root menu, the pager and the switcher: (background, text)
+    pub tab_active: (u32, u32),
+    pub tab_inactive: (u32, u32),
+    /// the button under the pointer on title bars
+    pub button_hover: u32,
+    /// the borders (focused, unfocused) of windows of specific classes (config::BORDER_COLORS)
+    pub class_borders: Vec<(&'static str, u32, u32)>,
+}
+
+impl Theme {
+    /// Loads the theme from config::THEME and the theme file. Colors which can't be resolved
+    /// are black.
+    pub fn load() -> Theme {
+        let mut table: HashMap<&str, String> = config::THEME
+            .iter()
+            .map(|&(name, value)| (name, value.to_owned()))
+            .collect();
+
+        let file =
+            cli::config_file().and_then(|path| Some((std::fs::read_to_string(&path).ok()?, path)));
+        if let Some((text, path)) = &file {
+            for (_, line, entry) in entries(text) {
+                match entry {
+                    Some((name, value)) => {
+                        table.insert(name, value.to_owned());
+                    }
+                    None => log::warn!("ignored a malformed line in {path:?}: {line:?}"),
+                }
+            }
+        }
+
+        let color = |name: &str| {
+            resolve(&table, name).unwrap_or_else(|| {
+                log::error!("the color of {name:?} in the theme is not resolved");
+                0x000000
+            })
+        };
+        let class_color = |class: &str, value: &str| {
+            resolve_value(&table, value).unwrap_or_else(|| {
+                log::error!("the border color {value:?} of {class:?} is not resolved");
+                0x000000
+            })
+        };
+        let class_borders = config::BORDER_COLORS
+            .iter()
+            .map(|&(class, focused, unfocused)| {
+                let focused = class_color(class, focused);
+                (class, focused, class_color(class, unfocused))
+            })
+            .collect();
+        Theme {
+            border_focused: color("border-focused"),
+            border_unfocused: color("border-unfocused"),
+            border_urgent: color("border-urgent"),
+            preview: (color("preview"), color("preview-border")),
+            wallpaper: color("wallpaper"),
+            background: color("background"),
+            tab_active: (color("tab-active"), color("tab-active-text")),
+            tab_inactive: (color("tab-inactive"), color("tab-inactive-text")),
+            button_hover: color("button-hover"),
+            class_borders,
+        }
+    }
+
+    /// Returns the border colors (focused, unfocused) of windows of the class.
+    pub fn borders(&self, class: Option<&str>) -> (u32, u32) {
+        self.class_borders
+            .iter()
+            .find(|(c, _, _)| Some(*c) == class)
+            .map_or(
+                (self.border_focused, self.border_unfocused),
+                |&(_, f, u)| (f, u),
+            )
+    }
+}
+
+/// Reads the theme file like `load`, and returns the problems: malformed lines and colors which
+/// can't be resolved, with the line numbers in the file.
+pub fn check() -> Vec<String> {
+    let mut table: HashMap<&str, String> = config::THEME
+        .iter()
+        .map(|&(name, value)| (name, value.to_owned()))
+        .collect();
+    let mut problems = Vec::new();
+
+    let path = cli::config_file().unwrap_or_default();
+    let text = std::fs::read_to_string(&path).unwrap_or_default();
+    let path = path.display();
+    let mut defined = Vec::new();
+    for (number, line, entry) in entries(&text) {
+        match entry {
+            Some((name, value)) => {
+                table.insert(name, value.to_owned());
+                defined.push((number, name, value));
+            }
+            None => problems.push(format!("{path}:{number}: malformed line {line:?}")),
+        }
+    }
+    for (number, name, value) in defined {
+        if resolve_value(&table, value).is_none() {
+            problems.push(format!(
+                "{path}:{number}: the color of {name:?} is not resolved"
+            ));
+        }
+    }
+
+    for name in ELEMENTS {
+        if resolve(&table, name).is_none() {
+            problems.push(format!("the color of {name:?} is not resolved"));
+        }
+    }
+    for &(class, focused, unfocused) in config::BORDER_COLORS {
+        for value in [focused, unfocused] {
+            if resolve_value(&table, value).is_none() {
+                problems.push(format!(
+                    "the border color {value:?} of {class:?} is not resolved"
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// the names of the colors of the elements, which must be resolved
+const ELEMENTS: &[&str] = &[
+    "border-focused",
+    "border-unfocused",
+    "border-urgent",
+    "preview",
+    "preview-border",
+    "wallpaper",
+    "background",
+    "tab-active",
+    "tab-active-text",
+    "tab-inactive",
+    "tab-inactive-text",
+    "button-hover",
+];
+
+/// Splits the theme file into the lines, with their numbers (1-origin) and the pairs of the name
+/// and the value (None for malformed lines). Empty lines and comments are skipped.
+fn entries(text: &str) -> impl Iterator<Item = (usize, &str, Option<(&str, &str)>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("# ") || line == "#" {
+            return None;
+        }
+        let entry = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, value] => Some((name, value)),
+            _ => None,
+        };
+        Some((i + 1, line, entry))
+    })
+}
+
+fn resolve(table: &HashMap<&str, String>, name: &str) -> Option<u32> {
+    resolve_value(table, table.get(name)?)
+}
+
+/// Follows the names from the value until a hex color, giving up on cycles.
+fn resolve_value<'a>(table: &'a HashMap<&str, String>, mut value: &'a str) -> Option<u32> {
+    for _ in 0..=table.len() {
+        if let Some(hex) = value.strip_prefix('#') {
+            return parse_hex(hex);
+        }
+        value = table.get(value)?.as_str();
+    }
+    None
+}
+
+/// Parses "RRGGBB", or the short form "RGB".
+fn parse_hex(hex: &str) -> Option<u32> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        6 => u32::from_str_radix(hex, 16).ok(),
+        3 => {
+            let short = u32::from_str_radix(hex, 16).ok()?;
+            let channel = |shift: u32| (short >> shift & 0xF) * 0x11;
+            Some(channel(8) << 16 | channel(4) << 8 | channel(0))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colors_are_parsed_in_both_forms() {
+        assert_eq!(parse_hex("3465A4"), Some(0x3465A4));
+        assert_eq!(parse_hex("ffffff"), Some(0xFFFFFF));
+        assert_eq!(parse_hex("F0a"), Some(0xFF00AA));
+        assert_eq!(parse_hex("000"), Some(0));
+        for hex in ["", "12", "1234", "1234567", "GGGGGG", "+12345", " 12345"] {
+            assert_eq!(parse_hex(hex), None, "{hex:?} is accepted");
+        }
+    }
+
+    #[test]
+    fn names_are_followed_until_a_color() {
+        let table: HashMap<&str, String> = [
+            ("blue", "#3465A4"),
+            ("border", "blue"),
+            ("border-focused", "border"),
+            ("white", "#FFF"),
+            ("broken", "#12"),
+            ("dangling", "nowhere"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.to_owned()))
+        .collect();
+        assert_eq!(resolve(&table, "blue"), Some(0x3465A4));
+        assert_eq!(resolve(&table, "border-focused"), Some(0x3465A4));
+        assert_eq!(resolve(&table, "white"), Some(0xFFFFFF));
+        assert_eq!(resolve_value(&table, "border"), Some(0x3465A4));
+        assert_eq!(resolve_value(&table, "#000"), Some(0));
+        assert_eq!(resolve(&table, "broken"), None);
+        assert_eq!(resolve(&table, "dangling"), None);
+        assert_eq!(resolve(&table, "unknown"), None);
+    }
+
+    #[test]
+    fn cycles_are_given_up() {
+        let table: HashMap<&str, String> = [("a", "b"), ("b", "c"), ("c", "a"), ("d", "d")]
+            .into_iter()
+            .map(|(name, value)| (name, value.to_owned()))
+            .collect();
+        assert_eq!(resolve(&table, "a"), None);
+        assert_eq!(resolve(&table, "d"), None);
+
+        // the longest chain without a cycle goes through all the names
+        let names: Vec<String> = (0..8).map(|i| format!("c{i}")).collect();
+        let mut table: HashMap<&str, String> = names
+            .windows(2)
+            .map(|pair| (pair[0].as_str(), pair[1].clone()))
+            .collect();
+        table.insert(&names[7], "#123456".to_owned());
+        assert_eq!(resolve(&table, "c0"), Some(0x123456));
+        assert_eq!(resolve_value(&table, "c0"), Some(0x123456));
+    }
+}
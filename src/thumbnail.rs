@@ -0,0 +1,157 @@
+use x11rb::connection::{Connection as _, RequestConnection as _};
+use x11rb::protocol::{composite, render, xproto};
+
+use composite::ConnectionExt as _;
+use render::ConnectionExt as _;
+use xproto::ConnectionExt as _;
+
+use crate::error::Result;
+use crate::layout::Rect;
+use crate::utils;
+
+// Thumbnails are scaled with RENDER from the pixmaps of windows named by the Composite
+// extension. All top-level windows are redirected automatically, so that the server keeps the
+// contents of windows covered by others while still drawing them on the screen as usual.
+
+/// draws thumbnails of windows
+pub struct Thumbnailer {
+    formats: render::QueryPictFormatsReply,
+}
+
+impl Thumbnailer {
+    /// Redirects the top-level windows, or returns None if Composite 0.2 or RENDER 0.6 (for
+    /// transforms) is not available.
+    pub fn new(ctx: &utils::Context) -> Result<Option<Thumbnailer>> {
+        for name in [composite::X11_EXTENSION_NAME, render::X11_EXTENSION_NAME] {
+            if ctx.conn.extension_information(name)?.is_none() {
+                log::info!("thumbnails are not drawn without the {name} extension");
+                return Ok(None);
+            }
+        }
+        let composite = ctx.conn.composite_query_version(0, 4)?.reply()?;
+        let render = ctx.conn.render_query_version(0, 11)?.reply()?;
+        if (composite.major_version, composite.minor_version) < (0, 2)
+            || (render.major_version, render.minor_version) < (0, 6)
+        {
+            log::info!("thumbnails are not drawn with old Composite or RENDER");
+            return Ok(None);
+        }
+
+        ctx.conn
+            .composite_redirect_subwindows(ctx.root, composite::Redirect::AUTOMATIC)?;
+        let formats = ctx.conn.render_query_pict_formats()?.reply()?;
+        Ok(Some(Thumbnailer { formats }))
+    }
+
+    fn format_of(&self, visual: xproto::Visualid) -> Option<render::Pictformat> {
+        self.formats
+            .screens
+            .iter()
+            .flat_map(|screen| screen.depths.iter())
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|v| v.visual == visual)
+            .map(|v| v.format)
+    }
+
+    /// Creates a picture on the window to draw thumbnails into.
+    pub fn create_picture(
+        &self,
+        ctx: &utils::Context,
+        window: xproto::Window,
+        visual: xproto::Visualid,
+    ) -> Result<Option<render::Picture>> {
+        let Some(format) = self.format_of(visual) else {
+            return Ok(None);
+        };
+        let picture = ctx.conn.generate_id()?;
+        ctx.conn
+            .render_create_picture(picture, window, format, &Default::default())?;
+        Ok(Some(picture))
+    }
+
+    /// Draws the windows (including their borders) scaled down to fit in their areas of the
+    /// picture, centered and keeping their aspect ratios. Nothing is drawn for a window that has
+    /// no contents, e.g. when it's unmapped.
+    ///
+    /// The requests for all the windows are sent before any reply is waited for, so that the
+    /// redraw takes a single round trip however many windows there are.
+    pub fn draw_all(
+        &self,
+        ctx: &utils::Context,
+        dst: render::Picture,
+        windows: &[(xproto::Window, Rect)],
+    ) -> Result<()> {
+        let mut requests = Vec::with_capacity(windows.len());
+        for &(window, area) in windows {
+            let pixmap = ctx.conn.generate_id()?;
+            let attrs = ctx.conn.get_window_attributes(window)?;
+            let named = ctx.conn.composite_name_window_pixmap(window, pixmap)?;
+            // fails with BadDrawable if the pixmap isn't named
+            let geo = ctx.conn.get_geometry(pixmap)?;
+            requests.push((pixmap, area, attrs, named, geo));
+        }
+        for (pixmap, area, attrs, named, geo) in requests {
+            let named = named.check().is_ok();
+            let (Ok(attrs), Ok(geo)) = (attrs.reply(), geo.reply()) else {
+                if named {
+                    ctx.conn.free_pixmap(pixmap)?;
+                }
+                continue;
+            };
+            if let Some(format) = self.format_of(attrs.visual) {
+                self.draw(ctx, pixmap, format, &geo, dst, area)?;
+            }
+            ctx.conn.free_pixmap(pixmap)?;
+        }
+        Ok(())
+    }
+
+    fn draw(
+        &self,
+        ctx: &utils::Context,
+        pixmap: xproto::Pixmap,
+        format: render::Pictformat,
+        geo: &xproto::GetGeometryReply,
+        dst: render::Picture,
+        area: Rect,
+    ) -> Result<()> {
+        let (w, h) = (geo.width.max(1) as f64, geo.height.max(1) as f64);
+        let scale = (area.w as f64 / w).min(area.h as f64 / h).min(1.0);
+        let (tw, th) = ((w * scale).round().max(1.0), (h * scale).round().max(1.0));
+
+        let src = ctx.conn.generate_id()?;
+        ctx.conn
+            .render_create_picture(src, pixmap, format, &Default::default())?;
+        // the transform maps the thumbnail back to the window, in 16.16 fixed point
+        let fixed = |v: f64| (v * 65536.0).round() as render::Fixed;
+        let transform = render::Transform {
+            matrix11: fixed(1.0 / scale),
+            matrix12: 0,
+            matrix13: 0,
+            matrix21: 0,
+            matrix22: fixed(1.0 / scale),
+            matrix23: 0,
+            matrix31: 0,
+            matrix32: 0,
+            matrix33: fixed(1.0),
+        };
+        ctx.conn.render_set_picture_transform(src, transform)?;
+        ctx.conn.render_set_picture_filter(src, b"bilinear", &[])?;
+        ctx.conn.render_composite(
+            render::PictOp::OVER,
+            src,
+            x11rb::NONE,
+            dst,
+            0,
+            0,
+            0,
+            0,
+            (area.x + (area.w - tw as i32) / 2) as i16,
+            (area.y + (area.h - th as i32) / 2) as i16,
+            tw as u16,
+            th as u16,
+        )?;
+        ctx.conn.render_free_picture(src)?;
+        Ok(())
+    }
+}
@@ -1,8 +1,15 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
 use std::rc::Rc;
 
 use x11rb::connection::Connection as _;
-use x11rb::protocol::xproto;
+use x11rb::cookie::Cookie;
+use x11rb::protocol::{res, shape, xproto};
 use x11rb::rust_connection::RustConnection;
+
+use res::ConnectionExt as _;
+use shape::ConnectionExt as _;
 use xproto::ConnectionExt as _;
 
 use crate::error::Result;
@@ -17,60 +24,418 @@ x11rb::atom_manager! {
         _NET_MOVERESIZE_WINDOW,
         _NET_WM_STATE,
         _NET_WM_STATE_FULLSCREEN,
+        _NET_WM_STATE_ABOVE,
+        _NET_WM_STATE_DEMANDS_ATTENTION,
+        _NET_WM_STATE_SKIP_TASKBAR,
+        _NET_WM_STATE_SKIP_PAGER,
+        _NET_WM_STATE_HIDDEN,
         _NET_WM_WINDOW_TYPE,
         _NET_WM_WINDOW_TYPE_DIALOG,
+        _NET_WM_WINDOW_TYPE_DOCK,
+        _NET_WM_WINDOW_TYPE_NOTIFICATION,
+        _NET_WM_STRUT,
+        _NET_WM_STRUT_PARTIAL,
+        _NET_WORKAREA,
+        _NET_WM_NAME,
+        UTF8_STRING,
+        _DAILY2_STATE,
+        _DAILY2_MODE,
+        _DAILY2_PROMPT,
+        _DAILY2_KEYBOARD_LAYOUT,
+        _NET_WM_PID,
+        EDID,
+        I3_SOCKET_PATH,
+        WM_PROTOCOLS,
+        WM_TAKE_FOCUS,
+        WM_DELETE_WINDOW,
+        MANAGER,
+        _XROOTPMAP_ID,
+        ESETROOT_PMAP_ID,
     }
 }
 
 #[derive(Clone)]
 pub struct Context {
     pub conn: Rc<RustConnection>,
+    /// the screen given by the display name (e.g. 1 for ":0.1"), which is managed alone
+    pub screen_num: usize,
     pub root: xproto::Window,
     pub atom: AtomCollection,
+    /// atoms looked up by `get_atom_name` and `intern_atom`, shared by the clones
+    atom_cache: Rc<RefCell<AtomCache>>,
+}
+
+/// atom names and atoms in both directions, which never change while connected
+#[derive(Default)]
+struct AtomCache {
+    names: HashMap<xproto::Atom, String>,
+    atoms: HashMap<String, xproto::Atom>,
+}
+
+impl AtomCache {
+    fn insert(&mut self, atom: xproto::Atom, name: String) {
+        self.atoms.insert(name.clone(), atom);
+        self.names.insert(atom, name);
+    }
 }
 
 impl Context {
     pub fn new() -> Result<Self> {
-        let conn = match RustConnection::connect(None) {
-            Ok((conn, _)) => conn,
+        let (conn, screen) = match RustConnection::connect(None) {
+            Ok(connected) => connected,
             Err(err) => {
                 panic!("Failed to connect with the X server: {}", err);
             }
         };
-        let root = conn.setup().roots[0].root;
+        Self::with_connection(conn, screen)
+    }
+
+    pub fn with_connection(conn: RustConnection, screen: usize) -> Result<Self> {
+        let root = conn.setup().roots[screen].root;
         let atom = AtomCollection::new(&conn)?.reply()?;
         Ok(Self {
             conn: Rc::new(conn),
+            screen_num: screen,
             root,
             atom,
+            atom_cache: Rc::default(),
         })
     }
+
+    /// the screen of the root window
+    pub fn screen(&self) -> &xproto::Screen {
+        &self.conn.setup().roots[self.screen_num]
+    }
 }
 
+/// Returns the name of the atom, asking the server only for the first time.
 pub fn get_atom_name(ctx: &Context, atom: xproto::Atom) -> Result<String> {
+    if let Some(name) = ctx.atom_cache.borrow().names.get(&atom) {
+        return Ok(name.clone());
+    }
     let name_reply = ctx.conn.get_atom_name(atom)?.reply()?;
     let len = name_reply.name_len() as usize;
     let bytes = &name_reply.name.as_slice()[..len];
-    let name = std::str::from_utf8(bytes).unwrap().to_owned();
+    let name = String::from_utf8_lossy(bytes).into_owned();
+    ctx.atom_cache.borrow_mut().insert(atom, name.clone());
     Ok(name)
 }
 
-pub fn get_net_wm_window_type(
-    ctx: &Context,
+/// Returns the atom of the name, creating it if it doesn't exist.
+/// The server is asked only for the first time.
+pub fn intern_atom(ctx: &Context, name: &str) -> Result<xproto::Atom> {
+    if let Some(atom) = ctx.atom_cache.borrow().atoms.get(name) {
+        return Ok(*atom);
+    }
+    let atom = ctx.conn.intern_atom(false, name.as_bytes())?.reply()?.atom;
+    ctx.atom_cache.borrow_mut().insert(atom, name.to_owned());
+    Ok(atom)
+}
+
+type PropertyCookie<'a> = Cookie<'a, RustConnection, xproto::GetPropertyReply>;
+
+fn request_property<'a>(
+    ctx: &'a Context,
     window: xproto::Window,
-) -> Result<Option<xproto::Atom>> {
-    let net_wm_type = ctx.atom._NET_WM_WINDOW_TYPE;
+    property: impl Into<xproto::Atom>,
+    type_: impl Into<xproto::Atom>,
+    long_length: u32,
+) -> Result<PropertyCookie<'a>> {
     Ok(ctx
         .conn
-        .get_property(false, window, net_wm_type, xproto::AtomEnum::ATOM, 0, 1)?
-        .reply()?
+        .get_property(false, window, property, type_, 0, long_length)?)
+}
+
+fn first_value32(reply: &xproto::GetPropertyReply) -> Option<u32> {
+    reply.value32().and_then(|mut iter| iter.next())
+}
+
+fn values32(reply: &xproto::GetPropertyReply) -> Vec<u32> {
+    reply
         .value32()
-        .and_then(|mut iter| iter.next()))
+        .map(|iter| iter.collect())
+        .unwrap_or_default()
+}
+
+fn request_window_type(ctx: &Context, window: xproto::Window) -> Result<PropertyCookie<'_>> {
+    let net_wm_type = ctx.atom._NET_WM_WINDOW_TYPE;
+    request_property(ctx, window, net_wm_type, xproto::AtomEnum::ATOM, 1)
+}
+
+/// Returns the title of the window, preferring `_NET_WM_NAME` over `WM_NAME`.
+pub fn get_window_title(ctx: &Context, window: xproto::Window) -> Result<String> {
+    let (net_wm_name, wm_name) = request_title(ctx, window)?;
+    Ok(parse_title(&net_wm_name.reply()?, &wm_name.reply()?))
+}
+
+fn request_title(
+    ctx: &Context,
+    window: xproto::Window,
+) -> Result<(PropertyCookie<'_>, PropertyCookie<'_>)> {
+    Ok((
+        request_property(
+            ctx,
+            window,
+            ctx.atom._NET_WM_NAME,
+            ctx.atom.UTF8_STRING,
+            u32::MAX,
+        )?,
+        request_property(
+            ctx,
+            window,
+            xproto::AtomEnum::WM_NAME,
+            xproto::AtomEnum::STRING,
+            u32::MAX,
+        )?,
+    ))
+}
+
+fn parse_title(
+    net_wm_name: &xproto::GetPropertyReply,
+    wm_name: &xproto::GetPropertyReply,
+) -> String {
+    if !net_wm_name.value.is_empty() {
+        return String::from_utf8_lossy(&net_wm_name.value).into_owned();
+    }
+    // WM_NAME of type STRING is encoded in ISO-8859-1
+    wm_name.value.iter().map(|&b| b as char).collect()
+}
+
+pub fn get_wm_class(ctx: &Context, window: xproto::Window) -> Result<Option<String>> {
+    Ok(parse_wm_class(&request_wm_class(ctx, window)?.reply()?))
+}
+
+fn request_wm_class(ctx: &Context, window: xproto::Window) -> Result<PropertyCookie<'_>> {
+    let (property, type_) = (xproto::AtomEnum::WM_CLASS, xproto::AtomEnum::STRING);
+    request_property(ctx, window, property, type_, u32::MAX)
+}
+
+/// Returns the class (the second string of WM_CLASS) of the window.
+fn parse_wm_class(reply: &xproto::GetPropertyReply) -> Option<String> {
+    // WM_CLASS consists of two null-terminated strings: instance and class
+    reply
+        .value
+        .split(|&b| b == 0)
+        .nth(1)
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Returns the 12 values of _NET_WM_STRUT_PARTIAL, or the 4 values of _NET_WM_STRUT.
+pub fn get_net_wm_strut(ctx: &Context, window: xproto::Window) -> Result<Option<Vec<u32>>> {
+    let (partial, strut) = request_strut(ctx, window)?;
+    Ok(parse_strut(&partial.reply()?, &strut.reply()?))
+}
+
+fn request_strut(
+    ctx: &Context,
+    window: xproto::Window,
+) -> Result<(PropertyCookie<'_>, PropertyCookie<'_>)> {
+    let cardinal = xproto::AtomEnum::CARDINAL;
+    Ok((
+        request_property(ctx, window, ctx.atom._NET_WM_STRUT_PARTIAL, cardinal, 12)?,
+        request_property(ctx, window, ctx.atom._NET_WM_STRUT, cardinal, 4)?,
+    ))
+}
+
+fn parse_strut(
+    partial: &xproto::GetPropertyReply,
+    strut: &xproto::GetPropertyReply,
+) -> Option<Vec<u32>> {
+    [(partial, 12), (strut, 4)]
+        .into_iter()
+        .map(|(reply, len)| (values32(reply), len))
+        .find(|(values, len)| values.len() == *len)
+        .map(|(values, _)| values)
+}
+
+/// Returns the input field of WM_HINTS, which is true if the hint is absent.
+fn parse_wm_hints_input(reply: &xproto::GetPropertyReply) -> bool {
+    // the input field is valid only if InputHint is set in the flags
+    const INPUT_HINT: u32 = 1;
+    match values32(reply)[..] {
+        [flags, input, ..] if flags & INPUT_HINT != 0 => input != 0,
+        _ => true,
+    }
+}
+
+/// Returns the host name of the client (WM_CLIENT_MACHINE).
+fn parse_wm_client_machine(reply: &xproto::GetPropertyReply) -> Option<String> {
+    (!reply.value.is_empty()).then(|| String::from_utf8_lossy(&reply.value).into_owned())
+}
+
+/// the properties of a client window read when it is managed
+pub struct ClientProperties {
+    pub geometry: xproto::GetGeometryReply,
+    pub window_type: Option<xproto::Atom>,
+    pub title: String,
+    pub class: Option<String>,
+    pub transient_for: Option<xproto::Window>,
+    pub net_wm_state: Vec<xproto::Atom>,
+    /// the input field of WM_HINTS
+    pub input: bool,
+    pub protocols: Vec<xproto::Atom>,
+    pub strut: Option<Vec<u32>>,
+    pub net_wm_pid: Option<u32>,
+    /// WM_CLIENT_MACHINE
+    pub client_machine: Option<String>,
+    /// the PID of the client told by X-Resource, if the server supports it
+    pub local_client_pid: Option<u32>,
+    /// whether the bounding region is shaped, if the server supports the Shape extension
+    pub shaped: bool,
+}
+
+/// the requests for `ClientProperties`, all sent before waiting for any reply
+pub struct ClientPropertiesCookie<'a> {
+    geometry: Cookie<'a, RustConnection, xproto::GetGeometryReply>,
+    window_type: PropertyCookie<'a>,
+    title: (PropertyCookie<'a>, PropertyCookie<'a>),
+    class: PropertyCookie<'a>,
+    transient_for: PropertyCookie<'a>,
+    net_wm_state: PropertyCookie<'a>,
+    hints: PropertyCookie<'a>,
+    protocols: PropertyCookie<'a>,
+    strut: (PropertyCookie<'a>, PropertyCookie<'a>),
+    net_wm_pid: PropertyCookie<'a>,
+    client_machine: PropertyCookie<'a>,
+    client_ids: Option<Cookie<'a, RustConnection, res::QueryClientIdsReply>>,
+    shape_extents: Option<Cookie<'a, RustConnection, shape::QueryExtentsReply>>,
+}
+
+impl<'a> ClientPropertiesCookie<'a> {
+    /// Requests the properties. X-Resource and Shape are used only if they are supported,
+    /// and the shape changes of the window are selected before the query.
+    pub fn new(
+        ctx: &'a Context,
+        window: xproto::Window,
+        res: bool,
+        shape: bool,
+    ) -> Result<ClientPropertiesCookie<'a>> {
+        let client_ids = if res {
+            let spec = res::ClientIdSpec {
+                client: window,
+                mask: res::ClientIdMask::LOCAL_CLIENT_PID,
+            };
+            Some(ctx.conn.res_query_client_ids(&[spec])?)
+        } else {
+            None
+        };
+        let shape_extents = if shape {
+            ctx.conn.shape_select_input(window, true)?;
+            Some(ctx.conn.shape_query_extents(window)?)
+        } else {
+            None
+        };
+        Ok(ClientPropertiesCookie {
+            geometry: ctx.conn.get_geometry(window)?,
+            window_type: request_window_type(ctx, window)?,
+            title: request_title(ctx, window)?,
+            class: request_wm_class(ctx, window)?,
+            transient_for: request_property(
+                ctx,
+                window,
+                xproto::AtomEnum::WM_TRANSIENT_FOR,
+                xproto::AtomEnum::WINDOW,
+                1,
+            )?,
+            net_wm_state: request_property(
+                ctx,
+                window,
+                ctx.atom._NET_WM_STATE,
+                xproto::AtomEnum::ATOM,
+                u32::MAX,
+            )?,
+            hints: request_property(
+                ctx,
+                window,
+                xproto::AtomEnum::WM_HINTS,
+                xproto::AtomEnum::WM_HINTS,
+                2,
+            )?,
+            protocols: request_property(
+                ctx,
+                window,
+                ctx.atom.WM_PROTOCOLS,
+                xproto::AtomEnum::ATOM,
+                u32::MAX,
+            )?,
+            strut: request_strut(ctx, window)?,
+            net_wm_pid: request_property(
+                ctx,
+                window,
+                ctx.atom._NET_WM_PID,
+                xproto::AtomEnum::CARDINAL,
+                1,
+            )?,
+            client_machine: request_property(
+                ctx,
+                window,
+                xproto::AtomEnum::WM_CLIENT_MACHINE,
+                xproto::AtomEnum::STRING,
+                u32::MAX,
+            )?,
+            client_ids,
+            shape_extents,
+        })
+    }
+
+    pub fn reply(self) -> Result<ClientProperties> {
+        let local_client_pid = match self.client_ids {
+            Some(cookie) => cookie
+                .reply()?
+                .ids
+                .iter()
+                .filter(|id| id.spec.mask == res::ClientIdMask::LOCAL_CLIENT_PID)
+                .find_map(|id| id.value.first().copied()),
+            None => None,
+        };
+        let shaped = match self.shape_extents {
+            Some(cookie) => cookie.reply()?.bounding_shaped,
+            None => false,
+        };
+        Ok(ClientProperties {
+            geometry: self.geometry.reply()?,
+            window_type: first_value32(&self.window_type.reply()?),
+            title: parse_title(&self.title.0.reply()?, &self.title.1.reply()?),
+            class: parse_wm_class(&self.class.reply()?),
+            transient_for: first_value32(&self.transient_for.reply()?)
+                .filter(|&parent| parent != x11rb::NONE),
+            net_wm_state: values32(&self.net_wm_state.reply()?),
+            input: parse_wm_hints_input(&self.hints.reply()?),
+            protocols: values32(&self.protocols.reply()?),
+            strut: parse_strut(&self.strut.0.reply()?, &self.strut.1.reply()?),
+            net_wm_pid: first_value32(&self.net_wm_pid.reply()?),
+            client_machine: parse_wm_client_machine(&self.client_machine.reply()?),
+            local_client_pid,
+            shaped,
+        })
+    }
+}
+
+/// Returns the host name of this machine.
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Returns the parent process ID of the process (Linux only).
+pub fn get_parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the format is "pid (comm) state ppid ...", where comm may contain spaces and parentheses
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
 }
 
 pub enum Property<'a> {
     Window(xproto::Window),
+    Pixmap(xproto::Pixmap),
     AtomList(&'a [xproto::Atom]),
+    CardinalList(&'a [u32]),
+    Utf8String(&'a str),
 }
 
 pub fn replace_property(
@@ -79,18 +444,33 @@ pub fn replace_property(
     key: xproto::Atom,
     value: Property<'_>,
 ) -> Result<()> {
-    let (type_, format, data): (xproto::AtomEnum, u8, Vec<u8>);
+    let (type_, format, data): (xproto::Atom, u8, Vec<u8>);
     match value {
         Property::Window(window) => {
-            type_ = xproto::AtomEnum::WINDOW;
+            type_ = xproto::AtomEnum::WINDOW.into();
             format = 32;
             data = window.to_ne_bytes().to_vec();
         }
+        Property::Pixmap(pixmap) => {
+            type_ = xproto::AtomEnum::PIXMAP.into();
+            format = 32;
+            data = pixmap.to_ne_bytes().to_vec();
+        }
         Property::AtomList(atoms) => {
-            type_ = xproto::AtomEnum::ATOM;
+            type_ = xproto::AtomEnum::ATOM.into();
             format = 32;
             data = atoms.iter().flat_map(|a| a.to_ne_bytes()).collect();
         }
+        Property::CardinalList(values) => {
+            type_ = xproto::AtomEnum::CARDINAL.into();
+            format = 32;
+            data = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        }
+        Property::Utf8String(string) => {
+            type_ = ctx.atom.UTF8_STRING;
+            format = 8;
+            data = string.as_bytes().to_vec();
+        }
     };
 
     ctx.conn.change_property(
@@ -102,6 +482,31 @@ pub fn replace_property(
         (data.len() as u32) / (format as u32 / 8),
         &data,
     )?;
-    ctx.conn.flush()?;
     Ok(())
 }
+
+/// Waits until any of the file descriptors becomes readable or the timeout expires,
+/// and returns which ones are. An interrupt by a signal returns none of them.
+pub fn wait_readable(
+    fds: &[RawFd],
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<Vec<bool>> {
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    let ret = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(vec![false; fds.len()]);
+        }
+        return Err(err);
+    }
+    Ok(pollfds.iter().map(|p| p.revents != 0).collect())
+}
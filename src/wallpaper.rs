@@ -0,0 +1,356 @@
+use std::path::{Path, PathBuf};
+
+use x11rb::connection::{Connection as _, RequestConnection as _};
+use x11rb::protocol::xproto;
+
+use xproto::ConnectionExt as _;
+
+use crate::error::Result;
+use crate::layout::Rect;
+use crate::utils;
+
+// Wallpapers are binary PPM (P6) images, which other formats can be converted to,
+// e.g. `convert wallpaper.jpg wallpaper.ppm`.
+//
+// Each image is drawn once per size of monitors into a pixmap, and those of the desktops shown
+// on the monitors are copied to a pixmap covering the screen. It becomes the background of the
+// root window and is published by _XROOTPMAP_ID and ESETROOT_PMAP_ID like feh and hsetroot do,
+// so that pseudo-transparent clients can copy from it.
+
+/// how a wallpaper fits a monitor
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// covers the whole monitor, cropping the image
+    Fill,
+    /// shows the whole image, leaving margins
+    Fit,
+    /// fills the monitor, ignoring the aspect ratio of the image
+    Stretch,
+    /// keeps the size of the image
+    Center,
+}
+
+/// an RGB image with 8 bits per channel
+pub struct Image {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    /// Loads the image. A path starting with "~/" is relative to the home directory.
+    pub fn load(path: &str) -> std::result::Result<Image, String> {
+        let path = expand_home(path);
+        let data = std::fs::read(&path).map_err(|err| format!("{path:?}: {err}"))?;
+        parse_ppm(&data).ok_or_else(|| format!("{path:?} is not a binary PPM (P6) image"))
+    }
+
+    /// Returns the color (0xRRGGBB) at the position interpolated between the pixels,
+    /// whose centers are at half-integers.
+    fn sample(&self, x: f64, y: f64) -> u32 {
+        let fx = (x - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let fy = (y - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (fx as usize, fy as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+
+        let pixel = |x: usize, y: usize| &self.rgb[(y * self.width + x) * 3..][..3];
+        let (p00, p10, p01, p11) = (pixel(x0, y0), pixel(x1, y0), pixel(x0, y1), pixel(x1, y1));
+        let mut color = 0;
+        for c in 0..3 {
+            let top = p00[c] as f64 * (1.0 - tx) + p10[c] as f64 * tx;
+            let bottom = p01[c] as f64 * (1.0 - tx) + p11[c] as f64 * tx;
+            let value = (top * (1.0 - ty) + bottom * ty).round() as u32;
+            color = (color << 8) | value.min(255);
+        }
+        color
+    }
+
+    /// Renders the image scaled into a `w` x `h` area, as 0xRRGGBB pixels row by row.
+    /// The area not covered by the image is filled with the background color.
+    pub fn render(&self, w: usize, h: usize, scaling: Scaling, background: u32) -> Vec<u32> {
+        let (iw, ih) = (self.width as f64, self.height as f64);
+        let (fw, fh) = (w as f64, h as f64);
+        let (sx, sy) = match scaling {
+            Scaling::Fill => {
+                let s = (fw / iw).max(fh / ih);
+                (s, s)
+            }
+            Scaling::Fit => {
+                let s = (fw / iw).min(fh / ih);
+                (s, s)
+            }
+            Scaling::Stretch => (fw / iw, fh / ih),
+            Scaling::Center => (1.0, 1.0),
+        };
+        // the scaled image is centered in the area
+        let (ox, oy) = ((fw - iw * sx) / 2.0, (fh - ih * sy) / 2.0);
+
+        let mut pixels = Vec::with_capacity(w * h);
+        for y in 0..h {
+            let v = (y as f64 + 0.5 - oy) / sy;
+            for x in 0..w {
+                let u = (x as f64 + 0.5 - ox) / sx;
+                if (0.0..iw).contains(&u) && (0.0..ih).contains(&v) {
+                    pixels.push(self.sample(u, v));
+                } else {
+                    pixels.push(background);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Parses a binary PPM image. Samples of 16 bits are reduced to 8 bits.
+fn parse_ppm(data: &[u8]) -> Option<Image> {
+    let mut rest = data.strip_prefix(b"P6")?;
+    let mut fields = [0usize; 3];
+    for field in &mut fields {
+        // whitespace and comments (from '#' to the end of the line) may precede each field
+        loop {
+            match rest.first()? {
+                c if c.is_ascii_whitespace() => rest = &rest[1..],
+                b'#' => {
+                    let end = rest.iter().position(|&c| c == b'\n')?;
+                    rest = &rest[end..];
+                }
+                _ => break,
+            }
+        }
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        *field = std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+        rest = &rest[digits..];
+    }
+    // a single whitespace separates the header from the samples
+    rest = rest.get(1..)?;
+
+    let [width, height, maxval] = fields;
+    if width == 0 || height == 0 || maxval == 0 || maxval > 65535 {
+        return None;
+    }
+    let samples = width.checked_mul(height)?.checked_mul(3)?;
+    let rgb = if maxval < 256 {
+        let samples = rest.get(..samples)?;
+        samples
+            .iter()
+            .map(|&s| (s as usize * 255 / maxval) as u8)
+            .collect()
+    } else {
+        let samples = rest.get(..samples * 2)?;
+        samples
+            .chunks_exact(2)
+            .map(|s| ((u16::from_be_bytes([s[0], s[1]]) as usize) * 255 / maxval) as u8)
+            .collect()
+    };
+    Some(Image { width, height, rgb })
+}
+
+/// how pixels are encoded for the root visual
+struct PixelFormat {
+    depth: u8,
+    /// shifts of the red, green and blue channels of 8 bits
+    shifts: [u32; 3],
+    lsb_first: bool,
+}
+
+impl PixelFormat {
+    /// Returns the format of the root visual, or None if it isn't TrueColor of 32 bits per pixel.
+    fn of_root(ctx: &utils::Context) -> Option<PixelFormat> {
+        let setup = ctx.conn.setup();
+        let screen = ctx.screen();
+        let depth = screen.root_depth;
+        let visual = screen
+            .allowed_depths
+            .iter()
+            .flat_map(|d| d.visuals.iter())
+            .find(|v| v.visual_id == screen.root_visual);
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|f| f.depth == depth)
+            .map(|f| f.bits_per_pixel);
+        match (visual, bits_per_pixel) {
+            (Some(visual), Some(32)) if visual.class == xproto::VisualClass::TRUE_COLOR => {
+                Some(PixelFormat {
+                    depth,
+                    shifts: [
+                        visual.red_mask.trailing_zeros(),
+                        visual.green_mask.trailing_zeros(),
+                        visual.blue_mask.trailing_zeros(),
+                    ],
+                    lsb_first: setup.image_byte_order == xproto::ImageOrder::LSB_FIRST,
+                })
+            }
+            _ => {
+                log::warn!("The wallpaper can't be drawn with the root visual of depth {depth}");
+                None
+            }
+        }
+    }
+
+    fn pixel(&self, color: u32) -> u32 {
+        ((color >> 16 & 0xFF) << self.shifts[0])
+            | ((color >> 8 & 0xFF) << self.shifts[1])
+            | ((color & 0xFF) << self.shifts[2])
+    }
+
+    fn encode(&self, color: u32) -> [u8; 4] {
+        if self.lsb_first {
+            self.pixel(color).to_le_bytes()
+        } else {
+            self.pixel(color).to_be_bytes()
+        }
+    }
+}
+
+/// Draws the image scaled into a new `w` x `h` pixmap, or returns None if the pixel format of
+/// the screen isn't supported.
+pub fn render_pixmap(
+    ctx: &utils::Context,
+    image: &Image,
+    w: u16,
+    h: u16,
+    scaling: Scaling,
+    background: u32,
+) -> Result<Option<xproto::Pixmap>> {
+    let Some(format) = PixelFormat::of_root(ctx) else {
+        return Ok(None);
+    };
+    let (w, h) = (w.max(1), h.max(1));
+    let pixmap = ctx.conn.generate_id()?;
+    ctx.conn
+        .create_pixmap(format.depth, pixmap, ctx.root, w, h)?;
+    let gc = ctx.conn.generate_id()?;
+    ctx.conn
+        .create_gc(gc, pixmap, &xproto::CreateGCAux::new())?;
+
+    // the image is sent in strips fitting in requests
+    let (w, h) = (w as usize, h as usize);
+    let pixels = image.render(w, h, scaling, background);
+    let max_bytes = ctx.conn.maximum_request_bytes() - 32;
+    let rows_per_strip = (max_bytes / (w * 4)).max(1);
+    for (i, rows) in pixels.chunks(w * rows_per_strip).enumerate() {
+        let data: Vec<u8> = rows
+            .iter()
+            .flat_map(|&color| format.encode(color))
+            .collect();
+        ctx.conn.put_image(
+            xproto::ImageFormat::Z_PIXMAP,
+            pixmap,
+            gc,
+            w as u16,
+            (rows.len() / w) as u16,
+            0,
+            (i * rows_per_strip) as i16,
+            0,
+            format.depth,
+            &data,
+        )?;
+    }
+    ctx.conn.free_gc(gc)?;
+    Ok(Some(pixmap))
+}
+
+/// Copies the pixmaps (made by render_pixmap) to the monitors (absolute geometries) in a new
+/// pixmap covering the screen, and makes it the root pixmap. Monitors without a pixmap are
+/// filled with the background color. The pixmap replaced by it is freed, unless the pixel
+/// format of the screen isn't supported and the previous one is kept.
+pub fn set_root_pixmap(
+    ctx: &utils::Context,
+    previous: Option<xproto::Pixmap>,
+    monitors: &[(Rect, Option<xproto::Pixmap>)],
+    background: u32,
+) -> Result<Option<xproto::Pixmap>> {
+    let Some(format) = PixelFormat::of_root(ctx) else {
+        return Ok(previous);
+    };
+    let setup = ctx.conn.setup();
+
+    let screen_w = monitors
+        .iter()
+        .map(|(g, _)| g.right())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let screen_h = monitors
+        .iter()
+        .map(|(g, _)| g.bottom())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let pixmap = ctx.conn.generate_id()?;
+    ctx.conn.create_pixmap(
+        format.depth,
+        pixmap,
+        ctx.root,
+        screen_w as u16,
+        screen_h as u16,
+    )?;
+    let gc = ctx.conn.generate_id()?;
+    let aux = xproto::CreateGCAux::new().foreground(format.pixel(background));
+    ctx.conn.create_gc(gc, pixmap, &aux)?;
+    let whole = xproto::Rectangle {
+        x: 0,
+        y: 0,
+        width: screen_w as u16,
+        height: screen_h as u16,
+    };
+    ctx.conn.poly_fill_rectangle(pixmap, gc, &[whole])?;
+    for (geometry, source) in monitors {
+        if let Some(source) = source {
+            ctx.conn.copy_area(
+                *source,
+                pixmap,
+                gc,
+                0,
+                0,
+                geometry.x as i16,
+                geometry.y as i16,
+                geometry.w.max(0) as u16,
+                geometry.h.max(0) as u16,
+            )?;
+        }
+    }
+    ctx.conn.free_gc(gc)?;
+
+    // a pixmap left by feh or hsetroot is retained after they exit, and freed by killing
+    // its client as Esetroot does (unless it's gone, or its ID is now one of ours)
+    if previous.is_none() {
+        let root_pmap = get_pixmap_property(ctx, ctx.atom._XROOTPMAP_ID)?;
+        let esetroot_pmap = get_pixmap_property(ctx, ctx.atom.ESETROOT_PMAP_ID)?;
+        if let (Some(a), Some(b)) = (root_pmap, esetroot_pmap) {
+            let ours = a & !setup.resource_id_mask == setup.resource_id_base;
+            if a == b && !ours && ctx.conn.get_geometry(a)?.reply().is_ok() {
+                ctx.conn.kill_client(a)?;
+            }
+        }
+    }
+
+    let aux = xproto::ChangeWindowAttributesAux::new().background_pixmap(pixmap);
+    ctx.conn.change_window_attributes(ctx.root, &aux)?;
+    ctx.conn.clear_area(false, ctx.root, 0, 0, 0, 0)?;
+    for atom in [ctx.atom._XROOTPMAP_ID, ctx.atom.ESETROOT_PMAP_ID] {
+        utils::replace_property(ctx, ctx.root, atom, utils::Property::Pixmap(pixmap))?;
+    }
+    if let Some(previous) = previous {
+        ctx.conn.free_pixmap(previous)?;
+    }
+    Ok(Some(pixmap))
+}
+
+fn get_pixmap_property(ctx: &utils::Context, atom: xproto::Atom) -> Result<Option<u32>> {
+    let reply = ctx
+        .conn
+        .get_property(false, ctx.root, atom, xproto::AtomEnum::PIXMAP, 0, 1)?
+        .reply()?;
+    Ok(reply.value32().and_then(|mut values| values.next()))
+}
@@ -0,0 +1,597 @@
+use std::ffi::OsString;
+use std::io::{Read, Write as _};
+use std::os::unix::ffi::OsStringExt as _;
+use std::os::unix::io::{AsRawFd as _, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// A minimal client of the X Session Management Protocol, spoken over ICE (Inter-Client
+// Exchange) to the session manager found in $SESSION_MANAGER. The connection is set up (with
+// MIT-MAGIC-COOKIE-1 from $ICEAUTHORITY or ~/.ICEauthority if the manager asks) and the client
+// is registered synchronously; after that, the manager asks to save the state (SaveYourself),
+// which is answered right away, and tells when the session ends (Die).
+//
+// Every ICE message starts with a header of 8 bytes: the major opcode (0 for ICE itself, and
+// the one chosen by the sender for XSMP), the minor opcode, 2 bytes of data, and the length of
+// the rest in units of 8 bytes. Each side writes in its own byte order, told by the ByteOrder
+// message sent first.
+
+const ICE_ERROR: u8 = 0;
+const ICE_BYTE_ORDER: u8 = 1;
+const ICE_CONNECTION_SETUP: u8 = 2;
+const ICE_AUTH_REQUIRED: u8 = 3;
+const ICE_AUTH_REPLY: u8 = 4;
+const ICE_CONNECTION_REPLY: u8 = 6;
+const ICE_PROTOCOL_SETUP: u8 = 7;
+const ICE_PROTOCOL_REPLY: u8 = 8;
+const ICE_PING: u8 = 9;
+const ICE_PING_REPLY: u8 = 10;
+
+const SM_ERROR: u8 = 0;
+const SM_REGISTER_CLIENT: u8 = 1;
+const SM_REGISTER_CLIENT_REPLY: u8 = 2;
+const SM_SAVE_YOURSELF: u8 = 3;
+const SM_SAVE_YOURSELF_DONE: u8 = 8;
+const SM_DIE: u8 = 9;
+const SM_SHUTDOWN_CANCELLED: u8 = 10;
+const SM_CLOSE_CONNECTION: u8 = 11;
+const SM_SET_PROPERTIES: u8 = 12;
+const SM_SAVE_COMPLETE: u8 = 18;
+
+const LSB_FIRST: u8 = 0;
+const MSB_FIRST: u8 = 1;
+
+/// the major opcode of XSMP in the messages written by this client
+const MY_OPCODE: u8 = 1;
+const AUTH_NAME: &str = "MIT-MAGIC-COOKIE-1";
+const VENDOR: &str = "daily2";
+/// how long the session manager may take to reply while connecting
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// what the session manager asks
+#[derive(Debug)]
+pub enum Request {
+    /// The state should be saved, and the session may end after it if `shutdown`.
+    SaveYourself { shutdown: bool },
+    /// The session ends, and this client should exit.
+    Die,
+}
+
+/// a client registered to the session manager
+pub struct Client {
+    stream: UnixStream,
+    /// whether the manager writes in the other byte order
+    swap: bool,
+    /// the major opcode of XSMP in the messages written by the manager
+    his_opcode: u8,
+    /// the bytes read but not handled yet
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Connects to the session manager of $SESSION_MANAGER and registers this client, or
+    /// returns None if no session manager is running or it fails.
+    pub fn connect() -> Option<Client> {
+        let addresses = std::env::var("SESSION_MANAGER").ok()?;
+        let (stream, network_id) = addresses
+            .split(',')
+            .find_map(|network_id| match connect_to(network_id) {
+                Ok(stream) => Some((stream, network_id)),
+                Err(err) => {
+                    log::warn!("Failed to connect to the session manager at {network_id}: {err}");
+                    None
+                }
+            })?;
+        match Client::register(stream, network_id) {
+            Ok(client) => Some(client),
+            Err(err) => {
+                log::error!("Failed to register to the session manager: {err}");
+                None
+            }
+        }
+    }
+
+    fn register(stream: UnixStream, network_id: &str) -> std::io::Result<Client> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut client = Client {
+            stream,
+            swap: false,
+            his_opcode: 0,
+            buf: Vec::new(),
+        };
+
+        let order = if cfg!(target_endian = "big") {
+            MSB_FIRST
+        } else {
+            LSB_FIRST
+        };
+        client.send(Message::new(0, ICE_BYTE_ORDER, [order, 0]))?;
+        let ice_cookie = find_cookie("ICE", network_id);
+        let mut setup = Message::new(0, ICE_CONNECTION_SETUP, [1, ice_cookie.is_some() as u8]);
+        setup.u8(0); // must-authenticate
+        setup.pad(8);
+        setup.string(VENDOR);
+        setup.string(env!("CARGO_PKG_VERSION"));
+        if ice_cookie.is_some() {
+            setup.string(AUTH_NAME);
+        }
+        setup.version(1, 0);
+        client.send(setup)?;
+
+        let (header, _) = client.read_message()?;
+        if header.minor != ICE_BYTE_ORDER {
+            return Err(protocol_error("no byte order is told"));
+        }
+        client.swap = match header.data[0] {
+            MSB_FIRST => cfg!(target_endian = "little"),
+            LSB_FIRST => cfg!(target_endian = "big"),
+            _ => return Err(protocol_error("unknown byte order")),
+        };
+        client.authenticate(ICE_CONNECTION_REPLY, ice_cookie.as_deref())?;
+
+        let sm_cookie = find_cookie("XSMP", network_id);
+        let mut setup = Message::new(0, ICE_PROTOCOL_SETUP, [MY_OPCODE, 0]);
+        setup.u8(1); // the number of versions
+        setup.u8(sm_cookie.is_some() as u8);
+        setup.pad(8);
+        setup.string("XSMP");
+        setup.string(VENDOR);
+        setup.string(env!("CARGO_PKG_VERSION"));
+        if sm_cookie.is_some() {
+            setup.string(AUTH_NAME);
+        }
+        setup.version(1, 0);
+        client.send(setup)?;
+        let header = client.authenticate(ICE_PROTOCOL_REPLY, sm_cookie.as_deref())?;
+        client.his_opcode = header.data[1];
+
+        // the ID given to the previous process by a session manager starting this one
+        let mut previous_id = std::env::var("DESKTOP_AUTOSTART_ID").unwrap_or_default();
+        std::env::remove_var("DESKTOP_AUTOSTART_ID");
+        let mut register = Message::new(MY_OPCODE, SM_REGISTER_CLIENT, [0, 0]);
+        register.array8(previous_id.as_bytes());
+        client.send(register)?;
+        loop {
+            let (header, body) = client.read_message()?;
+            if header.major == client.his_opcode && header.minor == SM_REGISTER_CLIENT_REPLY {
+                let id = Reader::new(&body, client.swap).array8();
+                log::info!(
+                    "registered to the session manager as {:?}",
+                    String::from_utf8_lossy(id.unwrap_or_default())
+                );
+                break;
+            }
+            // an unknown previous ID is rejected, and then a new ID is asked
+            if header.major == client.his_opcode && header.minor == SM_ERROR {
+                if previous_id.is_empty() {
+                    return Err(protocol_error("the registration is rejected"));
+                }
+                previous_id.clear();
+                let mut register = Message::new(MY_OPCODE, SM_REGISTER_CLIENT, [0, 0]);
+                register.array8(b"");
+                client.send(register)?;
+            }
+        }
+        client.set_properties()?;
+        client.stream.set_nonblocking(true)?;
+        Ok(client)
+    }
+
+    /// Answers the authentication requests until the reply of the setup, and returns its header.
+    fn authenticate(&mut self, reply: u8, cookie: Option<&[u8]>) -> std::io::Result<Header> {
+        loop {
+            let (header, _) = self.read_message()?;
+            if header.major != 0 {
+                continue;
+            }
+            match header.minor {
+                minor if minor == reply => return Ok(header),
+                ICE_AUTH_REQUIRED => {
+                    let cookie = cookie.ok_or_else(|| protocol_error("no cookie is found"))?;
+                    let mut auth = Message::new(0, ICE_AUTH_REPLY, [0, 0]);
+                    auth.u16(cookie.len() as u16);
+                    auth.pad(8);
+                    auth.bytes(cookie);
+                    self.send(auth)?;
+                }
+                ICE_ERROR => return Err(protocol_error("the setup is refused")),
+                _ => {}
+            }
+        }
+    }
+
+    /// Tells the session manager how to start this program again.
+    fn set_properties(&mut self) -> std::io::Result<()> {
+        // the arguments (e.g. --config) are given again as they are
+        let argv: Vec<Vec<u8>> = std::env::args_os().map(OsString::into_vec).collect();
+        let program = argv.first().cloned().unwrap_or_else(|| VENDOR.into());
+        let user = std::env::var("USER").unwrap_or_else(|_| unsafe { libc::getuid() }.to_string());
+        let properties: [(&str, &str, &[Vec<u8>]); 4] = [
+            ("Program", "ARRAY8", &[program]),
+            ("UserID", "ARRAY8", &[user.into_bytes()]),
+            ("RestartCommand", "LISTofARRAY8", &argv),
+            ("CloneCommand", "LISTofARRAY8", &argv),
+        ];
+        let mut message = Message::new(MY_OPCODE, SM_SET_PROPERTIES, [0, 0]);
+        message.u32(properties.len() as u32);
+        message.pad(8);
+        for (name, kind, values) in properties {
+            message.array8(name.as_bytes());
+            message.array8(kind.as_bytes());
+            message.u32(values.len() as u32);
+            message.pad(8);
+            for value in values {
+                message.array8(value);
+            }
+        }
+        self.send(message)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    /// Reads the requests of the session manager, or returns None if the connection is closed.
+    pub fn read(&mut self) -> Option<Vec<Request>> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return None,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    log::warn!("Failed to read from the session manager: {err}");
+                    return None;
+                }
+            }
+        }
+
+        let mut requests = Vec::new();
+        while let Some((header, body)) = self.take_message() {
+            if header.major == 0 {
+                match header.minor {
+                    ICE_PING => self.reply(Message::new(0, ICE_PING_REPLY, [0, 0])),
+                    ICE_ERROR => log::warn!("the session manager sent an ICE error"),
+                    minor => log::debug!("ignored an ICE message {minor}"),
+                }
+                continue;
+            }
+            match header.minor {
+                SM_SAVE_YOURSELF => {
+                    let shutdown = body.get(1).is_some_and(|&b| b != 0);
+                    requests.push(Request::SaveYourself { shutdown });
+                }
+                SM_DIE => requests.push(Request::Die),
+                SM_SHUTDOWN_CANCELLED => log::info!("the session manager cancelled the shutdown"),
+                SM_SAVE_COMPLETE => {}
+                SM_ERROR => log::warn!("the session manager sent an XSMP error"),
+                minor => log::debug!("ignored an XSMP message {minor}"),
+            }
+        }
+        Some(requests)
+    }
+
+    /// Tells the session manager that the state requested by SaveYourself is saved.
+    pub fn save_yourself_done(&mut self, success: bool) {
+        self.reply(Message::new(
+            MY_OPCODE,
+            SM_SAVE_YOURSELF_DONE,
+            [success as u8, 0],
+        ));
+    }
+
+    /// Closes the connection before exiting, as asked by Die.
+    pub fn close(&mut self) {
+        let mut message = Message::new(MY_OPCODE, SM_CLOSE_CONNECTION, [0, 0]);
+        message.u32(0); // the number of reasons
+        self.reply(message);
+    }
+
+    fn reply(&mut self, message: Message) {
+        if let Err(err) = self.send(message) {
+            log::warn!("Failed to write to the session manager: {err}");
+        }
+    }
+
+    fn send(&mut self, message: Message) -> std::io::Result<()> {
+        // the socket is non-blocking after the setup, but the messages are small
+        self.stream.write_all(&message.finish())
+    }
+
+    /// Reads a whole message, blocking until the timeout.
+    fn read_message(&mut self) -> std::io::Result<(Header, Vec<u8>)> {
+        let mut raw = [0; 8];
+        self.stream.read_exact(&mut raw)?;
+        let header = Header::parse(&raw, self.swap);
+        let mut body = vec![0; header.length * 8];
+        self.stream.read_exact(&mut body)?;
+        Ok((header, body))
+    }
+
+    /// Takes a whole message out of the buffer, if it has been read.
+    fn take_message(&mut self) -> Option<(Header, Vec<u8>)> {
+        let header = Header::parse(self.buf.get(..8)?, self.swap);
+        let end = 8 + header.length * 8;
+        if self.buf.len() < end {
+            return None;
+        }
+        let body = self.buf[8..end].to_vec();
+        self.buf.drain(..end);
+        Some((header, body))
+    }
+}
+
+struct Header {
+    major: u8,
+    minor: u8,
+    data: [u8; 2],
+    /// the length of the body in units of 8 bytes
+    length: usize,
+}
+
+impl Header {
+    fn parse(raw: &[u8], swap: bool) -> Header {
+        let length = Reader::new(&raw[4..8], swap).u32().unwrap_or(0);
+        Header {
+            major: raw[0],
+            minor: raw[1],
+            data: [raw[2], raw[3]],
+            length: length as usize,
+        }
+    }
+}
+
+/// a message written in the native byte order
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(major: u8, minor: u8, data: [u8; 2]) -> Message {
+        // the length is filled by finish()
+        Message(vec![major, minor, data[0], data[1], 0, 0, 0, 0])
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// Pads the message to a multiple of the bytes.
+    fn pad(&mut self, align: usize) {
+        let len = self.0.len().next_multiple_of(align);
+        self.0.resize(len, 0);
+    }
+
+    /// a STRING of ICE: the length in 2 bytes and the bytes, padded to 4 bytes
+    fn string(&mut self, s: &str) {
+        self.u16(s.len() as u16);
+        self.bytes(s.as_bytes());
+        self.pad(4);
+    }
+
+    /// an ARRAY8 of XSMP: the length in 4 bytes and the bytes, padded to 8 bytes
+    fn array8(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+        self.pad(8);
+    }
+
+    fn version(&mut self, major: u16, minor: u16) {
+        self.u16(major);
+        self.u16(minor);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.pad(8);
+        let length = ((self.0.len() - 8) / 8) as u32;
+        self.0[4..8].copy_from_slice(&length.to_ne_bytes());
+        self.0
+    }
+}
+
+/// a reader of the data written in the byte order of the session manager
+struct Reader<'a> {
+    data: &'a [u8],
+    swap: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], swap: bool) -> Reader<'a> {
+        Reader { data, swap }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(..4)?.try_into().ok()?;
+        self.data = &self.data[4..];
+        let value = u32::from_ne_bytes(bytes);
+        Some(if self.swap { value.swap_bytes() } else { value })
+    }
+
+    fn array8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let bytes = self.data.get(..len)?;
+        self.data = self.data.get((4 + len).next_multiple_of(8) - 4..)?;
+        Some(bytes)
+    }
+}
+
+/// Connects to a local network ID like "local/host:/tmp/.ICE-unix/123" or
+/// "unix/host:@/tmp/.ICE-unix/123" (an abstract socket). TCP is not supported.
+fn connect_to(network_id: &str) -> std::io::Result<UnixStream> {
+    let unsupported = || std::io::Error::new(std::io::ErrorKind::Unsupported, "not a local socket");
+    let (transport, rest) = network_id.split_once('/').ok_or_else(unsupported)?;
+    let (_host, path) = rest.split_once(':').ok_or_else(unsupported)?;
+    if !matches!(transport, "local" | "unix") {
+        return Err(unsupported());
+    }
+    match path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt as _;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            UnixStream::connect_addr(&addr)
+        }
+        None => UnixStream::connect(path),
+    }
+}
+
+/// Returns the MIT-MAGIC-COOKIE-1 of the protocol ("ICE" or "XSMP") for the network ID.
+fn find_cookie(protocol: &str, network_id: &str) -> Option<Vec<u8>> {
+    let data = std::fs::read(authority_path()?).ok()?;
+    find_cookie_in(&data, protocol, network_id)
+}
+
+/// Finds the cookie in the content of an ICEauthority file.
+fn find_cookie_in(data: &[u8], protocol: &str, network_id: &str) -> Option<Vec<u8>> {
+    // each entry consists of 5 fields, each of which is the length in 2 bytes (big endian)
+    // and the bytes: the protocol name, the protocol data, the network ID, the authentication
+    // name and the data
+    let mut rest = data;
+    let mut field = || {
+        let len = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+        let value = rest.get(2..2 + len)?;
+        rest = &rest[2 + len..];
+        Some(value)
+    };
+    loop {
+        let entry = [field()?, field()?, field()?, field()?, field()?];
+        if entry[0] == protocol.as_bytes()
+            && entry[2] == network_id.as_bytes()
+            && entry[3] == AUTH_NAME.as_bytes()
+        {
+            return Some(entry[4].to_vec());
+        }
+    }
+}
+
+/// `$ICEAUTHORITY`, or `~/.ICEauthority`
+fn authority_path() -> Option<PathBuf> {
+    match std::env::var_os("ICEAUTHORITY") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => Some(PathBuf::from(std::env::var_os("HOME")?).join(".ICEauthority")),
+    }
+}
+
+fn protocol_error(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_padded_to_8_bytes() {
+        let mut message = Message::new(MY_OPCODE, SM_REGISTER_CLIENT, [0, 0]);
+        message.array8(b"abc");
+        let raw = message.finish();
+        // the header, the length of the array in 4 bytes, and the 3 bytes padded to 8 bytes
+        assert_eq!(raw.len(), 16);
+        assert_eq!(&raw[8..12], &3u32.to_ne_bytes());
+        assert_eq!(&raw[12..], b"abc\0");
+        assert_eq!(&raw[4..8], &1u32.to_ne_bytes());
+
+        let mut message = Message::new(0, ICE_CONNECTION_SETUP, [1, 0]);
+        message.u8(0);
+        message.pad(8);
+        message.string("daily2");
+        message.version(1, 0);
+        let raw = message.finish();
+        assert_eq!(raw.len(), 32);
+        assert_eq!(&raw[16..24], &[6, 0, b'd', b'a', b'i', b'l', b'y', b'2']);
+        assert_eq!(&raw[4..8], &3u32.to_ne_bytes());
+
+        // an empty body
+        let raw = Message::new(0, ICE_PING, [0, 0]).finish();
+        assert_eq!(raw, [0, ICE_PING, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn arrays_are_read_in_the_byte_order_of_the_manager() {
+        let mut message = Message::new(0, 0, [0, 0]);
+        message.array8(b"first");
+        message.array8(b"");
+        message.array8(b"12345678");
+        let raw = message.finish();
+        let mut reader = Reader::new(&raw[8..], false);
+        assert_eq!(reader.array8(), Some(&b"first"[..]));
+        assert_eq!(reader.array8(), Some(&b""[..]));
+        assert_eq!(reader.array8(), Some(&b"12345678"[..]));
+        assert_eq!(reader.array8(), None);
+
+        let mut swapped = 4u32.to_ne_bytes();
+        swapped.reverse();
+        let mut raw = swapped.to_vec();
+        raw.extend_from_slice(b"abcd");
+        assert_eq!(Reader::new(&raw, true).array8(), Some(&b"abcd"[..]));
+
+        // the array is longer than the data
+        assert_eq!(Reader::new(&raw[..6], true).array8(), None);
+    }
+
+    #[test]
+    fn cookies_are_found_by_the_protocol_and_the_network_id() {
+        let network_id = "local/host:/tmp/.ICE-unix/123";
+        let mut data = Vec::new();
+        let mut entry = |fields: [&[u8]; 5]| {
+            for field in fields {
+                data.extend_from_slice(&(field.len() as u16).to_be_bytes());
+                data.extend_from_slice(field);
+            }
+        };
+        entry([
+            b"ICE",
+            b"",
+            b"local/host:/tmp/.ICE-unix/1",
+            AUTH_NAME.as_bytes(),
+            b"other",
+        ]);
+        entry([
+            b"ICE",
+            b"",
+            network_id.as_bytes(),
+            b"XDM-AUTHORIZATION-1",
+            b"xdm",
+        ]);
+        entry([
+            b"ICE",
+            b"",
+            network_id.as_bytes(),
+            AUTH_NAME.as_bytes(),
+            b"ice",
+        ]);
+        entry([
+            b"XSMP",
+            b"",
+            network_id.as_bytes(),
+            AUTH_NAME.as_bytes(),
+            b"xsmp",
+        ]);
+
+        let find = |protocol| find_cookie_in(&data, protocol, network_id);
+        assert_eq!(find("ICE").as_deref(), Some(&b"ice"[..]));
+        assert_eq!(find("XSMP").as_deref(), Some(&b"xsmp"[..]));
+        assert_eq!(find("OTHER"), None);
+        assert_eq!(
+            find_cookie_in(&data, "ICE", "local/host:/tmp/.ICE-unix/2"),
+            None
+        );
+        // a truncated file
+        assert_eq!(
+            find_cookie_in(&data[..data.len() - 2], "XSMP", network_id),
+            None
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// A harness running daily2 on a headless X server (Xvfb) for integration tests.
+// Tests are skipped when Xvfb is not installed.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use x11rb::connection::Connection as _;
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+pub const SCREEN_WIDTH: i32 = 1280;
+pub const SCREEN_HEIGHT: i32 = 800;
+
+/// how long to wait for the WM to react
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_DISPLAY: AtomicU32 = AtomicU32::new(0);
+
+/// an X server and the WM running on it, killed when dropped
+pub struct Session {
+    xvfb: Child,
+    wm: Option<Child>,
+    pub display: String,
+    /// `$XDG_RUNTIME_DIR` and `$XDG_STATE_HOME` of the WM, not to touch those of the user
+    dir: PathBuf,
+    pub conn: RustConnection,
+    pub root: xproto::Window,
+}
+
+impl Session {
+    /// Starts Xvfb and the WM. Panics if Xvfb is not found.
+    pub fn start() -> Session {
+        let (xvfb, display) = start_xvfb();
+        let (conn, screen) = connect(&display);
+        let root = conn.setup().roots[screen].root;
+
+        let dir = std::env::temp_dir().join(format!("daily2-test{}", &display[1..]));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let wm = Command::new(env!("CARGO_BIN_EXE_daily2"))
+            .env("DISPLAY", &display)
+            .env("XDG_RUNTIME_DIR", &dir)
+            .env("XDG_STATE_HOME", &dir)
+            .env("RUST_LOG", "daily2=debug")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(std::fs::File::create(dir.join("daily2.log")).unwrap())
+            .spawn()
+            .expect("failed to start daily2");
+
+        let session = Session {
+            xvfb,
+            wm: Some(wm),
+            display,
+            dir,
+            conn,
+            root,
+        };
+        let atom = session.atom("_NET_SUPPORTING_WM_CHECK");
+        session.wait_until("the WM starts", |s| {
+            s.get_property32(s.root, atom, xproto::AtomEnum::WINDOW.into())
+                .is_some()
+        });
+        session
+    }
+
+    pub fn atom(&self, name: &str) -> xproto::Atom {
+        self.conn
+            .intern_atom(false, name.as_bytes())
+            .unwrap()
+            .reply()
+            .unwrap()
+            .atom
+    }
+
+    pub fn get_property32(
+        &self,
+        window: xproto::Window,
+        property: xproto::Atom,
+        type_: xproto::Atom,
+    ) -> Option<Vec<u32>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, type_, 0, u32::MAX)
+            .unwrap()
+            .reply()
+            .unwrap();
+        reply.value32().map(|values| values.collect())
+    }
+
+    /// Creates a top-level window of a dummy client with WM_CLASS, and maps it.
+    pub fn map_window(&self, class: &str) -> xproto::Window {
+        let window = self.conn.generate_id().unwrap();
+        self.conn
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                self.root,
+                0,
+                0,
+                100,
+                100,
+                0,
+                xproto::WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &xproto::CreateWindowAux::new(),
+            )
+            .unwrap();
+        let wm_class = format!("{class}\0{class}\0");
+        self.conn
+            .change_property8(
+                xproto::PropMode::REPLACE,
+                window,
+                xproto::AtomEnum::WM_CLASS,
+                xproto::AtomEnum::STRING,
+                wm_class.as_bytes(),
+            )
+            .unwrap();
+        self.conn.map_window(window).unwrap();
+        self.conn.flush().unwrap();
+        self.wait_until("the window is mapped", |s| s.is_viewable(window));
+        window
+    }
+
+    pub fn is_viewable(&self, window: xproto::Window) -> bool {
+        match self.conn.get_window_attributes(window).unwrap().reply() {
+            Ok(attrs) => attrs.map_state == xproto::MapState::VIEWABLE,
+            Err(_) => false,
+        }
+    }
+
+    /// the geometry of the window (x, y, width, height) including the border
+    pub fn outer_geometry(&self, window: xproto::Window) -> (i32, i32, i32, i32) {
+        let geo = self.conn.get_geometry(window).unwrap().reply().unwrap();
+        let border = geo.border_width as i32 * 2;
+        (
+            geo.x as i32,
+            geo.y as i32,
+            geo.width as i32 + border,
+            geo.height as i32 + border,
+        )
+    }
+
+    pub fn input_focus(&self) -> xproto::Window {
+        self.conn.get_input_focus().unwrap().reply().unwrap().focus
+    }
+
+    /// Sends the request to the IPC socket of the WM, and returns the reply.
+    pub fn ipc(&self, request: &str) -> String {
+        // the socket is named after the display without the colon
+        let socket = self.dir.join(format!("daily2.{}.sock", &self.display[1..]));
+        let mut stream = UnixStream::connect(socket).expect("failed to connect to the IPC socket");
+        writeln!(stream, "{request}").unwrap();
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply).unwrap();
+        reply.trim().to_owned()
+    }
+
+    /// Polls the condition until it holds, and panics if it doesn't in time.
+    pub fn wait_until(&self, what: &str, condition: impl Fn(&Session) -> bool) {
+        let started = Instant::now();
+        while !condition(self) {
+            if started.elapsed() > TIMEOUT {
+                panic!("timed out waiting until {what}\n{}", self.log());
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// what the WM has logged so far
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("daily2.log")).unwrap_or_default()
+    }
+
+    /// Stops the WM, and returns whether it exited successfully.
+    pub fn stop(&mut self) -> bool {
+        let Some(mut wm) = self.wm.take() else {
+            return false;
+        };
+        unsafe { libc::kill(wm.id() as libc::pid_t, libc::SIGTERM) };
+        let started = Instant::now();
+        while started.elapsed() < TIMEOUT {
+            if let Some(status) = wm.try_wait().unwrap() {
+                return status.success();
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let _ = wm.kill();
+        let _ = wm.wait();
+        false
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(mut wm) = self.wm.take() {
+            let _ = wm.kill();
+            let _ = wm.wait();
+        }
+        let _ = self.xvfb.kill();
+        let _ = self.xvfb.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Starts Xvfb on an unused display, and waits until it accepts connections.
+fn start_xvfb() -> (Child, String) {
+    // the tests in a process run in parallel, and so may the test processes
+    let base = 100 + (std::process::id() % 100) * 10;
+    for _ in 0..20 {
+        let n = base + NEXT_DISPLAY.fetch_add(1, Ordering::SeqCst);
+        if PathBuf::from(format!("/tmp/.X{n}-lock")).exists() {
+            continue;
+        }
+        let display = format!(":{n}");
+        let size = format!("{SCREEN_WIDTH}x{SCREEN_HEIGHT}x24");
+        let spawned = Command::new("Xvfb")
+            .args([&display, "-nolisten", "tcp", "-screen", "0", &size])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut xvfb = match spawned {
+            Ok(xvfb) => xvfb,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                panic!("Xvfb is not found in $PATH, which the integration tests need");
+            }
+            Err(err) => panic!("failed to start Xvfb: {err}"),
+        };
+
+        let socket = PathBuf::from(format!("/tmp/.X11-unix/X{n}"));
+        let started = Instant::now();
+        while !socket.exists() && started.elapsed() < TIMEOUT {
+            if xvfb.try_wait().unwrap().is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        if socket.exists() && x11rb::connect(Some(&display)).is_ok() {
+            return (xvfb, display);
+        }
+        // another server may have taken the display in the meantime
+        let _ = xvfb.kill();
+        let _ = xvfb.wait();
+    }
+    panic!("failed to start Xvfb on any display");
+}
+
+fn connect(display: &str) -> (RustConnection, usize) {
+    x11rb::connect(Some(display)).expect("failed to connect to Xvfb")
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{Session, SCREEN_HEIGHT, SCREEN_WIDTH};
+use x11rb::protocol::xproto;
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn advertises_ewmh_support() {
+    let session = Session::start();
+    let check = session.atom("_NET_SUPPORTING_WM_CHECK");
+    let window = xproto::AtomEnum::WINDOW.into();
+    let dummy = session.get_property32(session.root, check, window).unwrap()[0];
+    // the dummy window points to itself
+    assert_eq!(
+        session.get_property32(dummy, check, window),
+        Some(vec![dummy])
+    );
+
+    let supported = session.atom("_NET_SUPPORTED");
+    let atoms = session
+        .get_property32(session.root, supported, xproto::AtomEnum::ATOM.into())
+        .unwrap();
+    assert!(atoms.contains(&session.atom("_NET_WM_STATE_FULLSCREEN")));
+    assert!(atoms.contains(&session.atom("_NET_WORKAREA")));
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn tiles_windows_side_by_side() {
+    let session = Session::start();
+    let first = session.map_window("first");
+    // a single tile covers the whole screen
+    session.wait_until("the first window is tiled", |s| {
+        s.outer_geometry(first) == (0, 0, SCREEN_WIDTH, SCREEN_HEIGHT)
+    });
+
+    let second = session.map_window("second");
+    let half = SCREEN_WIDTH / 2;
+    session.wait_until("the windows share the width", |s| {
+        s.outer_geometry(first) == (0, 0, half, SCREEN_HEIGHT)
+            && s.outer_geometry(second) == (half, 0, half, SCREEN_HEIGHT)
+    });
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn focuses_mapped_windows() {
+    let session = Session::start();
+    let first = session.map_window("first");
+    session.wait_until("the first window is focused", |s| s.input_focus() == first);
+    let second = session.map_window("second");
+    session.wait_until("the second window is focused", |s| {
+        s.input_focus() == second
+    });
+
+    session.ipc("focus --class first");
+    session.wait_until("the first window is focused again", |s| {
+        s.input_focus() == first
+    });
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn replies_to_ipc_queries() {
+    let session = Session::start();
+    let window = session.map_window("ipc-test");
+    session.wait_until("the window is focused", |s| s.input_focus() == window);
+
+    let windows = session.ipc("get_windows");
+    assert!(windows.contains(&format!("\"id\":{window}")), "{windows}");
+    assert!(windows.contains("\"class\":\"ipc-test\""), "{windows}");
+
+    let reply = session.ipc("no-such-command");
+    assert!(reply.contains("\"success\":false"), "{reply}");
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn exits_on_sigterm() {
+    let mut session = Session::start();
+    assert!(session.stop(), "{}", session.log());
+}